@@ -0,0 +1,251 @@
+//! Connects to a WebSocket echo server through the C API and reports
+//! connect time, ping round trips and echo throughput.
+
+use std::ffi::{CStr, CString, c_char, c_void};
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use c_wspp_rs::{
+    WsppResult, WsppWs, wspp_close, wspp_connect, wspp_delete, wspp_new_ext, wspp_ping, wspp_poll,
+    wspp_send_binary, wspp_set_close_handler, wspp_set_error_handler, wspp_set_message_handler,
+    wspp_set_open_handler, wspp_set_pong_handler, wspp_stopped,
+};
+
+const USAGE: &str = "usage: wspp-bench <uri> [--pings N] [--sizes N,N,...] [--count N] \
+                     [--compression]\n\nThe server must echo binary messages back.";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const ROUND_TIMEOUT: Duration = Duration::from_secs(30);
+
+static OPENED: AtomicBool = AtomicBool::new(false);
+static CLOSED: AtomicBool = AtomicBool::new(false);
+static PONGS: AtomicU64 = AtomicU64::new(0);
+static ECHOED: AtomicU64 = AtomicU64::new(0);
+static ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+extern "C" fn on_open(_: *mut c_void) {
+    OPENED.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn on_close(_: *mut c_void) {
+    CLOSED.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn on_message(_: *mut c_void, _data: *const c_char, len: u64, _op_code: i32) {
+    ECHOED.fetch_add(len, Ordering::Relaxed);
+}
+
+extern "C" fn on_pong(_: *mut c_void, _data: *const c_char, _len: u64) {
+    PONGS.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn on_error(_: *mut c_void, msg: *const c_char) {
+    let msg = unsafe { CStr::from_ptr(msg) }
+        .to_string_lossy()
+        .into_owned();
+    *ERROR.lock().unwrap_or_else(|err| err.into_inner()) = Some(msg);
+}
+
+#[derive(Debug)]
+struct Args {
+    uri: String,
+    pings: usize,
+    sizes: Vec<usize>,
+    count: usize,
+    compression: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self {
+            uri: String::new(),
+            pings: 100,
+            sizes: vec![64, 1024, 64 * 1024],
+            count: 1000,
+            compression: false,
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+            match arg.as_str() {
+                "--pings" => parsed.pings = number(&value("--pings")?)?,
+                "--count" => parsed.count = number(&value("--count")?)?,
+                "--sizes" => {
+                    parsed.sizes = value("--sizes")?
+                        .split(',')
+                        .map(number)
+                        .collect::<Result<_, _>>()?;
+                }
+                "--compression" => parsed.compression = true,
+                "-h" | "--help" => return Err(USAGE.into()),
+                _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+                _ if parsed.uri.is_empty() => parsed.uri = arg,
+                _ => return Err(format!("unexpected argument {arg}")),
+            }
+        }
+        if parsed.uri.is_empty() {
+            return Err(USAGE.into());
+        }
+        Ok(parsed)
+    }
+}
+
+fn number(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("not a number: {text}"))
+}
+
+/// Value at percentile `p` of sorted `samples`, nearest rank.
+fn percentile(samples: &[Duration], p: f64) -> Duration {
+    let rank = ((p / 100.0) * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+fn ms(duration: Duration) -> String {
+    format!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Polls without sleeping until `done` holds, the connection failed or
+/// `timeout` passed.
+fn poll_until(ws: *mut WsppWs, timeout: Duration, done: impl Fn() -> bool) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        wspp_poll(ws);
+        if let Some(err) = ERROR.lock().unwrap_or_else(|err| err.into_inner()).take() {
+            return Err(err);
+        }
+        if done() {
+            return Ok(());
+        }
+        if CLOSED.load(Ordering::Relaxed) {
+            return Err("connection closed".into());
+        }
+        if Instant::now() >= deadline {
+            return Err("timed out".into());
+        }
+        std::hint::spin_loop();
+    }
+}
+
+fn run(ws: *mut WsppWs, args: &Args) -> Result<(), String> {
+    let started = Instant::now();
+    if wspp_connect(ws) != WsppResult::Ok {
+        return Err("connect refused".into());
+    }
+    poll_until(ws, CONNECT_TIMEOUT, || OPENED.load(Ordering::Relaxed))?;
+    println!("connect      {}", ms(started.elapsed()));
+
+    if args.pings > 0 {
+        let mut rtts = Vec::with_capacity(args.pings);
+        for _ in 0..args.pings {
+            let before = PONGS.load(Ordering::Relaxed);
+            let sent = Instant::now();
+            if wspp_ping(ws, std::ptr::null(), 0) != WsppResult::Ok {
+                return Err("ping refused".into());
+            }
+            poll_until(ws, ROUND_TIMEOUT, || PONGS.load(Ordering::Relaxed) > before)?;
+            rtts.push(sent.elapsed());
+        }
+        rtts.sort();
+        println!(
+            "rtt          min {}  p50 {}  p95 {}  p99 {}  max {}",
+            ms(rtts[0]),
+            ms(percentile(&rtts, 50.0)),
+            ms(percentile(&rtts, 95.0)),
+            ms(percentile(&rtts, 99.0)),
+            ms(rtts[rtts.len() - 1]),
+        );
+    }
+
+    for &size in &args.sizes {
+        let payload = vec![0x5a_u8; size];
+        let expected = ECHOED.load(Ordering::Relaxed) + (size * args.count) as u64;
+        let started = Instant::now();
+        for _ in 0..args.count {
+            if wspp_send_binary(ws, payload.as_ptr().cast(), size as u64) != WsppResult::Ok {
+                return Err("send refused".into());
+            }
+        }
+        poll_until(ws, ROUND_TIMEOUT, || {
+            ECHOED.load(Ordering::Relaxed) >= expected
+        })?;
+        let secs = started.elapsed().as_secs_f64();
+        println!(
+            "echo {size:>7} B  {:>10.0} msg/s  {:>8.2} MiB/s",
+            args.count as f64 / secs,
+            (size * args.count) as f64 / secs / (1024.0 * 1024.0),
+        );
+    }
+
+    let reason = CString::default();
+    if wspp_close(ws, 1000, reason.as_ptr()) == WsppResult::Ok {
+        let _ = poll_until(ws, CONNECT_TIMEOUT, || wspp_stopped(ws));
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return ExitCode::from(2);
+        }
+    };
+    let Ok(uri) = CString::new(args.uri.as_str()) else {
+        eprintln!("uri contains a NUL byte");
+        return ExitCode::from(2);
+    };
+
+    let ws = wspp_new_ext(uri.as_ptr(), args.compression);
+    if ws.is_null() {
+        eprintln!("invalid uri {}", args.uri);
+        return ExitCode::from(2);
+    }
+    wspp_set_open_handler(ws, Some(on_open), std::ptr::null_mut());
+    wspp_set_close_handler(ws, Some(on_close), std::ptr::null_mut());
+    wspp_set_message_handler(ws, Some(on_message), std::ptr::null_mut());
+    wspp_set_pong_handler(ws, Some(on_pong), std::ptr::null_mut());
+    wspp_set_error_handler(ws, Some(on_error), std::ptr::null_mut());
+
+    let result = run(ws, &args);
+    wspp_delete(ws);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("failed: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Args, percentile};
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = parse(&["ws://h/", "--sizes", "1,2", "--count", "5"]).expect("valid");
+        assert_eq!(args.uri, "ws://h/");
+        assert_eq!(args.sizes, [1, 2]);
+        assert_eq!(args.count, 5);
+        assert_eq!(args.pings, 100);
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["ws://h/", "--count"]).is_err());
+        assert!(parse(&["ws://h/", "--sizes", "1,x"]).is_err());
+    }
+
+    #[test]
+    fn percentile_uses_nearest_rank() {
+        let samples: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_millis(5));
+        assert_eq!(percentile(&samples, 99.0), Duration::from_millis(10));
+        assert_eq!(percentile(&samples, 0.0), Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,262 @@
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+use crate::callback::{OnMemoryPressureCallback, WithCtx};
+use crate::logging;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BudgetPolicy {
+    PauseReads = 0,
+    DropMessages = 1,
+    CloseLargest = 2,
+}
+
+impl BudgetPolicy {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::PauseReads),
+            1 => Some(Self::DropMessages),
+            2 => Some(Self::CloseLargest),
+            _ => None,
+        }
+    }
+}
+
+/// Library-wide cap on payload bytes held in event/command queues. A limit
+/// of zero disables enforcement but usage is still tracked.
+pub struct Budget {
+    limit: AtomicU64,
+    policy: AtomicI32,
+    used: AtomicU64,
+    accounts: Mutex<Vec<Weak<Usage>>>,
+    on_pressure: RwLock<Option<WithCtx<OnMemoryPressureCallback>>>,
+}
+
+static GLOBAL: Budget = Budget::new();
+
+pub fn global() -> &'static Budget {
+    &GLOBAL
+}
+
+struct Usage {
+    budget: &'static Budget,
+    bytes: AtomicU64,
+}
+
+impl Drop for Usage {
+    fn drop(&mut self) {
+        // Whatever was still queued when both ends went away is freed now.
+        self.budget.sub_used(*self.bytes.get_mut());
+    }
+}
+
+/// Bytes one connection holds in its queues, shared by client and worker.
+#[derive(Clone)]
+pub struct BudgetAccount {
+    usage: Arc<Usage>,
+}
+
+fn saturating_sub(counter: &AtomicU64, bytes: u64) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        Some(v.saturating_sub(bytes))
+    });
+}
+
+impl Budget {
+    pub const fn new() -> Self {
+        Self {
+            limit: AtomicU64::new(0),
+            policy: AtomicI32::new(BudgetPolicy::PauseReads as i32),
+            used: AtomicU64::new(0),
+            accounts: Mutex::new(Vec::new()),
+            on_pressure: RwLock::new(None),
+        }
+    }
+
+    pub fn configure(&self, limit: u64, policy: BudgetPolicy) {
+        self.policy.store(policy as i32, Ordering::Relaxed);
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn set_pressure_handler(&self, handler: Option<WithCtx<OnMemoryPressureCallback>>) {
+        if let Ok(mut slot) = self.on_pressure.write() {
+            *slot = handler;
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn policy(&self) -> BudgetPolicy {
+        BudgetPolicy::from_ffi(self.policy.load(Ordering::Relaxed))
+            .unwrap_or(BudgetPolicy::PauseReads)
+    }
+
+    pub fn exceeded(&self) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+        limit > 0 && self.used() > limit
+    }
+
+    pub fn account(&'static self) -> BudgetAccount {
+        let usage = Arc::new(Usage {
+            budget: self,
+            bytes: AtomicU64::new(0),
+        });
+        if let Ok(mut accounts) = self.accounts.lock() {
+            accounts.retain(|account| account.strong_count() > 0);
+            accounts.push(Arc::downgrade(&usage));
+        }
+        BudgetAccount { usage }
+    }
+
+    fn add_used(&self, bytes: u64) {
+        let before = self.used.fetch_add(bytes, Ordering::Relaxed);
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 || before > limit || before + bytes <= limit {
+            return;
+        }
+
+        logging::emit(2, "memory budget exceeded");
+        let handler = match self.on_pressure.read() {
+            Ok(slot) => *slot,
+            Err(_) => None,
+        };
+        if let Some(handler) = handler {
+            (handler.f)(handler.ctx, before + bytes, limit);
+        }
+    }
+
+    fn sub_used(&self, bytes: u64) {
+        saturating_sub(&self.used, bytes);
+    }
+
+    fn largest(&self) -> Option<Arc<Usage>> {
+        let accounts = self.accounts.lock().ok()?;
+        accounts
+            .iter()
+            .filter_map(Weak::upgrade)
+            .max_by_key(|usage| usage.bytes.load(Ordering::Relaxed))
+    }
+}
+
+impl BudgetAccount {
+    pub fn charge(&self, bytes: usize) {
+        let bytes = bytes as u64;
+        self.usage.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.usage.budget.add_used(bytes);
+    }
+
+    pub fn release(&self, bytes: usize) {
+        let bytes = bytes as u64;
+        saturating_sub(&self.usage.bytes, bytes);
+        self.usage.budget.sub_used(bytes);
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.usage.budget.exceeded()
+    }
+
+    pub fn policy(&self) -> BudgetPolicy {
+        self.usage.budget.policy()
+    }
+
+    /// Whether this connection currently holds the most queued bytes.
+    pub fn is_largest(&self) -> bool {
+        self.usage
+            .budget
+            .largest()
+            .is_some_and(|largest| Arc::ptr_eq(&largest, &self.usage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::{Budget, BudgetPolicy};
+    use crate::callback::WithCtx;
+
+    fn budget() -> &'static Budget {
+        Box::leak(Box::new(Budget::new()))
+    }
+
+    static PRESSURE_CALLS: AtomicU64 = AtomicU64::new(0);
+
+    extern "C" fn on_pressure(_: *mut c_void, _used: u64, _limit: u64) {
+        PRESSURE_CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn tracks_charges_and_releases() {
+        let budget = budget();
+        let account = budget.account();
+
+        account.charge(100);
+        account.charge(50);
+        account.release(30);
+        assert_eq!(budget.used(), 120);
+        account.release(1000);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn dropping_accounts_frees_leftovers() {
+        let budget = budget();
+        let account = budget.account();
+        let worker_side = account.clone();
+
+        account.charge(64);
+        drop(account);
+        assert_eq!(budget.used(), 64);
+        drop(worker_side);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn pressure_fires_once_per_crossing() {
+        let budget = budget();
+        budget.set_pressure_handler(WithCtx::wrap(Some(on_pressure), std::ptr::null_mut()));
+        budget.configure(100, BudgetPolicy::DropMessages);
+        let account = budget.account();
+
+        account.charge(80);
+        assert!(!budget.exceeded());
+        account.charge(40);
+        account.charge(40);
+        assert!(budget.exceeded());
+        assert_eq!(PRESSURE_CALLS.load(Ordering::Relaxed), 1);
+        assert_eq!(account.policy(), BudgetPolicy::DropMessages);
+
+        account.release(160);
+        assert!(!budget.exceeded());
+    }
+
+    #[test]
+    fn zero_limit_disables_enforcement() {
+        let budget = budget();
+        let account = budget.account();
+        account.charge(usize::MAX / 2);
+        assert!(!budget.exceeded());
+    }
+
+    #[test]
+    fn finds_largest_account() {
+        let budget = budget();
+        let small = budget.account();
+        let large = budget.account();
+
+        small.charge(10);
+        large.charge(20);
+        assert!(large.is_largest());
+        assert!(!small.is_largest());
+    }
+
+    #[test]
+    fn maps_ffi_policies() {
+        assert_eq!(BudgetPolicy::from_ffi(2), Some(BudgetPolicy::CloseLargest));
+        assert_eq!(BudgetPolicy::from_ffi(3), None);
+    }
+}
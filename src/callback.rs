@@ -1,17 +1,174 @@
-use std::ffi::c_char;
+use std::ffi::{c_char, c_void};
 
-pub type OnOpenCallback = extern "C" fn();
-pub type OnCloseCallback = extern "C" fn();
-pub type OnMessageCallback = extern "C" fn(data: *const c_char, len: u64, op_code: i32);
-pub type OnErrorCallback = extern "C" fn(msg: *const c_char);
-pub type OnPongCallback = extern "C" fn(data: *const c_char, len: u64);
-pub type OnLogCallback = extern "C" fn(level: i32, msg: *const c_char);
+use crate::client::{WsppErrorInfo, WsppEvent, WsppHandshakeInfo};
+use crate::result::WsppResult;
 
-#[derive(Default)]
+pub type OnOpenCallback = extern "C" fn(user_data: *mut c_void);
+/// Called right after `OnOpenCallback`; `info` is only valid until the
+/// callback returns.
+pub type OnOpenExtCallback = extern "C" fn(user_data: *mut c_void, info: *const WsppHandshakeInfo);
+pub type OnCloseCallback = extern "C" fn(user_data: *mut c_void);
+/// Called right after `OnCloseCallback` with the peer's close code, 1005
+/// if its close frame had none and 1006 if the connection ended without
+/// one. `reason` is not NUL-terminated and only valid until the callback
+/// returns.
+pub type OnCloseExtCallback =
+    extern "C" fn(user_data: *mut c_void, code: u16, reason: *const c_char, len: u64);
+/// Called instead of the open callback once a restarted worker is
+/// connected again; see `wspp_set_auto_resume`.
+pub type OnResumedCallback = extern "C" fn(user_data: *mut c_void);
+/// Called when a reconnect attempt is scheduled, with its number counting
+/// from one; see `wspp_set_auto_reconnect`.
+pub type OnReconnectingCallback = extern "C" fn(user_data: *mut c_void, attempt: u32);
+/// Called instead of the open callbacks once a reconnect attempt is
+/// connected, with the attempt's number and how long the connection was
+/// down, so the host can tell it apart from the first connection.
+pub type OnReconnectedCallback =
+    extern "C" fn(user_data: *mut c_void, attempt: u32, downtime_ms: u64);
+/// Called once reconnecting stopped after `attempts` failed in a row, right
+/// after the error or close of the last one was reported.
+pub type OnReconnectGaveUpCallback = extern "C" fn(user_data: *mut c_void, attempts: u32);
+/// `data` points into the received frame and is only valid until the
+/// callback returns; copy it to keep it. `op_code` is a `WsppOpcode`.
+pub type OnMessageCallback =
+    extern "C" fn(user_data: *mut c_void, data: *const c_char, len: u64, op_code: i32);
+/// Returns a `FilterAction`: 0 delivers the message, 1 drops it. Runs
+/// before response routing and `OnMessageCallback`.
+pub type MessageFilter =
+    extern "C" fn(user_data: *mut c_void, data: *const c_char, len: u64, op_code: i32) -> i32;
+pub type OnMessageFileCallback =
+    extern "C" fn(user_data: *mut c_void, path: *const c_char, len: u64, op_code: i32);
+pub type OnErrorCallback = extern "C" fn(user_data: *mut c_void, msg: *const c_char);
+/// Called right after `OnErrorCallback`; `info` is only valid until the
+/// callback returns.
+pub type OnErrorExtCallback = extern "C" fn(user_data: *mut c_void, info: *const WsppErrorInfo);
+/// Same lifetime rule as `OnMessageCallback` applies to `data`.
+pub type OnPongCallback = extern "C" fn(user_data: *mut c_void, data: *const c_char, len: u64);
+/// One incoming frame as received: `rsv_bits` holds RSV1..RSV3 in its low
+/// three bits and `opcode` is the 4-bit wire opcode. Same lifetime rule as
+/// `OnMessageCallback` applies to `data`.
+#[cfg(feature = "unsafe-protocol")]
+pub type OnRawFrameCallback = extern "C" fn(
+    user_data: *mut c_void,
+    fin: bool,
+    rsv_bits: i32,
+    opcode: i32,
+    data: *const c_char,
+    len: u64,
+);
+/// Every event drained by one poll, in order, instead of the per-event
+/// callbacks. `events` is only valid until the callback returns.
+pub type OnEventsCallback =
+    extern "C" fn(user_data: *mut c_void, events: *const WsppEvent, count: u64);
+pub type OnDeletedCallback = extern "C" fn(userdata: *mut c_void);
+/// Hands a borrowed send buffer back. May run on the worker thread.
+pub type ReleaseCallback = extern "C" fn(userdata: *mut c_void);
+pub type OnBackpressureCallback = extern "C" fn(user_data: *mut c_void, active: bool);
+pub type OnWatchdogCallback = extern "C" fn(user_data: *mut c_void);
+pub type OnHealthCallback = extern "C" fn(user_data: *mut c_void, score: u32);
+pub type OnLogCallback = extern "C" fn(user_data: *mut c_void, level: i32, msg: *const c_char);
+/// Raw bytes read from or written to a socket, `direction` being a
+/// `WsppWireDirection`. Runs on worker threads; `data` is only valid until
+/// the callback returns.
+pub type OnWireDataCallback =
+    extern "C" fn(user_data: *mut c_void, direction: i32, data: *const c_char, len: u64);
+pub type OnMemoryPressureCallback = extern "C" fn(user_data: *mut c_void, used: u64, limit: u64);
+/// Depths of the command and event queues when one reached the threshold.
+pub type OnQueuePressureCallback =
+    extern "C" fn(user_data: *mut c_void, commands: u64, events: u64);
+pub type OnResponseCallback = extern "C" fn(
+    user_data: *mut c_void,
+    request_id: u64,
+    data: *const c_char,
+    len: u64,
+    result: WsppResult,
+);
+/// Fills `buf` with up to `cap` bytes and returns the count, 0 at the end
+/// of the message or a negative value to abort. Runs on the worker thread.
+pub type StreamProvider = extern "C" fn(userdata: *mut c_void, buf: *mut c_char, cap: u64) -> i64;
+/// Writes `len` random bytes to `buf` and returns whether it succeeded.
+/// Runs on the worker thread.
+pub type RandomSource = extern "C" fn(userdata: *mut c_void, buf: *mut c_char, len: u64) -> bool;
+/// Writes the payload of the next keepalive ping, at most `cap` bytes, to
+/// `buf` and returns its length, or a negative value to send the numbered
+/// default. Runs on the worker thread.
+pub type PingPayloadSource =
+    extern "C" fn(userdata: *mut c_void, buf: *mut c_char, cap: u64) -> i64;
+/// Writes the passphrase of an encrypted client key, or the PIN of a
+/// PKCS#11 token, at most `cap` bytes, to `buf` and returns its length, or
+/// a negative value to fail the connect. Runs on the thread connecting; `buf` is wiped afterwards.
+pub type KeyPassphraseSource =
+    extern "C" fn(userdata: *mut c_void, buf: *mut c_char, cap: u64) -> i64;
+pub type ResponseIdExtractor = extern "C" fn(
+    user_data: *mut c_void,
+    data: *const c_char,
+    len: u64,
+    op_code: i32,
+    out_id: *mut u64,
+) -> bool;
+
+/// A host pointer the library only hands back.
+#[derive(Clone, Copy, Debug)]
+pub struct Userdata(pub *mut c_void);
+
+// Whatever it points to is the host's business, on any thread.
+unsafe impl Send for Userdata {}
+
+impl Userdata {
+    pub fn get(self) -> *mut c_void {
+        self.0
+    }
+}
+
+impl Default for Userdata {
+    fn default() -> Self {
+        Self(std::ptr::null_mut())
+    }
+}
+
+/// A callback and the user data pointer it is called with.
+#[derive(Clone, Copy)]
+pub struct WithCtx<F> {
+    pub f: F,
+    pub ctx: *mut c_void,
+}
+
+impl<F> WithCtx<F> {
+    /// Pairs a callback the host may have left null with its user data.
+    pub fn wrap(f: Option<F>, ctx: *mut c_void) -> Option<Self> {
+        f.map(|f| Self { f, ctx })
+    }
+}
+
+// The host decides which thread polls and owns whatever `ctx` points to;
+// handlers that run on worker threads say so.
+unsafe impl<F: Send> Send for WithCtx<F> {}
+unsafe impl<F: Sync> Sync for WithCtx<F> {}
+
+#[derive(Clone, Copy, Default)]
 pub struct Callbacks {
-    pub on_open: Option<OnOpenCallback>,
-    pub on_close: Option<OnCloseCallback>,
-    pub on_message: Option<OnMessageCallback>,
-    pub on_error: Option<OnErrorCallback>,
-    pub on_pong: Option<OnPongCallback>,
+    pub on_open: Option<WithCtx<OnOpenCallback>>,
+    pub on_open_ext: Option<WithCtx<OnOpenExtCallback>>,
+    pub on_close: Option<WithCtx<OnCloseCallback>>,
+    pub on_close_ext: Option<WithCtx<OnCloseExtCallback>>,
+    pub on_resumed: Option<WithCtx<OnResumedCallback>>,
+    pub on_reconnecting: Option<WithCtx<OnReconnectingCallback>>,
+    pub on_reconnected: Option<WithCtx<OnReconnectedCallback>>,
+    pub on_reconnect_gave_up: Option<WithCtx<OnReconnectGaveUpCallback>>,
+    pub on_message: Option<WithCtx<OnMessageCallback>>,
+    pub on_filter: Option<WithCtx<MessageFilter>>,
+    pub on_message_file: Option<WithCtx<OnMessageFileCallback>>,
+    pub on_error: Option<WithCtx<OnErrorCallback>>,
+    pub on_error_ext: Option<WithCtx<OnErrorExtCallback>>,
+    pub on_pong: Option<WithCtx<OnPongCallback>>,
+    pub on_unsolicited_pong: Option<WithCtx<OnPongCallback>>,
+    pub on_watchdog: Option<WithCtx<OnWatchdogCallback>>,
+    pub on_health: Option<WithCtx<OnHealthCallback>>,
+    pub on_backpressure: Option<WithCtx<OnBackpressureCallback>>,
+    pub on_queue_pressure: Option<WithCtx<OnQueuePressureCallback>>,
+    #[cfg(feature = "unsafe-protocol")]
+    pub on_raw_frame: Option<WithCtx<OnRawFrameCallback>>,
+    pub on_response: Option<WithCtx<OnResponseCallback>>,
+    pub on_events: Option<WithCtx<OnEventsCallback>>,
+    pub extract_response_id: Option<WithCtx<ResponseIdExtractor>>,
 }
@@ -0,0 +1,122 @@
+use bytes::{Bytes, BytesMut};
+
+/// Largest block a growing arena will allocate.
+const MAX_GROWN_BLOCK: usize = 16 * 1024 * 1024;
+
+/// What the arena does with payloads too large for its current blocks.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum BufferGrowth {
+    /// Keeps the configured block size; large payloads keep their own buffer.
+    #[default]
+    Fixed = 0,
+    /// Enlarges blocks so later payloads of that size are packed as well.
+    Grow = 1,
+}
+
+impl BufferGrowth {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Fixed),
+            1 => Some(Self::Grow),
+            _ => None,
+        }
+    }
+}
+
+/// Packs small event payloads into shared blocks so a message storm costs
+/// one allocation per block instead of one per message. A block is reused
+/// once every payload carved from it was dropped, i.e. after poll drains.
+pub struct PayloadArena {
+    block: BytesMut,
+    block_size: usize,
+    growth: BufferGrowth,
+}
+
+impl PayloadArena {
+    pub fn new(block_size: usize, growth: BufferGrowth) -> Self {
+        Self {
+            block: BytesMut::with_capacity(block_size),
+            block_size,
+            growth,
+        }
+    }
+
+    /// Returns a copy of `payload` in the arena, or the payload itself when
+    /// it is too large to be worth packing.
+    pub fn alloc(&mut self, payload: &Bytes) -> Bytes {
+        let len = payload.len();
+        if len == 0 {
+            return payload.clone();
+        }
+        if len > self.block_size / 4 {
+            let grown = len.saturating_mul(4).next_power_of_two();
+            if self.growth == BufferGrowth::Fixed || grown > MAX_GROWN_BLOCK {
+                return payload.clone();
+            }
+            self.block_size = grown;
+        }
+        if self.block.capacity() < len {
+            // Reclaims the old block in place when nothing references it.
+            self.block.reserve(self.block_size);
+        }
+        self.block.extend_from_slice(payload);
+        self.block.split().freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{BufferGrowth, PayloadArena};
+
+    #[test]
+    fn small_payloads_share_a_block() {
+        let mut arena = PayloadArena::new(64, BufferGrowth::Fixed);
+        let first = arena.alloc(&Bytes::from_static(b"abcd"));
+        let second = arena.alloc(&Bytes::from_static(b"efgh"));
+
+        assert_eq!(first.as_ref(), b"abcd");
+        assert_eq!(second.as_ref(), b"efgh");
+        assert_eq!(second.as_ptr(), first.as_ptr().wrapping_add(4));
+    }
+
+    #[test]
+    fn large_payloads_pass_through() {
+        let mut arena = PayloadArena::new(64, BufferGrowth::Fixed);
+        let large = Bytes::from(vec![7; 32]);
+        assert_eq!(arena.alloc(&large).as_ptr(), large.as_ptr());
+    }
+
+    #[test]
+    fn growing_arenas_pack_large_payloads() {
+        let mut arena = PayloadArena::new(64, BufferGrowth::Grow);
+        let large = Bytes::from(vec![7; 32]);
+        let first = arena.alloc(&large);
+        let second = arena.alloc(&large);
+
+        assert_ne!(first.as_ptr(), large.as_ptr());
+        assert_eq!(second.as_ptr(), first.as_ptr().wrapping_add(32));
+        assert_eq!(second, large);
+    }
+
+    #[test]
+    fn drained_blocks_are_reused() {
+        let mut arena = PayloadArena::new(16, BufferGrowth::Fixed);
+        let payloads: Vec<Bytes> = (0..4)
+            .map(|_| arena.alloc(&Bytes::from_static(b"wxyz")))
+            .collect();
+        let start = payloads[0].as_ptr();
+        drop(payloads);
+
+        let reused = arena.alloc(&Bytes::from_static(b"next"));
+        assert_eq!(reused.as_ptr(), start);
+    }
+
+    #[test]
+    fn maps_ffi_growth_policies() {
+        assert_eq!(BufferGrowth::from_ffi(1), Some(BufferGrowth::Grow));
+        assert_eq!(BufferGrowth::from_ffi(2), None);
+    }
+}
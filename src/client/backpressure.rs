@@ -0,0 +1,107 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use super::worker::{Event, EventSender};
+
+/// A write blocked for longer than this counts as a stall.
+pub const WRITE_STALL_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// Flags writes that sit on a full socket buffer. The flag is shared with
+/// the client for queries; transitions are also queued as events.
+pub struct StallMonitor {
+    stalled: Arc<AtomicBool>,
+    event_tx: EventSender,
+    threshold: Duration,
+}
+
+impl StallMonitor {
+    pub fn new(stalled: Arc<AtomicBool>, event_tx: EventSender, threshold: Duration) -> Self {
+        Self {
+            stalled,
+            event_tx,
+            threshold,
+        }
+    }
+
+    /// Drives a write to completion, reporting backpressure while it takes
+    /// longer than the threshold.
+    pub async fn watch<F: Future>(&self, write: F) -> F::Output {
+        let mut write = std::pin::pin!(write);
+        if let Ok(output) = tokio::time::timeout(self.threshold, &mut write).await {
+            return output;
+        }
+
+        self.set(true);
+        let output = write.await;
+        self.set(false);
+        output
+    }
+
+    fn set(&self, active: bool) {
+        self.stalled.store(active, Ordering::Relaxed);
+        let _ = self.event_tx.send(Event::Backpressure(active));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use tokio::runtime::Builder;
+
+    use super::StallMonitor;
+    use crate::client::worker::{Event, EventSender};
+
+    fn run<F: Future>(fut: F) -> F::Output {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime")
+            .block_on(fut)
+    }
+
+    #[test]
+    fn fast_writes_stay_silent() {
+        let (tx, rx) = mpsc::channel();
+        let stalled = Arc::new(AtomicBool::new(false));
+        let monitor = StallMonitor::new(
+            stalled.clone(),
+            EventSender::new(tx, Arc::default()),
+            Duration::from_millis(50),
+        );
+
+        assert_eq!(run(monitor.watch(async { 7 })), 7);
+        assert!(rx.try_recv().is_err());
+        assert!(!stalled.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn slow_writes_report_start_and_end() {
+        let (tx, rx) = mpsc::channel();
+        let stalled = Arc::new(AtomicBool::new(false));
+        let monitor = StallMonitor::new(
+            stalled.clone(),
+            EventSender::new(tx, Arc::default()),
+            Duration::from_millis(5),
+        );
+        let flag = stalled.clone();
+
+        let seen_during = run(monitor.watch(async move {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            flag.load(Ordering::Relaxed)
+        }));
+
+        assert!(seen_during);
+        assert!(!stalled.load(Ordering::Relaxed));
+        let events: Vec<bool> = rx
+            .try_iter()
+            .map(|(_, event)| matches!(event, Event::Backpressure(true)))
+            .collect();
+        assert_eq!(events, vec![true, false]);
+    }
+}
@@ -0,0 +1,238 @@
+use std::ffi::{CString, c_char};
+use std::path::PathBuf;
+
+use crate::callback::{OnEventsCallback, WithCtx};
+
+use super::error::{WorkerError, WsppErrorInfo};
+use super::payload::Payload;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WsppEventKind {
+    Open = 0,
+    Close = 1,
+    Message = 2,
+    MessageFile = 3,
+    Pong = 4,
+    UnsolicitedPong = 5,
+    Backpressure = 6,
+    Watchdog = 7,
+    Error = 8,
+    /// The connection was resumed after a restart of the worker.
+    Resumed = 9,
+    /// A reconnect attempt was scheduled.
+    Reconnecting = 10,
+    /// Reconnecting stopped with all attempts used up.
+    ReconnectGaveUp = 11,
+    /// A reconnect attempt is connected; reported instead of `Open`.
+    Reconnected = 12,
+}
+
+/// One event of a batch handed to `OnEventsCallback`. Pointers are only
+/// valid until the callback returns.
+#[repr(C)]
+#[derive(Debug)]
+pub struct WsppEvent {
+    pub kind: WsppEventKind,
+    /// Sequence number of the event, as in `wspp_poll_ex`.
+    pub sequence: u64,
+    /// Message or pong payload, close reason, the NUL-terminated spill file
+    /// path of a `MessageFile` or the NUL-terminated message of an `Error`;
+    /// null for the other kinds.
+    pub data: *const c_char,
+    /// Length of `data`, without the terminator.
+    pub len: u64,
+    /// Size of the spilled message of a `MessageFile`, the downtime in
+    /// milliseconds of a `Reconnected`, 0 otherwise.
+    pub message_len: u64,
+    /// `WsppOpcode` of a message, close code of a `Close`, 1 or 0 for
+    /// `Backpressure` turning on or off, the attempt of a `Reconnecting` or
+    /// `Reconnected`, the attempts made for a `ReconnectGaveUp`, 0
+    /// otherwise.
+    pub value: i32,
+    /// Details of an `Error`, null for the other kinds.
+    pub error: *const WsppErrorInfo,
+}
+
+/// Owns what the events of one batch point into.
+struct Item {
+    kind: WsppEventKind,
+    sequence: u64,
+    data: Option<Payload>,
+    value: i32,
+    message_len: u64,
+    error: Option<(CString, WsppErrorInfo)>,
+    spill: Option<PathBuf>,
+}
+
+/// Events gathered during one poll for a single `OnEventsCallback` call.
+#[derive(Default)]
+pub struct Batch {
+    items: Vec<Item>,
+}
+
+impl Batch {
+    pub fn push(&mut self, kind: WsppEventKind, sequence: u64) {
+        self.push_data(kind, sequence, None, 0);
+    }
+
+    pub fn push_data(
+        &mut self,
+        kind: WsppEventKind,
+        sequence: u64,
+        data: Option<Payload>,
+        value: i32,
+    ) {
+        self.items.push(Item {
+            kind,
+            sequence,
+            data,
+            value,
+            message_len: 0,
+            error: None,
+            spill: None,
+        });
+    }
+
+    /// Queues a spilled message; the file is removed after delivery.
+    pub fn push_file(&mut self, sequence: u64, path: PathBuf, len: u64, opcode: i32) {
+        let mut c_path = path.to_string_lossy().into_owned().into_bytes();
+        c_path.push(0);
+        self.items.push(Item {
+            kind: WsppEventKind::MessageFile,
+            sequence,
+            data: Some(Payload::from(c_path)),
+            value: opcode,
+            message_len: len,
+            error: None,
+            spill: Some(path),
+        });
+    }
+
+    pub fn push_reconnected(&mut self, sequence: u64, attempt: u32, downtime_ms: u64) {
+        self.items.push(Item {
+            kind: WsppEventKind::Reconnected,
+            sequence,
+            data: None,
+            value: attempt as i32,
+            message_len: downtime_ms,
+            error: None,
+            spill: None,
+        });
+    }
+
+    pub fn push_error(&mut self, sequence: u64, err: &WorkerError) {
+        let message = err.c_message();
+        let info = err.info(&message);
+        self.items.push(Item {
+            kind: WsppEventKind::Error,
+            sequence,
+            data: None,
+            value: 0,
+            message_len: 0,
+            error: Some((message, info)),
+            spill: None,
+        });
+    }
+
+    /// Hands every gathered event to `cb` in one call, unless there are
+    /// none, and returns how many it was.
+    pub fn deliver(self, cb: WithCtx<OnEventsCallback>) -> usize {
+        if self.items.is_empty() {
+            return 0;
+        }
+        let events: Vec<WsppEvent> = self.items.iter().map(Item::event).collect();
+        (cb.f)(cb.ctx, events.as_ptr(), events.len() as u64);
+        for path in self.items.iter().filter_map(|item| item.spill.as_ref()) {
+            let _ = std::fs::remove_file(path);
+        }
+        events.len()
+    }
+}
+
+impl Item {
+    fn event(&self) -> WsppEvent {
+        let (data, len) = match (&self.error, &self.data) {
+            (Some((message, _)), _) => (message.as_ptr(), message.as_bytes().len()),
+            (None, Some(data)) if self.spill.is_some() => {
+                (data.as_ptr() as *const c_char, data.len() - 1)
+            }
+            (None, Some(data)) => (data.as_ptr() as *const c_char, data.len()),
+            (None, None) => (std::ptr::null(), 0),
+        };
+        WsppEvent {
+            kind: self.kind,
+            sequence: self.sequence,
+            data,
+            len: len as u64,
+            message_len: self.message_len,
+            value: self.value,
+            error: self
+                .error
+                .as_ref()
+                .map_or(std::ptr::null(), |(_, info)| info as *const WsppErrorInfo),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::ffi::{CStr, c_void};
+
+    use super::{Batch, WsppEvent, WsppEventKind};
+    use crate::callback::WithCtx;
+    use crate::client::Payload;
+    use crate::client::error::{WorkerError, WsppErrorCategory};
+
+    thread_local! {
+        static SEEN: RefCell<Vec<(WsppEventKind, u64, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    extern "C" fn record(_: *mut c_void, events: *const WsppEvent, count: u64) {
+        let events = unsafe { std::slice::from_raw_parts(events, count as usize) };
+        SEEN.with_borrow_mut(|seen| {
+            for event in events {
+                let data = if event.data.is_null() {
+                    Vec::new()
+                } else {
+                    unsafe {
+                        std::slice::from_raw_parts(event.data as *const u8, event.len as usize)
+                    }
+                    .to_vec()
+                };
+                if event.kind == WsppEventKind::Error {
+                    let info = unsafe { &*event.error };
+                    let message = unsafe { CStr::from_ptr(info.message) };
+                    assert_eq!(message.to_bytes(), data);
+                }
+                seen.push((event.kind, event.sequence, data));
+            }
+        });
+    }
+
+    const RECORD: WithCtx<super::OnEventsCallback> = WithCtx {
+        f: record,
+        ctx: std::ptr::null_mut(),
+    };
+
+    #[test]
+    fn delivers_all_events_in_one_call() {
+        let mut batch = Batch::default();
+        batch.push(WsppEventKind::Open, 1);
+        batch.push_data(WsppEventKind::Message, 2, Some(Payload::from("hi")), 1);
+        batch.push_error(3, &WorkerError::new(WsppErrorCategory::Tcp, "reset"));
+        assert_eq!(batch.deliver(RECORD), 3);
+
+        let seen = SEEN.take();
+        assert_eq!(
+            seen,
+            [
+                (WsppEventKind::Open, 1, Vec::new()),
+                (WsppEventKind::Message, 2, b"hi".to_vec()),
+                (WsppEventKind::Error, 3, b"reset".to_vec()),
+            ]
+        );
+        assert_eq!(Batch::default().deliver(RECORD), 0);
+    }
+}
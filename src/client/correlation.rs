@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Requests awaiting a response, keyed by the caller-chosen request id.
+/// A `None` deadline waits until the connection goes away.
+#[derive(Default)]
+pub struct PendingRequests {
+    deadlines: HashMap<u64, Option<Instant>>,
+}
+
+impl PendingRequests {
+    pub fn insert(&mut self, id: u64, deadline: Option<Instant>) -> bool {
+        if self.deadlines.contains_key(&id) {
+            return false;
+        }
+
+        self.deadlines.insert(id, deadline);
+        true
+    }
+
+    pub fn complete(&mut self, id: u64) -> bool {
+        self.deadlines.remove(&id).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    pub fn take_expired(&mut self, now: Instant) -> Vec<u64> {
+        if self.deadlines.is_empty() {
+            return Vec::new();
+        }
+
+        let mut expired: Vec<u64> = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| deadline.is_some_and(|d| d <= now))
+            .map(|(id, _)| *id)
+            .collect();
+        expired.sort_unstable();
+        for id in &expired {
+            self.deadlines.remove(id);
+        }
+        expired
+    }
+
+    pub fn take_all(&mut self) -> Vec<u64> {
+        let mut all: Vec<u64> = self.deadlines.drain().map(|(id, _)| id).collect();
+        all.sort_unstable();
+        all
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::PendingRequests;
+
+    #[test]
+    fn rejects_duplicate_ids() {
+        let mut pending = PendingRequests::default();
+        assert!(pending.insert(1, None));
+        assert!(!pending.insert(1, None));
+        assert!(pending.complete(1));
+        assert!(!pending.complete(1));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn expires_only_past_deadlines() {
+        let now = Instant::now();
+        let mut pending = PendingRequests::default();
+        pending.insert(3, Some(now + Duration::from_secs(1)));
+        pending.insert(2, Some(now));
+        pending.insert(1, None);
+
+        assert_eq!(pending.take_expired(now), vec![2]);
+        assert_eq!(pending.take_expired(now + Duration::from_secs(1)), vec![3]);
+        assert_eq!(pending.take_all(), vec![1]);
+        assert!(pending.is_empty());
+    }
+}
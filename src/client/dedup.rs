@@ -0,0 +1,112 @@
+use std::time::{Duration, Instant};
+
+use super::error::WorkerError;
+
+/// The error delivered last and the identical ones held back since.
+struct Held {
+    error: WorkerError,
+    since: Instant,
+    suppressed: u32,
+}
+
+/// Collapses identical consecutive errors within a window, so hosts that
+/// show every error are not flooded while a server is down.
+pub struct ErrorDedup {
+    window: Duration,
+    held: Option<Held>,
+}
+
+impl ErrorDedup {
+    pub fn new(window: Duration) -> Self {
+        Self { window, held: None }
+    }
+
+    /// Errors to deliver for `err`: nothing while it repeats the last one
+    /// within the window, else the summary of held back repeats, if any,
+    /// followed by `err` itself.
+    pub fn admit(&mut self, err: WorkerError, now: Instant) -> Vec<WorkerError> {
+        if let Some(held) = self.held.as_mut()
+            && now.duration_since(held.since) < self.window
+            && held.error.category == err.category
+            && held.error.message == err.message
+        {
+            held.suppressed += 1;
+            return Vec::new();
+        }
+        let mut out: Vec<WorkerError> = self.expire(now, true).into_iter().collect();
+        self.held = Some(Held {
+            error: err.clone(),
+            since: now,
+            suppressed: 0,
+        });
+        out.push(err);
+        out
+    }
+
+    /// Summary of the repeats held back once their window is over, or
+    /// right away with `force`.
+    pub fn expire(&mut self, now: Instant, force: bool) -> Option<WorkerError> {
+        let held = self.held.as_ref()?;
+        if !force && now.duration_since(held.since) < self.window {
+            return None;
+        }
+        let held = self.held.take()?;
+        (held.suppressed > 0).then_some(WorkerError {
+            repeats: held.suppressed,
+            ..held.error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::ErrorDedup;
+    use crate::client::error::{WorkerError, WsppErrorCategory};
+
+    fn refused() -> WorkerError {
+        WorkerError::new(WsppErrorCategory::Tcp, "connection refused")
+    }
+
+    #[test]
+    fn collapses_repeats_into_one_summary() {
+        let start = Instant::now();
+        let mut dedup = ErrorDedup::new(Duration::from_secs(5));
+        assert_eq!(dedup.admit(refused(), start).len(), 1);
+        for i in 1..=3 {
+            let at = start + Duration::from_secs(i);
+            assert!(dedup.admit(refused(), at).is_empty());
+        }
+        assert!(
+            dedup
+                .expire(start + Duration::from_secs(4), false)
+                .is_none()
+        );
+
+        let summary = dedup
+            .expire(start + Duration::from_secs(5), false)
+            .expect("summary");
+        assert_eq!(summary.repeats, 3);
+        assert_eq!(summary.message, "connection refused");
+        assert!(
+            dedup
+                .expire(start + Duration::from_secs(6), false)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn a_different_error_flushes_the_summary_first() {
+        let start = Instant::now();
+        let mut dedup = ErrorDedup::new(Duration::from_secs(5));
+        dedup.admit(refused(), start);
+        dedup.admit(refused(), start);
+
+        let out = dedup.admit(WorkerError::new(WsppErrorCategory::Dns, "no host"), start);
+        let repeats: Vec<u32> = out.iter().map(|err| err.repeats).collect();
+        assert_eq!(repeats, [1, 1]);
+        assert_eq!(out[0].category, WsppErrorCategory::Tcp);
+        assert_eq!(out[1].category, WsppErrorCategory::Dns);
+    }
+}
@@ -0,0 +1,1246 @@
+use std::ffi::{c_char, c_void};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::queue::Priority;
+use super::worker::Command;
+use super::{
+    ChunkSource, HostRandom, IpFamily, Jitter, Payload, PingPayload, PongPolicy, ProviderSource,
+    QueuePolicy, Reconnect, WsState, WsppErrorCategory, WsppPollReport, WsppTimeoutPhase,
+    WsppWsImpl,
+};
+use crate::result::WsppResult;
+use crate::test_support::{
+    Recorded, ServerConfig, TestServer, connected, install_recorder, no_ctx, on_close_ext,
+    on_error_ext, on_open_ext, poll_until, unused_url,
+};
+
+#[test]
+fn events_arrive_in_order() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    assert_eq!(ws.send_message("hello"), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 2).len(), 2);
+    assert_eq!(ws.send_binary(vec![1, 2, 3]), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 3).len(), 3);
+    assert_eq!(ws.ping(b"p".to_vec()), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 4).len(), 4);
+    assert_eq!(ws.close(1000, "done"), Ok(WsppResult::Ok));
+
+    let events = poll_until(&mut ws, 5);
+    assert_eq!(
+        events,
+        vec![
+            Recorded::Open,
+            Recorded::Message(b"hello".to_vec(), 1),
+            Recorded::Message(vec![1, 2, 3], 2),
+            Recorded::Pong(b"p".to_vec()),
+            Recorded::Close,
+        ]
+    );
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn poll_report_breaks_down_dispatched_events() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(ws.send_message("hello"), Err(WsppResult::InvalidState));
+
+    let mut totals = WsppPollReport::default();
+    let deadline = Instant::now() + crate::test_support::EVENT_TIMEOUT;
+    while totals.total < 4 && Instant::now() < deadline {
+        let report = ws.poll_report();
+        totals.total += report.total;
+        totals.messages += report.messages;
+        totals.pongs += report.pongs;
+        totals.state_changes += report.state_changes;
+        totals.last_sequence = totals.last_sequence.max(report.last_sequence);
+        if matches!(ws.get_state(), WsState::Connected) && totals.total == 1 {
+            assert_eq!(ws.send_message("hello"), Ok(WsppResult::Ok));
+            assert_eq!(ws.ping("p"), Ok(WsppResult::Ok));
+            assert_eq!(ws.close(1000, "done"), Ok(WsppResult::Ok));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    assert_eq!(
+        (totals.messages, totals.pongs, totals.state_changes),
+        (1, 1, 2)
+    );
+    assert_eq!(totals.last_sequence, 4);
+}
+
+#[test]
+fn open_ext_reports_handshake_response() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    ws.set_callbacks(|cb| cb.on_open_ext = Some(no_ctx(on_open_ext)));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![
+            Recorded::Open,
+            Recorded::OpenExt(101, Some("wspp".to_owned()))
+        ]
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn response_headers_are_kept_while_connected() {
+    let server = TestServer::start();
+    let idle = WsppWsImpl::new(&server.url(), false);
+    assert_eq!(
+        idle.response_header("x-test-server"),
+        Err(WsppResult::InvalidState)
+    );
+
+    let mut ws = connected(&server.url());
+    assert_eq!(ws.response_header("X-Test-Server"), Ok("wspp"));
+    assert_eq!(ws.response_header("Set-Cookie"), Err(WsppResult::NotFound));
+
+    assert_eq!(ws.close(1000, "done"), Ok(WsppResult::Ok));
+    poll_until(&mut ws, 2);
+    assert_eq!(
+        ws.response_header("X-Test-Server"),
+        Err(WsppResult::InvalidState)
+    );
+}
+
+#[test]
+fn added_headers_are_sent_with_the_upgrade() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.add_header("X-Echo", "hi"), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    assert_eq!(ws.response_header("X-Echo"), Ok("hi"));
+    assert_eq!(
+        ws.add_header("X-Other", "late"),
+        Err(WsppResult::InvalidState)
+    );
+    ws.shutdown();
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn fixed_handshake_key_is_sent() {
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_handshake_key(Some(KEY)), Ok(WsppResult::Ok));
+    assert_eq!(ws.set_mask_seed(Some(42)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    assert_eq!(ws.response_header("X-Client-Key"), Ok(KEY));
+
+    assert_eq!(ws.send_message("masked"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2)[1],
+        Recorded::Message(b"masked".to_vec(), 1)
+    );
+    ws.shutdown();
+}
+
+#[cfg(feature = "diagnostics")]
+#[test]
+fn recorded_session_replays_without_server() {
+    let path = std::env::temp_dir().join(format!("wspp-replay-{}.bin", std::process::id()));
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(
+        ws.set_session_record(Some(path.clone())),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    assert_eq!(ws.send_message("hello"), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 2).len(), 2);
+    // Closed by the server, so the close frame is part of the recording.
+    assert_eq!(ws.send_message("close"), Ok(WsppResult::Ok));
+    let live = poll_until(&mut ws, 3);
+    assert_eq!(live[2], Recorded::Close);
+    ws.shutdown();
+
+    let mut ws = WsppWsImpl::new(&unused_url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_session_replay(Some(&path)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 3), live);
+    assert_eq!(live[1], Recorded::Message(b"hello".to_vec(), 1));
+    ws.shutdown();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "fault-injection")]
+#[test]
+fn injected_faults_reach_the_client() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    let faults = super::Faults {
+        max_read: Some(16),
+        ..super::Faults::default()
+    };
+    assert_eq!(ws.set_faults(Some(faults)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    let long = "split across reads ".repeat(8);
+    assert_eq!(ws.send_message(&long), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Message(long.into_bytes(), 1)]
+    );
+    ws.shutdown();
+
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    let faults = super::Faults {
+        disconnect_ppm: 1_000_000,
+        ..super::Faults::default()
+    };
+    assert_eq!(ws.set_faults(Some(faults)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert!(matches!(poll_until(&mut ws, 1)[..], [Recorded::Error(_)]));
+}
+
+extern "C" fn count_mask_requests(userdata: *mut c_void, buf: *mut c_char, len: u64) -> bool {
+    let calls = unsafe { &*(userdata as *const AtomicUsize) };
+    calls.fetch_add(1, Ordering::Relaxed);
+    unsafe { std::ptr::write_bytes(buf, 0x5A, len as usize) };
+    true
+}
+
+#[test]
+fn host_mask_source_masks_outgoing_frames() {
+    let server = TestServer::start();
+    let calls = Box::new(AtomicUsize::new(0));
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    let source = HostRandom::new(count_mask_requests, &*calls as *const _ as *mut c_void);
+    assert_eq!(ws.set_mask_source(Some(source)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("masked"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2)[1],
+        Recorded::Message(b"masked".to_vec(), 1)
+    );
+    assert!(calls.load(Ordering::Relaxed) >= 1);
+    ws.shutdown();
+}
+
+#[cfg(feature = "unsafe-protocol")]
+#[test]
+fn raw_frames_reach_the_server_as_written() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    assert_eq!(
+        ws.send_raw_frame(0x1, 0, false, bytes::Bytes::from_static(b"ra")),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(
+        ws.send_raw_frame(0x0, 0, true, bytes::Bytes::from_static(b"w")),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(
+        poll_until(&mut ws, 2)[1],
+        Recorded::Message(b"raw".to_vec(), 1)
+    );
+
+    assert_eq!(
+        ws.send_raw_frame(0x10, 0, true, bytes::Bytes::new()),
+        Err(WsppResult::InvalidArgument)
+    );
+    // A reserved bit without a negotiated extension is a protocol error.
+    assert_eq!(
+        ws.send_raw_frame(0x2, 0b100, true, bytes::Bytes::from_static(b"x")),
+        Ok(WsppResult::Ok)
+    );
+    let events = poll_until(&mut ws, 3);
+    assert!(events.contains(&Recorded::Close) || matches!(events[2], Recorded::Error(_)));
+    ws.shutdown();
+}
+
+#[cfg(feature = "unsafe-protocol")]
+#[test]
+fn raw_receive_delivers_frames_without_auto_pong() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_raw_receive(true), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("hello"), Ok(WsppResult::Ok));
+    assert_eq!(ws.send_message("ping-me"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 3)[1..],
+        [
+            Recorded::RawFrame(true, 0, 1, b"hello".to_vec()),
+            Recorded::RawFrame(true, 0, 9, b"srv".to_vec()),
+        ]
+    );
+
+    // The close frame arrives raw instead of closing the handle.
+    assert_eq!(ws.send_message("close"), Ok(WsppResult::Ok));
+    let events = poll_until(&mut ws, 4);
+    assert!(matches!(events[3], Recorded::RawFrame(true, 0, 8, _)));
+    ws.shutdown();
+}
+
+extern "C" fn drop_heartbeats(_: *mut c_void, data: *const c_char, len: u64, _op_code: i32) -> i32 {
+    let data = unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) };
+    i32::from(data == b"heartbeat")
+}
+
+#[test]
+fn filter_drops_messages_before_delivery() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    ws.set_callbacks(|cb| cb.on_filter = Some(no_ctx(drop_heartbeats)));
+
+    assert_eq!(ws.send_message("heartbeat"), Ok(WsppResult::Ok));
+    assert_eq!(ws.send_message("data"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Message(b"data".to_vec(), 1)]
+    );
+    assert_eq!(ws.event_sequence(), 3);
+    ws.shutdown();
+}
+
+#[test]
+fn health_is_reported_for_connected_handles() {
+    let server = TestServer::start();
+    let mut idle = WsppWsImpl::new(&server.url(), false);
+    assert_eq!(idle.health(), 0);
+
+    let mut ws = connected(&server.url());
+    assert_eq!(ws.ping(b"h".to_vec()), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 2)[1], Recorded::Pong(b"h".to_vec()));
+    assert_eq!(ws.health(), 100);
+    ws.shutdown();
+}
+
+#[test]
+fn bandwidth_limit_slows_transfers() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_bandwidth_limit(100_000, 0), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    let started = Instant::now();
+    for _ in 0..6 {
+        assert_eq!(ws.send_binary(vec![7; 10_000]), Ok(WsppResult::Ok));
+    }
+    assert_eq!(poll_until(&mut ws, 7).len(), 7);
+    // 60 KB at 100 KB/s, less the initial 10 KB burst.
+    assert!(started.elapsed() >= Duration::from_millis(400));
+    ws.shutdown();
+}
+
+#[test]
+fn ping_round_trips_feed_latency_percentiles() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    assert_eq!(ws.latency_percentiles(), None);
+
+    assert_eq!(ws.ping(b"rtt".to_vec()), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Pong(b"rtt".to_vec())]
+    );
+
+    let (p50, p95, p99) = ws.latency_percentiles().expect("rtt sample");
+    assert!(p50 <= p95 && p95 <= p99);
+    assert!(p99 < 5_000_000);
+    ws.shutdown();
+}
+
+#[test]
+fn unsolicited_pongs_follow_the_policy() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_pong_policy(PongPolicy::Report), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("pong-me"), Ok(WsppResult::Ok));
+    assert_eq!(ws.ping(b"mine".to_vec()), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 3),
+        vec![
+            Recorded::Open,
+            Recorded::UnsolicitedPong(b"srv".to_vec()),
+            Recorded::Pong(b"mine".to_vec()),
+        ]
+    );
+    assert_eq!(ws.stats().unsolicited_pongs, 1);
+    ws.shutdown();
+}
+
+#[test]
+fn large_messages_spill_to_disk() {
+    let server = TestServer::start();
+    let dir = std::env::temp_dir().join(format!("wspp-spill-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("spill dir");
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_spill(16, dir.clone()), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    assert_eq!(ws.set_spill(0, dir.clone()), Err(WsppResult::InvalidState));
+
+    assert_eq!(ws.send_binary(vec![5; 64]), Ok(WsppResult::Ok));
+    assert_eq!(ws.send_message("small"), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 3),
+        vec![
+            Recorded::Open,
+            Recorded::MessageFile(vec![5; 64], 2),
+            Recorded::Message(b"small".to_vec(), 1),
+        ]
+    );
+    let leftovers = std::fs::read_dir(&dir).expect("spill dir").count();
+    assert_eq!(leftovers, 0);
+    ws.shutdown();
+    let _ = std::fs::remove_dir(dir);
+}
+
+/// Hands out `chunks` of `[seq; 5]`, counting `seq` down to zero.
+extern "C" fn countdown_chunks(userdata: *mut c_void, buf: *mut c_char, cap: u64) -> i64 {
+    let seq = unsafe { &mut *(userdata as *mut u8) };
+    if *seq == 0 {
+        return 0;
+    }
+    let n = cap.min(5) as usize;
+    unsafe { std::ptr::write_bytes(buf as *mut u8, *seq, n) };
+    *seq -= 1;
+    n as i64
+}
+
+#[test]
+fn streamed_sends_arrive_as_one_message() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    let mut seq = Box::new(3_u8);
+    let source = ChunkSource::Provider(ProviderSource::new(
+        countdown_chunks,
+        &mut *seq as *mut u8 as *mut c_void,
+    ));
+
+    assert_eq!(ws.send_stream(false, source), Ok(WsppResult::Ok));
+
+    let mut expected = vec![3; 5];
+    expected.extend([2; 5]);
+    expected.extend([1; 5]);
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Message(expected, 2)]
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn files_are_streamed_as_one_message() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    let path = std::env::temp_dir().join(format!("wspp-send-file-{}", std::process::id()));
+    let contents: Vec<u8> = (0..200_000_u32).map(|i| i as u8).collect();
+    std::fs::write(&path, &contents).expect("write upload");
+
+    assert_eq!(ws.send_file(false, &path), Ok(WsppResult::Ok));
+    assert_eq!(
+        ws.send_file(false, &path.with_extension("missing")),
+        Err(WsppResult::IoError)
+    );
+
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Message(contents, 2)]
+    );
+    ws.shutdown();
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn watchdog_reports_silence_without_closing() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(
+        ws.set_watchdog(Duration::from_millis(30)),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+
+    let events = poll_until(&mut ws, 3);
+    assert_eq!(
+        events,
+        vec![Recorded::Open, Recorded::Watchdog, Recorded::Watchdog]
+    );
+    assert!(matches!(ws.get_state(), WsState::Connected));
+    assert_eq!(
+        ws.set_watchdog(Duration::ZERO),
+        Err(WsppResult::InvalidState)
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn arena_payloads_are_delivered_intact() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_event_arena(64), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    for i in 0..20_u8 {
+        assert_eq!(ws.send_binary(vec![i; 10]), Ok(WsppResult::Ok));
+    }
+
+    let events = poll_until(&mut ws, 21);
+    let expected: Vec<Recorded> = (0..20_u8)
+        .map(|i| Recorded::Message(vec![i; 10], 2))
+        .collect();
+    assert_eq!(events[1..], expected[..]);
+    ws.shutdown();
+}
+
+#[test]
+fn chunked_writes_arrive_as_whole_messages() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_write_chunk_size(4), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("fragmented text"), Ok(WsppResult::Ok));
+    assert_eq!(ws.send_binary(vec![9; 10]), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 3),
+        vec![
+            Recorded::Open,
+            Recorded::Message(b"fragmented text".to_vec(), 1),
+            Recorded::Message(vec![9; 10], 2),
+        ]
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn fragmented_messages_are_capped_as_a_whole() {
+    let server = TestServer::start_with(ServerConfig {
+        fragment_sizes: vec![1000],
+        ..Default::default()
+    });
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_max_message_size(2048), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(ws.set_max_message_size(4096), Err(WsppResult::InvalidState));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_binary(vec![7; 2048]), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2)[1],
+        Recorded::Message(vec![7; 2048], 2)
+    );
+
+    assert_eq!(ws.send_binary(vec![8; 3000]), Ok(WsppResult::Ok));
+    let events = poll_until(&mut ws, 3);
+    assert!(matches!(events[2], Recorded::Error(_)), "{events:?}");
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn rejected_and_expired_sends_are_counted() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(
+        ws.set_send_queue(1, QueuePolicy::RejectNew),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    ws.set_send_ttl(Duration::from_nanos(1));
+    assert_eq!(ws.send_message("stale"), Ok(WsppResult::Ok));
+    assert_eq!(ws.send_message("full"), Err(WsppResult::QueueFull));
+    ws.set_send_ttl(Duration::ZERO);
+    assert_eq!(send_when_room(&mut ws, "fresh"), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Message(b"fresh".to_vec(), 1)]
+    );
+    let stats = ws.stats();
+    assert_eq!(stats.expired, 1);
+    assert!(stats.dropped_queue_full >= 1);
+    ws.shutdown();
+}
+
+/// Retries a send until the single-slot queue has drained.
+fn send_when_room(ws: &mut WsppWsImpl, message: &str) -> Result<WsppResult, WsppResult> {
+    let deadline = std::time::Instant::now() + crate::test_support::EVENT_TIMEOUT;
+    loop {
+        match ws.send_message(message) {
+            Err(WsppResult::QueueFull) if std::time::Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            result => return result,
+        }
+    }
+}
+
+#[test]
+fn server_close_emits_close() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    assert_eq!(ws.send_message("close"), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Close]
+    );
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn server_ping_is_delivered_as_message() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    assert_eq!(ws.send_message("ping-me"), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Message(b"srv".to_vec(), 9)]
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn close_callback_gets_code_and_reason() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    ws.set_callbacks(|cb| cb.on_close_ext = Some(no_ctx(on_close_ext)));
+
+    assert_eq!(ws.send_message("close"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 3)[1..],
+        [Recorded::Close, Recorded::CloseExt(1000, b"bye".to_vec())]
+    );
+}
+
+#[test]
+fn dropped_connection_emits_error() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+
+    let events = poll_until(&mut ws, 2);
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[1], Recorded::Error(_)));
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn dropped_connection_is_resumed_quietly() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_auto_resume(2), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    let first = ws.connection_id();
+
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Resumed]
+    );
+    assert!(matches!(ws.get_state(), WsState::Connected));
+    assert_ne!(ws.connection_id(), first);
+
+    assert_eq!(ws.send_message("again"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 3)[2],
+        Recorded::Message(b"again".to_vec(), 1)
+    );
+}
+
+#[test]
+fn reset_connection_is_resumed() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_auto_resume(1), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    let first = ws.connection_id();
+
+    assert_eq!(ws.send_message("reset"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Resumed]
+    );
+    assert!(matches!(ws.get_state(), WsState::Connected));
+    assert_ne!(ws.connection_id(), first);
+}
+
+#[test]
+fn resume_gives_up_after_its_attempts() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_auto_resume(2), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    drop(server);
+    let events = poll_until(&mut ws, 2);
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[1], Recorded::Error(_)));
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn dropped_connection_is_reconnected() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    let reconnect = Reconnect::new(3, Duration::from_millis(10), Duration::from_millis(50));
+    assert_eq!(ws.set_auto_reconnect(Some(reconnect)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    let first = ws.connection_id();
+
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 3),
+        vec![
+            Recorded::Open,
+            Recorded::Reconnecting(1),
+            Recorded::Reconnected(1)
+        ]
+    );
+    assert!(matches!(ws.get_state(), WsState::Connected));
+    assert_ne!(ws.connection_id(), first);
+
+    assert_eq!(ws.send_message("again"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 4)[3],
+        Recorded::Message(b"again".to_vec(), 1)
+    );
+}
+
+#[test]
+fn reconnect_rotates_through_fallback_uris() {
+    let (primary, fallback) = (TestServer::start(), TestServer::start());
+    let mut ws = WsppWsImpl::new(&primary.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.add_fallback_uri(&fallback.url()), Ok(WsppResult::Ok));
+    assert_eq!(
+        ws.add_fallback_uri("http://example.com/"),
+        Err(WsppResult::InvalidArgument)
+    );
+    let reconnect = Reconnect::new(0, Duration::from_millis(10), Duration::from_millis(50));
+    assert_eq!(ws.set_auto_reconnect(Some(reconnect)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    assert_eq!(ws.stats().endpoint, 0);
+
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 3)[2], Recorded::Reconnected(1));
+    assert_eq!(ws.stats().endpoint, 1);
+
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 5)[4], Recorded::Reconnected(1));
+    assert_eq!(ws.stats().endpoint, 0);
+    let failures: Vec<_> = (0..3).map(|i| ws.endpoint_failures(i)).collect();
+    assert_eq!(failures, [Some(1), Some(1), None]);
+}
+
+#[test]
+fn reconnect_replays_unsent_messages() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_replay_unsent(2), Ok(WsppResult::Ok));
+    let reconnect = Reconnect::new(0, Duration::from_millis(10), Duration::from_millis(50));
+    assert_eq!(ws.set_auto_reconnect(Some(reconnect)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    // Stands in for what the worker keeps when the connection drops with
+    // messages still queued; the oldest is past the limit.
+    let unsent = ws.options.unsent.clone().expect("replay on");
+    for data in ["lost", "first", "second"] {
+        unsent.keep(Command::SendText {
+            data: Payload::from(data),
+            expires_at: None,
+            priority: Priority::Normal,
+        });
+    }
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 5)[2..],
+        [
+            Recorded::Reconnected(1),
+            Recorded::Message(b"first".to_vec(), 1),
+            Recorded::Message(b"second".to_vec(), 1)
+        ]
+    );
+}
+
+#[test]
+fn reconnect_backs_off_and_gives_up() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    let reconnect = Reconnect::new(2, Duration::from_millis(20), Duration::from_secs(1));
+    assert_eq!(ws.set_auto_reconnect(Some(reconnect)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    drop(server);
+    let started = Instant::now();
+    let events = poll_until(&mut ws, 5);
+    assert_eq!(
+        events[1..3],
+        [Recorded::Reconnecting(1), Recorded::Reconnecting(2)]
+    );
+    assert!(matches!(events[3], Recorded::Error(_)));
+    assert_eq!(events[4], Recorded::ReconnectGaveUp(2));
+    assert!(started.elapsed() >= Duration::from_millis(60));
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn jitter_needs_reconnecting_on() {
+    let mut ws = WsppWsImpl::new(&unused_url(), true);
+    assert_eq!(
+        ws.set_reconnect_jitter(Jitter::Full),
+        Err(WsppResult::InvalidState)
+    );
+    let reconnect = Reconnect::new(1, Duration::from_millis(10), Duration::from_millis(10));
+    assert_eq!(ws.set_auto_reconnect(Some(reconnect)), Ok(WsppResult::Ok));
+    assert_eq!(ws.set_reconnect_jitter(Jitter::Full), Ok(WsppResult::Ok));
+}
+
+#[test]
+fn closing_stops_a_pending_reconnect() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), true);
+    install_recorder(&mut ws);
+    let reconnect = Reconnect::new(0, Duration::from_secs(60), Duration::from_secs(60));
+    assert_eq!(ws.set_auto_reconnect(Some(reconnect)), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("drop"), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 2)[1], Recorded::Reconnecting(1));
+    assert!(matches!(ws.get_state(), WsState::Connecting));
+    assert_eq!(ws.close(1000, "done"), Ok(WsppResult::Ok));
+    assert!(matches!(ws.get_state(), WsState::Closed));
+    assert_eq!(ws.poll(), 0);
+}
+
+#[test]
+fn shutdown_worker_can_be_joined() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    ws.shutdown();
+    assert!(ws.join_worker(Duration::from_secs(5)));
+    assert!(ws.join_worker(Duration::ZERO));
+}
+
+#[test]
+fn async_shutdown_reports_completion() {
+    let server = TestServer::start();
+    let ws = connected(&server.url());
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+
+    ws.shutdown_then(move || {
+        let _ = done_tx.send(());
+    });
+    assert!(done_rx.recv_timeout(Duration::from_secs(5)).is_ok());
+}
+
+#[test]
+fn refused_connection_emits_error() {
+    let mut ws = WsppWsImpl::new(&unused_url(), true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+
+    let events = poll_until(&mut ws, 1);
+    assert_eq!(events.len(), 1);
+    assert!(matches!(events[0], Recorded::Error(_)));
+    assert!(matches!(ws.get_state(), WsState::Closed));
+    assert_eq!(ws.last_error_category(), WsppErrorCategory::Tcp);
+}
+
+#[test]
+fn silent_server_times_out_the_connect() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("ws://{}/ws", listener.local_addr().expect("addr"));
+
+    let mut ws = WsppWsImpl::new(&url, false);
+    install_recorder(&mut ws);
+    ws.set_callbacks(|cb| cb.on_error_ext = Some(no_ctx(on_error_ext)));
+    assert_eq!(
+        ws.set_connect_timeout(Duration::from_millis(100)),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+
+    let events = poll_until(&mut ws, 2);
+    assert!(matches!(events[0], Recorded::Error(_)));
+    assert_eq!(
+        events[1],
+        Recorded::ErrorExt(WsppErrorCategory::Timeout, WsppTimeoutPhase::Connect, 100)
+    );
+    drop(listener);
+}
+
+#[test]
+fn half_sent_frame_fails_after_read_stall_timeout() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    ws.set_callbacks(|cb| cb.on_error_ext = Some(no_ctx(on_error_ext)));
+    assert_eq!(
+        ws.set_read_stall_timeout(Duration::from_millis(200)),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    // Quiet time between frames is not a stall.
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(ws.poll(), 0);
+    assert!(matches!(ws.get_state(), WsState::Connected));
+
+    assert_eq!(ws.send_message("stall"), Ok(WsppResult::Ok));
+    let events = poll_until(&mut ws, 3);
+    assert!(matches!(events[1], Recorded::Error(_)));
+    let Recorded::ErrorExt(category, phase, elapsed_ms) = events[2] else {
+        panic!("expected extended error, got {events:?}");
+    };
+    assert_eq!(
+        (category, phase),
+        (WsppErrorCategory::Timeout, WsppTimeoutPhase::ReadStall)
+    );
+    assert!(elapsed_ms >= 200);
+}
+
+#[test]
+fn peer_that_stops_reading_trips_the_write_timeout() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    ws.set_callbacks(|cb| cb.on_error_ext = Some(no_ctx(on_error_ext)));
+    assert_eq!(
+        ws.set_write_timeout(Duration::from_millis(200)),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("deaf"), Ok(WsppResult::Ok));
+    // Far more than loopback socket buffers hold.
+    for _ in 0..64 {
+        assert_eq!(ws.send_binary(vec![0; 1 << 20]), Ok(WsppResult::Ok));
+    }
+
+    let events = poll_until(&mut ws, 3);
+    let Some(Recorded::ErrorExt(category, phase, elapsed_ms)) = events.get(2) else {
+        panic!("expected extended error, got {events:?}");
+    };
+    assert_eq!(
+        (*category, *phase),
+        (WsppErrorCategory::Timeout, WsppTimeoutPhase::Write)
+    );
+    assert!(*elapsed_ms >= 200);
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn unanswered_keepalive_fails_the_connection() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    ws.set_callbacks(|cb| cb.on_error_ext = Some(no_ctx(on_error_ext)));
+    assert_eq!(
+        ws.set_keepalive(Duration::from_millis(20), Duration::from_millis(100)),
+        Ok(WsppResult::Ok)
+    );
+    let payload = PingPayload::Fixed(Payload::from("hb"));
+    assert_eq!(ws.set_keepalive_payload(payload), Ok(WsppResult::Ok));
+    let long = PingPayload::Fixed(Payload::from(vec![0; 126]));
+    assert_eq!(
+        ws.set_keepalive_payload(long),
+        Err(WsppResult::InvalidArgument)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    // Answered pings neither show up nor end the connection.
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(ws.poll(), 0);
+    assert!(matches!(ws.get_state(), WsState::Connected));
+    assert_eq!(ws.stats().unsolicited_pongs, 0);
+
+    // The host's own pings still get their pongs, even empty ones.
+    assert_eq!(ws.ping(Vec::new()), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 2)[1], Recorded::Pong(Vec::new()));
+
+    assert_eq!(ws.send_message("deaf"), Ok(WsppResult::Ok));
+    let events = poll_until(&mut ws, 4);
+    let Recorded::Error(message) = &events[2] else {
+        panic!("expected error, got {events:?}");
+    };
+    assert!(message.starts_with("pong timeout"));
+    let Recorded::ErrorExt(category, phase, elapsed_ms) = events[3] else {
+        panic!("expected extended error, got {events:?}");
+    };
+    assert_eq!(
+        (category, phase),
+        (WsppErrorCategory::Timeout, WsppTimeoutPhase::Pong)
+    );
+    assert!(elapsed_ms >= 100);
+    assert!(matches!(ws.get_state(), WsState::Closed));
+}
+
+#[test]
+fn ip_family_limits_the_addresses_tried() {
+    let server = TestServer::start();
+    let url = server.url().replace("127.0.0.1", "localhost");
+
+    let mut ws = WsppWsImpl::new(&url, false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_ip_family(IpFamily::V4Only), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    ws.shutdown();
+
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_ip_family(IpFamily::V6Only), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert!(matches!(poll_until(&mut ws, 1)[..], [Recorded::Error(_)]));
+    assert_eq!(ws.last_error_category(), WsppErrorCategory::Dns);
+}
+
+#[test]
+fn rejected_upgrade_is_a_handshake_error() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("ws://{}/ws", listener.local_addr().expect("addr"));
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut request = [0_u8; 1024];
+        let _ = stream.read(&mut request);
+        let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    });
+
+    let mut ws = WsppWsImpl::new(&url, false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert!(matches!(poll_until(&mut ws, 1)[..], [Recorded::Error(_)]));
+    assert_eq!(ws.last_error_category(), WsppErrorCategory::HttpHandshake);
+    server.join().expect("server thread");
+}
+
+#[test]
+fn handshake_record_covers_success_and_rejection() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.handshake_record(), Err(WsppResult::NotFound));
+    assert_eq!(ws.set_handshake_capture(true), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    let record = ws.handshake_record().expect("record");
+    assert!(record.contains(r#""method":"GET""#), "{record}");
+    assert!(record.contains(r#""status":101"#), "{record}");
+    assert!(record.contains(r#""upgrade":"#), "{record}");
+    assert!(record.ends_with(r#""error":null}"#), "{record}");
+    assert_eq!(
+        ws.set_handshake_capture(false),
+        Err(WsppResult::InvalidState)
+    );
+    ws.shutdown();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("ws://{}/ws", listener.local_addr().expect("addr"));
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut request = [0_u8; 1024];
+        let _ = stream.read(&mut request);
+        let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    });
+    let mut ws = WsppWsImpl::new(&url, false);
+    install_recorder(&mut ws);
+    assert_eq!(ws.set_handshake_capture(true), Ok(WsppResult::Ok));
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert!(matches!(poll_until(&mut ws, 1)[..], [Recorded::Error(_)]));
+    let record = ws.handshake_record().expect("record");
+    assert!(record.contains(r#""target":"/ws""#), "{record}");
+    assert!(record.contains(r#""status":404"#), "{record}");
+    assert!(!record.ends_with(r#""error":null}"#), "{record}");
+    server.join().expect("server thread");
+}
+
+#[test]
+fn inline_mode_runs_on_the_polling_thread() {
+    let server = TestServer::start();
+    let mut ws = WsppWsImpl::new(&server.url(), false);
+    install_recorder(&mut ws);
+    assert_eq!(
+        ws.set_inline(Some(Duration::from_millis(2))),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert!(matches!(ws.worker, Some(super::Runner::Inline(_))));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+
+    assert_eq!(ws.send_message("inline"), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2)[1],
+        Recorded::Message(b"inline".to_vec(), 1)
+    );
+    assert_eq!(ws.close(1000, ""), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 3)[2], Recorded::Close);
+    assert!(ws.join_worker(Duration::from_secs(1)));
+}
+
+#[test]
+fn reconnect_after_close() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    assert_eq!(ws.close(1000, ""), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 2),
+        vec![Recorded::Open, Recorded::Close]
+    );
+
+    let first_id = ws.connection_id();
+    assert_ne!(first_id, 0);
+    assert_eq!(ws.event_sequence(), 2);
+
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(
+        poll_until(&mut ws, 3),
+        vec![Recorded::Open, Recorded::Close, Recorded::Open]
+    );
+    assert!(ws.connection_id() > first_id);
+    assert_eq!(ws.event_sequence(), 1);
+    ws.shutdown();
+}
+
+/// Treats messages shaped like `id:<n>` as responses to request `n`.
+extern "C" fn extract_id(
+    _: *mut c_void,
+    data: *const c_char,
+    len: u64,
+    _op_code: i32,
+    out_id: *mut u64,
+) -> bool {
+    let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) };
+    let parsed = std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|text| text.strip_prefix("id:"))
+        .and_then(|id| id.parse().ok());
+    match parsed {
+        Some(id) => {
+            unsafe { *out_id = id };
+            true
+        }
+        None => false,
+    }
+}
+
+#[test]
+fn responses_are_routed_by_request_id() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    ws.set_callbacks(|cb| cb.extract_response_id = Some(no_ctx(extract_id)));
+
+    assert_eq!(
+        ws.request(42, "id:42", Duration::from_secs(5)),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(ws.send_message("id:7"), Ok(WsppResult::Ok));
+
+    assert_eq!(
+        poll_until(&mut ws, 3),
+        vec![
+            Recorded::Open,
+            Recorded::Response(42, b"id:42".to_vec(), WsppResult::Ok),
+            Recorded::Message(b"id:7".to_vec(), 1),
+        ]
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn unanswered_requests_time_out() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+    ws.set_callbacks(|cb| cb.extract_response_id = Some(no_ctx(extract_id)));
+
+    assert_eq!(
+        ws.request(1, "no id here", Duration::from_millis(20)),
+        Ok(WsppResult::Ok)
+    );
+    assert_eq!(
+        ws.request(1, "dup", Duration::from_millis(20)),
+        Err(WsppResult::InvalidArgument)
+    );
+
+    let events = poll_until(&mut ws, 3);
+    assert_eq!(events[1], Recorded::Message(b"no id here".to_vec(), 1));
+    assert_eq!(
+        events[2],
+        Recorded::Response(1, Vec::new(), WsppResult::Timeout)
+    );
+    ws.shutdown();
+}
+
+#[test]
+fn requests_need_an_extractor() {
+    let server = TestServer::start();
+    let mut ws = connected(&server.url());
+
+    assert_eq!(
+        ws.request(1, "x", Duration::ZERO),
+        Err(WsppResult::InvalidState)
+    );
+    ws.shutdown();
+}
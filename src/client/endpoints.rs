@@ -0,0 +1,78 @@
+/// The URI a handle was created with followed by its fallbacks, which
+/// reconnect attempts take in turn, and how often each one failed.
+#[derive(Debug)]
+pub struct Endpoints {
+    uris: Vec<String>,
+    failures: Vec<u64>,
+    current: usize,
+}
+
+impl Endpoints {
+    pub fn new(primary: &str) -> Self {
+        Self {
+            uris: vec![primary.to_owned()],
+            failures: vec![0],
+            current: 0,
+        }
+    }
+
+    pub fn add(&mut self, uri: &str) {
+        self.uris.push(uri.to_owned());
+        self.failures.push(0);
+    }
+
+    /// The URI the next connection goes to.
+    pub fn current(&self) -> &str {
+        &self.uris[self.current]
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    /// Moves on to the next endpoint, after the last one back to the first.
+    pub fn advance(&mut self) {
+        self.current = (self.current + 1) % self.uris.len();
+    }
+
+    /// Starts over at the primary URI, as a fresh connect does.
+    pub fn rewind(&mut self) {
+        self.current = 0;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures[self.current] += 1;
+    }
+
+    /// Failed connections of endpoint `index`, `None` past the last one.
+    pub fn failures(&self, index: usize) -> Option<u64> {
+        self.failures.get(index).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Endpoints;
+
+    #[test]
+    fn rotates_through_every_endpoint() {
+        let mut endpoints = Endpoints::new("ws://a/");
+        endpoints.add("ws://b/");
+        endpoints.add("ws://c/");
+
+        let mut visited = Vec::new();
+        for _ in 0..4 {
+            endpoints.record_failure();
+            visited.push(endpoints.current().to_owned());
+            endpoints.advance();
+        }
+        assert_eq!(visited, ["ws://a/", "ws://b/", "ws://c/", "ws://a/"]);
+        assert_eq!(
+            (0..4).map(|i| endpoints.failures(i)).collect::<Vec<_>>(),
+            [Some(2), Some(1), Some(1), None]
+        );
+
+        endpoints.rewind();
+        assert_eq!(endpoints.current_index(), 0);
+    }
+}
@@ -0,0 +1,296 @@
+use std::error::Error;
+use std::ffi::{CString, c_char};
+use std::io;
+use std::time::Duration;
+
+use rustls::{AlertDescription, CertificateError};
+use yawc::WebSocketError;
+
+/// Where an error event came from.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WsppErrorCategory {
+    /// No error reported yet.
+    #[default]
+    None = 0,
+    /// The host name could not be resolved.
+    Dns = 1,
+    /// The TCP connection could not be established.
+    Tcp = 2,
+    Tls = 3,
+    /// The server rejected or botched the upgrade request.
+    HttpHandshake = 4,
+    /// The peer violated RFC 6455 after the handshake.
+    Protocol = 5,
+    /// Reading or writing the established connection failed.
+    Io = 6,
+    /// Failures on our side, e.g. a stream source giving up.
+    Internal = 7,
+    /// A deadline passed; `WsppErrorInfo` tells which one.
+    Timeout = 8,
+    /// The server certificate was revoked, or its status could not be
+    /// confirmed while a stapled OCSP response is required.
+    CertRevoked = 9,
+    /// The server refused our client certificate, or wanted one while none
+    /// was set.
+    ClientCert = 10,
+}
+
+/// Which deadline a `Timeout` error is about.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WsppTimeoutPhase {
+    /// Not a timeout.
+    #[default]
+    None = 0,
+    /// TCP, TLS and the upgrade together.
+    Connect = 1,
+    /// Waiting for the server to answer our close frame.
+    Close = 2,
+    /// Waiting for the rest of a frame that started arriving.
+    ReadStall = 3,
+    /// Waiting for the socket to accept more outgoing bytes.
+    Write = 4,
+    /// Waiting for the pong to a keepalive ping.
+    Pong = 5,
+}
+
+impl WsppTimeoutPhase {
+    fn name(self) -> &'static str {
+        match self {
+            Self::None => "operation",
+            Self::Connect => "connect",
+            Self::Close => "close handshake",
+            Self::ReadStall => "frame read",
+            Self::Write => "write",
+            Self::Pong => "pong",
+        }
+    }
+}
+
+impl WsppErrorCategory {
+    /// Classifies an error of the established connection.
+    pub fn of_socket(err: &WebSocketError) -> Self {
+        match err.as_io_error() {
+            Some(err) => Self::of_io(err),
+            None if err.is_closed() => Self::Io,
+            None => Self::Protocol,
+        }
+    }
+
+    /// I/O errors carrying a rustls error come from the TLS layer.
+    pub fn of_io(err: &io::Error) -> Self {
+        match err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        {
+            Some(rustls::Error::InvalidCertificate(
+                CertificateError::Revoked | CertificateError::UnknownRevocationStatus,
+            )) => Self::CertRevoked,
+            // Certificate alerts from the server are about ours.
+            Some(rustls::Error::AlertReceived(
+                AlertDescription::BadCertificate
+                | AlertDescription::UnsupportedCertificate
+                | AlertDescription::CertificateRevoked
+                | AlertDescription::CertificateExpired
+                | AlertDescription::CertificateUnknown
+                | AlertDescription::UnknownCA
+                | AlertDescription::CertificateRequired,
+            )) => Self::ClientCert,
+            Some(_) => Self::Tls,
+            None => Self::Io,
+        }
+    }
+}
+
+/// Raised by the transport when the socket accepted nothing for the write
+/// timeout; carries how long the write was blocked.
+#[derive(Debug)]
+pub struct WriteTimedOut(pub Duration);
+
+impl std::fmt::Display for WriteTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "write blocked for {} ms", self.0.as_millis())
+    }
+}
+
+impl Error for WriteTimedOut {}
+
+/// Details passed to the extended error callback. `message` is only valid
+/// until the callback returns.
+#[repr(C)]
+pub struct WsppErrorInfo {
+    pub category: WsppErrorCategory,
+    pub message: *const c_char,
+    /// Set for `Timeout` errors.
+    pub timeout_phase: WsppTimeoutPhase,
+    /// How long the timed out phase had been running.
+    pub elapsed_ms: u64,
+    /// 1, or for a summary of de-duplicated errors how many identical
+    /// errors were held back; see `wspp_set_error_dedup_window`.
+    pub repeat_count: u32,
+}
+
+/// An error event as queued by the worker.
+#[derive(Clone, Debug)]
+pub struct WorkerError {
+    pub category: WsppErrorCategory,
+    pub message: String,
+    pub timeout_phase: WsppTimeoutPhase,
+    pub elapsed: Duration,
+    /// Identical errors this one stands for.
+    pub repeats: u32,
+}
+
+impl WorkerError {
+    /// Whether this ended an established connection in a way a fresh one
+    /// may not run into: the socket failing or a read or write stalling,
+    /// rather than the peer breaking the protocol or a close.
+    pub fn is_transport_loss(&self) -> bool {
+        self.category == WsppErrorCategory::Io
+            || matches!(
+                self.timeout_phase,
+                WsppTimeoutPhase::ReadStall | WsppTimeoutPhase::Write | WsppTimeoutPhase::Pong
+            )
+    }
+
+    pub fn new(category: WsppErrorCategory, message: impl Into<String>) -> Self {
+        Self {
+            category,
+            message: message.into(),
+            timeout_phase: WsppTimeoutPhase::None,
+            elapsed: Duration::ZERO,
+            repeats: 1,
+        }
+    }
+
+    pub fn timeout(phase: WsppTimeoutPhase, elapsed: Duration) -> Self {
+        Self {
+            category: WsppErrorCategory::Timeout,
+            message: format!(
+                "{} timed out after {} ms",
+                phase.name(),
+                elapsed.as_millis()
+            ),
+            timeout_phase: phase,
+            elapsed,
+            repeats: 1,
+        }
+    }
+
+    /// A keepalive ping got no pong within `waited`.
+    pub fn pong_timeout(waited: Duration) -> Self {
+        Self {
+            message: format!("pong timeout after {} ms", waited.as_millis()),
+            ..Self::timeout(WsppTimeoutPhase::Pong, waited)
+        }
+    }
+
+    pub fn socket(err: &WebSocketError) -> Self {
+        let timed_out = err
+            .as_io_error()
+            .and_then(|err| err.get_ref())
+            .and_then(|err| err.downcast_ref::<WriteTimedOut>());
+        match timed_out {
+            Some(WriteTimedOut(elapsed)) => Self::timeout(WsppTimeoutPhase::Write, *elapsed),
+            None => Self::new(WsppErrorCategory::of_socket(err), err.to_string()),
+        }
+    }
+
+    pub fn c_message(&self) -> CString {
+        let mut message = self.message.replace('\0', "");
+        if self.repeats > 1 {
+            message = format!("{message} (repeated {} times)", self.repeats);
+        }
+        CString::new(message).unwrap_or_default()
+    }
+
+    /// `message` must be the string returned by `c_message`.
+    pub fn info(&self, message: &CString) -> WsppErrorInfo {
+        WsppErrorInfo {
+            category: self.category,
+            message: message.as_ptr(),
+            timeout_phase: self.timeout_phase,
+            elapsed_ms: self.elapsed.as_millis() as u64,
+            repeat_count: self.repeats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use yawc::WebSocketError;
+
+    use super::{WorkerError, WriteTimedOut, WsppErrorCategory, WsppTimeoutPhase};
+
+    #[test]
+    fn rustls_errors_are_tls() {
+        let tls = io::Error::new(io::ErrorKind::InvalidData, rustls::Error::DecryptError);
+        assert_eq!(WsppErrorCategory::of_io(&tls), WsppErrorCategory::Tls);
+
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(WsppErrorCategory::of_io(&reset), WsppErrorCategory::Io);
+
+        let revoked = io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::Error::from(rustls::CertificateError::Revoked),
+        );
+        assert_eq!(
+            WsppErrorCategory::of_io(&revoked),
+            WsppErrorCategory::CertRevoked
+        );
+
+        let refused = io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::Error::AlertReceived(rustls::AlertDescription::CertificateRequired),
+        );
+        assert_eq!(
+            WsppErrorCategory::of_io(&refused),
+            WsppErrorCategory::ClientCert
+        );
+    }
+
+    #[test]
+    fn socket_io_errors_are_io() {
+        let reset = WebSocketError::IoError(io::ErrorKind::ConnectionReset.into());
+        assert_eq!(WsppErrorCategory::of_socket(&reset), WsppErrorCategory::Io);
+
+        let tls = WebSocketError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::Error::DecryptError,
+        ));
+        assert_eq!(WsppErrorCategory::of_socket(&tls), WsppErrorCategory::Tls);
+        assert_eq!(
+            WsppErrorCategory::of_socket(&WebSocketError::InvalidUTF8),
+            WsppErrorCategory::Protocol
+        );
+    }
+
+    #[test]
+    fn blocked_writes_become_write_timeouts() {
+        let io = io::Error::new(
+            io::ErrorKind::TimedOut,
+            WriteTimedOut(Duration::from_millis(300)),
+        );
+        let err = WorkerError::socket(&WebSocketError::from(io));
+
+        assert_eq!(err.category, WsppErrorCategory::Timeout);
+        assert_eq!(err.timeout_phase, WsppTimeoutPhase::Write);
+        assert_eq!(err.elapsed, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn timeouts_report_phase_and_elapsed_time() {
+        let err = WorkerError::timeout(WsppTimeoutPhase::Close, Duration::from_millis(5250));
+        let message = err.c_message();
+        let info = err.info(&message);
+
+        assert_eq!(info.category, WsppErrorCategory::Timeout);
+        assert_eq!(info.timeout_phase, WsppTimeoutPhase::Close);
+        assert_eq!(info.elapsed_ms, 5250);
+        assert_eq!(err.message, "close handshake timed out after 5250 ms");
+    }
+}
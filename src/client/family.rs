@@ -0,0 +1,53 @@
+use std::net::SocketAddr;
+
+/// Which resolved addresses a connect may use.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum IpFamily {
+    #[default]
+    Auto = 0,
+    V4Only = 1,
+    V6Only = 2,
+}
+
+impl IpFamily {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Auto),
+            1 => Some(Self::V4Only),
+            2 => Some(Self::V6Only),
+            _ => None,
+        }
+    }
+
+    pub fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Auto => true,
+            Self::V4Only => addr.is_ipv4(),
+            Self::V6Only => addr.is_ipv6(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::IpFamily;
+
+    #[test]
+    fn filters_by_family() {
+        let v4: SocketAddr = "127.0.0.1:80".parse().expect("v4");
+        let v6: SocketAddr = "[::1]:80".parse().expect("v6");
+
+        assert!(IpFamily::Auto.allows(&v4) && IpFamily::Auto.allows(&v6));
+        assert!(IpFamily::V4Only.allows(&v4) && !IpFamily::V4Only.allows(&v6));
+        assert!(!IpFamily::V6Only.allows(&v4) && IpFamily::V6Only.allows(&v6));
+    }
+
+    #[test]
+    fn maps_ffi_values() {
+        assert_eq!(IpFamily::from_ffi(2), Some(IpFamily::V6Only));
+        assert_eq!(IpFamily::from_ffi(3), None);
+    }
+}
@@ -0,0 +1,241 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::logging;
+
+/// Chances are given in parts per million.
+const PPM: u64 = 1_000_000;
+
+/// Faults injected into a connection's byte stream. On `wss` they hit the
+/// decrypted bytes, so corruption shows up in frames rather than in TLS.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Faults {
+    /// Held back before received bytes are handed on.
+    pub latency: Option<Duration>,
+    /// Most bytes handed on per read; `None` leaves reads whole.
+    pub max_read: Option<usize>,
+    /// Chance per read or write that the connection fails as reset.
+    pub disconnect_ppm: u32,
+    /// Chance per read that one bit of the received bytes is flipped.
+    pub corrupt_ppm: u32,
+    /// Seed of the generator deciding when faults hit, so runs repeat.
+    pub seed: u64,
+}
+
+impl Faults {
+    pub fn is_none(&self) -> bool {
+        self.latency.is_none()
+            && self.max_read.is_none()
+            && self.disconnect_ppm == 0
+            && self.corrupt_ppm == 0
+    }
+}
+
+/// Wraps a stream and injects `Faults` into it.
+pub struct Faulty<S> {
+    inner: S,
+    faults: Faults,
+    rng: u64,
+    /// Bytes read but not handed on yet, and when they may be.
+    held: BytesMut,
+    due: Instant,
+    timer: Option<Pin<Box<Sleep>>>,
+    broken: bool,
+}
+
+impl<S> Faulty<S> {
+    pub fn new(inner: S, faults: Faults) -> Self {
+        Self {
+            inner,
+            faults,
+            rng: faults.seed,
+            held: BytesMut::new(),
+            due: Instant::now(),
+            timer: None,
+            broken: false,
+        }
+    }
+
+    /// splitmix64, seeded from the faults.
+    fn next(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn hits(&mut self, ppm: u32) -> bool {
+        ppm > 0 && self.next() % PPM < u64::from(ppm)
+    }
+
+    /// Fails this and every later operation when a disconnect is drawn.
+    fn check_disconnect(&mut self) -> io::Result<()> {
+        if !self.broken && self.hits(self.faults.disconnect_ppm) {
+            logging::emit(3, "fault injection: dropping the connection");
+            self.broken = true;
+        }
+        if self.broken {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "injected disconnect",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Faulty<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.broken {
+            this.check_disconnect()?;
+        }
+        if this.held.is_empty() {
+            let mut chunk = [0_u8; 8192];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            this.check_disconnect()?;
+            if read.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.held.extend_from_slice(read.filled());
+            if this.hits(this.faults.corrupt_ppm) {
+                let bit = this.next() as usize % (this.held.len() * 8);
+                this.held[bit / 8] ^= 1 << (bit % 8);
+            }
+            this.due = Instant::now() + this.faults.latency.unwrap_or_default();
+        }
+
+        if Instant::now() < this.due {
+            let due = this.due;
+            let timer = this
+                .timer
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(due)));
+            ready!(timer.as_mut().poll(cx));
+        }
+        this.timer = None;
+        let len = this
+            .faults
+            .max_read
+            .unwrap_or(usize::MAX)
+            .min(buf.remaining())
+            .min(this.held.len());
+        buf.put_slice(&this.held[..len]);
+        this.held.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Faulty<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        this.check_disconnect()?;
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+    use tokio::runtime::{Builder, Runtime};
+
+    use super::{Faults, Faulty};
+
+    fn runtime() -> Runtime {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime")
+    }
+
+    /// Writes `data` into a faulty stream's far end and reads it back.
+    fn read_through(faults: Faults, data: &'static [u8]) -> (Vec<Vec<u8>>, Duration) {
+        runtime().block_on(async move {
+            let (near, mut far) = duplex(64);
+            far.write_all(data).await.expect("write");
+            drop(far);
+            let mut stream = Faulty::new(near, faults);
+            let started = Instant::now();
+            let mut reads = Vec::new();
+            loop {
+                let mut buf = [0_u8; 64];
+                let read = stream.read(&mut buf).await.expect("read");
+                if read == 0 {
+                    break;
+                }
+                reads.push(buf[..read].to_vec());
+            }
+            (reads, started.elapsed())
+        })
+    }
+
+    #[test]
+    fn truncates_and_delays_reads() {
+        let faults = Faults {
+            latency: Some(Duration::from_millis(20)),
+            max_read: Some(2),
+            ..Faults::default()
+        };
+        let (reads, elapsed) = read_through(faults, b"hello");
+        assert_eq!(reads, [b"he".to_vec(), b"ll".to_vec(), b"o".to_vec()]);
+        assert!(elapsed >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn corrupts_one_bit() {
+        let faults = Faults {
+            corrupt_ppm: 1_000_000,
+            seed: 7,
+            ..Faults::default()
+        };
+        let (reads, _) = read_through(faults, b"hello");
+        let flipped: u32 = reads
+            .concat()
+            .iter()
+            .zip(b"hello")
+            .map(|(got, sent)| (got ^ sent).count_ones())
+            .sum();
+        assert_eq!(flipped, 1);
+    }
+
+    #[test]
+    fn disconnects_stay_disconnected() {
+        let faults = Faults {
+            disconnect_ppm: 1_000_000,
+            ..Faults::default()
+        };
+        runtime().block_on(async move {
+            let (near, _far) = duplex(64);
+            let mut stream = Faulty::new(near, faults);
+            for _ in 0..2 {
+                let err = stream.write_all(b"x").await.expect_err("reset");
+                assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+            }
+        });
+    }
+}
@@ -0,0 +1,32 @@
+/// What the message filter decided for a message.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FilterAction {
+    /// Continues to response routing and the message callback.
+    #[default]
+    Deliver = 0,
+    /// Discarded without reaching any other callback.
+    Drop = 1,
+}
+
+impl FilterAction {
+    /// Unknown values deliver, so a buggy filter can't silently eat traffic.
+    pub fn from_ffi(value: i32) -> Self {
+        match value {
+            1 => Self::Drop,
+            _ => Self::Deliver,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FilterAction;
+
+    #[test]
+    fn unknown_actions_deliver() {
+        assert_eq!(FilterAction::from_ffi(1), FilterAction::Drop);
+        assert_eq!(FilterAction::from_ffi(0), FilterAction::Deliver);
+        assert_eq!(FilterAction::from_ffi(7), FilterAction::Deliver);
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::callback::{Callbacks, OnMessageCallback, WithCtx};
+
+/// Message handlers registered next to `on_message`, called in the order
+/// they were added. Cloning is cheap, so dispatch can hold on to the list
+/// while it is changed.
+#[derive(Clone, Default)]
+pub struct HandlerChain {
+    last_id: u64,
+    handlers: Arc<[(u64, WithCtx<OnMessageCallback>)]>,
+}
+
+impl HandlerChain {
+    /// Appends `handler` and returns the id that removes it again. Ids are
+    /// never zero and not reused.
+    pub fn add(&mut self, handler: WithCtx<OnMessageCallback>) -> u64 {
+        self.last_id += 1;
+        let mut handlers = self.handlers.to_vec();
+        handlers.push((self.last_id, handler));
+        self.handlers = handlers.into();
+        self.last_id
+    }
+
+    /// Whether a handler with `id` was registered.
+    pub fn remove(&mut self, id: u64) -> bool {
+        let before = self.handlers.len();
+        let mut handlers = self.handlers.to_vec();
+        handlers.retain(|&(handler_id, _)| handler_id != id);
+        self.handlers = handlers.into();
+        self.handlers.len() != before
+    }
+
+    /// Removes every handler; ids handed out before stay unused.
+    pub fn clear(&mut self) {
+        self.handlers = Arc::new([]);
+    }
+
+    pub fn call(&self, data: &[u8], op_code: i32) {
+        for (_, handler) in self.handlers.iter() {
+            (handler.f)(
+                handler.ctx,
+                data.as_ptr().cast(),
+                data.len() as u64,
+                op_code,
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    callbacks: Callbacks,
+    chain: HandlerChain,
+}
+
+/// The handlers of a handle, which the host may replace from another
+/// thread while a poll is running. Dispatch works on a snapshot taken per
+/// event, so a new handler takes effect from the next event on and a
+/// cleared one is never called once `clear` returned, except by an event
+/// already being dispatched.
+#[derive(Default)]
+pub struct CallbackSlots(Mutex<Slots>);
+
+impl CallbackSlots {
+    pub fn snapshot(&self) -> (Callbacks, HandlerChain) {
+        let slots = self.lock();
+        (slots.callbacks, slots.chain.clone())
+    }
+
+    pub fn callbacks(&self) -> Callbacks {
+        self.lock().callbacks
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut Callbacks)) {
+        f(&mut self.lock().callbacks);
+    }
+
+    pub fn add_message_handler(&self, handler: WithCtx<OnMessageCallback>) -> u64 {
+        self.lock().chain.add(handler)
+    }
+
+    pub fn remove_message_handler(&self, id: u64) -> bool {
+        self.lock().chain.remove(id)
+    }
+
+    /// Unsets every callback and removes every added message handler.
+    pub fn clear(&self) {
+        let mut slots = self.lock();
+        slots.callbacks = Callbacks::default();
+        slots.chain.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Slots> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::ffi::{c_char, c_void};
+
+    use super::HandlerChain;
+    use crate::callback::WithCtx;
+
+    thread_local! {
+        static CALLS: RefCell<Vec<(char, u64)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Records the tag it was registered with as user data.
+    extern "C" fn tagged(tag: *mut c_void, _data: *const c_char, len: u64, _op_code: i32) {
+        let tag = char::from(tag.addr() as u8);
+        CALLS.with_borrow_mut(|calls| calls.push((tag, len)));
+    }
+
+    fn handler(tag: char) -> WithCtx<super::OnMessageCallback> {
+        WithCtx {
+            f: tagged,
+            ctx: std::ptr::without_provenance_mut(tag as usize),
+        }
+    }
+
+    #[test]
+    fn calls_handlers_in_order_until_removed() {
+        let mut chain = HandlerChain::default();
+        let a = chain.add(handler('a'));
+        let b = chain.add(handler('b'));
+        assert_ne!(a, b);
+        chain.call(b"hi", 1);
+
+        assert!(chain.remove(a));
+        assert!(!chain.remove(a));
+        chain.call(b"bye", 1);
+        assert_eq!(CALLS.take(), [('a', 2), ('b', 2), ('b', 3)]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::ffi::{CString, c_char};
+
+/// What the server answered to the upgrade request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Handshake {
+    pub status: u16,
+    /// Response headers in the order received, names as sent by the server.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Handshake {
+    /// Parses a raw HTTP response head. Malformed lines are skipped.
+    pub fn parse(head: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(head);
+        let mut lines = text.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .unwrap_or(0);
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+            .filter(|(name, _)| !name.is_empty())
+            .collect();
+        Self { status, headers }
+    }
+
+    /// Returns the first header called `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn subprotocol(&self) -> Option<&str> {
+        self.header("Sec-WebSocket-Protocol")
+    }
+
+    pub fn extensions(&self) -> Option<&str> {
+        self.header("Sec-WebSocket-Extensions")
+    }
+}
+
+/// Whether `key` has the shape of a Sec-WebSocket-Key: 16 bytes in base64.
+#[cfg(feature = "diagnostics")]
+pub fn is_valid_key(key: &str) -> bool {
+    key.len() == 24
+        && key.ends_with("==")
+        && key[..22]
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+}
+
+/// Handshake details passed to the extended open callback. Strings are
+/// NUL-terminated and only valid until the callback returns; `subprotocol`
+/// and `extensions` are null when the server did not send them.
+#[repr(C)]
+pub struct WsppHandshakeInfo {
+    pub status: u16,
+    pub subprotocol: *const c_char,
+    pub extensions: *const c_char,
+    /// All response headers as `Name: value` lines separated by `\n`.
+    pub headers: *const c_char,
+}
+
+/// Owns the C strings a `WsppHandshakeInfo` points into.
+pub struct HandshakeStrings {
+    status: u16,
+    subprotocol: Option<CString>,
+    extensions: Option<CString>,
+    headers: CString,
+}
+
+fn c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap_or_default()
+}
+
+impl HandshakeStrings {
+    pub fn new(handshake: &Handshake) -> Self {
+        let headers = handshake
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self {
+            status: handshake.status,
+            subprotocol: handshake.subprotocol().map(c_string),
+            extensions: handshake.extensions().map(c_string),
+            headers: c_string(&headers),
+        }
+    }
+
+    pub fn info(&self) -> WsppHandshakeInfo {
+        let ptr = |value: &Option<CString>| {
+            value
+                .as_ref()
+                .map_or(std::ptr::null(), |value| value.as_ptr())
+        };
+        WsppHandshakeInfo {
+            status: self.status,
+            subprotocol: ptr(&self.subprotocol),
+            extensions: ptr(&self.extensions),
+            headers: self.headers.as_ptr(),
+        }
+    }
+}
+
+/// Headers of the upgrade request the handshake itself is made of.
+const RESERVED_HEADERS: [&str; 6] = [
+    "Host",
+    "Upgrade",
+    "Connection",
+    "Sec-WebSocket-Key",
+    "Sec-WebSocket-Version",
+    "Sec-WebSocket-Extensions",
+];
+
+/// Whether `name: value` can be added to the upgrade request: `name` an
+/// HTTP token the handshake does not set itself, `value` free of control
+/// characters other than tab.
+pub fn is_valid_header(name: &str, value: &str) -> bool {
+    let is_tchar = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    !name.is_empty()
+        && name.bytes().all(is_tchar)
+        && !RESERVED_HEADERS
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name))
+        && value.bytes().all(|b| b == b'\t' || !b.is_ascii_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use super::{Handshake, HandshakeStrings, is_valid_header};
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Protocol: chat\r\n\
+        sec-websocket-extensions: permessage-deflate; client_max_window_bits\r\n\
+        \r\n";
+
+    #[test]
+    fn parses_status_and_headers() {
+        let handshake = Handshake::parse(RESPONSE);
+        assert_eq!(handshake.status, 101);
+        assert_eq!(handshake.headers.len(), 4);
+        assert_eq!(handshake.header("upgrade"), Some("websocket"));
+        assert_eq!(handshake.subprotocol(), Some("chat"));
+        assert_eq!(
+            handshake.extensions(),
+            Some("permessage-deflate; client_max_window_bits")
+        );
+        assert_eq!(handshake.header("X-Missing"), None);
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn handshake_keys_must_encode_sixteen_bytes() {
+        assert!(super::is_valid_key("dGhlIHNhbXBsZSBub25jZQ=="));
+        assert!(!super::is_valid_key("dGhlIHNhbXBsZSBub25jZQ"));
+        assert!(!super::is_valid_key("dGhlIHNhbXBsZSBub25j#Q=="));
+    }
+
+    #[test]
+    fn info_leaves_missing_fields_null() {
+        let handshake =
+            Handshake::parse(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n");
+        let strings = HandshakeStrings::new(&handshake);
+        let info = strings.info();
+
+        assert_eq!(info.status, 101);
+        assert!(info.subprotocol.is_null());
+        assert!(info.extensions.is_null());
+        let headers = unsafe { CStr::from_ptr(info.headers) };
+        assert_eq!(headers.to_str(), Ok("Upgrade: websocket"));
+    }
+
+    #[test]
+    fn only_well_formed_extra_headers_are_allowed() {
+        assert!(is_valid_header("Authorization", "Bearer abc.def"));
+        assert!(is_valid_header("X-Tenant", ""));
+        assert!(!is_valid_header("", "value"));
+        assert!(!is_valid_header("Bad Name", "value"));
+        assert!(!is_valid_header("X-Split", "a\r\nInjected: yes"));
+        assert!(!is_valid_header(
+            "sec-websocket-key",
+            "dGhlIHNhbXBsZSBub25jZQ=="
+        ));
+    }
+}
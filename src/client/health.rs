@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long reconnects and errors keep counting against the score.
+const HEALTH_WINDOW: Duration = Duration::from_secs(300);
+
+/// Signals behind the 0-100 connection health score.
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    /// Smoothed ping round-trip time.
+    rtt: Option<Duration>,
+    /// Pings sent since the last one was answered.
+    unanswered: u32,
+    opens: VecDeque<Instant>,
+    errors: VecDeque<Instant>,
+}
+
+impl HealthTracker {
+    pub fn record_ping(&mut self) {
+        self.unanswered = self.unanswered.saturating_add(1);
+    }
+
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        self.unanswered = 0;
+        self.rtt = Some(match self.rtt {
+            Some(smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+
+    pub fn record_open(&mut self, now: Instant) {
+        self.unanswered = 0;
+        self.opens.push_back(now);
+    }
+
+    pub fn record_error(&mut self, now: Instant) {
+        self.errors.push_back(now);
+    }
+
+    /// 100 for a healthy connection, lower the slower, lossier and less
+    /// stable it has been recently.
+    pub fn score(&mut self, now: Instant) -> u32 {
+        for events in [&mut self.opens, &mut self.errors] {
+            while events
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > HEALTH_WINDOW)
+            {
+                events.pop_front();
+            }
+        }
+
+        // Up to 30 points, scaling from 100 ms to 1 s of round-trip time.
+        let rtt = self.rtt.map_or(0, |rtt| {
+            let ms = u32::try_from(rtt.as_millis()).unwrap_or(u32::MAX);
+            ms.saturating_sub(100).min(900) / 30
+        });
+        // The newest ping may simply still be in flight.
+        let missed = (self.unanswered.saturating_sub(1) * 10).min(30);
+        let reconnects = (self.opens.len().saturating_sub(1) as u32 * 10).min(20);
+        let errors = (self.errors.len() as u32 * 5).min(20);
+
+        100 - rtt - missed - reconnects - errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{HEALTH_WINDOW, HealthTracker};
+
+    #[test]
+    fn fresh_connection_is_fully_healthy() {
+        let now = Instant::now();
+        let mut health = HealthTracker::default();
+        health.record_open(now);
+        health.record_ping();
+        health.record_rtt(Duration::from_millis(20));
+
+        assert_eq!(health.score(now), 100);
+    }
+
+    #[test]
+    fn penalties_are_capped_per_signal() {
+        let now = Instant::now();
+        let mut health = HealthTracker::default();
+        health.record_rtt(Duration::from_secs(5));
+        for _ in 0..10 {
+            health.record_open(now);
+            health.record_error(now);
+        }
+        for _ in 0..10 {
+            health.record_ping();
+        }
+
+        assert_eq!(health.score(now), 0);
+    }
+
+    #[test]
+    fn old_reconnects_and_errors_stop_counting() {
+        let start = Instant::now();
+        let mut health = HealthTracker::default();
+        health.record_open(start);
+        health.record_error(start);
+        health.record_open(start);
+        assert_eq!(health.score(start), 85);
+
+        let later = start + HEALTH_WINDOW + Duration::from_secs(1);
+        assert_eq!(health.score(later), 100);
+    }
+}
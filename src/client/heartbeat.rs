@@ -0,0 +1,221 @@
+use std::ffi::{c_char, c_void};
+use std::time::{Duration, Instant};
+
+use crate::callback::PingPayloadSource;
+use crate::logging;
+
+use super::payload::Payload;
+
+/// Largest payload of a control frame.
+pub const MAX_PING_PAYLOAD: usize = 125;
+
+/// What keepalive pings carry.
+#[derive(Clone, Debug, Default)]
+pub enum PingPayload {
+    /// `wspp-keepalive-<n>`, numbered per ping.
+    #[default]
+    Numbered,
+    /// The same bytes in every ping, for servers expecting particular ones.
+    Fixed(Payload),
+    /// Asked from the host for each ping.
+    Host(HostPayload),
+}
+
+/// Host payload callback. The userdata pointer is only ever used from the
+/// worker thread.
+#[derive(Clone, Copy, Debug)]
+pub struct HostPayload {
+    fill: PingPayloadSource,
+    userdata: *mut c_void,
+}
+
+unsafe impl Send for HostPayload {}
+
+impl HostPayload {
+    pub fn new(fill: PingPayloadSource, userdata: *mut c_void) -> Self {
+        Self { fill, userdata }
+    }
+
+    /// The payload the host wrote, `None` if it declined or overran.
+    fn next(&self) -> Option<Payload> {
+        let mut buf = [0_u8; MAX_PING_PAYLOAD];
+        let len = (self.fill)(
+            self.userdata,
+            buf.as_mut_ptr() as *mut c_char,
+            MAX_PING_PAYLOAD as u64,
+        );
+        match usize::try_from(len) {
+            Ok(len) if len <= MAX_PING_PAYLOAD => Some(Payload::copy_from_slice(&buf[..len])),
+            Ok(_) => {
+                logging::emit(2, "keepalive payload too long; using the default");
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+/// Pings sent on a timer so a connection that died without a word is
+/// noticed.
+#[derive(Clone, Debug, Default)]
+pub struct Heartbeat {
+    /// Time between pings; `None` sends none.
+    pub interval: Option<Duration>,
+    /// How long a ping may go unanswered before the connection is dead.
+    pub timeout: Duration,
+    pub payload: PingPayload,
+}
+
+/// What the heartbeat needs from the connection right now.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Beat {
+    Idle,
+    /// A ping with this payload is due.
+    Ping(Payload),
+    /// No pong came for this long.
+    TimedOut(Duration),
+}
+
+/// The heartbeat of one connection.
+pub struct HeartbeatTimer {
+    interval: Duration,
+    timeout: Duration,
+    payload: PingPayload,
+    /// Pings sent so far, which numbers their payloads.
+    sent: u64,
+    next_ping: Instant,
+    /// When the ping waiting for its pong was sent, and its payload.
+    awaiting: Option<(Instant, Payload)>,
+}
+
+impl HeartbeatTimer {
+    pub fn new(heartbeat: &Heartbeat, now: Instant) -> Option<Self> {
+        let interval = heartbeat.interval?;
+        Some(Self {
+            interval,
+            timeout: heartbeat.timeout,
+            payload: heartbeat.payload.clone(),
+            sent: 0,
+            next_ping: now + interval,
+            awaiting: None,
+        })
+    }
+
+    /// A ping is due once the interval passed and the last one was
+    /// answered; it is then taken as sent. Unless set otherwise each gets
+    /// a payload of its own, so pongs to the host's pings are never taken
+    /// for its pong.
+    pub fn poll(&mut self, now: Instant) -> Beat {
+        if let Some((sent, _)) = self.awaiting {
+            let waited = now.saturating_duration_since(sent);
+            return if waited >= self.timeout {
+                Beat::TimedOut(waited)
+            } else {
+                Beat::Idle
+            };
+        }
+        if now < self.next_ping {
+            return Beat::Idle;
+        }
+        self.sent += 1;
+        let payload = match &self.payload {
+            PingPayload::Fixed(payload) => Some(payload.clone()),
+            PingPayload::Host(host) => host.next(),
+            PingPayload::Numbered => None,
+        }
+        .unwrap_or_else(|| Payload::from(format!("wspp-keepalive-{}", self.sent).into_bytes()));
+        self.awaiting = Some((now, payload.clone()));
+        self.next_ping = now + self.interval;
+        Beat::Ping(payload)
+    }
+
+    /// Whether `data` answers the ping in flight, which is then settled.
+    pub fn pong(&mut self, data: &[u8]) -> bool {
+        let answered = matches!(&self.awaiting, Some((_, payload)) if data == &**payload);
+        if answered {
+            self.awaiting = None;
+        }
+        answered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use std::ffi::{c_char, c_void};
+
+    use super::{Beat, Heartbeat, HeartbeatTimer, HostPayload, PingPayload};
+    use crate::client::payload::Payload;
+
+    extern "C" fn counting(userdata: *mut c_void, buf: *mut c_char, cap: u64) -> i64 {
+        let calls = unsafe { &mut *(userdata as *mut i64) };
+        *calls += 1;
+        if *calls == 2 {
+            return -1;
+        }
+        assert_eq!(cap, 125);
+        unsafe { *buf = b'a' as c_char };
+        1
+    }
+
+    #[test]
+    fn pings_each_interval_until_a_pong_is_missing() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let heartbeat = Heartbeat {
+            interval: Some(Duration::from_millis(100)),
+            timeout: Duration::from_millis(50),
+            ..Heartbeat::default()
+        };
+        let mut timer = HeartbeatTimer::new(&heartbeat, start).expect("enabled");
+        assert_eq!(timer.poll(at(99)), Beat::Idle);
+        assert_eq!(
+            timer.poll(at(100)),
+            Beat::Ping(Payload::from("wspp-keepalive-1"))
+        );
+        assert!(!timer.pong(b""));
+        assert!(timer.pong(b"wspp-keepalive-1"));
+        assert!(!timer.pong(b"wspp-keepalive-1"));
+
+        assert_eq!(timer.poll(at(150)), Beat::Idle);
+        assert_eq!(
+            timer.poll(at(200)),
+            Beat::Ping(Payload::from("wspp-keepalive-2"))
+        );
+        assert!(!timer.pong(b"wspp-keepalive-1"));
+        assert_eq!(timer.poll(at(249)), Beat::Idle);
+        assert_eq!(
+            timer.poll(at(260)),
+            Beat::TimedOut(Duration::from_millis(60))
+        );
+        assert!(HeartbeatTimer::new(&Heartbeat::default(), start).is_none());
+    }
+
+    #[test]
+    fn payloads_can_be_fixed_or_come_from_the_host() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut heartbeat = Heartbeat {
+            interval: Some(Duration::from_millis(10)),
+            timeout: Duration::from_millis(10),
+            payload: PingPayload::Fixed(Payload::from("hb")),
+        };
+        let mut timer = HeartbeatTimer::new(&heartbeat, start).expect("enabled");
+        assert_eq!(timer.poll(at(10)), Beat::Ping(Payload::from("hb")));
+        assert!(timer.pong(b"hb"));
+
+        let mut calls = 0_i64;
+        let userdata = &mut calls as *mut i64 as *mut c_void;
+        heartbeat.payload = PingPayload::Host(HostPayload::new(counting, userdata));
+        let mut timer = HeartbeatTimer::new(&heartbeat, start).expect("enabled");
+        assert_eq!(timer.poll(at(10)), Beat::Ping(Payload::from("a")));
+        assert!(timer.pong(b"a"));
+        // A declining host gets the numbered default.
+        assert_eq!(
+            timer.poll(at(20)),
+            Beat::Ping(Payload::from("wspp-keepalive-2"))
+        );
+        assert_eq!(calls, 2);
+    }
+}
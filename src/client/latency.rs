@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const HALF_BUCKETS: usize = SUB_BUCKETS / 2;
+const BUCKET_COUNT: usize = (64 - SUB_BUCKET_BITS as usize + 2) * HALF_BUCKETS;
+
+/// Log-linear (HDR-style) histogram of microsecond samples. Every value is
+/// kept within ~6% relative error using a fixed number of buckets.
+#[derive(Default)]
+pub struct LatencyHistogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - (SUB_BUCKET_BITS - 1);
+    let sub = (value >> shift) as usize;
+    shift as usize * HALF_BUCKETS + sub
+}
+
+/// Highest value that maps into `index`.
+fn bucket_value(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+
+    let shift = index / HALF_BUCKETS - 1;
+    let sub = (index - shift * HALF_BUCKETS) as u64;
+    ((sub + 1) << shift).saturating_sub(1)
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, sample: Duration) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKET_COUNT];
+        }
+
+        let micros = u64::try_from(sample.as_micros()).unwrap_or(u64::MAX);
+        self.counts[bucket_index(micros)] += 1;
+        self.total += 1;
+    }
+
+    /// Returns the value in microseconds at or below which `percentile`
+    /// percent of the samples fall.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0) * self.total as f64).ceil() as u64;
+        let rank = rank.max(1);
+        let mut seen = 0_u64;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(bucket_value(index));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{BUCKET_COUNT, LatencyHistogram, bucket_index, bucket_value};
+
+    #[test]
+    fn buckets_are_contiguous_and_bounded() {
+        assert_eq!(bucket_index(31), 31);
+        assert_eq!(bucket_index(32), 32);
+        assert_eq!(bucket_index(63), 47);
+        assert_eq!(bucket_index(64), 48);
+        assert_eq!(bucket_index(u64::MAX), BUCKET_COUNT - 1);
+        for value in [0, 1, 31, 32, 33, 1000, 123_456, 10_000_000] {
+            let upper = bucket_value(bucket_index(value));
+            assert!(upper >= value);
+            assert!(upper - value <= value / 16 + 1, "{value} -> {upper}");
+        }
+    }
+
+    #[test]
+    fn empty_histogram_has_no_percentiles() {
+        let hist = LatencyHistogram::default();
+        assert_eq!(hist.percentile(50.0), None);
+    }
+
+    #[test]
+    fn reports_percentiles() {
+        let mut hist = LatencyHistogram::default();
+        for ms in 1..=100 {
+            hist.record(Duration::from_millis(ms));
+        }
+
+        let p50 = hist.percentile(50.0).expect("p50");
+        let p99 = hist.percentile(99.0).expect("p99");
+        assert!((48_000..=53_000).contains(&p50), "p50 {p50}");
+        assert!((97_000..=103_000).contains(&p99), "p99 {p99}");
+        assert!(hist.percentile(100.0).expect("max") >= 100_000);
+    }
+}
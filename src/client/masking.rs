@@ -0,0 +1,142 @@
+use std::ffi::{c_char, c_void};
+
+use crate::callback::RandomSource;
+use crate::logging;
+
+/// Where masking keys for outgoing frames come from. Without one, yawc
+/// draws them from a secure RNG.
+#[derive(Clone, Copy, Debug)]
+pub enum MaskSource {
+    /// Deterministic keys for reproducible captures.
+    #[cfg(feature = "diagnostics")]
+    Seeded(u64),
+    /// Host-supplied random bytes, e.g. from a platform-mandated RNG.
+    Host(HostRandom),
+}
+
+/// Host random callback. The userdata pointer is only ever used from the
+/// worker thread.
+#[derive(Clone, Copy, Debug)]
+pub struct HostRandom {
+    fill: RandomSource,
+    userdata: *mut c_void,
+}
+
+unsafe impl Send for HostRandom {}
+
+impl HostRandom {
+    pub fn new(fill: RandomSource, userdata: *mut c_void) -> Self {
+        Self { fill, userdata }
+    }
+}
+
+/// Per-connection state of a `MaskSource`.
+pub enum Masks {
+    #[cfg(feature = "diagnostics")]
+    Seeded(SeededMasks),
+    Host(HostRandom),
+}
+
+impl Masks {
+    pub fn new(source: MaskSource) -> Self {
+        match source {
+            #[cfg(feature = "diagnostics")]
+            MaskSource::Seeded(seed) => Self::Seeded(SeededMasks::new(seed)),
+            MaskSource::Host(host) => Self::Host(host),
+        }
+    }
+
+    /// Key for the next frame, or `None` to let yawc pick one. A failing
+    /// host source falls back to yawc rather than sending unmasked.
+    pub fn next_mask(&mut self) -> Option<[u8; 4]> {
+        match self {
+            #[cfg(feature = "diagnostics")]
+            Self::Seeded(masks) => Some(masks.next_mask()),
+            Self::Host(host) => {
+                let mut mask = [0_u8; 4];
+                if (host.fill)(host.userdata, mask.as_mut_ptr() as *mut c_char, 4) {
+                    Some(mask)
+                } else {
+                    logging::emit(2, "mask source failed; using built-in RNG");
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Deterministic masking keys for reproducible captures. Not suitable for
+/// production traffic: RFC 6455 requires unpredictable masks.
+#[cfg(feature = "diagnostics")]
+#[derive(Clone, Debug)]
+pub struct SeededMasks {
+    state: u64,
+}
+
+#[cfg(feature = "diagnostics")]
+impl SeededMasks {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// splitmix64; the same seed yields the same key sequence on every run.
+    pub fn next_mask(&mut self) -> [u8; 4] {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z as u32).to_le_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, c_void};
+
+    use super::{HostRandom, MaskSource, Masks};
+
+    extern "C" fn fill_counting(userdata: *mut c_void, buf: *mut c_char, len: u64) -> bool {
+        let next = unsafe { &mut *(userdata as *mut u8) };
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf as *mut u8, len as usize) };
+        for byte in buf {
+            *next += 1;
+            *byte = *next;
+        }
+        true
+    }
+
+    extern "C" fn fill_failing(_: *mut c_void, _: *mut c_char, _: u64) -> bool {
+        false
+    }
+
+    #[test]
+    fn host_source_supplies_each_mask() {
+        let mut next = 0_u8;
+        let host = HostRandom::new(fill_counting, &mut next as *mut u8 as *mut c_void);
+        let mut masks = Masks::new(MaskSource::Host(host));
+
+        assert_eq!(masks.next_mask(), Some([1, 2, 3, 4]));
+        assert_eq!(masks.next_mask(), Some([5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn failing_host_source_defers_to_yawc() {
+        let host = HostRandom::new(fill_failing, std::ptr::null_mut());
+        assert_eq!(Masks::new(MaskSource::Host(host)).next_mask(), None);
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn same_seed_repeats_the_sequence() {
+        use super::SeededMasks;
+
+        let mut first = SeededMasks::new(7);
+        let mut second = SeededMasks::new(7);
+        let keys: Vec<_> = (0..4).map(|_| first.next_mask()).collect();
+
+        assert_eq!(keys, (0..4).map(|_| second.next_mask()).collect::<Vec<_>>());
+        assert_ne!(keys[0], keys[1]);
+        assert_ne!(SeededMasks::new(8).next_mask(), keys[0]);
+    }
+}
@@ -1,38 +1,233 @@
+mod arena;
+mod backpressure;
+mod batch;
+mod correlation;
+mod dedup;
+mod endpoints;
+mod error;
+mod family;
+#[cfg(feature = "fault-injection")]
+mod fault;
+mod filter;
+mod handlers;
+mod handshake;
+mod health;
+mod heartbeat;
+mod latency;
+mod masking;
+mod ocsp;
+mod options;
+mod pair;
+mod payload;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+mod pong;
+mod priority;
+mod queue;
+#[cfg(feature = "unsafe-protocol")]
+mod raw;
+mod reconnect;
+mod record;
+mod report;
+#[cfg(all(windows, feature = "schannel"))]
+mod schannel;
+#[cfg(feature = "diagnostics")]
+mod session;
+mod sockopt;
 mod state;
+mod stats;
+mod stream;
+mod throttle;
+mod tls;
+mod transport;
+mod unsent;
 mod worker;
 
-use std::ffi::CString;
+#[cfg(test)]
+mod e2e_tests;
+#[cfg(test)]
+mod prop_tests;
+#[cfg(test)]
+mod soak_tests;
+
+use std::ffi::{CString, c_char};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::callback::Callbacks;
+#[cfg(feature = "unsafe-protocol")]
+use bytes::Bytes;
+
+use crate::budget::{self, BudgetAccount};
+use crate::callback::{Callbacks, Userdata};
+use crate::close_code::{self, WsppCloseCode};
+use crate::lifecycle::{self, Live};
 use crate::logging;
+use crate::opcode::WsppOpcode;
 use crate::result::WsppResult;
 
-use worker::{Command, Event};
+use batch::Batch;
+use correlation::PendingRequests;
+use dedup::ErrorDedup;
+use endpoints::Endpoints;
+use error::WorkerError;
+use filter::FilterAction;
+use handlers::HandlerChain;
+use handshake::{Handshake, HandshakeStrings};
+use health::HealthTracker;
+use latency::LatencyHistogram;
+use masking::MaskSource;
+use options::{ArenaOptions, ConnectOptions, SpillOptions};
+use queue::SendQueue;
+use stats::{HandleStats, Loss};
+use throttle::BandwidthLimit;
+use tls::{ClientCert, LockedClientCert};
+use unsent::Unsent;
+use worker::{Command, Event, Runner};
 
+pub use arena::BufferGrowth;
+pub use batch::{WsppEvent, WsppEventKind};
+#[cfg(test)]
+pub use error::WsppTimeoutPhase;
+pub use error::{WsppErrorCategory, WsppErrorInfo};
+pub use family::IpFamily;
+#[cfg(feature = "fault-injection")]
+pub use fault::Faults;
+pub use handlers::CallbackSlots;
+pub use handshake::WsppHandshakeInfo;
+pub use heartbeat::{HostPayload, PingPayload};
+pub use masking::HostRandom;
+pub use payload::Payload;
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Pkcs11Identity;
+pub use pong::PongPolicy;
+pub use priority::ThreadPriority;
+pub use queue::{Priority, QueuePolicy, SendFlags};
+pub use reconnect::{Jitter, Reconnect};
+pub use report::WsppPollReport;
+pub use sockopt::Keepalive;
 pub use state::WsState;
+pub use stats::WsppStats;
+pub use stream::{ChunkSource, ProviderSource};
+pub use tls::{HostPassphrase, Passphrase, RevocationMode, VerifyPolicy, is_fips};
 
 pub struct WsppWsImpl {
     state: WsState,
-    uri: String,
-    compression: bool,
-    event_rx: Option<Receiver<Event>>,
+    endpoints: Endpoints,
+    options: ConnectOptions,
+    event_rx: Option<Receiver<(u64, Event)>>,
+    /// Sequence number of the event being dispatched, zero before any.
+    event_seq: u64,
     cmd_tx: Option<Sender<Command>>,
-    pub callbacks: Callbacks,
+    account: Option<BudgetAccount>,
+    worker: Option<Runner>,
+    write_stalled: Arc<AtomicBool>,
+    send_ttl: Option<Duration>,
+    queue: Arc<SendQueue>,
+    stats: Arc<HandleStats>,
+    handshake: Option<Handshake>,
+    handshake_record: Arc<Mutex<Option<String>>>,
+    last_error_category: WsppErrorCategory,
+    pending: PendingRequests,
+    latency: LatencyHistogram,
+    health: HealthTracker,
+    /// Period of the health callback and when it is next due.
+    health_report: Option<(Duration, Instant)>,
+    /// Queue pressure threshold and whether it is currently exceeded.
+    pressure: Option<(u64, bool)>,
+    error_dedup: Option<ErrorDedup>,
+    /// Worker restarts allowed after a lost connection, zero if off.
+    resume_limit: u32,
+    /// Restarts made since the connection was lost, zero while it is up.
+    resume_attempts: u32,
+    reconnect: Option<Reconnect>,
+    /// Attempts made when reconnecting last gave up, until that is reported.
+    gave_up: Option<u32>,
+    /// Events of the running poll, while `on_events` is set.
+    batch: Option<Batch>,
+    slots: Arc<CallbackSlots>,
+    userdata: Userdata,
+    /// Snapshot of `slots` the event being dispatched is delivered to.
+    callbacks: Callbacks,
+    /// Further message handlers, called after `callbacks.on_message`.
+    message_handlers: HandlerChain,
+    _live: Live,
 }
 
 impl WsppWsImpl {
     pub fn new(uri: &str, compression: bool) -> Self {
         Self {
             state: WsState::New,
-            uri: uri.to_owned(),
-            compression,
+            endpoints: Endpoints::new(uri),
+            options: ConnectOptions {
+                compression,
+                ..ConnectOptions::default()
+            },
             event_rx: None,
+            event_seq: 0,
             cmd_tx: None,
+            account: None,
+            worker: None,
+            write_stalled: Arc::default(),
+            send_ttl: None,
+            queue: Arc::default(),
+            stats: Arc::default(),
+            handshake: None,
+            handshake_record: Arc::default(),
+            last_error_category: WsppErrorCategory::None,
+            pending: PendingRequests::default(),
+            latency: LatencyHistogram::default(),
+            health: HealthTracker::default(),
+            health_report: None,
+            pressure: None,
+            error_dedup: None,
+            resume_limit: 0,
+            resume_attempts: 0,
+            reconnect: None,
+            gave_up: None,
+            batch: None,
+            message_handlers: HandlerChain::default(),
+            slots: Arc::default(),
+            userdata: Userdata::default(),
             callbacks: Callbacks::default(),
+            _live: Live::new(),
         }
     }
 
+    /// Two handles already connected to each other in memory, without
+    /// sockets or threads: what one sends arrives at the other when either
+    /// polls, a ping is answered at once and closing one closes both.
+    /// Connecting either again opens a real connection to `uri`.
+    pub fn new_pair(uri: &str) -> (Self, Self) {
+        let mut a = Self::new(uri, false);
+        let mut b = Self::new(uri, false);
+        let (link, [end_a, end_b]) = pair::open([a.pair_side(), b.pair_side()]);
+        a.join_pair(end_a, link.clone());
+        b.join_pair(end_b, link);
+        (a, b)
+    }
+
+    fn pair_side(&self) -> pair::Side {
+        let queue = SendQueue::new(self.options.queue_limit, self.options.queue_policy);
+        (
+            self.stats.clone(),
+            budget::global().account(),
+            Arc::new(queue),
+        )
+    }
+
+    fn join_pair(&mut self, end: pair::PairEnd, link: Arc<Mutex<pair::PairLink>>) {
+        self.cmd_tx = Some(end.cmd_tx);
+        self.event_rx = Some(end.event_rx);
+        self.account = Some(end.account);
+        self.queue = end.queue;
+        self.worker = Some(Runner::Pair(link));
+        self.state = WsState::Connecting;
+    }
+
     pub fn connect(&mut self) -> Result<WsppResult, WsppResult> {
         if matches!(
             self.state,
@@ -42,34 +237,249 @@ impl WsppWsImpl {
         }
 
         self.cleanup();
+        self.pending.take_all();
+        self.handshake = None;
+        self.resume_attempts = 0;
+        self.cancel_reconnect();
+        self.discard_unsent();
+        self.endpoints.rewind();
 
-        match worker::spawn_ws_worker(self.uri.clone(), self.compression) {
-            Ok((cmd_tx, event_rx)) => {
-                self.cmd_tx = Some(cmd_tx);
-                self.event_rx = Some(event_rx);
+        match self.start_worker() {
+            Ok(()) => {
                 self.state = WsState::Connecting;
                 logging::emit(3, "wspp connect queued");
                 Ok(WsppResult::Ok)
             }
             Err(err) => {
                 self.state = WsState::Closed;
+                Err(err)
+            }
+        }
+    }
+
+    fn start_worker(&mut self) -> Result<(), WsppResult> {
+        let mut options = self.options.clone();
+        options.tls.unlock_client_cert().map_err(|err| {
+            logging::emit(1, &format!("client key not unlocked: {err}"));
+            WsppResult::InvalidArgument
+        })?;
+        let account = budget::global().account();
+        match worker::spawn_ws_worker(
+            self.endpoints.current().to_owned(),
+            options,
+            account.clone(),
+            self.stats.clone(),
+        ) {
+            Ok(worker) => {
+                self.cmd_tx = Some(worker.cmd_tx);
+                self.event_rx = Some(worker.event_rx);
+                self.account = Some(account);
+                if let Some(previous) = self.worker.replace(worker.runner) {
+                    previous.detach();
+                }
+                self.write_stalled = worker.write_stalled;
+                self.queue = worker.queue;
+                self.handshake_record = worker.handshake_record;
+                Ok(())
+            }
+            Err(err) => {
                 logging::emit(1, &format!("worker spawn failed: {err}"));
                 Err(err.to_wspp_result())
             }
         }
     }
 
+    /// Restarts the worker after `err` instead of failing the handle, if
+    /// resuming is on, `err` lost an open connection or a restart already
+    /// under way failed, and attempts are left.
+    fn try_resume(&mut self, err: &WorkerError) -> bool {
+        let lost = matches!(self.state, WsState::Connected) && err.is_transport_loss();
+        if !lost && self.resume_attempts == 0 {
+            return false;
+        }
+        if self.resume_attempts >= self.resume_limit {
+            self.resume_attempts = 0;
+            return false;
+        }
+        self.resume_attempts += 1;
+        logging::emit(
+            2,
+            &format!(
+                "connection lost ({}); restarting worker, attempt {} of {}",
+                err.message, self.resume_attempts, self.resume_limit
+            ),
+        );
+        self.cleanup();
+        self.start_worker().is_ok()
+    }
+
+    /// Schedules a new connection after the one of the handle ended
+    /// abnormally, if reconnecting is on and attempts are left. `lost` says
+    /// whether an open connection ended; otherwise only a failed attempt
+    /// leads to the next one.
+    fn try_reconnect(&mut self, lost: bool) -> bool {
+        let Some(reconnect) = self.reconnect.as_mut() else {
+            return false;
+        };
+        if !lost && !reconnect.is_active() {
+            return false;
+        }
+        let Some((attempt, delay)) = reconnect.schedule(Instant::now()) else {
+            self.gave_up = Some(reconnect.max_attempts());
+            return false;
+        };
+        self.endpoints.advance();
+        logging::emit(
+            2,
+            &format!(
+                "connection ended; reconnecting to {} in {} ms, attempt {attempt}",
+                self.endpoints.current(),
+                delay.as_millis()
+            ),
+        );
+        self.cleanup();
+        let pending = self.pending.take_all();
+        self.fail_requests(pending, WsppResult::IoError);
+        self.handshake = None;
+        self.state = WsState::Connecting;
+        if let Some(batch) = self.batch.as_mut() {
+            batch.push_data(
+                WsppEventKind::Reconnecting,
+                self.event_seq,
+                None,
+                attempt as i32,
+            );
+        } else if let Some(cb) = self.callbacks.on_reconnecting {
+            (cb.f)(cb.ctx, attempt);
+        }
+        true
+    }
+
+    /// Reports that reconnecting gave up, once the last attempt's error or
+    /// close has been.
+    fn report_gave_up(&mut self) {
+        let Some(attempts) = self.gave_up.take() else {
+            return;
+        };
+        self.discard_unsent();
+        logging::emit(
+            2,
+            &format!("reconnecting gave up after {attempts} attempts"),
+        );
+        if let Some(batch) = self.batch.as_mut() {
+            batch.push_data(
+                WsppEventKind::ReconnectGaveUp,
+                self.event_seq,
+                None,
+                attempts as i32,
+            );
+        } else if let Some(cb) = self.callbacks.on_reconnect_gave_up {
+            (cb.f)(cb.ctx, attempts);
+        }
+    }
+
+    /// Starts the scheduled reconnect attempt once it is due.
+    fn start_due_reconnect(&mut self, now: Instant) {
+        if !self
+            .reconnect
+            .as_mut()
+            .is_some_and(|reconnect| reconnect.take_due(now))
+        {
+            return;
+        }
+        if self.start_worker().is_err() {
+            self.cancel_reconnect();
+            self.state = WsState::Closed;
+            let err = WorkerError::new(WsppErrorCategory::Internal, "reconnect failed to start");
+            self.report_error(&err);
+        }
+    }
+
+    fn cancel_reconnect(&mut self) {
+        if let Some(reconnect) = self.reconnect.as_mut() {
+            reconnect.reset();
+        }
+    }
+
+    fn discard_unsent(&self) {
+        if let Some(unsent) = self.options.unsent.as_ref() {
+            unsent.clear();
+        }
+    }
+
+    /// Sends the messages the lost connection never wrote, in order.
+    fn replay_unsent(&mut self) {
+        let Some(unsent) = self.options.unsent.clone() else {
+            return;
+        };
+        let messages = unsent.take();
+        if messages.is_empty() {
+            return;
+        }
+        logging::emit(3, &format!("replaying {} unsent messages", messages.len()));
+        for cmd in messages {
+            // A full queue already counted the loss.
+            let _ = self.send_command(cmd);
+        }
+    }
+
+    fn awaits_reconnect(&self) -> bool {
+        self.reconnect.as_ref().is_some_and(Reconnect::is_waiting)
+    }
+
     pub fn poll(&mut self) -> u64 {
+        self.poll_report().total
+    }
+
+    /// Like `poll`, broken down by the kind of event dispatched.
+    pub fn poll_report(&mut self) -> WsppPollReport {
+        match self.worker.as_mut() {
+            Some(Runner::Inline(inline)) => inline.drive(),
+            Some(Runner::Pair(link)) => link.lock().unwrap_or_else(|err| err.into_inner()).pump(),
+            _ => {}
+        }
+        self.refresh_callbacks();
+        self.report_health(Instant::now());
+        self.report_queue_pressure();
+        let on_events = self.callbacks.on_events;
+        self.batch = on_events.map(|_| Batch::default());
+        if let Some(summary) = self
+            .error_dedup
+            .as_mut()
+            .and_then(|dedup| dedup.expire(Instant::now(), false))
+        {
+            self.report_error(&summary);
+        }
+        let mut report = WsppPollReport {
+            total: self.expire_requests(Instant::now()),
+            ..WsppPollReport::default()
+        };
+        self.start_due_reconnect(Instant::now());
+        self.drain_events(&mut report);
+        if let (Some(batch), Some(cb)) = (self.batch.take(), on_events) {
+            batch.deliver(cb);
+        }
+        report
+    }
+
+    fn drain_events(&mut self, report: &mut WsppPollReport) {
         let Some(event_rx) = self.event_rx.take() else {
-            return 0;
+            return;
         };
 
-        let mut count = 0_u64;
         let mut keep_receiver = true;
-        while let Ok(event) = event_rx.try_recv() {
+        while let Ok((seq, event)) = event_rx.try_recv() {
+            self.stats.events.pop();
+            self.event_seq = seq;
+            report.count(seq, &event);
             self.dispatch(event);
-            count += 1;
-            if matches!(self.state, WsState::Closed) {
+            // A restarted worker comes with its own receiver; what is left
+            // in this one belongs to the lost connection, as it does when a
+            // reconnect is scheduled.
+            if matches!(self.state, WsState::Closed)
+                || self.event_rx.is_some()
+                || self.awaits_reconnect()
+            {
                 keep_receiver = false;
                 break;
             }
@@ -77,114 +487,1328 @@ impl WsppWsImpl {
         if keep_receiver {
             self.event_rx = Some(event_rx);
         }
-
-        count
     }
 
     pub fn close(&mut self, code: u16, reason: &str) -> Result<WsppResult, WsppResult> {
+        if !close_code::is_sendable(code) {
+            return Err(WsppResult::InvalidArgument);
+        }
         if !matches!(
             self.state,
             WsState::Connecting | WsState::Connected | WsState::Closing
         ) {
             return Err(WsppResult::InvalidState);
         }
+        if self.awaits_reconnect() {
+            self.cancel_reconnect();
+            self.discard_unsent();
+            self.state = WsState::Closed;
+            return Ok(WsppResult::Ok);
+        }
 
-        let sender = self.cmd_tx.as_ref().ok_or(WsppResult::InvalidState)?;
-        sender
-            .send(Command::Close {
-                code,
-                reason: Some(reason.to_owned()),
-            })
-            .map_err(|_| WsppResult::IoError)?;
+        self.queue_command(Command::Close {
+            code,
+            reason: Some(reason.to_owned()),
+        })?;
 
         self.state = WsState::Closing;
         Ok(WsppResult::Ok)
     }
 
     pub fn send_message(&mut self, message: &str) -> Result<WsppResult, WsppResult> {
-        self.send_command(Command::SendText(message.to_owned()))
+        self.send_text(Payload::from(message))
     }
 
-    pub fn send_binary(&mut self, data: Vec<u8>) -> Result<WsppResult, WsppResult> {
-        self.send_command(Command::SendBinary(data))
+    /// Queues an already validated UTF-8 payload, sharing the buffer.
+    pub fn send_text(&mut self, text: Payload) -> Result<WsppResult, WsppResult> {
+        self.send_prioritized(true, text, Priority::Normal)
     }
 
-    pub fn ping(&mut self, data: Vec<u8>) -> Result<WsppResult, WsppResult> {
-        self.send_command(Command::Ping(data))
+    pub fn send_binary(&mut self, data: impl Into<Payload>) -> Result<WsppResult, WsppResult> {
+        self.send_prioritized(false, data.into(), Priority::Normal)
     }
 
-    pub fn shutdown(&mut self) {
-        if let Some(sender) = self.cmd_tx.as_ref() {
-            let _ = sender.send(Command::Shutdown);
+    /// Queues a message with an explicit priority for the drop policy.
+    /// Text payloads must already be valid UTF-8.
+    pub fn send_prioritized(
+        &mut self,
+        text: bool,
+        data: Payload,
+        priority: Priority,
+    ) -> Result<WsppResult, WsppResult> {
+        let expires_at = self.expiry();
+        self.send_data(text, data, priority, expires_at)
+    }
+
+    /// Queues a message with per-message `flags`. Text payloads must
+    /// already be valid UTF-8.
+    pub fn send_flagged(
+        &mut self,
+        text: bool,
+        data: Payload,
+        flags: SendFlags,
+    ) -> Result<WsppResult, WsppResult> {
+        let mut expires_at = self.expiry();
+        if flags.short_ttl {
+            let short = Instant::now() + SendFlags::SHORT_TTL;
+            expires_at = Some(expires_at.map_or(short, |at| at.min(short)));
+        }
+        match self.send_data(text, data, flags.priority, expires_at) {
+            Err(WsppResult::QueueFull) if flags.fire_and_forget => Ok(WsppResult::Ok),
+            result => result,
+        }
+    }
+
+    fn send_data(
+        &mut self,
+        text: bool,
+        data: Payload,
+        priority: Priority,
+        expires_at: Option<Instant>,
+    ) -> Result<WsppResult, WsppResult> {
+        self.send_command(if text {
+            Command::SendText {
+                data,
+                expires_at,
+                priority,
+            }
+        } else {
+            Command::SendBinary {
+                data,
+                expires_at,
+                priority,
+            }
+        })
+    }
+
+    /// Bounds the outgoing message queue to `limit` messages, zero meaning
+    /// unbounded. Only allowed while disconnected.
+    pub fn set_send_queue(
+        &mut self,
+        limit: usize,
+        policy: QueuePolicy,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.queue_limit = limit;
+        self.options.queue_policy = policy;
+        Ok(WsppResult::Ok)
+    }
+
+    pub fn send_stream(
+        &mut self,
+        text: bool,
+        source: ChunkSource,
+    ) -> Result<WsppResult, WsppResult> {
+        self.send_command(Command::SendStream { text, source })
+    }
+
+    pub fn send_file(&mut self, text: bool, path: &Path) -> Result<WsppResult, WsppResult> {
+        let file = File::open(path).map_err(|_| WsppResult::IoError)?;
+        self.send_stream(text, ChunkSource::File(file))
+    }
+
+    pub fn ping(&mut self, data: impl Into<Payload>) -> Result<WsppResult, WsppResult> {
+        let result = self.send_command(Command::Ping(data.into()));
+        if result.is_ok() {
+            self.health.record_ping();
+        }
+        result
+    }
+
+    /// Writes one frame exactly as given, without validating the opcode,
+    /// reserved bits or control frame rules.
+    #[cfg(feature = "unsafe-protocol")]
+    pub fn send_raw_frame(
+        &mut self,
+        opcode: u8,
+        rsv: u8,
+        fin: bool,
+        payload: Bytes,
+    ) -> Result<WsppResult, WsppResult> {
+        if opcode > 0xF || rsv > 0x7 {
+            return Err(WsppResult::InvalidArgument);
         }
+        self.send_command(Command::SendRawFrame(raw::RawFrame {
+            fin,
+            rsv,
+            opcode,
+            payload,
+        }))
+    }
+
+    /// Switches later connections to raw receive mode: every frame goes to
+    /// `on_raw_frame` as received, without reassembly, automatic pongs or
+    /// close handling. Only allowed while disconnected.
+    #[cfg(feature = "unsafe-protocol")]
+    pub fn set_raw_receive(&mut self, enabled: bool) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.raw_receive = enabled;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Sends `payload` as text and routes the message whose extracted id
+    /// matches `id` to the response callback. A zero timeout never expires.
+    pub fn request(
+        &mut self,
+        id: u64,
+        payload: &str,
+        timeout: Duration,
+    ) -> Result<WsppResult, WsppResult> {
+        let callbacks = self.slots.callbacks();
+        if callbacks.on_response.is_none() || callbacks.extract_response_id.is_none() {
+            return Err(WsppResult::InvalidState);
+        }
+        if !matches!(self.state, WsState::Connected) {
+            return Err(WsppResult::InvalidState);
+        }
+
+        let deadline = (!timeout.is_zero()).then(|| Instant::now() + timeout);
+        if !self.pending.insert(id, deadline) {
+            return Err(WsppResult::InvalidArgument);
+        }
+
+        self.send_message(payload).inspect_err(|_| {
+            self.pending.complete(id);
+        })
+    }
+
+    pub fn shutdown(&mut self) {
+        let _ = self.queue_command(Command::Shutdown);
+        self.cancel_reconnect();
+        self.pending.take_all();
         self.cleanup();
         self.state = WsState::Closed;
     }
 
+    /// Shuts down without blocking and runs `done` on a background thread
+    /// once the worker has exited. An inline connection gets one more slice
+    /// to send its close frame, a pair tells its peer, and `done` runs
+    /// before this returns.
+    pub fn shutdown_then(mut self, done: impl FnOnce() + Send + 'static) {
+        self.shutdown();
+        match self.worker.take() {
+            Some(Runner::Thread(thread)) => {
+                lifecycle::adopt(std::thread::spawn(move || {
+                    let _ = thread.join();
+                    done();
+                }));
+            }
+            Some(Runner::Inline(mut inline)) => {
+                inline.drive();
+                done();
+            }
+            Some(Runner::Pair(link)) => {
+                link.lock().unwrap_or_else(|err| err.into_inner()).pump();
+                done();
+            }
+            None => done(),
+        }
+    }
+
+    /// Waits for the last worker to exit, driving an inline connection on
+    /// this thread meanwhile. Returns `false` if it was still running after
+    /// `timeout` and had to be detached or dropped.
+    pub fn join_worker(&mut self, timeout: Duration) -> bool {
+        match self.worker.take() {
+            Some(worker) => worker.finish(timeout),
+            None => true,
+        }
+    }
+
+    /// Discards messages still queued `ttl` after they were sent instead of
+    /// writing them late. A zero duration keeps messages indefinitely.
+    pub fn set_send_ttl(&mut self, ttl: Duration) {
+        self.send_ttl = (!ttl.is_zero()).then_some(ttl);
+    }
+
+    /// Sends `name: value` with the upgrade request of later connects, after
+    /// any added before. Only allowed while disconnected.
+    pub fn add_header(&mut self, name: &str, value: &str) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if !handshake::is_valid_header(name, value) {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options
+            .headers
+            .push((name.to_owned(), value.to_owned()));
+        Ok(WsppResult::Ok)
+    }
+
+    /// Removes the headers added with `add_header`. Only allowed while
+    /// disconnected.
+    pub fn clear_headers(&mut self) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.headers.clear();
+        Ok(WsppResult::Ok)
+    }
+
+    /// Fails later connections on an incoming message larger than `limit`
+    /// bytes, counting all fragments of a fragmented one. Zero keeps the
+    /// default. Only allowed while disconnected.
+    pub fn set_max_message_size(&mut self, limit: usize) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.max_message_size = (limit > 0).then_some(limit);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Keeps a record of the upgrade request, response and timings of later
+    /// connection attempts. Only allowed while disconnected.
+    pub fn set_handshake_capture(&mut self, enabled: bool) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.capture_handshake = enabled;
+        Ok(WsppResult::Ok)
+    }
+
+    /// JSON record of the last connection attempt, once it finished.
+    pub fn handshake_record(&self) -> Result<String, WsppResult> {
+        self.handshake_record
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+            .ok_or(WsppResult::NotFound)
+    }
+
+    /// Looks up a header of the 101 response of the current connection.
+    pub fn response_header(&self, name: &str) -> Result<&str, WsppResult> {
+        if !matches!(self.state, WsState::Connected | WsState::Closing) {
+            return Err(WsppResult::InvalidState);
+        }
+        let handshake = self.handshake.as_ref().ok_or(WsppResult::InvalidState)?;
+        handshake.header(name).ok_or(WsppResult::NotFound)
+    }
+
+    /// Sequence number of the event being dispatched. Numbering restarts
+    /// at 1 with each connection and rises by one per event.
+    pub fn event_sequence(&self) -> u64 {
+        self.event_seq
+    }
+
+    /// 0-100 score combining round-trip time, unanswered pings, recent
+    /// reconnects and errors. Zero while not connected.
+    pub fn health(&mut self) -> u32 {
+        if !matches!(self.state, WsState::Connected) {
+            return 0;
+        }
+        self.health.score(Instant::now())
+    }
+
+    /// Calls the health callback with the current score every `interval`
+    /// from `poll`. `None` stops the reports.
+    pub fn set_health_interval(&mut self, interval: Option<Duration>) {
+        self.health_report = interval.map(|interval| (interval, Instant::now() + interval));
+    }
+
+    fn report_health(&mut self, now: Instant) {
+        let (Some((interval, due)), Some(cb)) = (self.health_report, self.callbacks.on_health)
+        else {
+            return;
+        };
+        if now >= due {
+            self.health_report = Some((interval, now + interval));
+            (cb.f)(cb.ctx, self.health());
+        }
+    }
+
+    /// Commands handed to the worker that it has not taken yet.
+    pub fn pending_commands(&self) -> u64 {
+        self.stats.commands.depth()
+    }
+
+    /// Changes the handlers; safe while another thread polls, the change
+    /// applying from the next event on.
+    pub fn set_callbacks(&self, f: impl FnOnce(&mut Callbacks)) {
+        self.slots.update(f);
+    }
+
+    /// The handler slots, for changing handlers without borrowing the
+    /// handle.
+    pub fn slots(&self) -> Arc<CallbackSlots> {
+        self.slots.clone()
+    }
+
+    /// Host pointer returned by `wspp_get_user_data`. Callbacks get the
+    /// pointer passed to their own setter instead.
+    pub fn set_userdata(&mut self, userdata: Userdata) {
+        self.userdata = userdata;
+    }
+
+    pub fn userdata(&self) -> Userdata {
+        self.userdata
+    }
+
+    #[cfg(test)]
+    pub fn callbacks(&self) -> Callbacks {
+        self.slots.callbacks()
+    }
+
+    fn refresh_callbacks(&mut self) {
+        (self.callbacks, self.message_handlers) = self.slots.snapshot();
+    }
+
+    /// Restarts the worker up to `attempts` times when an open connection
+    /// is lost to an I/O error or stalled read or write, instead of
+    /// reporting the error. The handle stays connected meanwhile and the
+    /// resumed callback replaces the open one once the connection is back;
+    /// messages queued for the lost connection are gone. Zero turns it
+    /// off. Only allowed while idle.
+    pub fn set_auto_resume(&mut self, attempts: u32) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.resume_limit = attempts;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Connects again on its own after the connection ended without a
+    /// close handshake or with an error, waiting `base_delay` before the
+    /// first attempt and twice as long before each further one, up to
+    /// `max_delay`. Gives up after `max_attempts` in a row, zero meaning
+    /// never; `None` turns it off. Only allowed while idle.
+    pub fn set_auto_reconnect(
+        &mut self,
+        reconnect: Option<Reconnect>,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.reconnect = reconnect;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Keeps up to `limit` messages that were queued but never written when
+    /// a connection was lost, sending them in order once a reconnect
+    /// succeeds; past the limit the oldest are dropped. Zero turns it off.
+    /// Only allowed while idle.
+    pub fn set_replay_unsent(&mut self, limit: usize) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.unsent = (limit > 0).then(|| Arc::new(Unsent::new(limit)));
+        Ok(WsppResult::Ok)
+    }
+
+    /// Randomizes the delays of the reconnecting set up before, which a new
+    /// `set_auto_reconnect` resets. Only allowed while idle.
+    pub fn set_reconnect_jitter(&mut self, jitter: Jitter) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        let Some(reconnect) = self.reconnect.as_mut() else {
+            return Err(WsppResult::InvalidState);
+        };
+        reconnect.set_jitter(jitter);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Collapses identical errors following each other within `window`
+    /// into one summary carrying the repeat count. Zero turns it off.
+    pub fn set_error_dedup_window(&mut self, window: Duration) {
+        self.error_dedup = (!window.is_zero()).then(|| ErrorDedup::new(window));
+    }
+
+    /// Calls the queue pressure callback once either queue holds `threshold`
+    /// items; zero disables it.
+    pub fn set_queue_pressure_threshold(&mut self, threshold: u64) {
+        self.pressure = (threshold > 0).then_some((threshold, false));
+    }
+
+    /// Fires on the way up only; re-arms once both queues are back under
+    /// the threshold.
+    fn report_queue_pressure(&mut self) {
+        let (Some((threshold, active)), Some(cb)) =
+            (self.pressure, self.callbacks.on_queue_pressure)
+        else {
+            return;
+        };
+        let (commands, events) = (self.stats.commands.depth(), self.stats.events.depth());
+        let pressured = commands >= threshold || events >= threshold;
+        self.pressure = Some((threshold, pressured));
+        if pressured && !active {
+            (cb.f)(cb.ctx, commands, events);
+        }
+    }
+
+    /// Category of the most recent error event, kept across reconnects.
+    pub fn last_error_category(&self) -> WsppErrorCategory {
+        self.last_error_category
+    }
+
+    /// Id of the most recent successful connection, or zero if none.
+    pub fn connection_id(&self) -> u64 {
+        self.stats.snapshot().connection_id
+    }
+
+    pub fn stats(&self) -> WsppStats {
+        WsppStats {
+            endpoint: self.endpoints.current_index() as u64,
+            ..self.stats.snapshot()
+        }
+    }
+
+    /// Adds a URI for reconnect attempts to try after the ones before it;
+    /// see `wspp_add_fallback_uri`. Only allowed while idle.
+    pub fn add_fallback_uri(&mut self, uri: &str) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if crate::uri::validate(uri).is_err() {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.endpoints.add(uri);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Failed connections to endpoint `index`, 0 being the URI the handle
+    /// was created with and the fallbacks following in the order added.
+    pub fn endpoint_failures(&self, index: usize) -> Option<u64> {
+        self.endpoints.failures(index)
+    }
+
+    /// Whether the worker is currently blocked writing to the socket.
+    pub fn is_write_stalled(&self) -> bool {
+        self.write_stalled.load(Ordering::Relaxed)
+    }
+
     pub fn get_state(&self) -> WsState {
         self.state
     }
 
+    /// Spills incoming messages above `threshold` bytes to files in `dir`,
+    /// readable only by the current user. A message is still received
+    /// whole in memory and only written out once complete. A zero
+    /// threshold disables spilling. Only allowed while disconnected.
+    pub fn set_spill(&mut self, threshold: usize, dir: PathBuf) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.spill = (threshold > 0).then_some(SpillOptions { threshold, dir });
+        Ok(WsppResult::Ok)
+    }
+
+    /// Fails connects that take longer than `timeout` with a `Timeout`
+    /// error; zero waits as long as the OS does. Only allowed while idle.
+    pub fn set_connect_timeout(&mut self, timeout: Duration) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.connect_timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Fails the connection with a `ReadStall` timeout when bytes of a
+    /// frame stop arriving for `timeout`; zero disables it. Only allowed
+    /// while idle.
+    pub fn set_read_stall_timeout(&mut self, timeout: Duration) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.read_stall_timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Fails the connection with a `Write` timeout when the socket accepts
+    /// nothing for `timeout`; zero disables it. Only allowed while idle.
+    pub fn set_write_timeout(&mut self, timeout: Duration) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.write_timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Sets TCP_USER_TIMEOUT on later connections; zero keeps the system
+    /// default. Only allowed while idle.
+    pub fn set_tcp_user_timeout(&mut self, timeout: Duration) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.socket.user_timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Enables TCP keepalive on later connections, or disables it with
+    /// `None`. Only allowed while idle.
+    pub fn set_tcp_keepalive(
+        &mut self,
+        keepalive: Option<Keepalive>,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.socket.keepalive = keepalive;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Restricts later connects to addresses of one family. Only allowed
+    /// while idle.
+    pub fn set_ip_family(&mut self, family: IpFamily) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.ip_family = family;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Gives up on a resolved address after `timeout` and tries the next;
+    /// zero waits as long as the OS does. Only allowed while idle.
+    pub fn set_address_timeout(&mut self, timeout: Duration) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.address_timeout = (!timeout.is_zero()).then_some(timeout);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Chooses how server certificates are checked for revocation on later
+    /// `wss` connects. Only allowed while idle.
+    pub fn set_revocation_mode(&mut self, mode: RevocationMode) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.tls.revocation = mode;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Relaxes certificate verification for later `wss` connects. Only
+    /// allowed while idle, and on SChannel only for name mismatches.
+    pub fn set_verify_policy(&mut self, policy: VerifyPolicy) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        #[cfg(all(windows, feature = "schannel"))]
+        if !schannel::supports(policy) {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.tls.policy = policy;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Offers permessage-deflate on later connects, or stops offering it.
+    /// Only allowed while idle.
+    pub fn set_compression(&mut self, enabled: bool) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.compression = enabled;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Turns matching the certificate against the host name off or back
+    /// on for later `wss` connects. Only allowed while idle.
+    pub fn set_verify_hostname(&mut self, enabled: bool) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.tls.skip_hostname_check = !enabled;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Turns verifying the server certificate of later `wss` connects off
+    /// or back on. Only allowed while idle.
+    pub fn set_tls_verify(&mut self, enabled: bool) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if !enabled {
+            logging::emit(
+                2,
+                &format!(
+                    "certificate verification disabled for {}",
+                    self.endpoints.current()
+                ),
+            );
+        }
+        self.options.tls.skip_verify = !enabled;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Verifies later `wss` connects through the macOS Security framework
+    /// rather than the bundled roots. Only allowed while idle, and only on
+    /// Apple platforms.
+    pub fn set_system_trust(&mut self, enabled: bool) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if enabled && !cfg!(target_vendor = "apple") {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.tls.system_trust = enabled;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Adds a DER-encoded CRL to consult while revocation checks are on;
+    /// an empty `der` drops all added CRLs. Only allowed while idle, and
+    /// not on SChannel.
+    pub fn add_crl(&mut self, der: &[u8]) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if der.is_empty() {
+            self.options.tls.crls.clear();
+            return Ok(WsppResult::Ok);
+        }
+        if cfg!(all(windows, feature = "schannel")) || !tls::is_valid_crl(der) {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.tls.crls.push(der.to_vec().into());
+        Ok(WsppResult::Ok)
+    }
+
+    /// Trusts the certificates of the PEM bundle `pem` on later `wss`
+    /// connects, next to the bundled roots, replacing any set before. An
+    /// empty `pem` trusts the bundled roots only again. Only allowed while
+    /// idle.
+    pub fn set_ca_pem(&mut self, pem: &[u8]) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if pem.is_empty() {
+            self.options.tls.ca_certs.clear();
+            return Ok(WsppResult::Ok);
+        }
+        self.options.tls.ca_certs = tls::parse_ca_pem(pem).ok_or(WsppResult::InvalidArgument)?;
+        Ok(WsppResult::Ok)
+    }
+
+    /// `set_ca_pem` with the bundle read from `path`.
+    pub fn set_ca_file(&mut self, path: &Path) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        let pem = std::fs::read(path).map_err(|_| WsppResult::IoError)?;
+        if pem.is_empty() {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.set_ca_pem(&pem)
+    }
+
+    /// Presents the chain in `cert_pem` with the key in `key_pem` to servers
+    /// asking for a client certificate on later `wss` connects. An encrypted
+    /// PKCS#8 key stays encrypted until each connect unlocks it with the
+    /// key passphrase. Both empty removes it. Only allowed while idle.
+    pub fn set_client_cert(
+        &mut self,
+        cert_pem: &[u8],
+        key_pem: &[u8],
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        let tls = &mut self.options.tls;
+        if cert_pem.is_empty() && key_pem.is_empty() {
+            tls.clear_client_cert();
+            return Ok(WsppResult::Ok);
+        }
+        let not_usable = |err: rustls::Error| {
+            logging::emit(2, &format!("client certificate not usable: {err}"));
+            WsppResult::InvalidArgument
+        };
+        if LockedClientCert::is_encrypted(key_pem) {
+            let locked = LockedClientCert::from_pem(cert_pem, key_pem).map_err(not_usable)?;
+            tls.clear_client_cert();
+            tls.locked_client_cert = Some(locked);
+        } else {
+            let cert = ClientCert::from_pem(cert_pem, key_pem).map_err(not_usable)?;
+            tls.clear_client_cert();
+            tls.client_cert = Some(cert);
+        }
+        Ok(WsppResult::Ok)
+    }
+
+    /// Presents the first key and chain of the PKCS#12 bundle `der`, opened
+    /// with `passphrase`, like `set_client_cert`. Only allowed while idle.
+    pub fn set_client_identity_p12(
+        &mut self,
+        der: &[u8],
+        passphrase: &str,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        let cert = ClientCert::from_pkcs12(der, passphrase).map_err(|err| {
+            logging::emit(2, &format!("client identity not usable: {err}"));
+            WsppResult::InvalidArgument
+        })?;
+        self.options.tls.clear_client_cert();
+        self.options.tls.client_cert = Some(cert);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Presents the certificate of `identity`, signing with its key on the
+    /// token, instead of a PEM or PKCS#12 one. The token is opened on each
+    /// connect. Only allowed while idle, and not on SChannel.
+    #[cfg(feature = "pkcs11")]
+    pub fn set_client_cert_pkcs11(
+        &mut self,
+        identity: Pkcs11Identity,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if cfg!(all(windows, feature = "schannel")) {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.tls.clear_client_cert();
+        self.options.tls.pkcs11 = Some(identity);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Where the passphrase of an encrypted client key comes from on later
+    /// connects. Only allowed while idle.
+    pub fn set_client_key_passphrase(
+        &mut self,
+        passphrase: Passphrase,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.tls.key_passphrase = passphrase;
+        Ok(WsppResult::Ok)
+    }
+
+    /// `set_client_cert` with the PEM read from `cert_path` and `key_path`.
+    pub fn set_client_cert_file(
+        &mut self,
+        cert_path: &Path,
+        key_path: &Path,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        let cert_pem = std::fs::read(cert_path).map_err(|_| WsppResult::IoError)?;
+        let key_pem = std::fs::read(key_path).map_err(|_| WsppResult::IoError)?;
+        if cert_pem.is_empty() || key_pem.is_empty() {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.set_client_cert(&cert_pem, &key_pem)
+    }
+
+    /// Pings every `interval` on later connects and fails the connection
+    /// when a pong takes longer than `timeout`. A zero `interval` turns it
+    /// off. Only allowed while idle.
+    pub fn set_keepalive(
+        &mut self,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if !interval.is_zero() && timeout.is_zero() {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.heartbeat.interval = (!interval.is_zero()).then_some(interval);
+        self.options.heartbeat.timeout = timeout;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Chooses what keepalive pings carry; fixed payloads are limited to
+    /// 125 bytes. Only allowed while idle.
+    pub fn set_keepalive_payload(
+        &mut self,
+        payload: PingPayload,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if matches!(&payload, PingPayload::Fixed(data) if data.len() > heartbeat::MAX_PING_PAYLOAD)
+        {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.heartbeat.payload = payload;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Reports silent periods of `silence` to the watchdog callback. A zero
+    /// duration disables it. Only allowed while disconnected.
+    pub fn set_watchdog(&mut self, silence: Duration) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.watchdog = (!silence.is_zero()).then_some(silence);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Packs small incoming payloads into shared blocks of `block_size`
+    /// bytes. Zero disables the arena. Only allowed while disconnected.
+    pub fn set_event_arena(&mut self, block_size: usize) -> Result<WsppResult, WsppResult> {
+        self.set_receive_buffer(block_size, BufferGrowth::Fixed)
+    }
+
+    /// Pre-allocates a reusable receive buffer of `capacity` bytes that
+    /// grows according to `growth`. Zero disables it. Only while idle.
+    pub fn set_receive_buffer(
+        &mut self,
+        capacity: usize,
+        growth: BufferGrowth,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.arena = (capacity > 0).then_some(ArenaOptions {
+            block_size: capacity,
+            growth,
+        });
+        Ok(WsppResult::Ok)
+    }
+
+    /// Fragments outgoing messages into frames of at most `size` bytes.
+    /// Zero restores the defaults. Only allowed while disconnected.
+    pub fn set_write_chunk_size(&mut self, size: usize) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.write_chunk = (size > 0).then_some(size);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Chooses how pongs answering no outstanding ping are handled. Only
+    /// allowed while disconnected.
+    pub fn set_pong_policy(&mut self, policy: PongPolicy) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.pong_policy = policy;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Sends `key` as Sec-WebSocket-Key on later connects instead of a
+    /// random one. `key` must be the base64 encoding of 16 bytes.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_handshake_key(&mut self, key: Option<&str>) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        if key.is_some_and(|key| !handshake::is_valid_key(key)) {
+            return Err(WsppResult::InvalidArgument);
+        }
+        self.options.handshake_key = key.map(str::to_owned);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Records the traffic of later connections to `path`, replacing the
+    /// file on each connect. `None` stops recording.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_session_record(&mut self, path: Option<PathBuf>) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.session_record = path;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Plays the session recorded at `path` back on later connects instead
+    /// of reaching the server. `None` connects normally again.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_session_replay(&mut self, path: Option<&Path>) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.replay = match path {
+            Some(path) => match session::Session::load(path) {
+                Ok(session) => Some(Arc::new(session)),
+                Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                    return Err(WsppResult::InvalidArgument);
+                }
+                Err(_) => return Err(WsppResult::IoError),
+            },
+            None => None,
+        };
+        Ok(WsppResult::Ok)
+    }
+
+    /// Injects `faults` into the byte stream of later connections; `None`
+    /// or faults that do nothing turn injection off. Only allowed while
+    /// disconnected.
+    #[cfg(feature = "fault-injection")]
+    pub fn set_faults(&mut self, faults: Option<Faults>) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.faults = faults.filter(|faults| !faults.is_none());
+        Ok(WsppResult::Ok)
+    }
+
+    /// Masks outgoing frames with keys drawn from a generator seeded with
+    /// `seed` instead of a secure RNG. Replaces any host mask source.
+    #[cfg(feature = "diagnostics")]
+    pub fn set_mask_seed(&mut self, seed: Option<u64>) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.masks = seed.map(MaskSource::Seeded);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Takes frame masking keys from `source`; `None` restores the built-in
+    /// secure RNG. Only allowed while disconnected.
+    pub fn set_mask_source(
+        &mut self,
+        source: Option<HostRandom>,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.masks = source.map(MaskSource::Host);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Caps socket throughput in bytes per second for later connects; zero
+    /// leaves a direction unlimited.
+    pub fn set_bandwidth_limit(&mut self, up: u64, down: u64) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.bandwidth = BandwidthLimit {
+            up: (up > 0).then_some(up),
+            down: (down > 0).then_some(down),
+        };
+        Ok(WsppResult::Ok)
+    }
+
+    /// Scheduling priority for worker threads spawned by later connects.
+    pub fn set_thread_priority(
+        &mut self,
+        priority: ThreadPriority,
+    ) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.priority = priority;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Runs later connections on the thread calling `poll`, for up to
+    /// `slice` per call, instead of on a worker thread. `None` goes back to
+    /// worker threads. Only allowed while disconnected.
+    pub fn set_inline(&mut self, slice: Option<Duration>) -> Result<WsppResult, WsppResult> {
+        self.ensure_idle()?;
+        self.options.inline_slice = slice;
+        Ok(WsppResult::Ok)
+    }
+
+    /// Ping round-trip percentiles in microseconds as `(p50, p95, p99)`.
+    pub fn latency_percentiles(&self) -> Option<(u64, u64, u64)> {
+        Some((
+            self.latency.percentile(50.0)?,
+            self.latency.percentile(95.0)?,
+            self.latency.percentile(99.0)?,
+        ))
+    }
+
+    fn expiry(&self) -> Option<Instant> {
+        self.send_ttl.map(|ttl| Instant::now() + ttl)
+    }
+
+    fn ensure_idle(&self) -> Result<(), WsppResult> {
+        if matches!(self.state, WsState::New | WsState::Closed) {
+            Ok(())
+        } else {
+            Err(WsppResult::InvalidState)
+        }
+    }
+
     fn send_command(&mut self, cmd: Command) -> Result<WsppResult, WsppResult> {
         if !matches!(self.state, WsState::Connected) {
             return Err(WsppResult::InvalidState);
         }
 
-        let sender = self.cmd_tx.as_ref().ok_or(WsppResult::InvalidState)?;
-        sender.send(cmd).map_err(|_| WsppResult::IoError)?;
+        if self.cmd_tx.is_none() {
+            return Err(WsppResult::InvalidState);
+        }
+        let priority = cmd.priority();
+        if let Some(priority) = priority {
+            self.queue
+                .admit(priority)
+                .inspect_err(|_| self.stats.record_loss(Loss::QueueFull))?;
+        }
+        let len = cmd.payload_len();
+        if let Some(account) = self.account.as_ref() {
+            account.charge(len);
+        }
+        self.queue_command(cmd).inspect_err(|_| {
+            if let Some(account) = self.account.as_ref() {
+                account.release(len);
+            }
+            if let Some(priority) = priority {
+                self.queue.cancel(priority);
+            }
+        })?;
         Ok(WsppResult::Ok)
     }
 
+    /// Hands `cmd` to the worker, counting it in the command queue depth.
+    fn queue_command(&self, cmd: Command) -> Result<(), WsppResult> {
+        let sender = self.cmd_tx.as_ref().ok_or(WsppResult::InvalidState)?;
+        self.stats.commands.push();
+        sender.send(cmd).map_err(|_| {
+            self.stats.commands.pop();
+            WsppResult::IoError
+        })
+    }
+
     fn cleanup(&mut self) {
         self.cmd_tx = None;
-        self.event_rx = None;
+        if let Some(event_rx) = self.event_rx.take() {
+            for (_, event) in event_rx.try_iter() {
+                if let Event::MessageFile { path, .. } = event {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+        self.stats.commands.reset();
+        self.stats.events.reset();
+        self.account = None;
+    }
+
+    fn expire_requests(&mut self, now: Instant) -> u64 {
+        let expired = self.pending.take_expired(now);
+        self.fail_requests(expired, WsppResult::Timeout)
+    }
+
+    fn fail_requests(&mut self, ids: Vec<u64>, result: WsppResult) -> u64 {
+        let count = ids.len() as u64;
+        if let Some(cb) = self.callbacks.on_response {
+            for id in ids {
+                (cb.f)(cb.ctx, id, std::ptr::null(), 0, result);
+            }
+        }
+        count
+    }
+
+    fn filter(&self, data: &[u8], opcode: WsppOpcode) -> FilterAction {
+        match self.callbacks.on_filter {
+            Some(filter) => FilterAction::from_ffi((filter.f)(
+                filter.ctx,
+                data.as_ptr() as *const c_char,
+                data.len() as u64,
+                opcode.to_ffi(),
+            )),
+            None => FilterAction::Deliver,
+        }
+    }
+
+    fn deliver_response(&mut self, data: &[u8], opcode: WsppOpcode) -> bool {
+        let (Some(extract), Some(cb)) = (
+            self.callbacks.extract_response_id,
+            self.callbacks.on_response,
+        ) else {
+            return false;
+        };
+        if self.pending.is_empty() {
+            return false;
+        }
+
+        let mut id = 0_u64;
+        if !(extract.f)(
+            extract.ctx,
+            data.as_ptr() as *const c_char,
+            data.len() as u64,
+            opcode.to_ffi(),
+            &mut id,
+        ) {
+            return false;
+        }
+        if !self.pending.complete(id) {
+            return false;
+        }
+
+        (cb.f)(
+            cb.ctx,
+            id,
+            data.as_ptr() as *const c_char,
+            data.len() as u64,
+            WsppResult::Ok,
+        );
+        true
+    }
+
+    /// Hands a spilled message to the file callback, or reads it back for
+    /// the message handlers when none is set. The file is removed
+    /// once the callback returns.
+    fn deliver_file(&mut self, path: &std::path::Path, len: u64, opcode: WsppOpcode) {
+        if let Some(cb) = self.callbacks.on_message_file {
+            let Ok(c_path) = CString::new(path.to_string_lossy().into_owned()) else {
+                logging::emit(1, "spill path is not representable as a C string");
+                return;
+            };
+            (cb.f)(cb.ctx, c_path.as_ptr(), len, opcode.to_ffi());
+            return;
+        }
+
+        match std::fs::read(path) {
+            Ok(data) => self.deliver_message(&data, opcode),
+            Err(err) => logging::emit(1, &format!("reading spilled message failed: {err}")),
+        }
+    }
+
+    fn deliver_message(&self, data: &[u8], opcode: WsppOpcode) {
+        if let Some(cb) = self.callbacks.on_message {
+            (cb.f)(
+                cb.ctx,
+                data.as_ptr() as *const c_char,
+                data.len() as u64,
+                opcode.to_ffi(),
+            );
+        }
+        self.message_handlers.call(data, opcode.to_ffi());
     }
 
     fn dispatch(&mut self, event: Event) {
+        self.refresh_callbacks();
+        if let Some(account) = self.account.as_ref() {
+            account.release(event.payload_len());
+        }
+
         match event {
-            Event::Open => {
+            Event::Open {
+                connection_id,
+                handshake,
+            } => {
                 self.state = WsState::Connected;
+                self.stats.set_connection_id(connection_id);
+                let now = Instant::now();
+                self.health.record_open(now);
+                let outage = self.reconnect.as_ref().and_then(|r| r.outage(now));
+                self.cancel_reconnect();
+                if self.resume_attempts > 0 {
+                    self.resume_attempts = 0;
+                    self.handshake = Some(handshake);
+                    if let Some(batch) = self.batch.as_mut() {
+                        batch.push(WsppEventKind::Resumed, self.event_seq);
+                    } else if let Some(cb) = self.callbacks.on_resumed {
+                        (cb.f)(cb.ctx);
+                    }
+                    return;
+                }
+                if let Some((attempt, downtime)) = outage {
+                    self.handshake = Some(handshake);
+                    let downtime_ms = downtime.as_millis().try_into().unwrap_or(u64::MAX);
+                    if let Some(batch) = self.batch.as_mut() {
+                        batch.push_reconnected(self.event_seq, attempt, downtime_ms);
+                    } else if let Some(cb) = self.callbacks.on_reconnected {
+                        (cb.f)(cb.ctx, attempt, downtime_ms);
+                    }
+                    self.replay_unsent();
+                    return;
+                }
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push(WsppEventKind::Open, self.event_seq);
+                    self.handshake = Some(handshake);
+                    return;
+                }
                 if let Some(cb) = self.callbacks.on_open {
-                    cb();
+                    (cb.f)(cb.ctx);
                 }
+                if let Some(cb) = self.callbacks.on_open_ext {
+                    let strings = HandshakeStrings::new(&handshake);
+                    let info = strings.info();
+                    (cb.f)(cb.ctx, &info);
+                }
+                self.handshake = Some(handshake);
             }
             Event::Message { data, opcode } => {
-                if let Some(cb) = self.callbacks.on_message {
-                    cb(data.as_ptr() as *const i8, data.len() as u64, opcode);
+                if self.filter(&data, opcode) == FilterAction::Drop {
+                    return;
+                }
+                if self.deliver_response(&data, opcode) {
+                    return;
+                }
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push_data(
+                        WsppEventKind::Message,
+                        self.event_seq,
+                        Some(data),
+                        opcode.to_ffi(),
+                    );
+                } else {
+                    self.deliver_message(&data, opcode);
+                }
+            }
+            Event::MessageFile { path, len, opcode } => {
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push_file(self.event_seq, path, len, opcode.to_ffi());
+                    return;
+                }
+                self.deliver_file(&path, len, opcode);
+                let _ = std::fs::remove_file(&path);
+            }
+            Event::Pong { data, rtt } => {
+                if let Some(rtt) = rtt {
+                    self.latency.record(rtt);
+                    self.health.record_rtt(rtt);
+                }
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push_data(WsppEventKind::Pong, self.event_seq, Some(data), 0);
+                } else if let Some(cb) = self.callbacks.on_pong {
+                    (cb.f)(cb.ctx, data.as_ptr() as *const i8, data.len() as u64);
                 }
             }
-            Event::Pong(data) => {
-                if let Some(cb) = self.callbacks.on_pong {
-                    cb(data.as_ptr() as *const i8, data.len() as u64);
+            Event::UnsolicitedPong(data) => {
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push_data(
+                        WsppEventKind::UnsolicitedPong,
+                        self.event_seq,
+                        Some(data),
+                        0,
+                    );
+                } else if let Some(cb) = self.callbacks.on_unsolicited_pong {
+                    (cb.f)(cb.ctx, data.as_ptr() as *const i8, data.len() as u64);
                 }
             }
-            Event::Close => {
+            Event::Backpressure(active) => {
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push_data(
+                        WsppEventKind::Backpressure,
+                        self.event_seq,
+                        None,
+                        i32::from(active),
+                    );
+                } else if let Some(cb) = self.callbacks.on_backpressure {
+                    (cb.f)(cb.ctx, active);
+                }
+            }
+            #[cfg(feature = "unsafe-protocol")]
+            Event::RawFrame(frame) => {
+                if let Some(cb) = self.callbacks.on_raw_frame {
+                    (cb.f)(
+                        cb.ctx,
+                        frame.fin,
+                        i32::from(frame.rsv),
+                        i32::from(frame.opcode),
+                        frame.payload.as_ptr() as *const c_char,
+                        frame.payload.len() as u64,
+                    );
+                }
+            }
+            Event::Watchdog => {
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push(WsppEventKind::Watchdog, self.event_seq);
+                } else if let Some(cb) = self.callbacks.on_watchdog {
+                    (cb.f)(cb.ctx);
+                }
+            }
+            Event::Close { code, reason } => {
+                if code == WsppCloseCode::Abnormal.code() {
+                    self.endpoints.record_failure();
+                }
+                let lost = matches!(self.state, WsState::Connected)
+                    && code == WsppCloseCode::Abnormal.code();
+                if self.try_reconnect(lost) {
+                    return;
+                }
                 self.state = WsState::Closed;
                 self.cleanup();
-                if let Some(cb) = self.callbacks.on_close {
-                    cb();
+                let pending = self.pending.take_all();
+                self.fail_requests(pending, WsppResult::IoError);
+                if let Some(batch) = self.batch.as_mut() {
+                    batch.push_data(
+                        WsppEventKind::Close,
+                        self.event_seq,
+                        Some(reason),
+                        i32::from(code),
+                    );
+                } else {
+                    if let Some(cb) = self.callbacks.on_close {
+                        (cb.f)(cb.ctx);
+                    }
+                    if let Some(cb) = self.callbacks.on_close_ext {
+                        (cb.f)(
+                            cb.ctx,
+                            code,
+                            reason.as_ptr() as *const c_char,
+                            reason.len() as u64,
+                        );
+                    }
                 }
+                self.report_gave_up();
             }
-            Event::Error(msg) => {
+            Event::Error(err) => {
+                self.health.record_error(Instant::now());
+                self.endpoints.record_failure();
+                self.last_error_category = err.category;
+                if self.try_resume(&err) {
+                    return;
+                }
+                let lost = matches!(self.state, WsState::Connected);
+                if self.try_reconnect(lost) {
+                    return;
+                }
                 self.state = WsState::Closed;
                 self.cleanup();
+                let pending = self.pending.take_all();
+                self.fail_requests(pending, WsppResult::IoError);
 
-                if let Some(cb) = self.callbacks.on_error {
-                    let c_msg =
-                        CString::new(msg).unwrap_or_else(|_| CString::new("Unknown").unwrap());
-                    cb(c_msg.as_ptr());
+                let errors = match self.error_dedup.as_mut() {
+                    Some(dedup) => dedup.admit(err, Instant::now()),
+                    None => vec![err],
+                };
+                for err in &errors {
+                    self.report_error(err);
                 }
+                self.report_gave_up();
             }
         }
     }
+
+    fn report_error(&mut self, err: &WorkerError) {
+        if let Some(batch) = self.batch.as_mut() {
+            batch.push_error(self.event_seq, err);
+            return;
+        }
+        let c_msg = err.c_message();
+        if let Some(cb) = self.callbacks.on_error {
+            (cb.f)(cb.ctx, c_msg.as_ptr());
+        }
+        if let Some(cb) = self.callbacks.on_error_ext {
+            (cb.f)(cb.ctx, &err.info(&c_msg));
+        }
+    }
+}
+
+impl Drop for WsppWsImpl {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            worker.detach();
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::ffi::{c_char, c_void};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use std::sync::mpsc;
 
-    use super::{Event, WsState, WsppWsImpl};
+    use super::error::WorkerError;
+    use super::queue::SendQueue;
+    use super::{
+        Event, Passphrase, Payload, QueuePolicy, SendFlags, WsState, WsppErrorCategory, WsppEvent,
+        WsppWsImpl,
+    };
+    use crate::callback::WithCtx;
+    use crate::opcode::WsppOpcode;
     use crate::result::WsppResult;
+    use crate::test_support::no_ctx;
 
     #[test]
     fn close_maps_disconnected_sender_to_io_error() {
@@ -198,6 +1822,81 @@ mod tests {
         assert_eq!(res, Err(WsppResult::IoError));
     }
 
+    #[test]
+    fn close_rejects_invalid_codes() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        ws.state = WsState::Connected;
+        let (tx, rx) = mpsc::channel();
+        ws.cmd_tx = Some(tx);
+
+        assert_eq!(ws.close(5, "bad"), Err(WsppResult::InvalidArgument));
+        assert_eq!(ws.close(1006, "bad"), Err(WsppResult::InvalidArgument));
+        assert!(rx.try_recv().is_err());
+        assert!(matches!(ws.state, WsState::Connected));
+    }
+
+    #[test]
+    #[cfg(not(target_vendor = "apple"))]
+    fn system_trust_needs_an_apple_platform() {
+        let mut ws = WsppWsImpl::new("wss://127.0.0.1:18765/ws", true);
+        assert_eq!(ws.set_system_trust(true), Err(WsppResult::InvalidArgument));
+        assert_eq!(ws.set_system_trust(false), Ok(WsppResult::Ok));
+    }
+
+    #[test]
+    fn ca_bundles_are_checked_when_set() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/client/testdata/tls");
+        let mut ws = WsppWsImpl::new("wss://127.0.0.1:18765/ws", true);
+        assert_eq!(ws.set_ca_file(&dir.join("ca.pem")), Ok(WsppResult::Ok));
+        assert_eq!(ws.options.tls.ca_certs.len(), 1);
+        assert_eq!(
+            ws.set_ca_file(&dir.join("missing.pem")),
+            Err(WsppResult::IoError)
+        );
+        assert_eq!(
+            ws.set_ca_file(&dir.join("ca.der")),
+            Err(WsppResult::InvalidArgument)
+        );
+        assert_eq!(ws.options.tls.ca_certs.len(), 1);
+        assert_eq!(ws.set_ca_pem(b""), Ok(WsppResult::Ok));
+        assert!(ws.options.tls.ca_certs.is_empty());
+    }
+
+    #[test]
+    fn encrypted_client_keys_need_a_passphrase_to_connect() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/client/testdata/tls");
+        let mut ws = WsppWsImpl::new("wss://127.0.0.1:18765/ws", true);
+        assert_eq!(
+            ws.set_client_cert_file(&dir.join("client.pem"), &dir.join("client.enc.key")),
+            Ok(WsppResult::Ok)
+        );
+        assert!(ws.options.tls.locked_client_cert.is_some());
+        assert_eq!(ws.connect(), Err(WsppResult::InvalidArgument));
+        assert!(matches!(ws.state, WsState::Closed));
+
+        let wrong = Passphrase::Fixed(b"hunter3".to_vec().into());
+        assert_eq!(ws.set_client_key_passphrase(wrong), Ok(WsppResult::Ok));
+        assert_eq!(ws.connect(), Err(WsppResult::InvalidArgument));
+
+        let right = Passphrase::Fixed(b"hunter2".to_vec().into());
+        assert_eq!(ws.set_client_key_passphrase(right), Ok(WsppResult::Ok));
+        assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+        // Only the worker's copy of the options is unlocked.
+        assert!(ws.options.tls.client_cert.is_none());
+        ws.shutdown();
+    }
+
+    #[test]
+    fn compression_changes_only_while_idle() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        assert_eq!(ws.set_compression(false), Ok(WsppResult::Ok));
+        assert!(!ws.options.compression);
+
+        ws.state = WsState::Connected;
+        assert_eq!(ws.set_compression(true), Err(WsppResult::InvalidState));
+        assert!(!ws.options.compression);
+    }
+
     #[test]
     fn send_maps_disconnected_sender_to_io_error() {
         let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
@@ -208,6 +1907,69 @@ mod tests {
 
         let res = ws.send_message("hello");
         assert_eq!(res, Err(WsppResult::IoError));
+        assert_eq!(ws.stats().queued_commands, 0);
+    }
+
+    #[test]
+    fn fire_and_forget_sends_drop_on_a_full_queue() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        ws.state = WsState::Connected;
+        let (tx, _rx) = mpsc::channel();
+        ws.cmd_tx = Some(tx);
+        ws.queue = Arc::new(SendQueue::new(1, QueuePolicy::RejectNew));
+
+        let flags = SendFlags {
+            fire_and_forget: true,
+            ..SendFlags::default()
+        };
+        assert_eq!(ws.send_binary(vec![1]), Ok(WsppResult::Ok));
+        assert_eq!(ws.send_binary(vec![2]), Err(WsppResult::QueueFull));
+        assert_eq!(
+            ws.send_flagged(false, Payload::from("x"), flags),
+            Ok(WsppResult::Ok)
+        );
+        assert_eq!(ws.pending_commands(), 1);
+    }
+
+    #[test]
+    fn queue_pressure_fires_once_per_excursion() {
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn on_pressure(_: *mut c_void, commands: u64, _events: u64) {
+            CALLS.fetch_add(commands, Ordering::Relaxed);
+        }
+
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        ws.state = WsState::Connected;
+        let (tx, _rx) = mpsc::channel();
+        ws.cmd_tx = Some(tx);
+        ws.set_queue_pressure_threshold(2);
+        ws.set_callbacks(|cb| cb.on_queue_pressure = Some(no_ctx(on_pressure)));
+
+        assert_eq!(ws.send_message("a"), Ok(WsppResult::Ok));
+        ws.poll();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 0);
+        assert_eq!(ws.send_message("b"), Ok(WsppResult::Ok));
+        ws.poll();
+        ws.poll();
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+
+        let stats = ws.stats();
+        assert_eq!((stats.queued_commands, stats.command_high_water), (2, 2));
+    }
+
+    #[test]
+    fn pending_commands_counts_until_the_worker_takes_them() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        ws.state = WsState::Connected;
+        let (tx, _rx) = mpsc::channel();
+        ws.cmd_tx = Some(tx);
+
+        assert_eq!(ws.send_message("a"), Ok(WsppResult::Ok));
+        assert_eq!(ws.ping("p"), Ok(WsppResult::Ok));
+        assert_eq!(ws.pending_commands(), 2);
+
+        ws.shutdown();
+        assert_eq!(ws.pending_commands(), 0);
     }
 
     #[test]
@@ -219,10 +1981,181 @@ mod tests {
         ws.cmd_tx = Some(cmd_tx);
         ws.event_rx = Some(event_rx);
 
-        ws.dispatch(Event::Error("x".to_string()));
+        ws.dispatch(Event::Error(WorkerError::new(WsppErrorCategory::Io, "x")));
 
         assert!(matches!(ws.state, WsState::Closed));
+        assert_eq!(ws.last_error_category(), WsppErrorCategory::Io);
         assert!(ws.cmd_tx.is_none());
         assert!(ws.event_rx.is_none());
     }
+
+    #[test]
+    fn paired_handles_talk_without_sockets() {
+        static RECEIVED: AtomicU64 = AtomicU64::new(0);
+        static CLOSED: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn on_message(_: *mut c_void, _data: *const c_char, len: u64, _op_code: i32) {
+            RECEIVED.fetch_add(len, Ordering::Relaxed);
+        }
+        extern "C" fn on_close(_: *mut c_void) {
+            CLOSED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let (mut a, mut b) = WsppWsImpl::new_pair("ws://wspp-pair.invalid/");
+        for ws in [&mut a, &mut b] {
+            ws.set_callbacks(|cb| {
+                cb.on_message = Some(no_ctx(on_message));
+                cb.on_close = Some(no_ctx(on_close));
+            });
+        }
+        assert_eq!((a.poll(), b.poll()), (1, 1));
+        assert!(matches!(a.state, WsState::Connected));
+
+        assert_eq!(a.send_message("hello"), Ok(WsppResult::Ok));
+        assert_eq!(b.poll(), 1);
+        assert_eq!(RECEIVED.load(Ordering::Relaxed), 5);
+
+        assert_eq!(b.close(1000, "done"), Ok(WsppResult::Ok));
+        a.poll();
+        b.poll();
+        assert_eq!(CLOSED.load(Ordering::Relaxed), 2);
+        assert!(matches!(a.state, WsState::Closed));
+        assert!(matches!(b.state, WsState::Closed));
+    }
+
+    #[test]
+    fn added_message_handlers_see_every_message() {
+        static SEEN: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn count(_: *mut c_void, _data: *const c_char, _len: u64, _op_code: i32) {
+            SEEN.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let (mut a, mut b) = WsppWsImpl::new_pair("ws://wspp-pair.invalid/");
+        b.set_callbacks(|cb| cb.on_message = Some(no_ctx(count)));
+        let id = b.slots().add_message_handler(no_ctx(count));
+        b.slots().add_message_handler(no_ctx(count));
+        a.poll();
+        b.poll();
+
+        assert_eq!(a.send_message("one"), Ok(WsppResult::Ok));
+        b.poll();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 3);
+
+        assert!(b.slots().remove_message_handler(id));
+        assert_eq!(a.send_message("two"), Ok(WsppResult::Ok));
+        b.poll();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn handlers_get_their_own_user_data() {
+        extern "C" fn on_message(ctx: *mut c_void, _data: *const c_char, len: u64, _op: i32) {
+            unsafe { *ctx.cast::<u64>() += len };
+        }
+        extern "C" fn on_close(ctx: *mut c_void) {
+            unsafe { *ctx.cast::<u64>() += 1 };
+        }
+
+        let mut received = 0_u64;
+        let mut closed = 0_u64;
+        let (mut a, mut b) = WsppWsImpl::new_pair("ws://wspp-pair.invalid/");
+        b.set_callbacks(|cb| {
+            cb.on_message = Some(WithCtx {
+                f: on_message,
+                ctx: (&raw mut received).cast(),
+            });
+            cb.on_close = Some(WithCtx {
+                f: on_close,
+                ctx: (&raw mut closed).cast(),
+            });
+        });
+        a.poll();
+        b.poll();
+
+        assert_eq!(a.send_message("hello"), Ok(WsppResult::Ok));
+        assert_eq!(a.close(1000, ""), Ok(WsppResult::Ok));
+        a.poll();
+        b.poll();
+        assert_eq!((received, closed), (5, 1));
+    }
+
+    #[test]
+    fn cleared_handlers_are_not_called_again() {
+        static SEEN: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn count(_: *mut c_void, _data: *const c_char, _len: u64, _op_code: i32) {
+            SEEN.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let (mut a, mut b) = WsppWsImpl::new_pair("ws://wspp-pair.invalid/");
+        b.set_callbacks(|cb| cb.on_message = Some(no_ctx(count)));
+        b.slots().add_message_handler(no_ctx(count));
+        a.poll();
+        b.poll();
+        assert_eq!(a.send_message("one"), Ok(WsppResult::Ok));
+        b.poll();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 2);
+
+        b.slots().clear();
+        assert_eq!(a.send_message("two"), Ok(WsppResult::Ok));
+        assert_eq!(b.poll(), 1);
+        assert_eq!(SEEN.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn one_handler_serves_handles_by_user_data() {
+        static SEEN: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn on_message(id: *mut c_void, _data: *const c_char, _len: u64, _op: i32) {
+            SEEN.store(unsafe { *id.cast::<u64>() }, Ordering::Relaxed);
+        }
+
+        let (mut a, mut b) = WsppWsImpl::new_pair("ws://wspp-pair.invalid/");
+        let (mut one, mut two) = (1_u64, 2_u64);
+        for (ws, id) in [(&mut a, &raw mut one), (&mut b, &raw mut two)] {
+            ws.set_callbacks(|cb| {
+                cb.on_message = Some(WithCtx {
+                    f: on_message,
+                    ctx: id.cast(),
+                })
+            });
+            ws.poll();
+        }
+
+        assert_eq!(a.send_message("to b"), Ok(WsppResult::Ok));
+        b.poll();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 2);
+        assert_eq!(b.send_message("to a"), Ok(WsppResult::Ok));
+        a.poll();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn poll_hands_events_over_in_one_batch() {
+        static CALLS: AtomicU64 = AtomicU64::new(0);
+        static EVENTS: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn on_events(_: *mut c_void, _events: *const WsppEvent, count: u64) {
+            CALLS.fetch_add(1, Ordering::Relaxed);
+            EVENTS.fetch_add(count, Ordering::Relaxed);
+        }
+
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        ws.state = WsState::Connected;
+        let (event_tx, event_rx) = mpsc::channel();
+        ws.event_rx = Some(event_rx);
+        ws.set_callbacks(|cb| cb.on_events = Some(no_ctx(on_events)));
+        let message = Event::Message {
+            data: Payload::from("hi"),
+            opcode: WsppOpcode::Text,
+        };
+        for (seq, event) in [
+            (1, message),
+            (2, Event::Watchdog),
+            (3, Event::Backpressure(true)),
+        ] {
+            event_tx.send((seq, event)).unwrap();
+        }
+
+        assert_eq!(ws.poll(), 3);
+        assert_eq!(ws.poll(), 0);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+        assert_eq!(EVENTS.load(Ordering::Relaxed), 3);
+    }
 }
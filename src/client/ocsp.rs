@@ -0,0 +1,450 @@
+use ring::digest;
+use rustls::RootCertStore;
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, UnixTime};
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OID: u8 = 0x06;
+const ENUMERATED: u8 = 0x0A;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const EXPLICIT_0: u8 = 0xA0;
+const EXPLICIT_3: u8 = 0xA3;
+
+/// id-pkix-ocsp-basic, 1.3.6.1.5.5.7.48.1.1
+const OCSP_BASIC: &[u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+/// id-sha1, 1.3.14.3.2.26
+const SHA1: &[u8] = &[0x2B, 0x0E, 0x03, 0x02, 0x1A];
+/// id-sha256, 2.16.840.1.101.3.4.2.1
+const SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// id-ce-extKeyUsage, 2.5.29.37
+const EXT_KEY_USAGE: &[u8] = &[0x55, 0x1D, 0x25];
+/// id-kp-OCSPSigning, 1.3.6.1.5.5.7.3.9
+const OCSP_SIGNING: &[u8] = &[0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+/// Tolerated clock difference for a response's thisUpdate.
+const CLOCK_SKEW_SECS: u64 = 5 * 60;
+
+/// Revocation status a responder gave for a certificate.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+type Result<T> = std::result::Result<T, &'static str>;
+
+/// Walks the DER elements of one constructed value.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Takes the next element as its tag, contents and whole encoding.
+    fn element(&mut self) -> Result<(u8, &'a [u8], &'a [u8])> {
+        let [tag, first, rest @ ..] = self.data else {
+            return Err("truncated DER");
+        };
+        let (len, header) = if first & 0x80 == 0 {
+            (usize::from(*first), 2)
+        } else {
+            let count = usize::from(first & 0x7F);
+            if count == 0 || count > 4 || rest.len() < count {
+                return Err("bad DER length");
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0, |len, byte| len << 8 | usize::from(*byte));
+            (len, 2 + count)
+        };
+        let end = header
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or("truncated DER")?;
+        let (whole, rest) = self.data.split_at(end);
+        self.data = rest;
+        Ok((*tag, &whole[header..], whole))
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        match self.element()? {
+            (found, contents, _) if found == tag => Ok(contents),
+            _ => Err("unexpected DER element"),
+        }
+    }
+
+    /// Takes the next element only if it has `tag`.
+    fn optional(&mut self, tag: u8) -> Result<Option<&'a [u8]>> {
+        if self.data.first() == Some(&tag) {
+            self.expect(tag).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn skip(&mut self) -> Result<()> {
+        self.element().map(drop)
+    }
+
+    fn bits(&mut self) -> Result<&'a [u8]> {
+        match self.expect(BIT_STRING)? {
+            [0, bits @ ..] => Ok(bits),
+            _ => Err("unsupported BIT STRING padding"),
+        }
+    }
+
+    /// Reads a UTCTime or GeneralizedTime as seconds since the epoch.
+    fn time(&mut self) -> Result<u64> {
+        let (year, rest) = match self.element()? {
+            (UTC_TIME, [y1, y2, rest @ ..], _) => {
+                let year = number(&[*y1, *y2])?;
+                (if year < 50 { 2000 + year } else { 1900 + year }, rest)
+            }
+            (GENERALIZED_TIME, [y1, y2, y3, y4, rest @ ..], _) => {
+                (number(&[*y1, *y2, *y3, *y4])?, rest)
+            }
+            _ => return Err("bad time"),
+        };
+        let [m1, m2, d1, d2, h1, h2, n1, n2, s1, s2, b'Z'] = rest else {
+            return Err("bad time");
+        };
+        let (month, day) = (number(&[*m1, *m2])?, number(&[*d1, *d2])?);
+        if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err("bad time");
+        }
+        let seconds =
+            number(&[*h1, *h2])? * 3600 + number(&[*n1, *n2])? * 60 + number(&[*s1, *s2])?;
+        Ok(days_since_epoch(year, month, day) * 86_400 + seconds)
+    }
+}
+
+fn number(digits: &[u8]) -> Result<u64> {
+    digits.iter().try_fold(0, |value, digit| match digit {
+        b'0'..=b'9' => Ok(value * 10 + u64::from(digit - b'0')),
+        _ => Err("bad time"),
+    })
+}
+
+/// Days from 1970-01-01 to the given civil date.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Public key from a SubjectPublicKeyInfo.
+struct Key<'a> {
+    alg: &'a [u8],
+    bits: &'a [u8],
+}
+
+impl<'a> Key<'a> {
+    fn parse(spki: &'a [u8]) -> Result<Self> {
+        let mut spki = Reader::new(spki);
+        Ok(Self {
+            alg: spki.expect(SEQUENCE)?,
+            bits: spki.bits()?,
+        })
+    }
+
+    fn verifies(
+        &self,
+        algs: &WebPkiSupportedAlgorithms,
+        sig_alg: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> bool {
+        algs.all.iter().any(|alg| {
+            alg.public_key_alg_id().as_ref() == self.alg
+                && alg.signature_alg_id().as_ref() == sig_alg
+                && alg.verify_signature(self.bits, message, signature).is_ok()
+        })
+    }
+}
+
+/// The parts of an X.509 certificate the checks below need.
+struct Cert<'a> {
+    tbs: &'a [u8],
+    serial: &'a [u8],
+    issuer: &'a [u8],
+    /// `issuer` with its tag and length, as CertID hashes it.
+    issuer_der: &'a [u8],
+    subject: &'a [u8],
+    not_before: u64,
+    not_after: u64,
+    spki: &'a [u8],
+    sig_alg: &'a [u8],
+    signature: &'a [u8],
+    ocsp_signing: bool,
+}
+
+impl<'a> Cert<'a> {
+    fn parse(der: &'a [u8]) -> Result<Self> {
+        let mut cert = Reader::new(Reader::new(der).expect(SEQUENCE)?);
+        let (SEQUENCE, fields, tbs) = cert.element()? else {
+            return Err("bad certificate");
+        };
+        let sig_alg = cert.expect(SEQUENCE)?;
+        let signature = cert.bits()?;
+
+        let mut fields = Reader::new(fields);
+        fields.optional(EXPLICIT_0)?;
+        let serial = fields.expect(INTEGER)?;
+        fields.skip()?;
+        let (SEQUENCE, issuer, issuer_der) = fields.element()? else {
+            return Err("bad certificate");
+        };
+        let mut validity = Reader::new(fields.expect(SEQUENCE)?);
+        let (not_before, not_after) = (validity.time()?, validity.time()?);
+        let subject = fields.expect(SEQUENCE)?;
+        let spki = fields.expect(SEQUENCE)?;
+
+        let mut ocsp_signing = false;
+        while !fields.is_empty() {
+            let (tag, contents, _) = fields.element()?;
+            if tag != EXPLICIT_3 {
+                continue;
+            }
+            let mut extensions = Reader::new(Reader::new(contents).expect(SEQUENCE)?);
+            while !extensions.is_empty() {
+                let mut extension = Reader::new(extensions.expect(SEQUENCE)?);
+                let id = extension.expect(OID)?;
+                extension.optional(BOOLEAN)?;
+                let value = extension.expect(OCTET_STRING)?;
+                if id == EXT_KEY_USAGE {
+                    let mut purposes = Reader::new(Reader::new(value).expect(SEQUENCE)?);
+                    while !purposes.is_empty() {
+                        ocsp_signing |= purposes.expect(OID)? == OCSP_SIGNING;
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            tbs,
+            serial,
+            issuer,
+            issuer_der,
+            subject,
+            not_before,
+            not_after,
+            spki,
+            sig_alg,
+            signature,
+            ocsp_signing,
+        })
+    }
+}
+
+/// notBefore and notAfter of a DER certificate, in seconds since the epoch.
+pub fn validity(cert: &[u8]) -> Result<(u64, u64)> {
+    Cert::parse(cert).map(|cert| (cert.not_before, cert.not_after))
+}
+
+/// Status of `leaf` according to a stapled OCSP response.
+///
+/// The response must be signed by the leaf's issuer, found among
+/// `intermediates` or `roots`, or by a responder certificate that issuer
+/// gave the OCSPSigning purpose, and must not be past its nextUpdate.
+/// Responses are matched to the leaf by serial number and the hashes of
+/// its issuer's name and key.
+pub fn leaf_status(
+    response: &[u8],
+    leaf: &[u8],
+    intermediates: &[CertificateDer<'_>],
+    roots: &RootCertStore,
+    now: UnixTime,
+    algs: &WebPkiSupportedAlgorithms,
+) -> Result<CertStatus> {
+    let now = now.as_secs();
+    let leaf = Cert::parse(leaf)?;
+    let issuer_spki = intermediates
+        .iter()
+        .filter_map(|der| Cert::parse(der).ok())
+        .find(|cert| cert.subject == leaf.issuer)
+        .map(|cert| cert.spki)
+        .or_else(|| {
+            roots
+                .roots
+                .iter()
+                .find(|anchor| anchor.subject.as_ref() == leaf.issuer)
+                .map(|anchor| anchor.subject_public_key_info.as_ref())
+        })
+        .ok_or("issuer certificate not available")?;
+    let issuer = Key::parse(issuer_spki)?;
+
+    let mut outer = Reader::new(Reader::new(response).expect(SEQUENCE)?);
+    if outer.expect(ENUMERATED)? != [0] {
+        return Err("responder did not answer successfully");
+    }
+    let mut bytes = Reader::new(Reader::new(outer.expect(EXPLICIT_0)?).expect(SEQUENCE)?);
+    if bytes.expect(OID)? != OCSP_BASIC {
+        return Err("unsupported OCSP response type");
+    }
+    let mut basic = Reader::new(Reader::new(bytes.expect(OCTET_STRING)?).expect(SEQUENCE)?);
+    let (SEQUENCE, data, signed) = basic.element()? else {
+        return Err("bad OCSP response");
+    };
+    let sig_alg = basic.expect(SEQUENCE)?;
+    let signature = basic.bits()?;
+
+    if !issuer.verifies(algs, sig_alg, signed, signature) {
+        let certs = basic
+            .optional(EXPLICIT_0)?
+            .ok_or("response not signed by the issuer")?;
+        let mut certs = Reader::new(Reader::new(certs).expect(SEQUENCE)?);
+        let mut delegated = false;
+        while !certs.is_empty() && !delegated {
+            let (_, _, der) = certs.element()?;
+            let responder = Cert::parse(der)?;
+            delegated = responder.ocsp_signing
+                && responder.issuer == leaf.issuer
+                && (responder.not_before..=responder.not_after).contains(&now)
+                && issuer.verifies(algs, responder.sig_alg, responder.tbs, responder.signature)
+                && Key::parse(responder.spki)?.verifies(algs, sig_alg, signed, signature);
+        }
+        if !delegated {
+            return Err("response not signed by the issuer or a delegated responder");
+        }
+    }
+
+    let mut data = Reader::new(data);
+    data.optional(EXPLICIT_0)?;
+    data.skip()?;
+    data.expect(GENERALIZED_TIME)?;
+    let mut responses = Reader::new(data.expect(SEQUENCE)?);
+    while !responses.is_empty() {
+        let mut single = Reader::new(responses.expect(SEQUENCE)?);
+        if !names_leaf(single.expect(SEQUENCE)?, &leaf, issuer.bits)? {
+            continue;
+        }
+        let status = match single.element()?.0 {
+            0x80 => CertStatus::Good,
+            0xA1 => CertStatus::Revoked,
+            0x82 => CertStatus::Unknown,
+            _ => return Err("bad certificate status"),
+        };
+        if single.time()? > now + CLOCK_SKEW_SECS {
+            return Err("OCSP response is not valid yet");
+        }
+        if let Some(next_update) = single.optional(EXPLICIT_0)?
+            && Reader::new(next_update).time()? < now
+        {
+            return Err("OCSP response has expired");
+        }
+        return Ok(status);
+    }
+    Err("OCSP response does not cover the certificate")
+}
+
+/// Whether a CertID is about `leaf`, as issued under the name and key
+/// of the issuer the response was checked against. CertIDs hashed with
+/// anything but SHA-1 or SHA-256 never match.
+fn names_leaf(id: &[u8], leaf: &Cert<'_>, issuer_key: &[u8]) -> Result<bool> {
+    let mut id = Reader::new(id);
+    let hash = match Reader::new(id.expect(SEQUENCE)?).expect(OID)? {
+        SHA1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        SHA256 => &digest::SHA256,
+        _ => return Ok(false),
+    };
+    let name_hash = id.expect(OCTET_STRING)?;
+    let key_hash = id.expect(OCTET_STRING)?;
+    Ok(id.expect(INTEGER)? == leaf.serial
+        && name_hash == digest::digest(hash, leaf.issuer_der).as_ref()
+        && key_hash == digest::digest(hash, issuer_key).as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rustls::RootCertStore;
+    use rustls::pki_types::{CertificateDer, UnixTime};
+
+    use super::{CertStatus, Reader, leaf_status};
+
+    const CA: &[u8] = include_bytes!("testdata/tls/ca.der");
+    const LEAF: &[u8] = include_bytes!("testdata/tls/leaf.der");
+    const OTHER: &[u8] = include_bytes!("testdata/tls/other.der");
+    const GOOD: &[u8] = include_bytes!("testdata/tls/good.der");
+    const REVOKED: &[u8] = include_bytes!("testdata/tls/revoked.der");
+    const DELEGATED: &[u8] = include_bytes!("testdata/tls/delegated.der");
+    /// Signed by the leaf's issuer, about another issuer's serial 1001.
+    const FOREIGN: &[u8] = include_bytes!("testdata/tls/foreign.der");
+    /// Signed by a certificate of the issuer lacking the OCSPSigning purpose.
+    const UNAUTHORIZED: &[u8] = include_bytes!("testdata/tls/unauthorized.der");
+    /// Signed by an OCSP responder of another issuer.
+    const STRANGER: &[u8] = include_bytes!("testdata/tls/stranger.der");
+
+    fn status_at(response: &[u8], leaf: &[u8], now: UnixTime) -> Result<CertStatus, &'static str> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(CA)).expect("test CA");
+        let algs = rustls::crypto::ring::default_provider().signature_verification_algorithms;
+        leaf_status(response, leaf, &[], &roots, now, &algs)
+    }
+
+    fn status(response: &[u8], leaf: &[u8]) -> Result<CertStatus, &'static str> {
+        status_at(response, leaf, UnixTime::now())
+    }
+
+    #[test]
+    fn reads_signed_statuses() {
+        assert_eq!(status(GOOD, LEAF), Ok(CertStatus::Good));
+        assert_eq!(status(REVOKED, LEAF), Ok(CertStatus::Revoked));
+        assert_eq!(status(DELEGATED, LEAF), Ok(CertStatus::Good));
+    }
+
+    #[test]
+    fn rejects_responses_that_do_not_vouch_for_the_leaf() {
+        assert!(status(GOOD, OTHER).is_err());
+
+        let mut tampered = GOOD.to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(status(&tampered, LEAF).is_err());
+
+        let later = UnixTime::since_unix_epoch(Duration::from_secs(200 * 365 * 86_400));
+        assert_eq!(
+            status_at(GOOD, LEAF, later),
+            Err("OCSP response has expired")
+        );
+    }
+
+    #[test]
+    fn rejects_other_issuers_and_unauthorized_responders() {
+        assert_eq!(
+            status(FOREIGN, LEAF),
+            Err("OCSP response does not cover the certificate")
+        );
+        let unauthorized = Err("response not signed by the issuer or a delegated responder");
+        assert_eq!(status(UNAUTHORIZED, LEAF), unauthorized);
+        assert_eq!(status(STRANGER, LEAF), unauthorized);
+    }
+
+    #[test]
+    fn parses_both_time_forms() {
+        let mut times = Reader::new(b"\x17\x0d700102000001Z\x18\x0f20000301120000Z");
+        assert_eq!(times.time(), Ok(86_401));
+        assert_eq!(times.time(), Ok(951_912_000));
+    }
+}
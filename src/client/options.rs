@@ -0,0 +1,108 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::arena::BufferGrowth;
+use super::family::IpFamily;
+#[cfg(feature = "fault-injection")]
+use super::fault::Faults;
+use super::heartbeat::Heartbeat;
+use super::masking::MaskSource;
+use super::pong::PongPolicy;
+use super::priority::ThreadPriority;
+use super::queue::QueuePolicy;
+#[cfg(feature = "diagnostics")]
+use super::session::Session;
+use super::sockopt::SocketOptions;
+use super::throttle::BandwidthLimit;
+use super::tls::TlsOptions;
+use super::unsent::Unsent;
+
+/// Per-handle settings handed to the worker on every connect.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub compression: bool,
+    /// Limit for TCP, TLS and the upgrade together.
+    pub connect_timeout: Option<Duration>,
+    /// Limit for each resolved address before the next one is tried.
+    pub address_timeout: Option<Duration>,
+    pub spill: Option<SpillOptions>,
+    /// Fails the connection when a partly received frame gets no new bytes
+    /// for this long.
+    pub read_stall_timeout: Option<Duration>,
+    /// Fails the connection when the socket accepts no outgoing bytes for
+    /// this long.
+    pub write_timeout: Option<Duration>,
+    /// Reports, without closing, when nothing was received for this long.
+    pub watchdog: Option<Duration>,
+    pub heartbeat: Heartbeat,
+    pub priority: ThreadPriority,
+    /// Runs the connection on the polling thread for up to this long per
+    /// poll instead of on a worker thread.
+    pub inline_slice: Option<Duration>,
+    /// Reusable receive buffer; `None` keeps one buffer per frame.
+    pub arena: Option<ArenaOptions>,
+    /// Largest incoming message, whole or assembled from fragments; a
+    /// bigger one fails the connection. `None` keeps yawc's limit.
+    pub max_message_size: Option<usize>,
+    /// Largest frame written for outgoing messages; bigger ones are fragmented.
+    pub write_chunk: Option<usize>,
+    /// Most text/binary messages queued toward the worker; zero is unbounded.
+    pub queue_limit: usize,
+    pub queue_policy: QueuePolicy,
+    pub pong_policy: PongPolicy,
+    pub bandwidth: BandwidthLimit,
+    pub socket: SocketOptions,
+    pub ip_family: IpFamily,
+    pub tls: TlsOptions,
+    /// Keeps the upgrade request, response and timings of each attempt.
+    pub capture_handshake: bool,
+    /// Extra headers sent with the upgrade request, in order.
+    pub headers: Vec<(String, String)>,
+    /// Fixed Sec-WebSocket-Key for reproducible captures; `None` is random.
+    pub handshake_key: Option<String>,
+    /// Source of frame masking keys; `None` leaves masking to yawc.
+    pub masks: Option<MaskSource>,
+    /// File each connection's traffic is recorded to.
+    #[cfg(feature = "diagnostics")]
+    pub session_record: Option<PathBuf>,
+    /// Recorded session played back instead of connecting.
+    #[cfg(feature = "diagnostics")]
+    pub replay: Option<Arc<Session>>,
+    #[cfg(feature = "fault-injection")]
+    pub faults: Option<Faults>,
+    /// Where messages a lost connection never wrote are kept for the next
+    /// one; `None` drops them.
+    pub unsent: Option<Arc<Unsent>>,
+    /// Delivers incoming frames as-is, skipping yawc's reassembly and
+    /// automatic pongs.
+    #[cfg(feature = "unsafe-protocol")]
+    pub raw_receive: bool,
+}
+
+impl ConnectOptions {
+    /// Sec-WebSocket-Key to send; a replayed session needs its recorded one.
+    pub fn handshake_key(&self) -> Option<&str> {
+        #[cfg(feature = "diagnostics")]
+        if let Some(key) = self.replay.as_ref().and_then(|session| session.key()) {
+            return Some(key);
+        }
+        self.handshake_key.as_deref()
+    }
+}
+
+/// Size and growth policy of the blocks incoming payloads are packed into.
+#[derive(Clone, Copy, Debug)]
+pub struct ArenaOptions {
+    pub block_size: usize,
+    pub growth: BufferGrowth,
+}
+
+/// Incoming messages larger than `threshold` bytes are written to a file
+/// in `dir` and delivered by path. They are still received whole in
+/// memory first, as yawc assembles them, but are let go once written.
+#[derive(Clone, Debug)]
+pub struct SpillOptions {
+    pub threshold: usize,
+    pub dir: PathBuf,
+}
@@ -0,0 +1,243 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::budget::BudgetAccount;
+use crate::close_code::WsppCloseCode;
+use crate::logging;
+use crate::opcode::WsppOpcode;
+
+use super::handshake::Handshake;
+use super::payload::Payload;
+use super::queue::SendQueue;
+use super::stats::{HandleStats, Loss};
+use super::worker::{self, Command, Event, EventSender, STREAM_CHUNK_SIZE};
+
+/// The commands one handle of a pair sends and where its events go.
+struct End {
+    cmd_rx: Receiver<Command>,
+    events: EventSender,
+    account: BudgetAccount,
+    queue: Arc<SendQueue>,
+    stats: Arc<HandleStats>,
+}
+
+/// Two handles connected back to back in memory. Whichever of them polls
+/// moves everything both sent so far to the other, so a test driving both
+/// from one thread sees the same events on every run.
+pub struct PairLink {
+    ends: [End; 2],
+    closed: bool,
+}
+
+/// What `open` hands each handle of a pair.
+pub struct PairEnd {
+    pub cmd_tx: mpsc::Sender<Command>,
+    pub event_rx: Receiver<(u64, Event)>,
+    pub account: BudgetAccount,
+    pub queue: Arc<SendQueue>,
+}
+
+/// What a handle brings to the pair: its stats, budget account and send
+/// queue.
+pub type Side = (Arc<HandleStats>, BudgetAccount, Arc<SendQueue>);
+
+/// Connects two handles, queueing an open event for each.
+pub fn open(sides: [Side; 2]) -> (Arc<Mutex<PairLink>>, [PairEnd; 2]) {
+    let [side_a, side_b] = sides;
+    let (end_a, out_a) = end(side_a);
+    let (end_b, out_b) = end(side_b);
+    for end in [&end_a, &end_b] {
+        let _ = end.events.send(Event::Open {
+            connection_id: worker::next_connection_id(),
+            handshake: Handshake {
+                status: 101,
+                headers: Vec::new(),
+            },
+        });
+    }
+    let link = PairLink {
+        ends: [end_a, end_b],
+        closed: false,
+    };
+    (Arc::new(Mutex::new(link)), [out_a, out_b])
+}
+
+fn end((stats, account, queue): Side) -> (End, PairEnd) {
+    let (cmd_tx, cmd_rx) = mpsc::channel();
+    let (event_tx, event_rx) = mpsc::channel();
+    let out = PairEnd {
+        cmd_tx,
+        event_rx,
+        account: account.clone(),
+        queue: queue.clone(),
+    };
+    let end = End {
+        cmd_rx,
+        events: EventSender::new(event_tx, stats.clone()),
+        account,
+        queue,
+        stats,
+    };
+    (end, out)
+}
+
+impl PairLink {
+    /// Delivers every command either handle queued to its peer.
+    pub fn pump(&mut self) {
+        for from in 0..2 {
+            while !self.closed {
+                match self.ends[from].cmd_rx.try_recv() {
+                    Ok(cmd) => self.deliver(from, cmd),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.close(WsppCloseCode::Abnormal.code(), b"");
+                    }
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self, from: usize, cmd: Command) {
+        let sender = &self.ends[from];
+        sender.stats.commands.pop();
+        sender.account.release(cmd.payload_len());
+        if cmd
+            .priority()
+            .is_some_and(|priority| !sender.queue.take(priority))
+        {
+            sender.stats.record_loss(Loss::QueueFull);
+            return;
+        }
+        if cmd.is_expired(Instant::now()) {
+            sender.stats.record_loss(Loss::Expired);
+            return;
+        }
+
+        let peer = 1 - from;
+        match cmd {
+            Command::SendText { data, .. } => self.message(peer, data, WsppOpcode::Text),
+            Command::SendBinary { data, .. } => self.message(peer, data, WsppOpcode::Binary),
+            Command::Ping(data) => {
+                self.message(peer, data.clone(), WsppOpcode::Ping);
+                self.event(
+                    from,
+                    Event::Pong {
+                        data,
+                        rtt: Some(Duration::ZERO),
+                    },
+                );
+            }
+            Command::SendStream { text, mut source } => {
+                let mut data = Vec::new();
+                loop {
+                    match source.next_chunk(STREAM_CHUNK_SIZE) {
+                        Ok(chunk) if chunk.is_empty() => break,
+                        Ok(chunk) => data.extend_from_slice(&chunk),
+                        Err(reason) => {
+                            logging::emit(2, &format!("stream send aborted: {reason}"));
+                            return;
+                        }
+                    }
+                }
+                let opcode = if text {
+                    WsppOpcode::Text
+                } else {
+                    WsppOpcode::Binary
+                };
+                self.message(peer, Payload::from(data), opcode);
+            }
+            #[cfg(feature = "unsafe-protocol")]
+            Command::SendRawFrame(_) => {
+                logging::emit(2, "raw frames are not carried between paired handles");
+            }
+            Command::Close { code, reason } => {
+                self.close(code, reason.unwrap_or_default().as_bytes());
+            }
+            Command::Shutdown => self.close(WsppCloseCode::GoingAway.code(), b"Going away"),
+        }
+    }
+
+    fn message(&self, to: usize, data: Payload, opcode: WsppOpcode) {
+        self.event(to, Event::Message { data, opcode });
+    }
+
+    fn event(&self, to: usize, event: Event) {
+        let end = &self.ends[to];
+        let len = event.payload_len();
+        end.account.charge(len);
+        if end.events.send(event).is_err() {
+            end.account.release(len);
+        }
+    }
+
+    /// Ends the connection for both handles, as if both had received a
+    /// close frame with `code` and `reason`.
+    fn close(&mut self, code: u16, reason: &[u8]) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        for end in &self.ends {
+            let _ = end.events.send(Event::Close {
+                code,
+                reason: Payload::copy_from_slice(reason),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::open;
+    use crate::budget;
+    use crate::client::payload::Payload;
+    use crate::client::queue::{Priority, SendQueue};
+    use crate::client::worker::{Command, Event};
+    use crate::opcode::WsppOpcode;
+
+    #[test]
+    fn carries_messages_and_close_both_ways() {
+        let queue = Arc::new(SendQueue::default());
+        let (link, [a, b]) = open([
+            (Arc::default(), budget::global().account(), Arc::default()),
+            (Arc::default(), budget::global().account(), queue.clone()),
+        ]);
+        a.cmd_tx.send(Command::Ping(Payload::from("p"))).unwrap();
+        queue.admit(Priority::Normal).unwrap();
+        b.cmd_tx
+            .send(Command::SendBinary {
+                data: Payload::from("hi"),
+                expires_at: None,
+                priority: Priority::Normal,
+            })
+            .unwrap();
+        b.cmd_tx
+            .send(Command::Close {
+                code: 1000,
+                reason: None,
+            })
+            .unwrap();
+        link.lock().unwrap().pump();
+
+        let kinds = |rx: &std::sync::mpsc::Receiver<(u64, Event)>| {
+            rx.try_iter()
+                .map(|(_, event)| match event {
+                    Event::Open { .. } => "open",
+                    Event::Message {
+                        opcode: WsppOpcode::Ping,
+                        ..
+                    } => "ping",
+                    Event::Message { .. } => "message",
+                    Event::Pong { .. } => "pong",
+                    Event::Close { .. } => "close",
+                    _ => "other",
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds(&a.event_rx), ["open", "pong", "message", "close"]);
+        assert_eq!(kinds(&b.event_rx), ["open", "ping", "close"]);
+    }
+}
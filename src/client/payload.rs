@@ -0,0 +1,184 @@
+use std::ffi::c_void;
+use std::ops::Deref;
+
+use bytes::Bytes;
+use smallvec::SmallVec;
+
+use crate::callback::ReleaseCallback;
+
+/// Payloads up to this size are kept inline.
+pub const INLINE_PAYLOAD: usize = 128;
+
+/// A message payload. Small ones, which covers most JSON control messages,
+/// live inside the event or command itself, so queueing them costs no heap
+/// allocation and does not pin the buffer they were read from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Payload {
+    Inline(SmallVec<[u8; INLINE_PAYLOAD]>),
+    Shared(Bytes),
+}
+
+impl Payload {
+    pub fn new() -> Self {
+        Self::Inline(SmallVec::new())
+    }
+
+    pub fn copy_from_slice(data: &[u8]) -> Self {
+        if data.len() <= INLINE_PAYLOAD {
+            Self::Inline(SmallVec::from_slice(data))
+        } else {
+            Self::Shared(Bytes::copy_from_slice(data))
+        }
+    }
+
+    /// Uses the host's buffer without copying it. `release` is called with
+    /// `userdata` once the last reference is gone, i.e. after the frame was
+    /// written or the message discarded.
+    ///
+    /// # Safety
+    /// `data` must stay valid and unchanged for `len` bytes until then.
+    pub unsafe fn borrowed(
+        data: *const u8,
+        len: usize,
+        release: ReleaseCallback,
+        userdata: *mut c_void,
+    ) -> Self {
+        Self::Shared(Bytes::from_owner(Borrowed {
+            data,
+            len,
+            release,
+            userdata,
+        }))
+    }
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Inline(data) => data,
+            Self::Shared(data) => data,
+        }
+    }
+}
+
+/// Copies small payloads out, releasing the buffer they point into.
+impl From<Bytes> for Payload {
+    fn from(data: Bytes) -> Self {
+        if data.len() <= INLINE_PAYLOAD {
+            Self::Inline(SmallVec::from_slice(&data))
+        } else {
+            Self::Shared(data)
+        }
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(data: Vec<u8>) -> Self {
+        if data.len() <= INLINE_PAYLOAD {
+            Self::Inline(SmallVec::from_slice(&data))
+        } else {
+            Self::Shared(Bytes::from(data))
+        }
+    }
+}
+
+impl From<&[u8]> for Payload {
+    fn from(data: &[u8]) -> Self {
+        Self::copy_from_slice(data)
+    }
+}
+
+impl From<&str> for Payload {
+    fn from(data: &str) -> Self {
+        Self::copy_from_slice(data.as_bytes())
+    }
+}
+
+impl From<Payload> for Bytes {
+    fn from(payload: Payload) -> Self {
+        match payload {
+            Payload::Inline(data) => Bytes::copy_from_slice(&data),
+            Payload::Shared(data) => data,
+        }
+    }
+}
+
+/// A host buffer lent to the library until `release` is called.
+struct Borrowed {
+    data: *const u8,
+    len: usize,
+    release: ReleaseCallback,
+    userdata: *mut c_void,
+}
+
+// The host promises the buffer stays put until released, from any thread.
+unsafe impl Send for Borrowed {}
+
+impl AsRef<[u8]> for Borrowed {
+    fn as_ref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl Drop for Borrowed {
+    fn drop(&mut self) {
+        (self.release)(self.userdata);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use bytes::Bytes;
+
+    use super::{INLINE_PAYLOAD, Payload};
+
+    #[test]
+    fn small_payloads_stay_inline() {
+        let small = Payload::from(Bytes::from_static(br#"{"op":"ping"}"#));
+        assert!(matches!(&small, Payload::Inline(data) if !data.spilled()));
+        assert_eq!(&*small, br#"{"op":"ping"}"#);
+
+        let edge = Payload::copy_from_slice(&[1; INLINE_PAYLOAD]);
+        assert!(matches!(edge, Payload::Inline(_)));
+    }
+
+    #[test]
+    fn borrowed_buffers_are_released_once() {
+        static RELEASED: AtomicU64 = AtomicU64::new(0);
+        extern "C" fn release(userdata: *mut c_void) {
+            RELEASED.fetch_add(userdata as u64, Ordering::Relaxed);
+        }
+
+        let buffer = vec![3_u8; 4096];
+        let payload =
+            unsafe { Payload::borrowed(buffer.as_ptr(), buffer.len(), release, 7 as *mut c_void) };
+        assert!(matches!(&payload, Payload::Shared(data) if data.as_ptr() == buffer.as_ptr()));
+        let frame = Bytes::from(payload.clone());
+        drop(payload);
+        assert_eq!(RELEASED.load(Ordering::Relaxed), 0);
+        drop(frame);
+        assert_eq!(RELEASED.load(Ordering::Relaxed), 7);
+    }
+
+    #[test]
+    fn large_payloads_share_their_buffer() {
+        let data = Bytes::from(vec![7; INLINE_PAYLOAD + 1]);
+        let payload = Payload::from(data.clone());
+        assert!(matches!(&payload, Payload::Shared(shared) if shared.as_ptr() == data.as_ptr()));
+        assert_eq!(Bytes::from(payload), data);
+    }
+}
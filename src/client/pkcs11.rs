@@ -0,0 +1,818 @@
+//! Client certificate keys held on a PKCS#11 token, e.g. a smartcard or
+//! HSM. Only the few Cryptoki calls needed to find a certificate and sign
+//! with its key are bound; signing uses the token's hash-and-sign
+//! mechanisms, so the key never leaves it.
+
+use std::ffi::{c_ulong, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, Mutex};
+
+use rustls::pki_types::CertificateDer;
+use rustls::sign::{CertifiedKey, Signer, SigningKey};
+use rustls::{SignatureAlgorithm, SignatureScheme};
+
+use super::tls::Passphrase;
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+/// A slot of the function list this binding never calls.
+type Unused = Option<unsafe extern "C" fn()>;
+
+const CKR_OK: CkRv = 0;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+const CKF_OS_LOCKING_OK: CkUlong = 0x2;
+const CKU_USER: CkUlong = 1;
+const CKA_CLASS: CkUlong = 0x0;
+const CKA_LABEL: CkUlong = 0x3;
+const CKA_VALUE: CkUlong = 0x11;
+const CKA_KEY_TYPE: CkUlong = 0x100;
+const CKA_ID: CkUlong = 0x102;
+const CKA_EC_PARAMS: CkUlong = 0x180;
+const CKO_CERTIFICATE: CkUlong = 1;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+const CKK_RSA: CkUlong = 0;
+const CKK_EC: CkUlong = 3;
+
+/// DER OIDs of the curves in `CKA_EC_PARAMS`.
+const P256: &[u8] = &[0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+const P384: &[u8] = &[0x06, 0x05, 0x2B, 0x81, 0x04, 0x00, 0x22];
+
+/// Room for an RSA-8192 signature.
+const MAX_SIGNATURE: usize = 1024;
+
+/// `CK_FUNCTION_LIST` up to `C_Sign`. Cryptoki packs its structures on
+/// Windows.
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+pub struct FunctionList {
+    pub version: [u8; 2],
+    pub initialize: Option<unsafe extern "C" fn(args: *mut c_void) -> CkRv>,
+    pub finalize: Unused,
+    pub unused_info_to_set_pin: [Unused; 10],
+    pub open_session: Option<
+        unsafe extern "C" fn(
+            slot: CkUlong,
+            flags: CkUlong,
+            application: *mut c_void,
+            notify: *mut c_void,
+            session: *mut CkUlong,
+        ) -> CkRv,
+    >,
+    pub close_session: Option<unsafe extern "C" fn(session: CkUlong) -> CkRv>,
+    pub unused_sessions: [Unused; 4],
+    pub login: Option<
+        unsafe extern "C" fn(session: CkUlong, user: CkUlong, pin: *const u8, len: CkUlong) -> CkRv,
+    >,
+    pub unused_logout_to_object_size: [Unused; 5],
+    pub get_attribute_value: Option<
+        unsafe extern "C" fn(
+            session: CkUlong,
+            object: CkUlong,
+            template: *mut Attribute,
+            count: CkUlong,
+        ) -> CkRv,
+    >,
+    pub unused_set_attribute_value: Unused,
+    pub find_objects_init: Option<
+        unsafe extern "C" fn(session: CkUlong, template: *const Attribute, count: CkUlong) -> CkRv,
+    >,
+    pub find_objects: Option<
+        unsafe extern "C" fn(
+            session: CkUlong,
+            objects: *mut CkUlong,
+            max: CkUlong,
+            count: *mut CkUlong,
+        ) -> CkRv,
+    >,
+    pub find_objects_final: Option<unsafe extern "C" fn(session: CkUlong) -> CkRv>,
+    pub unused_encrypt_to_digest: [Unused; 13],
+    pub sign_init: Option<
+        unsafe extern "C" fn(session: CkUlong, mechanism: *const Mechanism, key: CkUlong) -> CkRv,
+    >,
+    pub sign: Option<
+        unsafe extern "C" fn(
+            session: CkUlong,
+            data: *const u8,
+            len: CkUlong,
+            signature: *mut u8,
+            signature_len: *mut CkUlong,
+        ) -> CkRv,
+    >,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+pub struct Attribute {
+    pub kind: CkUlong,
+    pub value: *mut c_void,
+    pub len: CkUlong,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+pub struct Mechanism {
+    pub kind: CkUlong,
+    pub parameter: *const c_void,
+    pub len: CkUlong,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct PssParams {
+    hash: CkUlong,
+    mgf: CkUlong,
+    salt_len: CkUlong,
+}
+
+#[cfg_attr(windows, repr(C, packed))]
+#[cfg_attr(not(windows), repr(C))]
+struct InitArgs {
+    mutex_callbacks: [*mut c_void; 4],
+    flags: CkUlong,
+    reserved: *mut c_void,
+}
+
+pub type GetFunctionList = unsafe extern "C" fn(list: *mut *const FunctionList) -> CkRv;
+
+fn check(call: &str, rv: CkRv) -> Result<(), rustls::Error> {
+    match rv {
+        CKR_OK => Ok(()),
+        rv => Err(rustls::Error::General(format!(
+            "PKCS#11 {call} failed: 0x{rv:x}"
+        ))),
+    }
+}
+
+fn missing(call: &str) -> rustls::Error {
+    rustls::Error::General(format!("PKCS#11 module lacks {call}"))
+}
+
+/// A loaded and initialized PKCS#11 module. Modules stay loaded for the
+/// life of the process, as other handles may still use them.
+pub struct Module {
+    functions: *const FunctionList,
+    _library: Option<libloading::Library>,
+}
+
+// Initialized with OS locking, so Cryptoki may be called from any thread.
+unsafe impl Send for Module {}
+unsafe impl Sync for Module {}
+
+static MODULES: Mutex<Vec<(PathBuf, Arc<Module>)>> = Mutex::new(Vec::new());
+
+impl Module {
+    /// The module at `path`, loaded on first use.
+    fn load(path: &Path) -> Result<Arc<Self>, rustls::Error> {
+        let mut modules = MODULES.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some((_, module)) = modules.iter().find(|(loaded, _)| loaded == path) {
+            return Ok(module.clone());
+        }
+        let general = |err: libloading::Error| rustls::Error::General(err.to_string());
+        // Loading runs the module's initializers; naming it is the host's
+        // assurance that they are sound.
+        let library = unsafe { libloading::Library::new(path) }.map_err(general)?;
+        let get =
+            *unsafe { library.get::<GetFunctionList>(b"C_GetFunctionList\0") }.map_err(general)?;
+        let module = Arc::new(unsafe { Self::from_entry(get, Some(library)) }?);
+        modules.push((path.to_owned(), module.clone()));
+        Ok(module)
+    }
+
+    /// Initializes the module whose `C_GetFunctionList` is `get`.
+    ///
+    /// # Safety
+    ///
+    /// `get` must be a Cryptoki entry point that stays valid as long as
+    /// `library` is kept.
+    pub unsafe fn from_entry(
+        get: GetFunctionList,
+        library: Option<libloading::Library>,
+    ) -> Result<Self, rustls::Error> {
+        let mut functions = ptr::null();
+        check("C_GetFunctionList", unsafe { get(&mut functions) })?;
+        if functions.is_null() {
+            return Err(missing("a function list"));
+        }
+        let module = Self {
+            functions,
+            _library: library,
+        };
+        let initialize = module
+            .list()
+            .initialize
+            .ok_or_else(|| missing("C_Initialize"))?;
+        let mut args = InitArgs {
+            mutex_callbacks: [ptr::null_mut(); 4],
+            flags: CKF_OS_LOCKING_OK,
+            reserved: ptr::null_mut(),
+        };
+        match unsafe { initialize(ptr::addr_of_mut!(args).cast()) } {
+            CKR_CRYPTOKI_ALREADY_INITIALIZED => {}
+            rv => check("C_Initialize", rv)?,
+        }
+        Ok(module)
+    }
+
+    fn list(&self) -> &FunctionList {
+        unsafe { &*self.functions }
+    }
+}
+
+/// A session with a token. Cryptoki sessions take one operation at a
+/// time, so signing holds the lock from `C_SignInit` to `C_Sign`.
+struct Session {
+    module: Arc<Module>,
+    handle: Mutex<CkUlong>,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(close) = self.module.list().close_session {
+            let handle = *self.handle.get_mut().unwrap_or_else(|err| err.into_inner());
+            unsafe { close(handle) };
+        }
+    }
+}
+
+impl Session {
+    fn open(module: Arc<Module>, slot: u64) -> Result<Self, rustls::Error> {
+        let open = module
+            .list()
+            .open_session
+            .ok_or_else(|| missing("C_OpenSession"))?;
+        let slot = CkUlong::try_from(slot)
+            .map_err(|_| rustls::Error::General("PKCS#11 slot out of range".into()))?;
+        let mut handle = 0;
+        let rv = unsafe {
+            open(
+                slot,
+                CKF_SERIAL_SESSION,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut handle,
+            )
+        };
+        check("C_OpenSession", rv)?;
+        Ok(Self {
+            module,
+            handle: Mutex::new(handle),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CkUlong> {
+        self.handle.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn login(&self, pin: &[u8]) -> Result<(), rustls::Error> {
+        let login = self.module.list().login.ok_or_else(|| missing("C_Login"))?;
+        let len = CkUlong::try_from(pin.len()).unwrap_or(CkUlong::MAX);
+        match unsafe { login(*self.lock(), CKU_USER, pin.as_ptr(), len) } {
+            CKR_USER_ALREADY_LOGGED_IN => Ok(()),
+            rv => check("C_Login", rv),
+        }
+    }
+
+    /// The first object matching `template`.
+    fn find(&self, template: &[Attribute]) -> Result<Option<CkUlong>, rustls::Error> {
+        let list = self.module.list();
+        let (Some(init), Some(find), Some(fin)) = (
+            list.find_objects_init,
+            list.find_objects,
+            list.find_objects_final,
+        ) else {
+            return Err(missing("C_FindObjects"));
+        };
+        let handle = *self.lock();
+        check("C_FindObjectsInit", unsafe {
+            init(handle, template.as_ptr(), template.len() as CkUlong)
+        })?;
+        let (mut object, mut count) = (0, 0);
+        let found = check("C_FindObjects", unsafe {
+            find(handle, &mut object, 1, &mut count)
+        });
+        unsafe { fin(handle) };
+        found.map(|()| (count > 0).then_some(object))
+    }
+
+    /// The value of attribute `kind` of `object`.
+    fn attribute(&self, object: CkUlong, kind: CkUlong) -> Result<Vec<u8>, rustls::Error> {
+        let get = self
+            .module
+            .list()
+            .get_attribute_value
+            .ok_or_else(|| missing("C_GetAttributeValue"))?;
+        let handle = *self.lock();
+        let mut attr = Attribute {
+            kind,
+            value: ptr::null_mut(),
+            len: 0,
+        };
+        check("C_GetAttributeValue", unsafe {
+            get(handle, object, &mut attr, 1)
+        })?;
+        let mut value = vec![0_u8; attr.len as usize];
+        attr.value = value.as_mut_ptr().cast();
+        check("C_GetAttributeValue", unsafe {
+            get(handle, object, &mut attr, 1)
+        })?;
+        value.truncate(attr.len as usize);
+        Ok(value)
+    }
+
+    fn sign(
+        &self,
+        key: CkUlong,
+        mechanism: &Mechanism,
+        data: &[u8],
+    ) -> Result<Vec<u8>, rustls::Error> {
+        let list = self.module.list();
+        let (Some(init), Some(sign)) = (list.sign_init, list.sign) else {
+            return Err(missing("C_Sign"));
+        };
+        let handle = self.lock();
+        check("C_SignInit", unsafe { init(*handle, mechanism, key) })?;
+        let mut signature = vec![0_u8; MAX_SIGNATURE];
+        let mut len = MAX_SIGNATURE as CkUlong;
+        check("C_Sign", unsafe {
+            sign(
+                *handle,
+                data.as_ptr(),
+                data.len() as CkUlong,
+                signature.as_mut_ptr(),
+                &mut len,
+            )
+        })?;
+        signature.truncate(len as usize);
+        Ok(signature)
+    }
+}
+
+/// Attribute template entry pointing at `value`, which must outlive it.
+fn attribute<T: ?Sized>(kind: CkUlong, value: &T) -> Attribute {
+    Attribute {
+        kind,
+        value: ptr::from_ref(value).cast_mut().cast(),
+        len: size_of_val(value) as CkUlong,
+    }
+}
+
+/// A client certificate on a PKCS#11 token: the module, the slot holding
+/// the token and optionally the label of the certificate and its key.
+#[derive(Clone, Debug)]
+pub struct Pkcs11Identity {
+    pub module: PathBuf,
+    pub slot: u64,
+    pub label: Option<String>,
+    pub pin: Passphrase,
+}
+
+impl Pkcs11Identity {
+    /// Opens a session, logs in with the PIN if there is one and finds the
+    /// certificate and the private key sharing its `CKA_ID`.
+    pub fn open(&self) -> Result<CertifiedKey, rustls::Error> {
+        open_with(Module::load(&self.module)?, self)
+    }
+}
+
+fn open_with(
+    module: Arc<Module>,
+    identity: &Pkcs11Identity,
+) -> Result<CertifiedKey, rustls::Error> {
+    let session = Arc::new(Session::open(module, identity.slot)?);
+    if let Some(pin) = identity.pin.get() {
+        session.login(&pin)?;
+    }
+
+    let not_found = |what: &str| rustls::Error::General(format!("no {what} on the PKCS#11 token"));
+    let mut template = vec![attribute(CKA_CLASS, &CKO_CERTIFICATE)];
+    if let Some(label) = &identity.label {
+        template.push(attribute(CKA_LABEL, label.as_bytes()));
+    }
+    let cert = session
+        .find(&template)?
+        .ok_or_else(|| not_found("certificate"))?;
+    let der = session.attribute(cert, CKA_VALUE)?;
+    let id = session.attribute(cert, CKA_ID)?;
+
+    let template = [
+        attribute(CKA_CLASS, &CKO_PRIVATE_KEY),
+        attribute(CKA_ID, id.as_slice()),
+    ];
+    let key = session
+        .find(&template)?
+        .ok_or_else(|| not_found("private key for the certificate"))?;
+    let key_type = session.attribute(key, CKA_KEY_TYPE)?;
+    let kind = match <[u8; size_of::<CkUlong>()]>::try_from(key_type.as_slice()) {
+        Ok(bytes) if CkUlong::from_ne_bytes(bytes) == CKK_RSA => KeyKind::Rsa,
+        Ok(bytes) if CkUlong::from_ne_bytes(bytes) == CKK_EC => {
+            match session.attribute(key, CKA_EC_PARAMS)?.as_slice() {
+                P256 => KeyKind::Ecdsa(SignatureScheme::ECDSA_NISTP256_SHA256),
+                P384 => KeyKind::Ecdsa(SignatureScheme::ECDSA_NISTP384_SHA384),
+                _ => {
+                    return Err(rustls::Error::General(
+                        "unsupported PKCS#11 EC curve".into(),
+                    ));
+                }
+            }
+        }
+        _ => {
+            return Err(rustls::Error::General(
+                "unsupported PKCS#11 key type".into(),
+            ));
+        }
+    };
+
+    let key = Arc::new(TokenKey { session, key, kind });
+    Ok(CertifiedKey::new(vec![CertificateDer::from(der)], key))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum KeyKind {
+    Rsa,
+    /// The one scheme the key's curve allows.
+    Ecdsa(SignatureScheme),
+}
+
+/// RSA schemes in order of preference; TLS 1.3 only allows PSS.
+const RSA_SCHEMES: [SignatureScheme; 6] = [
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA512,
+];
+
+/// A private key that stays on the token.
+struct TokenKey {
+    session: Arc<Session>,
+    key: CkUlong,
+    kind: KeyKind,
+}
+
+impl std::fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenKey")
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl SigningKey for TokenKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let scheme = match self.kind {
+            KeyKind::Rsa => RSA_SCHEMES.into_iter().find(|s| offered.contains(s))?,
+            KeyKind::Ecdsa(scheme) => offered.contains(&scheme).then_some(scheme)?,
+        };
+        Some(Box::new(TokenSigner {
+            session: self.session.clone(),
+            key: self.key,
+            scheme,
+        }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        match self.kind {
+            KeyKind::Rsa => SignatureAlgorithm::RSA,
+            KeyKind::Ecdsa(_) => SignatureAlgorithm::ECDSA,
+        }
+    }
+}
+
+struct TokenSigner {
+    session: Arc<Session>,
+    key: CkUlong,
+    scheme: SignatureScheme,
+}
+
+impl std::fmt::Debug for TokenSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenSigner")
+            .field("scheme", &self.scheme)
+            .finish()
+    }
+}
+
+impl Signer for TokenSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        use SignatureScheme as S;
+        // Hash-and-sign mechanism and, for PSS, its hash, MGF and salt.
+        let (mechanism, pss) = match self.scheme {
+            S::RSA_PKCS1_SHA256 => (0x40, None),
+            S::RSA_PKCS1_SHA384 => (0x41, None),
+            S::RSA_PKCS1_SHA512 => (0x42, None),
+            S::RSA_PSS_SHA256 => (0x43, Some((0x250, 2, 32))),
+            S::RSA_PSS_SHA384 => (0x44, Some((0x260, 3, 48))),
+            S::RSA_PSS_SHA512 => (0x45, Some((0x270, 4, 64))),
+            S::ECDSA_NISTP256_SHA256 => (0x1044, None),
+            S::ECDSA_NISTP384_SHA384 => (0x1045, None),
+            _ => {
+                return Err(rustls::Error::General(
+                    "unsupported signature scheme".into(),
+                ));
+            }
+        };
+        let params = pss.map(|(hash, mgf, salt_len)| PssParams {
+            hash,
+            mgf,
+            salt_len,
+        });
+        let mechanism = Mechanism {
+            kind: mechanism,
+            parameter: params
+                .as_ref()
+                .map_or(ptr::null(), |p| ptr::from_ref(p).cast()),
+            len: params
+                .as_ref()
+                .map_or(0, |_| size_of::<PssParams>() as CkUlong),
+        };
+        let signature = self.session.sign(self.key, &mechanism, message)?;
+        match self.scheme {
+            S::ECDSA_NISTP256_SHA256 | S::ECDSA_NISTP384_SHA384 => ecdsa_der(&signature),
+            _ => Ok(signature),
+        }
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Encodes Cryptoki's `r || s` ECDSA signature as the DER TLS expects.
+fn ecdsa_der(raw: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+    if raw.is_empty() || !raw.len().is_multiple_of(2) || raw.len() > 2 * 48 {
+        return Err(rustls::Error::General("malformed ECDSA signature".into()));
+    }
+    let integer = |half: &[u8]| {
+        let start = half.iter().position(|&b| b != 0).unwrap_or(half.len() - 1);
+        let half = &half[start..];
+        let pad = half[0] & 0x80 != 0;
+        let mut out = vec![0x02, (half.len() + usize::from(pad)) as u8];
+        if pad {
+            out.push(0);
+        }
+        out.extend_from_slice(half);
+        out
+    };
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let body = [integer(r), integer(s)].concat();
+    Ok([vec![0x30, body.len() as u8], body].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_ulong;
+    use std::sync::{Arc, Mutex};
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{self, EcdsaKeyPair, KeyPair};
+    use rustls::SignatureScheme;
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+
+    use super::{
+        Attribute, CKA_CLASS, CKA_EC_PARAMS, CKA_ID, CKA_KEY_TYPE, CKA_LABEL, CKA_VALUE, CKK_EC,
+        CKO_CERTIFICATE, CKO_PRIVATE_KEY, CKR_OK, FunctionList, Mechanism, Module, P256,
+        Pkcs11Identity, ecdsa_der, open_with,
+    };
+    use crate::client::tls::Passphrase;
+
+    const CLIENT_PEM: &[u8] = include_bytes!("testdata/tls/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("testdata/tls/client.key");
+    const CKR_PIN_INCORRECT: c_ulong = 0xA0;
+    const CKR_MECHANISM_INVALID: c_ulong = 0x70;
+    const CERT: c_ulong = 1;
+    const KEY: c_ulong = 2;
+
+    /// A token holding the test client certificate and its P-256 key under
+    /// label "client" and PIN "1234".
+    struct Token {
+        searching: Option<c_ulong>,
+        mechanism: c_ulong,
+    }
+
+    static TOKEN: Mutex<Token> = Mutex::new(Token {
+        searching: None,
+        mechanism: 0,
+    });
+
+    fn cert_der() -> Vec<u8> {
+        CertificateDer::from_pem_slice(CLIENT_PEM)
+            .expect("cert")
+            .to_vec()
+    }
+
+    fn key_pair() -> EcdsaKeyPair {
+        let key = PrivatePkcs8KeyDer::from_pem_slice(CLIENT_KEY).expect("key");
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        EcdsaKeyPair::from_pkcs8(alg, key.secret_pkcs8_der(), &SystemRandom::new()).expect("key")
+    }
+
+    unsafe fn value(attr: &Attribute) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(attr.value.cast(), attr.len as usize) }
+    }
+
+    unsafe fn ulong(attr: &Attribute) -> c_ulong {
+        unsafe { *attr.value.cast::<c_ulong>() }
+    }
+
+    unsafe extern "C" fn initialize(_: *mut std::ffi::c_void) -> c_ulong {
+        CKR_OK
+    }
+
+    unsafe extern "C" fn open_session(
+        _slot: c_ulong,
+        _flags: c_ulong,
+        _app: *mut std::ffi::c_void,
+        _notify: *mut std::ffi::c_void,
+        session: *mut c_ulong,
+    ) -> c_ulong {
+        unsafe { *session = 7 };
+        CKR_OK
+    }
+
+    unsafe extern "C" fn close_session(_: c_ulong) -> c_ulong {
+        CKR_OK
+    }
+
+    unsafe extern "C" fn login(
+        _: c_ulong,
+        _user: c_ulong,
+        pin: *const u8,
+        len: c_ulong,
+    ) -> c_ulong {
+        match unsafe { std::slice::from_raw_parts(pin, len as usize) } {
+            b"1234" => CKR_OK,
+            _ => CKR_PIN_INCORRECT,
+        }
+    }
+
+    unsafe extern "C" fn find_init(
+        _: c_ulong,
+        template: *const Attribute,
+        count: c_ulong,
+    ) -> c_ulong {
+        let template = unsafe { std::slice::from_raw_parts(template, count as usize) };
+        let mut found = None;
+        for attr in template {
+            match attr.kind {
+                CKA_CLASS => {
+                    found = match unsafe { ulong(attr) } {
+                        CKO_CERTIFICATE => Some(CERT),
+                        CKO_PRIVATE_KEY => Some(KEY),
+                        _ => None,
+                    }
+                }
+                CKA_LABEL if unsafe { value(attr) } != b"client" => return CKR_OK,
+                CKA_ID if unsafe { value(attr) } != b"id" => return CKR_OK,
+                _ => {}
+            }
+        }
+        TOKEN.lock().expect("token").searching = found;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find(
+        _: c_ulong,
+        objects: *mut c_ulong,
+        _max: c_ulong,
+        count: *mut c_ulong,
+    ) -> c_ulong {
+        let found = TOKEN.lock().expect("token").searching.take();
+        unsafe {
+            *count = c_ulong::from(found.is_some());
+            *objects = found.unwrap_or_default();
+        }
+        CKR_OK
+    }
+
+    unsafe extern "C" fn find_final(_: c_ulong) -> c_ulong {
+        TOKEN.lock().expect("token").searching = None;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn get_attribute(
+        _: c_ulong,
+        object: c_ulong,
+        template: *mut Attribute,
+        _count: c_ulong,
+    ) -> c_ulong {
+        let attr = unsafe { &mut *template };
+        let value = match (object, attr.kind) {
+            (CERT, CKA_VALUE) => cert_der(),
+            (CERT, CKA_ID) => b"id".to_vec(),
+            (KEY, CKA_KEY_TYPE) => CKK_EC.to_ne_bytes().to_vec(),
+            (KEY, CKA_EC_PARAMS) => P256.to_vec(),
+            _ => return 0x12,
+        };
+        if !attr.value.is_null() {
+            let out = unsafe { std::slice::from_raw_parts_mut(attr.value.cast(), value.len()) };
+            out.copy_from_slice(&value);
+        }
+        attr.len = value.len() as c_ulong;
+        CKR_OK
+    }
+
+    unsafe extern "C" fn sign_init(
+        _: c_ulong,
+        mechanism: *const Mechanism,
+        key: c_ulong,
+    ) -> c_ulong {
+        assert_eq!(key, KEY);
+        TOKEN.lock().expect("token").mechanism = unsafe { (*mechanism).kind };
+        CKR_OK
+    }
+
+    unsafe extern "C" fn sign(
+        _: c_ulong,
+        data: *const u8,
+        len: c_ulong,
+        signature: *mut u8,
+        signature_len: *mut c_ulong,
+    ) -> c_ulong {
+        if TOKEN.lock().expect("token").mechanism != 0x1044 {
+            return CKR_MECHANISM_INVALID;
+        }
+        let data = unsafe { std::slice::from_raw_parts(data, len as usize) };
+        let sig = key_pair().sign(&SystemRandom::new(), data).expect("sign");
+        unsafe {
+            std::ptr::copy_nonoverlapping(sig.as_ref().as_ptr(), signature, sig.as_ref().len());
+            *signature_len = sig.as_ref().len() as c_ulong;
+        }
+        CKR_OK
+    }
+
+    static FUNCTIONS: FunctionList = FunctionList {
+        version: [2, 40],
+        initialize: Some(initialize),
+        finalize: None,
+        unused_info_to_set_pin: [None; 10],
+        open_session: Some(open_session),
+        close_session: Some(close_session),
+        unused_sessions: [None; 4],
+        login: Some(login),
+        unused_logout_to_object_size: [None; 5],
+        get_attribute_value: Some(get_attribute),
+        unused_set_attribute_value: None,
+        find_objects_init: Some(find_init),
+        find_objects: Some(find),
+        find_objects_final: Some(find_final),
+        unused_encrypt_to_digest: [None; 13],
+        sign_init: Some(sign_init),
+        sign: Some(sign),
+    };
+
+    unsafe extern "C" fn get_function_list(list: *mut *const FunctionList) -> c_ulong {
+        unsafe { *list = &FUNCTIONS };
+        CKR_OK
+    }
+
+    fn identity(label: &str, pin: &[u8]) -> Pkcs11Identity {
+        Pkcs11Identity {
+            module: "fake".into(),
+            slot: 0,
+            label: Some(label.to_owned()),
+            pin: Passphrase::Fixed(pin.to_vec().into()),
+        }
+    }
+
+    #[test]
+    fn token_keys_sign_handshakes() {
+        let module =
+            Arc::new(unsafe { Module::from_entry(get_function_list, None) }.expect("module"));
+        assert!(open_with(module.clone(), &identity("client", b"4321")).is_err());
+        assert!(open_with(module.clone(), &identity("other", b"1234")).is_err());
+
+        let key = open_with(module, &identity("client", b"1234")).expect("identity");
+        assert_eq!(key.cert, [CertificateDer::from(cert_der())]);
+        assert!(
+            key.key
+                .choose_scheme(&[SignatureScheme::RSA_PSS_SHA256])
+                .is_none()
+        );
+        let signer = key
+            .key
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .expect("scheme");
+        let signature = signer.sign(b"handshake").expect("signature");
+
+        let public = key_pair().public_key().as_ref().to_vec();
+        signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, public)
+            .verify(b"handshake", &signature)
+            .expect("valid signature");
+    }
+
+    #[test]
+    fn raw_ecdsa_signatures_become_der() {
+        let mut raw = [0_u8; 64];
+        raw[0] = 0x80;
+        raw[63] = 1;
+        let der = ecdsa_der(&raw).expect("der");
+        assert_eq!(&der[..5], [0x30, 38, 0x02, 33, 0x00]);
+        assert_eq!(&der[der.len() - 3..], [0x02, 1, 1]);
+        assert!(ecdsa_der(&[1, 2, 3]).is_err());
+    }
+}
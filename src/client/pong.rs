@@ -0,0 +1,34 @@
+/// How pongs that answer no outstanding ping are handled.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PongPolicy {
+    /// Delivered to the pong callback like any other pong.
+    #[default]
+    Deliver = 0,
+    /// Dropped; only the unsolicited pong counter is updated.
+    Ignore = 1,
+    /// Sent to the diagnostics callback instead of the pong callback.
+    Report = 2,
+}
+
+impl PongPolicy {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Deliver),
+            1 => Some(Self::Ignore),
+            2 => Some(Self::Report),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PongPolicy;
+
+    #[test]
+    fn maps_ffi_policies() {
+        assert_eq!(PongPolicy::from_ffi(2), Some(PongPolicy::Report));
+        assert_eq!(PongPolicy::from_ffi(-1), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::io;
+
+/// Scheduling hint applied to a connection's worker thread when it starts.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ThreadPriority {
+    #[default]
+    Normal = 0,
+    Low = 1,
+    High = 2,
+}
+
+impl ThreadPriority {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Low),
+            2 => Some(Self::High),
+            _ => None,
+        }
+    }
+
+    /// Applies the priority to the calling thread.
+    pub fn apply_current(self) -> io::Result<()> {
+        if self == Self::Normal {
+            return Ok(());
+        }
+        imp::apply(self)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+
+    use super::ThreadPriority;
+
+    pub fn apply(priority: ThreadPriority) -> io::Result<()> {
+        let nice = match priority {
+            ThreadPriority::Normal => 0,
+            ThreadPriority::Low => 10,
+            ThreadPriority::High => -5,
+        };
+        // On Linux the nice value is per thread when addressed by tid.
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(target_vendor = "apple")]
+mod imp {
+    use std::io;
+
+    use super::ThreadPriority;
+
+    pub fn apply(priority: ThreadPriority) -> io::Result<()> {
+        let class = match priority {
+            ThreadPriority::Normal => libc::qos_class_t::QOS_CLASS_DEFAULT,
+            ThreadPriority::Low => libc::qos_class_t::QOS_CLASS_UTILITY,
+            ThreadPriority::High => libc::qos_class_t::QOS_CLASS_USER_INITIATED,
+        };
+        match unsafe { libc::pthread_set_qos_class_self_np(class, 0) } {
+            0 => Ok(()),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::io;
+
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL,
+        THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_NORMAL,
+    };
+
+    use super::ThreadPriority;
+
+    pub fn apply(priority: ThreadPriority) -> io::Result<()> {
+        let level = match priority {
+            ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+            ThreadPriority::Low => THREAD_PRIORITY_BELOW_NORMAL,
+            ThreadPriority::High => THREAD_PRIORITY_ABOVE_NORMAL,
+        };
+        // The pseudo handle always names the calling thread and needs no close.
+        if unsafe { SetThreadPriority(GetCurrentThread(), level) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_vendor = "apple", windows)))]
+mod imp {
+    use std::io;
+
+    use super::ThreadPriority;
+
+    pub fn apply(_priority: ThreadPriority) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadPriority;
+
+    #[test]
+    fn maps_ffi_priorities() {
+        assert_eq!(ThreadPriority::from_ffi(1), Some(ThreadPriority::Low));
+        assert_eq!(ThreadPriority::from_ffi(3), None);
+    }
+
+    #[test]
+    fn normal_priority_is_a_no_op() {
+        assert!(ThreadPriority::Normal.apply_current().is_ok());
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    #[test]
+    fn lowering_priority_is_always_allowed() {
+        let applied = std::thread::spawn(|| ThreadPriority::Low.apply_current())
+            .join()
+            .expect("priority thread");
+        assert!(applied.is_ok());
+    }
+}
@@ -0,0 +1,83 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::result::WsppResult;
+use crate::test_support::{Recorded, ServerConfig, TestServer, connected, poll_until};
+
+fn fragment_count(len: usize, sizes: &[usize]) -> usize {
+    let mut offset = 0;
+    let mut count = 0;
+    for size in sizes.iter().cycle() {
+        count += 1;
+        offset = (offset + (*size).max(1)).min(len);
+        if offset == len {
+            break;
+        }
+    }
+    count
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn binary_roundtrip_preserves_payload(payload in vec(any::<u8>(), 0..256 * 1024)) {
+        let server = TestServer::start();
+        let mut ws = connected(&server.url());
+
+        prop_assert_eq!(ws.send_binary(payload.clone()), Ok(WsppResult::Ok));
+
+        let events = poll_until(&mut ws, 2);
+        prop_assert_eq!(&events[1..], &[Recorded::Message(payload, 2)]);
+        ws.shutdown();
+    }
+
+    #[test]
+    fn text_roundtrip_preserves_payload(text in "\\PC{0,2048}") {
+        prop_assume!(!matches!(text.as_str(), "close" | "drop" | "ping-me"));
+        let server = TestServer::start();
+        let mut ws = connected(&server.url());
+
+        prop_assert_eq!(ws.send_message(&text), Ok(WsppResult::Ok));
+
+        let events = poll_until(&mut ws, 2);
+        prop_assert_eq!(&events[1..], &[Recorded::Message(text.into_bytes(), 1)]);
+        ws.shutdown();
+    }
+
+    #[test]
+    fn fragmented_messages_reassemble(
+        payload in vec(any::<u8>(), 1..64 * 1024),
+        fragment_sizes in vec(1_usize..4096, 1..8),
+        ping_between_fragments in any::<bool>(),
+    ) {
+        let pings = if ping_between_fragments {
+            fragment_count(payload.len(), &fragment_sizes) - 1
+        } else {
+            0
+        };
+        let server = TestServer::start_with(ServerConfig {
+            fragment_sizes,
+            ping_between_fragments,
+        });
+        let mut ws = connected(&server.url());
+
+        prop_assert_eq!(ws.send_binary(payload.clone()), Ok(WsppResult::Ok));
+
+        let events = poll_until(&mut ws, 2 + pings);
+        let (control, data): (Vec<_>, Vec<_>) = events[1..]
+            .iter()
+            .cloned()
+            .partition(|event| matches!(event, Recorded::Message(_, 9)));
+        prop_assert_eq!(control.len(), pings);
+        prop_assert_eq!(data, vec![Recorded::Message(payload, 2)]);
+        ws.shutdown();
+    }
+}
+
+#[test]
+fn fragment_count_covers_payload() {
+    assert_eq!(fragment_count(1, &[4]), 1);
+    assert_eq!(fragment_count(10, &[4]), 3);
+    assert_eq!(fragment_count(10, &[3, 7]), 2);
+}
@@ -0,0 +1,247 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::result::WsppResult;
+
+/// What happens to a message sent while the outgoing queue is full.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum QueuePolicy {
+    /// The send fails with `QueueFull`.
+    #[default]
+    RejectNew = 0,
+    /// The oldest queued message is discarded to make room.
+    DropOldest = 1,
+    /// The oldest normal-priority message is discarded; high-priority
+    /// messages are never dropped.
+    DropByPriority = 2,
+}
+
+impl QueuePolicy {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::RejectNew),
+            1 => Some(Self::DropOldest),
+            2 => Some(Self::DropByPriority),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Priority {
+    #[default]
+    Normal = 0,
+    High = 1,
+}
+
+impl Priority {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Normal),
+            1 => Some(Self::High),
+            _ => None,
+        }
+    }
+}
+
+/// Per-message options of `wspp_send_*_flags`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SendFlags {
+    /// Asks for the message to go out uncompressed. Advisory: yawc has no
+    /// per-frame switch, so it is compressed like every other message once
+    /// permessage-deflate was negotiated.
+    pub no_compress: bool,
+    pub priority: Priority,
+    /// Discards the message if it is still queued after `SHORT_TTL`.
+    pub short_ttl: bool,
+    /// A full queue drops the message instead of failing the send.
+    pub fire_and_forget: bool,
+}
+
+impl SendFlags {
+    pub const SHORT_TTL: Duration = Duration::from_secs(1);
+
+    const NO_COMPRESS: u32 = 1;
+    const HIGH_PRIORITY: u32 = 1 << 1;
+    const SHORT_TTL_FLAG: u32 = 1 << 2;
+    const FIRE_AND_FORGET: u32 = 1 << 3;
+
+    /// Decodes the C flag bits; unknown bits are rejected.
+    pub fn from_ffi(flags: u32) -> Option<Self> {
+        let known =
+            Self::NO_COMPRESS | Self::HIGH_PRIORITY | Self::SHORT_TTL_FLAG | Self::FIRE_AND_FORGET;
+        (flags & !known == 0).then_some(Self {
+            no_compress: flags & Self::NO_COMPRESS != 0,
+            priority: if flags & Self::HIGH_PRIORITY != 0 {
+                Priority::High
+            } else {
+                Priority::Normal
+            },
+            short_ttl: flags & Self::SHORT_TTL_FLAG != 0,
+            fire_and_forget: flags & Self::FIRE_AND_FORGET != 0,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    queued: [usize; 2],
+    evicted: [usize; 2],
+}
+
+impl Counts {
+    fn live(&self, level: usize) -> usize {
+        self.queued[level] - self.evicted[level]
+    }
+}
+
+/// Bounds the text/binary messages in flight to the worker. Messages can't
+/// be pulled back out of the channel, so evictions are recorded here and
+/// the worker discards that many of the oldest messages as it receives them.
+#[derive(Default)]
+pub struct SendQueue {
+    limit: usize,
+    policy: QueuePolicy,
+    counts: Mutex<Counts>,
+}
+
+impl SendQueue {
+    /// A zero limit leaves the queue unbounded.
+    pub fn new(limit: usize, policy: QueuePolicy) -> Self {
+        Self {
+            limit,
+            policy,
+            counts: Mutex::default(),
+        }
+    }
+
+    /// Reserves room for a message before it is queued, evicting an older
+    /// one if the policy allows.
+    pub fn admit(&self, priority: Priority) -> Result<(), WsppResult> {
+        let level = self.level(priority);
+        let mut counts = self.counts.lock().map_err(|_| WsppResult::Unknown)?;
+        if self.limit > 0 && counts.live(0) + counts.live(1) >= self.limit {
+            match self.policy {
+                QueuePolicy::RejectNew => return Err(WsppResult::QueueFull),
+                QueuePolicy::DropOldest => counts.evicted[0] += 1,
+                QueuePolicy::DropByPriority if counts.live(0) > 0 => counts.evicted[0] += 1,
+                // Only high-priority messages are queued; they stay and a
+                // new high-priority one may exceed the limit.
+                QueuePolicy::DropByPriority if priority == Priority::Normal => {
+                    return Err(WsppResult::QueueFull);
+                }
+                QueuePolicy::DropByPriority => {}
+            }
+        }
+        counts.queued[level] += 1;
+        Ok(())
+    }
+
+    /// Gives back a reservation whose message never made it into the queue.
+    pub fn cancel(&self, priority: Priority) {
+        let level = self.level(priority);
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.queued[level] -= 1;
+        }
+    }
+
+    /// Called by the worker for each message it receives. Returns `false`
+    /// if the message was evicted and must be discarded.
+    pub fn take(&self, priority: Priority) -> bool {
+        let level = self.level(priority);
+        let Ok(mut counts) = self.counts.lock() else {
+            return true;
+        };
+        counts.queued[level] -= 1;
+        if counts.evicted[level] > 0 {
+            counts.evicted[level] -= 1;
+            return false;
+        }
+        true
+    }
+
+    fn level(&self, priority: Priority) -> usize {
+        match self.policy {
+            QueuePolicy::DropByPriority => priority as usize,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Priority, QueuePolicy, SendFlags, SendQueue};
+    use crate::result::WsppResult;
+
+    #[test]
+    fn send_flags_decode_known_bits_only() {
+        assert_eq!(SendFlags::from_ffi(0), Some(SendFlags::default()));
+        let flags = SendFlags::from_ffi(0b1010).expect("known bits");
+        assert_eq!(flags.priority, Priority::High);
+        assert!(flags.fire_and_forget && !flags.short_ttl && !flags.no_compress);
+        assert_eq!(SendFlags::from_ffi(1 << 4), None);
+    }
+
+    #[test]
+    fn unbounded_queue_admits_everything() {
+        let queue = SendQueue::default();
+        for _ in 0..100 {
+            assert_eq!(queue.admit(Priority::Normal), Ok(()));
+        }
+    }
+
+    #[test]
+    fn reject_new_fails_when_full() {
+        let queue = SendQueue::new(2, QueuePolicy::RejectNew);
+        assert_eq!(queue.admit(Priority::Normal), Ok(()));
+        assert_eq!(queue.admit(Priority::High), Ok(()));
+        assert_eq!(queue.admit(Priority::High), Err(WsppResult::QueueFull));
+
+        assert!(queue.take(Priority::Normal));
+        assert_eq!(queue.admit(Priority::Normal), Ok(()));
+    }
+
+    #[test]
+    fn drop_oldest_discards_in_arrival_order() {
+        let queue = SendQueue::new(2, QueuePolicy::DropOldest);
+        for _ in 0..4 {
+            assert_eq!(queue.admit(Priority::Normal), Ok(()));
+        }
+
+        let delivered: Vec<bool> = (0..4).map(|_| queue.take(Priority::Normal)).collect();
+        assert_eq!(delivered, vec![false, false, true, true]);
+    }
+
+    #[test]
+    fn drop_by_priority_keeps_high_priority_messages() {
+        let queue = SendQueue::new(2, QueuePolicy::DropByPriority);
+        assert_eq!(queue.admit(Priority::High), Ok(()));
+        assert_eq!(queue.admit(Priority::Normal), Ok(()));
+        assert_eq!(queue.admit(Priority::High), Ok(()));
+        assert_eq!(queue.admit(Priority::Normal), Err(WsppResult::QueueFull));
+        assert_eq!(queue.admit(Priority::High), Ok(()));
+
+        assert!(queue.take(Priority::High));
+        assert!(!queue.take(Priority::Normal));
+        assert!(queue.take(Priority::High));
+        assert!(queue.take(Priority::High));
+    }
+
+    #[test]
+    fn cancelled_reservations_free_their_slot() {
+        let queue = SendQueue::new(1, QueuePolicy::RejectNew);
+        assert_eq!(queue.admit(Priority::Normal), Ok(()));
+        queue.cancel(Priority::Normal);
+        assert_eq!(queue.admit(Priority::Normal), Ok(()));
+    }
+
+    #[test]
+    fn maps_ffi_values() {
+        assert_eq!(QueuePolicy::from_ffi(1), Some(QueuePolicy::DropOldest));
+        assert_eq!(QueuePolicy::from_ffi(3), None);
+        assert_eq!(Priority::from_ffi(1), Some(Priority::High));
+        assert_eq!(Priority::from_ffi(2), None);
+    }
+}
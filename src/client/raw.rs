@@ -0,0 +1,183 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Largest payload accepted from a raw frame header, so a bogus length
+/// can't make the worker buffer without bound.
+const MAX_RAW_PAYLOAD: usize = 64 * 1024 * 1024;
+
+/// A frame exactly as it appears on the wire, bypassing yawc's validation.
+/// Used to probe servers with malformed frames and to build protocol
+/// layers on top of the raw frame stream.
+#[derive(Debug)]
+pub struct RawFrame {
+    pub fin: bool,
+    /// RSV1..RSV3 in the low three bits.
+    pub rsv: u8,
+    /// Any 4-bit opcode, including reserved ones.
+    pub opcode: u8,
+    pub payload: Bytes,
+}
+
+impl RawFrame {
+    /// Encodes the frame as a client frame masked with `mask`.
+    pub fn encode(&self, mask: [u8; 4]) -> BytesMut {
+        let len = self.payload.len();
+        let mut out = BytesMut::with_capacity(len + 14);
+        out.put_u8(u8::from(self.fin) << 7 | (self.rsv & 0x7) << 4 | (self.opcode & 0xF));
+        match len {
+            0..=125 => out.put_u8(0x80 | len as u8),
+            126..=0xFFFF => {
+                out.put_u8(0x80 | 126);
+                out.put_u16(len as u16);
+            }
+            _ => {
+                out.put_u8(0x80 | 127);
+                out.put_u64(len as u64);
+            }
+        }
+        out.put_slice(&mask);
+        out.extend(
+            self.payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        out
+    }
+}
+
+impl RawFrame {
+    /// Takes one complete frame off the front of `buf`, or returns `None`
+    /// until enough bytes arrived. Masked frames are unmasked.
+    pub fn parse(buf: &mut BytesMut) -> Result<Option<Self>, String> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        let (first, second) = (buf[0], buf[1]);
+        let (len, mut offset) = match second & 0x7F {
+            126 if buf.len() < 4 => return Ok(None),
+            126 => (u64::from(u16::from_be_bytes([buf[2], buf[3]])), 4),
+            127 if buf.len() < 10 => return Ok(None),
+            127 => {
+                let mut len = [0_u8; 8];
+                len.copy_from_slice(&buf[2..10]);
+                (u64::from_be_bytes(len), 10)
+            }
+            len => (u64::from(len), 2),
+        };
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= MAX_RAW_PAYLOAD)
+            .ok_or_else(|| format!("raw frame of {len} bytes exceeds the limit"))?;
+
+        let mask = if second & 0x80 != 0 {
+            if buf.len() < offset + 4 {
+                return Ok(None);
+            }
+            let mask = [
+                buf[offset],
+                buf[offset + 1],
+                buf[offset + 2],
+                buf[offset + 3],
+            ];
+            offset += 4;
+            Some(mask)
+        } else {
+            None
+        };
+        if buf.len() < offset + len {
+            return Ok(None);
+        }
+
+        buf.advance(offset);
+        let mut payload = buf.split_to(len);
+        if let Some(mask) = mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        Ok(Some(Self {
+            fin: first & 0x80 != 0,
+            rsv: (first >> 4) & 0x7,
+            opcode: first & 0xF,
+            payload: payload.freeze(),
+        }))
+    }
+}
+
+/// Masking key from the system RNG, for when no mask source is configured.
+pub fn random_mask() -> [u8; 4] {
+    let mut mask = [0_u8; 4];
+    if SystemRandom::new().fill(&mut mask).is_err() {
+        crate::logging::emit(2, "system RNG failed; raw frame sent with a zero mask");
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Bytes, BytesMut};
+
+    use super::RawFrame;
+
+    #[test]
+    fn parses_what_encode_writes() {
+        let frame = RawFrame {
+            fin: false,
+            rsv: 0b010,
+            opcode: 0x9,
+            payload: Bytes::from(vec![7; 300]),
+        };
+        let mut buf = frame.encode([9, 8, 7, 6]);
+        buf.extend_from_slice(&[0x81]);
+
+        let parsed = RawFrame::parse(&mut buf).expect("valid").expect("complete");
+        assert!(!parsed.fin);
+        assert_eq!((parsed.rsv, parsed.opcode), (0b010, 0x9));
+        assert_eq!(parsed.payload, frame.payload);
+        assert_eq!(&buf[..], &[0x81]);
+    }
+
+    #[test]
+    fn waits_for_incomplete_frames_and_rejects_huge_ones() {
+        let mut partial = BytesMut::from(&[0x82, 0x05, b'a', b'b'][..]);
+        assert!(matches!(RawFrame::parse(&mut partial), Ok(None)));
+        assert_eq!(partial.len(), 4);
+
+        let mut huge = BytesMut::from(&[0x82, 0x7F, 0xFF, 0, 0, 0, 0, 0, 0, 0][..]);
+        assert!(RawFrame::parse(&mut huge).is_err());
+    }
+
+    #[test]
+    fn encodes_header_bits_and_masks_payload() {
+        let frame = RawFrame {
+            fin: false,
+            rsv: 0b101,
+            opcode: 0xB,
+            payload: Bytes::from_static(b"abcde"),
+        };
+        let encoded = frame.encode([1, 2, 3, 4]);
+
+        assert_eq!(&encoded[..6], &[0b0101_1011, 0x85, 1, 2, 3, 4]);
+        assert_eq!(
+            &encoded[6..],
+            &[b'a' ^ 1, b'b' ^ 2, b'c' ^ 3, b'd' ^ 4, b'e' ^ 1]
+        );
+    }
+
+    #[test]
+    fn uses_extended_lengths() {
+        let frame = |len| RawFrame {
+            fin: true,
+            rsv: 0,
+            opcode: 2,
+            payload: Bytes::from(vec![0; len]),
+        };
+
+        assert_eq!(&frame(126).encode([0; 4])[1..4], &[0xFE, 0, 126]);
+        assert_eq!(
+            &frame(70_000).encode([0; 4])[1..10],
+            &[0xFF, 0, 0, 0, 0, 0, 1, 0x11, 0x70]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// How much of each reconnect delay is left to chance, so clients that
+/// lost the same server do not all come back at once.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Jitter {
+    /// The delay as computed.
+    #[default]
+    None = 0,
+    /// Anywhere between zero and the delay.
+    Full = 1,
+    /// Half the delay plus anywhere up to the other half.
+    Equal = 2,
+}
+
+impl Jitter {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Full),
+            2 => Some(Self::Equal),
+            _ => None,
+        }
+    }
+
+    /// `delay` randomized by `roll`, a uniformly random number.
+    fn apply(self, delay: Duration, roll: u64) -> Duration {
+        let scaled = |range: Duration| {
+            let nanos = (range.as_nanos() * u128::from(roll)) >> 64;
+            Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+        };
+        match self {
+            Self::None => delay,
+            Self::Full => scaled(delay),
+            Self::Equal => delay / 2 + scaled(delay - delay / 2),
+        }
+    }
+}
+
+fn roll() -> u64 {
+    let mut bytes = [0_u8; 8];
+    // Without randomness the full delay is the safe side to err on.
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return u64::MAX;
+    }
+    u64::from_ne_bytes(bytes)
+}
+
+/// Reconnects a handle after it lost its connection, waiting twice as long
+/// before each further attempt.
+#[derive(Clone, Debug)]
+pub struct Reconnect {
+    /// Attempts in a row before giving up, zero for no limit.
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: Jitter,
+    /// Attempts made since the connection was lost, zero while it is up.
+    attempt: u32,
+    /// When the first of those attempts was scheduled.
+    lost_at: Option<Instant>,
+    /// When the next attempt is due, while waiting for it.
+    due: Option<Instant>,
+}
+
+impl Reconnect {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts,
+            base_delay,
+            max_delay,
+            jitter: Jitter::None,
+            attempt: 0,
+            lost_at: None,
+            due: None,
+        }
+    }
+
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.jitter = jitter;
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Wait before attempt `attempt`, counting from one, before jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1_u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Schedules the next attempt and returns its number and delay, or
+    /// `None` once all attempts are used up, which starts the count over.
+    pub fn schedule(&mut self, now: Instant) -> Option<(u32, Duration)> {
+        if self.max_attempts != 0 && self.attempt >= self.max_attempts {
+            self.reset();
+            return None;
+        }
+        if self.attempt == 0 {
+            self.lost_at = Some(now);
+        }
+        self.attempt += 1;
+        let delay = self.jitter.apply(self.delay(self.attempt), roll());
+        self.due = Some(now + delay);
+        Some((self.attempt, delay))
+    }
+
+    /// Whether the connection was lost and is not back yet.
+    pub fn is_active(&self) -> bool {
+        self.attempt > 0
+    }
+
+    /// The attempt that is being made and how long the connection has been
+    /// down, while reconnecting.
+    pub fn outage(&self, now: Instant) -> Option<(u32, Duration)> {
+        let lost_at = self.lost_at.filter(|_| self.attempt > 0)?;
+        Some((self.attempt, now.saturating_duration_since(lost_at)))
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.due.is_some()
+    }
+
+    /// Whether the scheduled attempt is due at `now`; it is then no longer
+    /// waited for.
+    pub fn take_due(&mut self, now: Instant) -> bool {
+        if self.due.is_some_and(|due| due <= now) {
+            self.due = None;
+            return true;
+        }
+        false
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+        self.lost_at = None;
+        self.due = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Jitter, Reconnect};
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let reconnect = Reconnect::new(0, Duration::from_millis(100), Duration::from_secs(1));
+        let delays: Vec<u128> = (1..=6)
+            .map(|attempt| reconnect.delay(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(reconnect.delay(200), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_range() {
+        let delay = Duration::from_millis(800);
+        let half = u64::MAX / 2 + 1;
+        assert_eq!(Jitter::None.apply(delay, 0), delay);
+        assert_eq!(Jitter::Full.apply(delay, 0), Duration::ZERO);
+        assert_eq!(Jitter::Full.apply(delay, half), delay / 2);
+        assert!(Jitter::Full.apply(delay, u64::MAX) < delay);
+        assert_eq!(Jitter::Equal.apply(delay, 0), delay / 2);
+        assert_eq!(Jitter::Equal.apply(delay, half), delay * 3 / 4);
+    }
+
+    #[test]
+    fn jittered_schedules_never_exceed_the_delay() {
+        let now = Instant::now();
+        let mut reconnect = Reconnect::new(0, Duration::from_millis(100), Duration::from_secs(1));
+        reconnect.set_jitter(Jitter::Equal);
+        for attempt in 1..=5 {
+            let (_, delay) = reconnect.schedule(now).expect("no limit");
+            assert!(delay >= reconnect.delay(attempt) / 2);
+            assert!(delay <= reconnect.delay(attempt));
+        }
+    }
+
+    #[test]
+    fn gives_up_after_its_attempts() {
+        let now = Instant::now();
+        let mut reconnect = Reconnect::new(2, Duration::from_millis(10), Duration::from_secs(1));
+        assert_eq!(
+            reconnect.schedule(now),
+            Some((1, Duration::from_millis(10)))
+        );
+        assert!(!reconnect.take_due(now));
+        assert!(reconnect.take_due(now + Duration::from_millis(10)));
+        assert_eq!(
+            reconnect.outage(now + Duration::from_millis(15)),
+            Some((1, Duration::from_millis(15)))
+        );
+        assert!(!reconnect.is_waiting());
+        assert_eq!(
+            reconnect.schedule(now),
+            Some((2, Duration::from_millis(20)))
+        );
+        assert_eq!(reconnect.schedule(now), None);
+        assert!(!reconnect.is_active());
+        assert_eq!(reconnect.outage(now), None);
+        assert_eq!(reconnect.schedule(now).map(|(attempt, _)| attempt), Some(1));
+    }
+}
@@ -0,0 +1,67 @@
+use super::worker::Event;
+
+/// What one `wspp_poll_ex` call dispatched.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WsppPollReport {
+    /// Same count `wspp_poll` returns, including expired requests.
+    pub total: u64,
+    /// Text and binary messages, whether delivered in memory or as files.
+    pub messages: u64,
+    /// Pongs, solicited or not.
+    pub pongs: u64,
+    pub errors: u64,
+    /// Opens and closes.
+    pub state_changes: u64,
+    /// Sequence number of the last event dispatched, zero if none was.
+    pub last_sequence: u64,
+}
+
+impl WsppPollReport {
+    pub fn count(&mut self, seq: u64, event: &Event) {
+        let counter = match event {
+            Event::Message { .. } | Event::MessageFile { .. } => Some(&mut self.messages),
+            Event::Pong { .. } | Event::UnsolicitedPong(_) => Some(&mut self.pongs),
+            Event::Error(_) => Some(&mut self.errors),
+            Event::Open { .. } | Event::Close { .. } => Some(&mut self.state_changes),
+            _ => None,
+        };
+        if let Some(counter) = counter {
+            *counter += 1;
+        }
+        self.total += 1;
+        self.last_sequence = seq;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsppPollReport;
+    use crate::client::Payload;
+    use crate::client::error::{WorkerError, WsppErrorCategory};
+    use crate::client::worker::Event;
+
+    #[test]
+    fn counts_events_by_kind() {
+        let mut report = WsppPollReport::default();
+        report.count(4, &Event::abnormal_close());
+        report.count(5, &Event::UnsolicitedPong(Payload::new()));
+        report.count(6, &Event::Watchdog);
+        report.count(
+            7,
+            &Event::Error(WorkerError::new(WsppErrorCategory::Io, "x")),
+        );
+
+        assert_eq!(
+            report,
+            WsppPollReport {
+                total: 4,
+                pongs: 1,
+                errors: 1,
+                state_changes: 1,
+                last_sequence: 7,
+                ..WsppPollReport::default()
+            }
+        );
+    }
+}
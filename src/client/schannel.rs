@@ -0,0 +1,182 @@
+use std::io;
+
+use pkcs8::der::pem::{self, PemLabel};
+use pkcs8::der::{AnyRef, Encode};
+use pkcs8::{AlgorithmIdentifierRef, LineEnding, ObjectIdentifier, PrivateKeyInfo};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio::net::TcpStream;
+use tokio_native_tls::native_tls;
+use tokio_native_tls::{TlsConnector, TlsStream};
+
+use crate::logging;
+
+use zeroize::Zeroizing;
+
+use super::tls::{RevocationMode, TlsOptions, VerifyPolicy};
+
+/// A client certificate and key loaded into SChannel. Only the loaded
+/// identity is kept, not the PEM it was built from.
+#[derive(Clone)]
+pub struct Identity(native_tls::Identity);
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Identity")
+    }
+}
+
+impl Identity {
+    /// Loads `chain` with `key`, which may be in any format rustls takes;
+    /// SChannel only takes PKCS#8, so it is converted first.
+    pub fn new(
+        chain: &[CertificateDer<'_>],
+        key: &PrivateKeyDer<'_>,
+    ) -> Result<Self, rustls::Error> {
+        let general = |err: &dyn std::fmt::Display| rustls::Error::General(err.to_string());
+        let mut cert_pem = String::new();
+        for cert in chain {
+            cert_pem += &pem::encode_string("CERTIFICATE", LineEnding::LF, cert.as_ref())
+                .map_err(|err| general(&err))?;
+        }
+        let key_pem = pkcs8_pem(key)?;
+        native_tls::Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes())
+            .map(Self)
+            .map_err(|err| general(&err))
+    }
+}
+
+/// `key`, in any format rustls loads, as the PEM of a PKCS#8 key for TLS
+/// stacks that only take that.
+fn pkcs8_pem(key: &PrivateKeyDer<'_>) -> Result<Zeroizing<String>, rustls::Error> {
+    const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+    const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+    let general = |err: &dyn std::fmt::Display| rustls::Error::General(err.to_string());
+    let wrap = |oid, parameters, key| {
+        let algorithm = AlgorithmIdentifierRef { oid, parameters };
+        PrivateKeyInfo::new(algorithm, key)
+            .to_der()
+            .map(Zeroizing::new)
+            .map_err(|err| general(&err))
+    };
+    let wrapped;
+    let der = match key {
+        PrivateKeyDer::Pkcs8(key) => key.secret_pkcs8_der(),
+        PrivateKeyDer::Pkcs1(key) => {
+            wrapped = wrap(RSA_ENCRYPTION, Some(AnyRef::NULL), key.secret_pkcs1_der())?;
+            &wrapped
+        }
+        PrivateKeyDer::Sec1(key) => {
+            let ec =
+                sec1::EcPrivateKey::try_from(key.secret_sec1_der()).map_err(|err| general(&err))?;
+            let curve = ec
+                .parameters
+                .and_then(|params| params.named_curve())
+                .ok_or_else(|| rustls::Error::General("EC key names no curve".into()))?;
+            wrapped = wrap(
+                EC_PUBLIC_KEY,
+                Some(AnyRef::from(&curve)),
+                key.secret_sec1_der(),
+            )?;
+            &wrapped
+        }
+        _ => return Err(rustls::Error::General("unsupported key format".into())),
+    };
+    pem::encode_string(PrivateKeyInfo::PEM_LABEL, LineEnding::LF, der)
+        .map(Zeroizing::new)
+        .map_err(|err| general(&err))
+}
+
+/// Whether SChannel can honor `policy`. It only turns certificate and name
+/// checks off as a whole, so only a name mismatch can be allowed alone.
+pub fn supports(policy: VerifyPolicy) -> bool {
+    !policy.allow_expired && !policy.allow_unknown_ca
+}
+
+/// Runs the TLS handshake on SChannel, which verifies against the Windows
+/// certificate store under system policy. Settings it cannot honor, like
+/// added CRLs, fail the connect rather than being dropped.
+pub async fn connect(
+    host: &str,
+    tcp: TcpStream,
+    options: &TlsOptions,
+) -> io::Result<TlsStream<TcpStream>> {
+    let unsupported = |what: &str| io::Error::new(io::ErrorKind::Unsupported, what.to_owned());
+    if !options.skip_verify && !supports(options.policy) {
+        return Err(unsupported(
+            "SChannel cannot allow expired or unknown-CA certificates alone",
+        ));
+    }
+    if !options.skip_verify && !options.crls.is_empty() {
+        return Err(unsupported("SChannel cannot check added CRLs"));
+    }
+    let accept_names =
+        options.skip_verify || options.skip_hostname_check || options.policy.allow_name_mismatch;
+    if options.skip_verify {
+        logging::emit(
+            1,
+            &format!("INSECURE: SChannel accepts any certificate for {host}"),
+        );
+    }
+    if options.revocation != RevocationMode::Off {
+        logging::emit(
+            2,
+            "revocation checks are left to SChannel and system policy",
+        );
+    }
+
+    let mut builder = native_tls::TlsConnector::builder();
+    for cert in &options.ca_certs {
+        let cert = native_tls::Certificate::from_der(cert).map_err(io::Error::other)?;
+        builder.add_root_certificate(cert);
+    }
+    if let Some(cert) = &options.client_cert {
+        let Some(identity) = &cert.identity else {
+            return Err(unsupported(
+                "SChannel cannot present a client key held on a token",
+            ));
+        };
+        builder.identity(identity.0.clone());
+    }
+    let connector = builder
+        .danger_accept_invalid_hostnames(accept_names)
+        .danger_accept_invalid_certs(options.skip_verify)
+        .build()
+        .map_err(io::Error::other)?;
+    TlsConnector::from(connector)
+        .connect(host, tcp)
+        .await
+        .map_err(io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    use super::{Identity, pkcs8_pem};
+    use crate::client::tls::ClientCert;
+
+    const CLIENT_PEM: &[u8] = include_bytes!("testdata/tls/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("testdata/tls/client.key");
+    /// `CLIENT_KEY` as a SEC1 "EC PRIVATE KEY".
+    const CLIENT_SEC1_KEY: &[u8] = include_bytes!("testdata/tls/client.sec1.key");
+    /// An RSA key as PKCS#1 "RSA PRIVATE KEY".
+    const RSA_KEY: &[u8] = include_bytes!("testdata/tls/rsa.key");
+
+    #[test]
+    fn keys_convert_to_pkcs8() {
+        for key in [CLIENT_KEY, CLIENT_SEC1_KEY, RSA_KEY] {
+            let key = PrivateKeyDer::from_pem_slice(key).expect("key");
+            let pem = pkcs8_pem(&key).expect("converted");
+            let pkcs8 = PrivateKeyDer::from_pem_slice(pem.as_bytes()).expect("pkcs8");
+            assert!(matches!(pkcs8, PrivateKeyDer::Pkcs8(_)));
+            rustls::crypto::ring::sign::any_supported_type(&pkcs8).expect("usable key");
+        }
+        let sec1 = PrivateKeyDer::from_pem_slice(CLIENT_SEC1_KEY).expect("key");
+        let pem = pkcs8_pem(&sec1).expect("converted");
+        ClientCert::from_pem(CLIENT_PEM, pem.as_bytes()).expect("same key");
+
+        let chain = [CertificateDer::from_pem_slice(CLIENT_PEM).expect("cert")];
+        Identity::new(&chain, &sec1).expect("identity");
+    }
+}
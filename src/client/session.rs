@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{Instant, Sleep};
+
+use crate::logging::{self, WsppWireDirection};
+
+use super::handshake::Handshake;
+
+const MAGIC: &[u8; 8] = b"WSPPSES1";
+/// Direction byte, microseconds since the stream opened and length.
+const ENTRY_HEADER_LEN: usize = 1 + 8 + 4;
+
+/// Appends every chunk a connection sends or receives, after TLS, to a
+/// session file. Each entry is the direction byte, microseconds since the
+/// stream opened as u64 and the length as u32, little endian, then the
+/// bytes.
+pub struct SessionRecorder {
+    out: BufWriter<File>,
+    opened: Instant,
+}
+
+impl SessionRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        Ok(Self {
+            out,
+            opened: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, direction: WsppWireDirection, data: &[u8]) -> io::Result<()> {
+        let micros = self.opened.elapsed().as_micros() as u64;
+        let len = u32::try_from(data.len()).map_err(|_| io::ErrorKind::InvalidInput)?;
+        self.out.write_all(&[direction as u8])?;
+        self.out.write_all(&micros.to_le_bytes())?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.write_all(data)?;
+        // Flushed per chunk so a session cut short by a crash is still usable.
+        self.out.flush()
+    }
+}
+
+/// Records through an optional recorder, dropping it after the first
+/// failure so a full disk does not break the connection.
+pub fn record(recorder: &mut Option<SessionRecorder>, direction: WsppWireDirection, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    if let Some(active) = recorder
+        && let Err(err) = active.record(direction, data)
+    {
+        logging::emit(2, &format!("session recording stopped: {err}"));
+        *recorder = None;
+    }
+}
+
+/// A recorded session loaded for replay.
+#[derive(Debug)]
+pub struct Session {
+    /// Received chunks and when they arrived.
+    received: Vec<(Duration, Bytes)>,
+    /// Sec-WebSocket-Key of the recorded upgrade request, which the
+    /// recorded response was computed from.
+    key: Option<String>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Self::parse(&data)
+    }
+
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a session recording");
+        let mut rest = data.strip_prefix(MAGIC).ok_or_else(invalid)?;
+        let mut received = Vec::new();
+        let mut sent = Vec::new();
+        while !rest.is_empty() {
+            if rest.len() < ENTRY_HEADER_LEN {
+                return Err(invalid());
+            }
+            let (header, tail) = rest.split_at(ENTRY_HEADER_LEN);
+            let mut header = header;
+            let direction = header.get_u8();
+            let at = Duration::from_micros(header.get_u64_le());
+            let len = header.get_u32_le() as usize;
+            if tail.len() < len {
+                return Err(invalid());
+            }
+            let (chunk, tail) = tail.split_at(len);
+            match direction {
+                0 => received.push((at, Bytes::copy_from_slice(chunk))),
+                1 => sent.extend_from_slice(chunk),
+                _ => return Err(invalid()),
+            }
+            rest = tail;
+        }
+        let key = Handshake::parse(&sent)
+            .header("Sec-WebSocket-Key")
+            .map(str::to_owned);
+        Ok(Self { received, key })
+    }
+
+    pub fn key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
+/// Plays the received side of a session back on its original schedule and
+/// discards everything written. Ends with EOF once the recording does.
+pub struct ReplayStream {
+    pending: VecDeque<(Duration, Bytes)>,
+    opened: Instant,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl ReplayStream {
+    pub fn new(session: &Arc<Session>) -> Self {
+        Self {
+            pending: session.received.iter().cloned().collect(),
+            opened: Instant::now(),
+            timer: None,
+        }
+    }
+}
+
+impl AsyncRead for ReplayStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let Some((at, chunk)) = this.pending.front_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let due = this.opened + *at;
+        if Instant::now() < due {
+            let timer = this
+                .timer
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(due)));
+            ready!(timer.as_mut().poll(cx));
+        }
+        this.timer = None;
+        let len = chunk.len().min(buf.remaining());
+        buf.put_slice(&chunk.split_to(len));
+        if chunk.is_empty() {
+            this.pending.pop_front();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for ReplayStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::runtime::Builder;
+
+    use super::{ReplayStream, Session, SessionRecorder, record};
+    use crate::logging::WsppWireDirection;
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!("wspp-session-{}.bin", std::process::id()));
+        let mut recorder = Some(SessionRecorder::create(&path).expect("create"));
+        record(
+            &mut recorder,
+            WsppWireDirection::Sent,
+            b"GET / HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        );
+        record(&mut recorder, WsppWireDirection::Received, b"first");
+        record(&mut recorder, WsppWireDirection::Received, b"second");
+        drop(recorder);
+
+        let session = Arc::new(Session::load(&path).expect("load"));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(session.key(), Some("dGhlIHNhbXBsZSBub25jZQ=="));
+
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime");
+        let replayed = rt.block_on(async {
+            let mut stream = ReplayStream::new(&session);
+            stream.write_all(b"ignored").await.expect("write");
+            let mut replayed = Vec::new();
+            stream.read_to_end(&mut replayed).await.expect("read");
+            replayed
+        });
+        assert_eq!(replayed, b"firstsecond");
+    }
+
+    #[test]
+    fn rejects_truncated_recordings() {
+        assert!(Session::parse(b"WSPPSES1\x00\x01").is_err());
+        assert!(Session::parse(b"not a recording").is_err());
+        assert!(Session::parse(b"WSPPSES1").is_ok());
+    }
+}
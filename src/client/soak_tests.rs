@@ -0,0 +1,135 @@
+use std::time::{Duration, Instant};
+
+use super::WsState;
+use crate::result::WsppResult;
+use crate::test_support::{Recorded, TestServer, connected, poll_until, recorded};
+
+const DEFAULT_SOAK_SECS: u64 = 2 * 60 * 60;
+const MESSAGES_PER_CYCLE: usize = 32;
+const FD_SLACK: usize = 16;
+const THREAD_SLACK: usize = 4;
+const RSS_SLACK_KB: u64 = 64 * 1024;
+
+fn soak_duration() -> Duration {
+    let secs = std::env::var("WSPP_SOAK_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SOAK_SECS);
+    Duration::from_secs(secs)
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd").ok().map(|d| d.count())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_fds() -> Option<usize> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn status_field(name: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(name))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|v| v.parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn status_field(_name: &str) -> Option<u64> {
+    None
+}
+
+fn threads() -> Option<usize> {
+    status_field("Threads:").map(|v| v as usize)
+}
+
+fn rss_kb() -> Option<u64> {
+    status_field("VmRSS:")
+}
+
+/// Waits for detached worker threads to wind down before sampling.
+fn settled_threads(limit: usize) -> Option<usize> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let current = threads()?;
+        if current <= limit || Instant::now() >= deadline {
+            return Some(current);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn run_cycle(url: &str, cycle: u64) {
+    let mut ws = connected(url);
+
+    for i in 0..MESSAGES_PER_CYCLE {
+        if i % 2 == 0 {
+            assert_eq!(ws.send_message("soak"), Ok(WsppResult::Ok));
+        } else {
+            assert_eq!(ws.send_binary(vec![i as u8; 1024]), Ok(WsppResult::Ok));
+        }
+    }
+    assert_eq!(ws.ping(b"soak".to_vec()), Ok(WsppResult::Ok));
+    let events = poll_until(&mut ws, 2 + MESSAGES_PER_CYCLE);
+    assert_eq!(events.len(), 2 + MESSAGES_PER_CYCLE, "cycle {cycle}");
+
+    if cycle % 10 == 9 {
+        ws.shutdown();
+    } else {
+        assert_eq!(ws.close(1000, "soak"), Ok(WsppResult::Ok));
+        let events = poll_until(&mut ws, 3 + MESSAGES_PER_CYCLE);
+        assert_eq!(events.last(), Some(&Recorded::Close), "cycle {cycle}");
+        assert!(matches!(ws.get_state(), WsState::Closed));
+    }
+
+    assert_eq!(ws.poll(), 0, "queue not drained after cycle {cycle}");
+    assert!(recorded().len() <= 3 + MESSAGES_PER_CYCLE);
+}
+
+/// Run with `WSPP_SOAK_SECS=<secs> cargo test soak -- --ignored`.
+#[test]
+#[ignore = "long-running soak test"]
+fn soak_connect_send_close_cycles() {
+    let server = TestServer::start();
+    let url = server.url();
+    let duration = soak_duration();
+
+    // Warm up so lazily allocated runtime and allocator state is in the baseline.
+    for cycle in 0..10 {
+        run_cycle(&url, cycle);
+    }
+    let base_fds = open_fds();
+    let base_threads = settled_threads(0);
+    let base_rss = rss_kb();
+
+    let started = Instant::now();
+    let mut cycle = 0_u64;
+    while started.elapsed() < duration {
+        run_cycle(&url, cycle);
+        cycle += 1;
+
+        if cycle.is_multiple_of(1000) {
+            if let (Some(base), Some(now)) = (base_fds, open_fds()) {
+                assert!(now <= base + FD_SLACK, "fd leak: {base} -> {now}");
+            }
+            if let (Some(base), Some(now)) = (base_rss, rss_kb()) {
+                assert!(now <= base + RSS_SLACK_KB, "rss growth: {base} -> {now} kB");
+            }
+        }
+    }
+
+    if let (Some(base), Some(now)) = (base_fds, open_fds()) {
+        assert!(now <= base + FD_SLACK, "fd leak: {base} -> {now}");
+    }
+    if let Some(base) = base_threads {
+        let now = settled_threads(base + THREAD_SLACK).unwrap_or(base);
+        assert!(now <= base + THREAD_SLACK, "thread leak: {base} -> {now}");
+    }
+    if let (Some(base), Some(now)) = (base_rss, rss_kb()) {
+        assert!(now <= base + RSS_SLACK_KB, "rss growth: {base} -> {now} kB");
+    }
+}
@@ -0,0 +1,183 @@
+use std::io;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+/// Kernel TCP settings applied to each new connection.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SocketOptions {
+    /// TCP_USER_TIMEOUT: how long sent data may stay unacknowledged before
+    /// the kernel drops the connection. Linux only.
+    pub user_timeout: Option<Duration>,
+    /// SO_KEEPALIVE, off unless set.
+    pub keepalive: Option<Keepalive>,
+}
+
+/// Keepalive probe tuning; `None` fields keep the system defaults.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Keepalive {
+    /// Idle time before the first probe.
+    pub idle: Option<Duration>,
+    /// Time between unanswered probes.
+    pub interval: Option<Duration>,
+    /// Unanswered probes before the connection is dropped.
+    pub count: Option<u32>,
+}
+
+impl SocketOptions {
+    pub fn apply(&self, tcp: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(tcp);
+        if let Some(keepalive) = self.keepalive {
+            let mut params = TcpKeepalive::new();
+            if let Some(idle) = keepalive.idle {
+                params = params.with_time(idle);
+            }
+            socket.set_tcp_keepalive(&probes::tune_probes(params, keepalive)?)?;
+        }
+        if let Some(timeout) = self.user_timeout {
+            imp::set_user_timeout(&socket, timeout)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_vendor = "apple",
+    windows
+))]
+mod probes {
+    use std::io;
+
+    use socket2::TcpKeepalive;
+
+    use super::Keepalive;
+
+    pub fn tune_probes(mut params: TcpKeepalive, keepalive: Keepalive) -> io::Result<TcpKeepalive> {
+        if let Some(interval) = keepalive.interval {
+            params = params.with_interval(interval);
+        }
+        if let Some(count) = keepalive.count {
+            params = params.with_retries(count);
+        }
+        Ok(params)
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_vendor = "apple",
+    windows
+)))]
+mod probes {
+    use std::io;
+
+    use socket2::TcpKeepalive;
+
+    use super::Keepalive;
+
+    pub fn tune_probes(params: TcpKeepalive, keepalive: Keepalive) -> io::Result<TcpKeepalive> {
+        if keepalive.interval.is_some() || keepalive.count.is_some() {
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        }
+        Ok(params)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod imp {
+    use std::io;
+    use std::time::Duration;
+
+    use socket2::SockRef;
+
+    pub fn set_user_timeout(socket: &SockRef<'_>, timeout: Duration) -> io::Result<()> {
+        socket.set_tcp_user_timeout(Some(timeout))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod imp {
+    use std::io;
+    use std::time::Duration;
+
+    use socket2::SockRef;
+
+    pub fn set_user_timeout(_socket: &SockRef<'_>, _timeout: Duration) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use socket2::SockRef;
+
+    use tokio::net::TcpStream;
+    use tokio::runtime::Builder;
+
+    use super::{Keepalive, SocketOptions};
+
+    /// Runs `check` on a client socket connected over loopback.
+    fn with_connected(check: impl FnOnce(&TcpStream)) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime");
+        let tcp = rt.block_on(TcpStream::connect(addr)).expect("connect");
+        check(&tcp);
+    }
+
+    #[test]
+    fn sets_user_timeout() {
+        with_connected(|tcp| {
+            let options = SocketOptions {
+                user_timeout: Some(Duration::from_secs(7)),
+                ..SocketOptions::default()
+            };
+            options.apply(tcp).expect("apply");
+            assert_eq!(
+                SockRef::from(tcp).tcp_user_timeout().expect("read back"),
+                Some(Duration::from_secs(7))
+            );
+        });
+    }
+
+    #[test]
+    fn tunes_keepalive_probes() {
+        with_connected(|tcp| {
+            let options = SocketOptions {
+                keepalive: Some(Keepalive {
+                    idle: Some(Duration::from_secs(30)),
+                    interval: Some(Duration::from_secs(5)),
+                    count: Some(4),
+                }),
+                ..SocketOptions::default()
+            };
+            options.apply(tcp).expect("apply");
+
+            let socket = SockRef::from(tcp);
+            assert!(socket.keepalive().expect("keepalive"));
+            assert_eq!(
+                socket.tcp_keepalive_time().ok(),
+                Some(Duration::from_secs(30))
+            );
+            assert_eq!(
+                socket.tcp_keepalive_interval().ok(),
+                Some(Duration::from_secs(5))
+            );
+            assert_eq!(socket.tcp_keepalive_retries().ok(), Some(4));
+        });
+    }
+}
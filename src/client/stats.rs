@@ -0,0 +1,204 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::logging;
+
+/// Minimum time between two data-loss warnings from one handle.
+const LOSS_WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Counter snapshot returned by `wspp_get_stats`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WsppStats {
+    /// Id of the most recent successful connection, unique in the process.
+    pub connection_id: u64,
+    /// Outgoing messages evicted or rejected because the send queue was full.
+    pub dropped_queue_full: u64,
+    /// Outgoing messages discarded after their time-to-live ran out.
+    pub expired: u64,
+    /// Incoming messages dropped by the memory budget policy.
+    pub dropped_memory_budget: u64,
+    /// Pongs that matched no outstanding ping.
+    pub unsolicited_pongs: u64,
+    /// Commands sent to the worker and not yet picked up.
+    pub queued_commands: u64,
+    /// Most commands ever queued at once.
+    pub command_high_water: u64,
+    /// Events received from the worker and not yet polled.
+    pub queued_events: u64,
+    /// Most events ever queued at once.
+    pub event_high_water: u64,
+    /// Index of the endpoint connected to or tried last: 0 for the URI the
+    /// handle was created with, then its fallbacks in the order added.
+    pub endpoint: u64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Loss {
+    QueueFull,
+    Expired,
+    MemoryBudget,
+}
+
+/// Depth of one channel between the handle and its worker.
+#[derive(Default)]
+pub struct QueueGauge {
+    depth: AtomicU64,
+    high_water: AtomicU64,
+}
+
+impl QueueGauge {
+    pub fn push(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.high_water.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Saturates at zero, as a worker left over from an earlier connection
+    /// may still take items after `reset`.
+    pub fn pop(&self) {
+        let _ = self
+            .depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                depth.checked_sub(1)
+            });
+    }
+
+    /// Forgets the items of an abandoned channel; the high-water mark stays.
+    pub fn reset(&self) {
+        self.depth.store(0, Ordering::Relaxed);
+    }
+
+    pub fn depth(&self) -> u64 {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn high_water(&self) -> u64 {
+        self.high_water.load(Ordering::Relaxed)
+    }
+}
+
+/// Per-handle counters, shared with every worker the handle spawns so they
+/// survive reconnects.
+#[derive(Default)]
+pub struct HandleStats {
+    connection_id: AtomicU64,
+    dropped_queue_full: AtomicU64,
+    expired: AtomicU64,
+    dropped_memory_budget: AtomicU64,
+    unsolicited_pongs: AtomicU64,
+    pub commands: QueueGauge,
+    pub events: QueueGauge,
+    last_warning: Mutex<Option<Instant>>,
+}
+
+impl HandleStats {
+    pub fn record_loss(&self, loss: Loss) {
+        let counter = match loss {
+            Loss::QueueFull => &self.dropped_queue_full,
+            Loss::Expired => &self.expired,
+            Loss::MemoryBudget => &self.dropped_memory_budget,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.warn(Instant::now());
+    }
+
+    pub fn record_unsolicited_pong(&self) {
+        self.unsolicited_pongs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_connection_id(&self, id: u64) {
+        self.connection_id.store(id, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> WsppStats {
+        WsppStats {
+            connection_id: self.connection_id.load(Ordering::Relaxed),
+            dropped_queue_full: self.dropped_queue_full.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            dropped_memory_budget: self.dropped_memory_budget.load(Ordering::Relaxed),
+            unsolicited_pongs: self.unsolicited_pongs.load(Ordering::Relaxed),
+            queued_commands: self.commands.depth(),
+            command_high_water: self.commands.high_water(),
+            queued_events: self.events.depth(),
+            event_high_water: self.events.high_water(),
+            endpoint: 0,
+        }
+    }
+
+    /// Logs the running totals, at most once per warning interval.
+    fn warn(&self, now: Instant) -> bool {
+        let Ok(mut last) = self.last_warning.lock() else {
+            return false;
+        };
+        if last.is_some_and(|at| now.duration_since(at) < LOSS_WARNING_INTERVAL) {
+            return false;
+        }
+        *last = Some(now);
+        drop(last);
+
+        let stats = self.snapshot();
+        logging::emit(
+            2,
+            &format!(
+                "connection {}: messages lost: {} queue full, {} expired, {} memory budget",
+                stats.connection_id,
+                stats.dropped_queue_full,
+                stats.expired,
+                stats.dropped_memory_budget
+            ),
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{HandleStats, Loss, QueueGauge, WsppStats};
+
+    #[test]
+    fn counts_each_kind_of_loss() {
+        let stats = HandleStats::default();
+        stats.record_loss(Loss::QueueFull);
+        stats.record_loss(Loss::Expired);
+        stats.record_loss(Loss::Expired);
+        stats.record_loss(Loss::MemoryBudget);
+
+        assert_eq!(
+            stats.snapshot(),
+            WsppStats {
+                dropped_queue_full: 1,
+                expired: 2,
+                dropped_memory_budget: 1,
+                ..WsppStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn gauge_keeps_the_high_water_mark() {
+        let gauge = QueueGauge::default();
+        gauge.push();
+        gauge.push();
+        gauge.pop();
+        gauge.push();
+        gauge.push();
+        assert_eq!((gauge.depth(), gauge.high_water()), (3, 3));
+
+        gauge.reset();
+        gauge.pop();
+        assert_eq!((gauge.depth(), gauge.high_water()), (0, 3));
+    }
+
+    #[test]
+    fn warnings_are_rate_limited() {
+        let stats = HandleStats::default();
+        let start = Instant::now();
+
+        assert!(stats.warn(start));
+        assert!(!stats.warn(start + Duration::from_secs(1)));
+        assert!(stats.warn(start + Duration::from_secs(6)));
+    }
+}
@@ -0,0 +1,119 @@
+use std::ffi::{c_char, c_void};
+use std::fs::File;
+use std::io::Read;
+
+use bytes::Bytes;
+
+use crate::callback::StreamProvider;
+
+/// Host callback producing the chunks of a streamed message. The userdata
+/// pointer is only ever used from the worker thread.
+#[derive(Debug)]
+pub struct ProviderSource {
+    provider: StreamProvider,
+    userdata: *mut c_void,
+}
+
+unsafe impl Send for ProviderSource {}
+
+impl ProviderSource {
+    pub fn new(provider: StreamProvider, userdata: *mut c_void) -> Self {
+        Self { provider, userdata }
+    }
+}
+
+/// Where the worker pulls the payload of a streamed message from.
+#[derive(Debug)]
+pub enum ChunkSource {
+    Provider(ProviderSource),
+    File(File),
+}
+
+impl ChunkSource {
+    /// Reads up to `max` bytes. An empty chunk marks the end of the message.
+    pub fn next_chunk(&mut self, max: usize) -> Result<Bytes, String> {
+        match self {
+            Self::Provider(source) => {
+                let mut buf = vec![0_u8; max];
+                let written =
+                    (source.provider)(source.userdata, buf.as_mut_ptr() as *mut c_char, max as u64);
+                let written =
+                    usize::try_from(written).map_err(|_| format!("provider returned {written}"))?;
+                if written > max {
+                    return Err(format!("provider wrote {written} bytes into {max}"));
+                }
+                buf.truncate(written);
+                Ok(Bytes::from(buf))
+            }
+            Self::File(file) => {
+                let mut buf = Vec::with_capacity(max);
+                file.by_ref()
+                    .take(max as u64)
+                    .read_to_end(&mut buf)
+                    .map_err(|err| err.to_string())?;
+                Ok(Bytes::from(buf))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, c_void};
+
+    use super::{ChunkSource, ProviderSource};
+
+    extern "C" fn countdown(userdata: *mut c_void, buf: *mut c_char, cap: u64) -> i64 {
+        let remaining = unsafe { &mut *(userdata as *mut u8) };
+        if *remaining == 0 {
+            return 0;
+        }
+        *remaining -= 1;
+        let n = cap.min(3) as usize;
+        unsafe { std::ptr::write_bytes(buf as *mut u8, *remaining, n) };
+        n as i64
+    }
+
+    extern "C" fn failing(_userdata: *mut c_void, _buf: *mut c_char, _cap: u64) -> i64 {
+        -1
+    }
+
+    extern "C" fn overflowing(_userdata: *mut c_void, _buf: *mut c_char, cap: u64) -> i64 {
+        cap as i64 + 1
+    }
+
+    #[test]
+    fn reads_chunks_until_empty() {
+        let mut remaining = 2_u8;
+        let mut source = ChunkSource::Provider(ProviderSource::new(
+            countdown,
+            &mut remaining as *mut u8 as *mut c_void,
+        ));
+
+        assert_eq!(source.next_chunk(8).expect("chunk").as_ref(), &[1, 1, 1]);
+        assert_eq!(source.next_chunk(2).expect("chunk").as_ref(), &[0, 0]);
+        assert!(source.next_chunk(8).expect("end").is_empty());
+    }
+
+    #[test]
+    fn reads_files_in_bounded_chunks() {
+        let path = std::env::temp_dir().join(format!("wspp-stream-{}", std::process::id()));
+        std::fs::write(&path, b"abcdefg").expect("write source file");
+        let mut source = ChunkSource::File(std::fs::File::open(&path).expect("open"));
+
+        assert_eq!(source.next_chunk(4).expect("chunk").as_ref(), b"abcd");
+        assert_eq!(source.next_chunk(4).expect("chunk").as_ref(), b"efg");
+        assert!(source.next_chunk(4).expect("end").is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejects_negative_and_oversized_lengths() {
+        let mut source = ChunkSource::Provider(ProviderSource::new(failing, std::ptr::null_mut()));
+        assert!(source.next_chunk(8).is_err());
+
+        let mut source =
+            ChunkSource::Provider(ProviderSource::new(overflowing, std::ptr::null_mut()));
+        assert!(source.next_chunk(8).is_err());
+    }
+}
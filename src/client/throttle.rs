@@ -0,0 +1,115 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{Instant, Sleep};
+
+/// Bytes let through before throttling starts waiting for smaller refills,
+/// so a limited stream is written in reasonably sized pieces.
+const MIN_REFILL: f64 = 512.0;
+
+/// Per-direction byte rates; `None` leaves that direction unlimited.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct BandwidthLimit {
+    pub up: Option<u64>,
+    pub down: Option<u64>,
+}
+
+/// Token bucket allowing `rate` bytes per second with bursts of up to a
+/// tenth of a second worth of traffic.
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    refilled_at: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        let capacity = (rate / 10.0).max(MIN_REFILL);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled_at: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled_at = now;
+    }
+
+    /// Number of bytes that may pass now, or pending until enough did
+    /// accumulate. Callers report what they used with `consume`.
+    pub fn poll_allowance(&mut self, cx: &mut Context<'_>) -> Poll<usize> {
+        self.refill(Instant::now());
+        let wanted = self.capacity.min(MIN_REFILL);
+        if self.tokens >= wanted {
+            return Poll::Ready(self.tokens as usize);
+        }
+
+        let wait = Duration::from_secs_f64((wanted - self.tokens) / self.rate);
+        let deadline = Instant::now() + wait;
+        let sleep = self
+            .sleep
+            .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+        sleep.as_mut().reset(deadline);
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                self.refill(Instant::now());
+                Poll::Ready((self.tokens as usize).max(1))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use std::time::Duration;
+
+    use tokio::runtime::Builder;
+    use tokio::time::Instant;
+
+    use super::TokenBucket;
+
+    #[test]
+    fn allowance_starts_with_a_burst() {
+        let mut bucket = TokenBucket::new(100_000);
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime");
+
+        let burst = rt.block_on(poll_fn(|cx| bucket.poll_allowance(cx)));
+        assert_eq!(burst, 10_000);
+    }
+
+    #[test]
+    fn waits_for_tokens_once_spent() {
+        let mut bucket = TokenBucket::new(10_000);
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime");
+
+        let burst = rt.block_on(poll_fn(|cx| bucket.poll_allowance(cx)));
+        bucket.consume(burst);
+        let started = Instant::now();
+        let refill = rt.block_on(poll_fn(|cx| bucket.poll_allowance(cx)));
+
+        assert!(refill >= 512);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+}
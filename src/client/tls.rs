@@ -0,0 +1,1092 @@
+use std::ffi::{c_char, c_void};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use p12_keystore::KeyStore;
+use pkcs8::der::pem::{self, PemLabel};
+use pkcs8::{EncryptedPrivateKeyInfo, LineEnding, PrivateKeyInfo};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{WantsClientCert, WebPkiServerVerifier, verify_server_name};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{
+    CertificateDer, CertificateRevocationListDer, PrivateKeyDer, ServerName, UnixTime,
+};
+use rustls::server::ParsedCertificate;
+use rustls::sign::{CertifiedKey, SingleCertAndKey};
+use rustls::{
+    CertificateError, ClientConfig, ConfigBuilder, DigitallySignedStruct, RootCertStore,
+    SignatureScheme,
+};
+use zeroize::Zeroizing;
+
+use crate::callback::KeyPassphraseSource;
+use crate::logging;
+
+use super::ocsp::{self, CertStatus};
+
+/// How the server certificate's revocation status is checked.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RevocationMode {
+    #[default]
+    Off = 0,
+    /// Rejects a certificate that a stapled OCSP response or a configured
+    /// CRL reports revoked; a missing or unusable status is only logged.
+    SoftFail = 1,
+    /// Like `SoftFail`, but the server must staple a valid OCSP response.
+    RequireStapled = 2,
+}
+
+impl RevocationMode {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Off),
+            1 => Some(Self::SoftFail),
+            2 => Some(Self::RequireStapled),
+            _ => None,
+        }
+    }
+}
+
+/// Certificate problems to accept anyway, for staging servers that fail
+/// verification in one known way. Every accepted problem is logged.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct VerifyPolicy {
+    /// Certificates outside their validity period.
+    pub allow_expired: bool,
+    /// Chains that do not end at a trusted root.
+    pub allow_unknown_ca: bool,
+    /// Certificates not issued for the host connected to.
+    pub allow_name_mismatch: bool,
+}
+
+impl VerifyPolicy {
+    const ALLOW_EXPIRED: u32 = 1;
+    const ALLOW_UNKNOWN_CA: u32 = 1 << 1;
+    const ALLOW_NAME_MISMATCH: u32 = 1 << 2;
+
+    /// Decodes the C flag bits; unknown bits are rejected.
+    pub fn from_ffi(flags: u32) -> Option<Self> {
+        let known = Self::ALLOW_EXPIRED | Self::ALLOW_UNKNOWN_CA | Self::ALLOW_NAME_MISMATCH;
+        (flags & !known == 0).then_some(Self {
+            allow_expired: flags & Self::ALLOW_EXPIRED != 0,
+            allow_unknown_ca: flags & Self::ALLOW_UNKNOWN_CA != 0,
+            allow_name_mismatch: flags & Self::ALLOW_NAME_MISMATCH != 0,
+        })
+    }
+}
+
+/// A certificate chain and its private key, presented to servers that ask
+/// for a client certificate.
+#[derive(Clone, Debug)]
+pub struct ClientCert {
+    key: Arc<CertifiedKey>,
+    /// The same pair for SChannel, `None` for a key that never leaves its
+    /// token.
+    #[cfg(all(windows, feature = "schannel"))]
+    pub identity: Option<super::schannel::Identity>,
+}
+
+impl ClientCert {
+    /// Loads the chain in `cert_pem` and the key in `key_pem`, checking the
+    /// key belongs to the first certificate.
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self, rustls::Error> {
+        let chain = CertificateDer::pem_slice_iter(cert_pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        if chain.is_empty() {
+            return Err(rustls::Error::NoCertificatesPresented);
+        }
+        let key = PrivateKeyDer::from_pem_slice(key_pem)
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        #[cfg(all(windows, feature = "schannel"))]
+        let identity = Some(super::schannel::Identity::new(&chain, &key)?);
+        let key = CertifiedKey::from_der(chain, key, &provider())?;
+        Ok(Self {
+            key: Arc::new(key),
+            #[cfg(all(windows, feature = "schannel"))]
+            identity,
+        })
+    }
+
+    /// A chain whose key signs elsewhere, e.g. on a PKCS#11 token, so only
+    /// rustls can present it.
+    #[cfg(feature = "pkcs11")]
+    pub fn from_key(key: CertifiedKey) -> Self {
+        Self {
+            key: Arc::new(key),
+            #[cfg(all(windows, feature = "schannel"))]
+            identity: None,
+        }
+    }
+
+    /// Loads the first key and its chain from the PKCS#12 bundle `der`,
+    /// decrypted and checked against its MAC with `passphrase`.
+    pub fn from_pkcs12(der: &[u8], passphrase: &str) -> Result<Self, rustls::Error> {
+        let general = |err: &dyn std::fmt::Display| rustls::Error::General(err.to_string());
+        let store = KeyStore::from_pkcs12(der, passphrase).map_err(|err| general(&err))?;
+        let (_, entry) = store
+            .private_key_chain()
+            .ok_or_else(|| rustls::Error::General("no private key in the bundle".into()))?;
+        let mut cert_pem = String::new();
+        for cert in entry.chain() {
+            cert_pem += &pem::encode_string("CERTIFICATE", LineEnding::LF, cert.as_der())
+                .map_err(|err| general(&err))?;
+        }
+        let key_pem = pem::encode_string(PrivateKeyInfo::PEM_LABEL, LineEnding::LF, entry.key())
+            .map(Zeroizing::new)
+            .map_err(|err| general(&err))?;
+        Self::from_pem(cert_pem.as_bytes(), key_pem.as_bytes())
+    }
+}
+
+/// A client certificate whose key is an encrypted PKCS#8 key, kept
+/// encrypted until a connect asks for the passphrase.
+#[derive(Clone, Debug)]
+pub struct LockedClientCert {
+    cert_pem: Vec<u8>,
+    key: pkcs8::Document,
+}
+
+impl LockedClientCert {
+    /// Whether `key_pem` holds an encrypted PKCS#8 key.
+    pub fn is_encrypted(key_pem: &[u8]) -> bool {
+        pkcs8::der::pem::decode_label(key_pem)
+            .is_ok_and(|label| label == EncryptedPrivateKeyInfo::PEM_LABEL)
+    }
+
+    /// Keeps the chain in `cert_pem` with the encrypted key in `key_pem`.
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self, rustls::Error> {
+        let general = |err: &dyn std::fmt::Display| rustls::Error::General(err.to_string());
+        let key_pem = std::str::from_utf8(key_pem).map_err(|err| general(&err))?;
+        let (_, key) = pkcs8::Document::from_pem(key_pem).map_err(|err| general(&err))?;
+        EncryptedPrivateKeyInfo::try_from(key.as_bytes()).map_err(|err| general(&err))?;
+        if CertificateDer::pem_slice_iter(cert_pem).next().is_none() {
+            return Err(rustls::Error::NoCertificatesPresented);
+        }
+        Ok(Self {
+            cert_pem: cert_pem.to_vec(),
+            key,
+        })
+    }
+
+    /// Decrypts the key with `passphrase`, checking it belongs to the
+    /// first certificate as `ClientCert::from_pem` does.
+    pub fn unlock(&self, passphrase: &[u8]) -> Result<ClientCert, rustls::Error> {
+        let info = EncryptedPrivateKeyInfo::try_from(self.key.as_bytes())
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        let key = info.decrypt(passphrase).map_err(|_| {
+            rustls::Error::General("wrong passphrase or unsupported key encryption".into())
+        })?;
+        let key_pem = key
+            .to_pem(PrivateKeyInfo::PEM_LABEL, LineEnding::LF)
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        ClientCert::from_pem(&self.cert_pem, key_pem.as_bytes())
+    }
+}
+
+/// Longest passphrase a host callback may hand over.
+const MAX_PASSPHRASE: usize = 1024;
+
+/// Where the passphrase of an encrypted client key comes from.
+#[derive(Clone, Debug, Default)]
+pub enum Passphrase {
+    #[default]
+    Missing,
+    Fixed(Zeroizing<Vec<u8>>),
+    /// Asked from the host on every connect, so it is held no longer than
+    /// the key takes to decrypt.
+    Host(HostPassphrase),
+}
+
+/// Host passphrase callback. The userdata pointer is only ever used from
+/// the thread connecting the handle.
+#[derive(Clone, Copy, Debug)]
+pub struct HostPassphrase {
+    fill: KeyPassphraseSource,
+    userdata: *mut c_void,
+}
+
+unsafe impl Send for HostPassphrase {}
+
+impl HostPassphrase {
+    pub fn new(fill: KeyPassphraseSource, userdata: *mut c_void) -> Self {
+        Self { fill, userdata }
+    }
+}
+
+impl Passphrase {
+    /// The passphrase, `None` if there is none or the host declined.
+    pub(super) fn get(&self) -> Option<Zeroizing<Vec<u8>>> {
+        match self {
+            Self::Missing => None,
+            Self::Fixed(passphrase) => Some(passphrase.clone()),
+            Self::Host(host) => {
+                let mut buf = Zeroizing::new(vec![0_u8; MAX_PASSPHRASE]);
+                let len = (host.fill)(
+                    host.userdata,
+                    buf.as_mut_ptr() as *mut c_char,
+                    MAX_PASSPHRASE as u64,
+                );
+                let len = usize::try_from(len)
+                    .ok()
+                    .filter(|&len| len <= MAX_PASSPHRASE)?;
+                buf.truncate(len);
+                Some(buf)
+            }
+        }
+    }
+}
+
+/// TLS settings for `wss` connections.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    pub revocation: RevocationMode,
+    /// DER CRLs consulted while revocation checks are on.
+    pub crls: Vec<CertificateRevocationListDer<'static>>,
+    pub policy: VerifyPolicy,
+    /// Validates the chain but not the name it was issued for, e.g. for
+    /// clusters sharing one certificate that are reached by IP.
+    pub skip_hostname_check: bool,
+    /// Accepts any server certificate, for development against
+    /// self-signed servers. Overrides every other verification setting.
+    pub skip_verify: bool,
+    /// Evaluates trust through the macOS Security framework, honoring
+    /// keychain trust settings and configuration profiles, instead of the
+    /// bundled roots. Only ever set on Apple platforms.
+    pub system_trust: bool,
+    /// Roots trusted next to the bundled ones, e.g. a private CA.
+    pub ca_certs: Vec<CertificateDer<'static>>,
+    pub client_cert: Option<ClientCert>,
+    /// A client certificate with an encrypted key, unlocked on connect.
+    pub locked_client_cert: Option<LockedClientCert>,
+    pub key_passphrase: Passphrase,
+    /// A client certificate whose key stays on a PKCS#11 token, opened on
+    /// connect.
+    #[cfg(feature = "pkcs11")]
+    pub pkcs11: Option<super::Pkcs11Identity>,
+}
+
+impl TlsOptions {
+    fn is_default(&self) -> bool {
+        self.revocation == RevocationMode::Off
+            && self.policy == VerifyPolicy::default()
+            && !self.skip_hostname_check
+            && !self.skip_verify
+            && !self.system_trust
+            && self.ca_certs.is_empty()
+            && self.client_cert.is_none()
+            && self.locked_client_cert.is_none()
+            && !self.has_pkcs11()
+    }
+
+    /// Forgets every client certificate, loaded, locked or on a token.
+    pub fn clear_client_cert(&mut self) {
+        (self.client_cert, self.locked_client_cert) = (None, None);
+        #[cfg(feature = "pkcs11")]
+        {
+            self.pkcs11 = None;
+        }
+    }
+
+    #[cfg(feature = "pkcs11")]
+    fn has_pkcs11(&self) -> bool {
+        self.pkcs11.is_some()
+    }
+
+    #[cfg(not(feature = "pkcs11"))]
+    fn has_pkcs11(&self) -> bool {
+        false
+    }
+
+    /// Decrypts the locked client certificate, if there is one, into the
+    /// one presented, dropping the passphrase from these options. A PKCS#11
+    /// certificate is looked up on its token instead.
+    pub fn unlock_client_cert(&mut self) -> Result<(), rustls::Error> {
+        let passphrase = std::mem::take(&mut self.key_passphrase);
+        #[cfg(feature = "pkcs11")]
+        if let Some(identity) = self.pkcs11.take() {
+            self.client_cert = Some(ClientCert::from_key(identity.open()?));
+            return Ok(());
+        }
+        let Some(locked) = self.locked_client_cert.take() else {
+            return Ok(());
+        };
+        let passphrase = passphrase.get().ok_or_else(|| {
+            rustls::Error::General("no passphrase for the encrypted client key".into())
+        })?;
+        self.client_cert = Some(locked.unlock(&passphrase)?);
+        Ok(())
+    }
+}
+
+#[cfg(not(any(feature = "post-quantum", feature = "fips")))]
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// With `fips` enabled this is the FIPS module.
+#[cfg(any(feature = "post-quantum", feature = "fips"))]
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::aws_lc_rs::default_provider())
+}
+
+/// Whether `wss` connections only use FIPS-approved algorithms from a
+/// validated module, as rustls judges the default config.
+pub fn is_fips() -> bool {
+    client_config(&TlsOptions::default()).is_ok_and(|config| config.fips())
+}
+
+fn web_roots() -> Arc<RootCertStore> {
+    Arc::new(RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    })
+}
+
+/// The bundled roots plus those of `options`.
+fn roots(options: &TlsOptions) -> Arc<RootCertStore> {
+    if options.ca_certs.is_empty() {
+        return web_roots();
+    }
+    let mut roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    roots.add_parsable_certificates(options.ca_certs.iter().cloned());
+    Arc::new(roots)
+}
+
+/// The certificates of a PEM bundle, if there is at least one and all of
+/// them can serve as trust anchors.
+pub fn parse_ca_pem(pem: &[u8]) -> Option<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let mut check = RootCertStore::empty();
+    for cert in &certs {
+        check.add(cert.clone()).ok()?;
+    }
+    (!certs.is_empty()).then_some(certs)
+}
+
+/// Whether `der` parses as a certificate revocation list.
+pub fn is_valid_crl(der: &[u8]) -> bool {
+    WebPkiServerVerifier::builder_with_provider(web_roots(), provider())
+        .with_crls([CertificateRevocationListDer::from(der.to_vec())])
+        .build()
+        .is_ok()
+}
+
+/// Client config for `options`. The common all-defaults config is built
+/// once and shared.
+pub fn client_config(options: &TlsOptions) -> Result<Arc<ClientConfig>, rustls::Error> {
+    static DEFAULT: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    if options.is_default()
+        && let Some(config) = DEFAULT.get()
+    {
+        return Ok(config.clone());
+    }
+
+    let builder =
+        ClientConfig::builder_with_provider(provider()).with_safe_default_protocol_versions()?;
+    if options.skip_verify {
+        let verifier = AcceptAny {
+            algorithms: provider().signature_verification_algorithms,
+        };
+        let builder = builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier));
+        return Ok(Arc::new(with_client_auth(builder, options)));
+    }
+    #[cfg(target_vendor = "apple")]
+    if options.system_trust {
+        let builder = builder
+            .dangerous()
+            .with_custom_certificate_verifier(system_verifier(options)?);
+        return Ok(Arc::new(with_client_auth(builder, options)));
+    }
+    if options.is_default() {
+        let config = builder
+            .with_root_certificates(web_roots())
+            .with_no_client_auth();
+        return Ok(DEFAULT.get_or_init(|| Arc::new(config)).clone());
+    }
+    let verifier = Verifier::new(roots(options), options)?;
+    let builder = builder
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    Ok(Arc::new(with_client_auth(builder, options)))
+}
+
+/// Presents the client certificate of `options`, if there is one.
+fn with_client_auth(
+    builder: ConfigBuilder<ClientConfig, WantsClientCert>,
+    options: &TlsOptions,
+) -> ClientConfig {
+    match &options.client_cert {
+        Some(cert) => {
+            builder.with_client_cert_resolver(Arc::new(SingleCertAndKey::from(cert.key.clone())))
+        }
+        None => builder.with_no_client_auth(),
+    }
+}
+
+/// Security framework trust evaluation. It applies its own revocation
+/// checks and has no notion of accepting single problems, so the other
+/// options are left out.
+#[cfg(target_vendor = "apple")]
+fn system_verifier(options: &TlsOptions) -> Result<Arc<dyn ServerCertVerifier>, rustls::Error> {
+    if options.revocation != RevocationMode::Off
+        || options.policy != VerifyPolicy::default()
+        || options.skip_hostname_check
+    {
+        logging::emit(
+            2,
+            "verify flags and revocation settings do not apply with system trust",
+        );
+    }
+    if !options.ca_certs.is_empty() {
+        return Ok(Arc::new(
+            rustls_platform_verifier::Verifier::new_with_extra_roots(
+                options.ca_certs.iter().cloned(),
+                provider(),
+            )?,
+        ));
+    }
+    Ok(Arc::new(rustls_platform_verifier::Verifier::new(
+        provider(),
+    )?))
+}
+
+fn is_validity_error(err: &CertificateError) -> bool {
+    matches!(
+        err,
+        CertificateError::Expired
+            | CertificateError::ExpiredContext { .. }
+            | CertificateError::NotValidYet
+            | CertificateError::NotValidYetContext { .. }
+    )
+}
+
+fn is_name_error(err: &CertificateError) -> bool {
+    matches!(
+        err,
+        CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. }
+    )
+}
+
+fn accept_anyway(problem: &str, server_name: &ServerName<'_>) {
+    logging::emit(
+        1,
+        &format!(
+            "INSECURE: accepting certificate for {} despite {problem}",
+            server_name.to_str()
+        ),
+    );
+}
+
+/// WebPKI verification relaxed by a `VerifyPolicy`, followed by the
+/// revocation checks of a `RevocationMode`.
+#[derive(Debug)]
+struct Verifier {
+    inner: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>,
+    policy: VerifyPolicy,
+    skip_hostname_check: bool,
+    revocation: RevocationMode,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl Verifier {
+    fn new(roots: Arc<RootCertStore>, options: &TlsOptions) -> Result<Self, rustls::Error> {
+        let crls = match options.revocation {
+            RevocationMode::Off => &[][..],
+            _ => &options.crls[..],
+        };
+        let provider = provider();
+        let inner = WebPkiServerVerifier::builder_with_provider(roots.clone(), provider.clone())
+            .with_crls(crls.iter().cloned())
+            .only_check_end_entity_revocation()
+            .allow_unknown_revocation_status()
+            .build()
+            .map_err(|err| rustls::Error::General(err.to_string()))?;
+        Ok(Self {
+            inner,
+            roots,
+            policy: options.policy,
+            skip_hostname_check: options.skip_hostname_check,
+            revocation: options.revocation,
+            algorithms: provider.signature_verification_algorithms,
+        })
+    }
+
+    /// Chain, validity and name checks, letting through what `policy`
+    /// allows. WebPKI stops at the first problem, so an accepted one is
+    /// worked around and the remaining checks are still run.
+    fn verify_chain(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        now: UnixTime,
+        policy: VerifyPolicy,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let err =
+            match self
+                .inner
+                .verify_server_cert(end_entity, intermediates, server_name, &[], now)
+            {
+                Err(rustls::Error::InvalidCertificate(err)) => err,
+                result => return result,
+            };
+
+        if is_validity_error(&err) && policy.allow_expired {
+            accept_anyway("its validity period", server_name);
+            // Check everything else as of a moment the certificate was valid.
+            let (not_before, not_after) =
+                ocsp::validity(end_entity).map_err(|_| CertificateError::BadEncoding)?;
+            let valid_at = not_before + not_after.saturating_sub(not_before) / 2;
+            let policy = VerifyPolicy {
+                allow_expired: false,
+                ..policy
+            };
+            let valid_at = UnixTime::since_unix_epoch(Duration::from_secs(valid_at));
+            return self.verify_chain(end_entity, intermediates, server_name, valid_at, policy);
+        }
+        if err == CertificateError::UnknownIssuer && policy.allow_unknown_ca {
+            accept_anyway("an unknown issuer", server_name);
+            let (not_before, not_after) =
+                ocsp::validity(end_entity).map_err(|_| CertificateError::BadEncoding)?;
+            if !policy.allow_expired && now.as_secs() > not_after {
+                return Err(CertificateError::Expired.into());
+            }
+            if !policy.allow_expired && now.as_secs() < not_before {
+                return Err(CertificateError::NotValidYet.into());
+            }
+            let cert = ParsedCertificate::try_from(end_entity)?;
+            return match verify_server_name(&cert, server_name) {
+                Err(rustls::Error::InvalidCertificate(err))
+                    if is_name_error(&err) && self.name_mismatch_ok(policy, server_name) =>
+                {
+                    Ok(ServerCertVerified::assertion())
+                }
+                result => result.map(|()| ServerCertVerified::assertion()),
+            };
+        }
+        // The name is checked last, so everything else already passed.
+        if is_name_error(&err) && self.name_mismatch_ok(policy, server_name) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        Err(err.into())
+    }
+
+    fn name_mismatch_ok(&self, policy: VerifyPolicy, server_name: &ServerName<'_>) -> bool {
+        if self.skip_hostname_check {
+            logging::emit(
+                3,
+                &format!("hostname check skipped for {}", server_name.to_str()),
+            );
+            return true;
+        }
+        if policy.allow_name_mismatch {
+            accept_anyway("a name mismatch", server_name);
+        }
+        policy.allow_name_mismatch
+    }
+
+    fn status_unknown(
+        &self,
+        reason: &str,
+        verified: ServerCertVerified,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.revocation == RevocationMode::RequireStapled {
+            logging::emit(2, &format!("revocation check failed: {reason}"));
+            return Err(CertificateError::UnknownRevocationStatus.into());
+        }
+        logging::emit(3, &format!("revocation status unknown: {reason}"));
+        Ok(verified)
+    }
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified =
+            self.verify_chain(end_entity, intermediates, server_name, now, self.policy)?;
+        if self.revocation == RevocationMode::Off {
+            return Ok(verified);
+        }
+        if ocsp_response.is_empty() {
+            return self.status_unknown("no OCSP response stapled", verified);
+        }
+        let status = ocsp::leaf_status(
+            ocsp_response,
+            end_entity,
+            intermediates,
+            &self.roots,
+            now,
+            &self.algorithms,
+        );
+        match status {
+            Ok(CertStatus::Good) => Ok(verified),
+            Ok(CertStatus::Revoked) => Err(CertificateError::Revoked.into()),
+            Ok(CertStatus::Unknown) => {
+                self.status_unknown("responder does not know the certificate", verified)
+            }
+            Err(reason) => self.status_unknown(reason, verified),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Takes any server certificate on trust. The handshake signatures are
+/// still checked, so the server must hold the key of what it presented.
+#[derive(Debug)]
+struct AcceptAny {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, c_void};
+    use std::ptr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rustls::client::danger::ServerCertVerifier;
+    use rustls::pki_types::{CertificateDer, CertificateRevocationListDer, ServerName, UnixTime};
+    use rustls::{CertificateError, RootCertStore};
+
+    use super::{
+        ClientCert, HostPassphrase, LockedClientCert, Passphrase, RevocationMode, TlsOptions,
+        Verifier, VerifyPolicy, client_config, is_valid_crl, parse_ca_pem,
+    };
+
+    const CA: &[u8] = include_bytes!("testdata/tls/ca.der");
+    const CA_PEM: &[u8] = include_bytes!("testdata/tls/ca.pem");
+    const CLIENT_PEM: &[u8] = include_bytes!("testdata/tls/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("testdata/tls/client.key");
+    const OTHER_KEY: &[u8] = include_bytes!("testdata/tls/other.key");
+    /// `CLIENT_KEY` encrypted with PBES2 (AES-256-CBC) and passphrase "hunter2".
+    const CLIENT_ENC_KEY: &[u8] = include_bytes!("testdata/tls/client.enc.key");
+    /// The client pair as PKCS#12 with passphrase "hunter2", in OpenSSL 3's
+    /// default AES-256 encryption and in the legacy 3DES one.
+    const CLIENT_P12: &[u8] = include_bytes!("testdata/tls/client.p12");
+    const CLIENT_LEGACY_P12: &[u8] = include_bytes!("testdata/tls/client-legacy.p12");
+    const LEAF: &[u8] = include_bytes!("testdata/tls/leaf.der");
+    const OTHER: &[u8] = include_bytes!("testdata/tls/other.der");
+    const GOOD: &[u8] = include_bytes!("testdata/tls/good.der");
+    const REVOKED: &[u8] = include_bytes!("testdata/tls/revoked.der");
+    const CRL: &[u8] = include_bytes!("testdata/tls/revoked.crl");
+
+    /// Verifies the test leaf, issued by `CA` for localhost.
+    struct Check<'a> {
+        options: TlsOptions,
+        root: &'a [u8],
+        host: &'a str,
+        ocsp: &'a [u8],
+        now: UnixTime,
+    }
+
+    impl Default for Check<'_> {
+        fn default() -> Self {
+            Self {
+                options: TlsOptions::default(),
+                root: CA,
+                host: "localhost",
+                ocsp: &[],
+                now: UnixTime::now(),
+            }
+        }
+    }
+
+    impl Check<'_> {
+        fn run(&self) -> Result<(), rustls::Error> {
+            let mut roots = RootCertStore::empty();
+            roots.add(CertificateDer::from(self.root)).expect("root");
+            let verifier = Verifier::new(Arc::new(roots), &self.options).expect("verifier");
+            let name = ServerName::try_from(self.host).expect("name");
+            verifier
+                .verify_server_cert(&CertificateDer::from(LEAF), &[], &name, self.ocsp, self.now)
+                .map(drop)
+        }
+    }
+
+    fn revocation(mode: RevocationMode) -> TlsOptions {
+        TlsOptions {
+            revocation: mode,
+            ..TlsOptions::default()
+        }
+    }
+
+    fn policy(flags: u32) -> TlsOptions {
+        TlsOptions {
+            policy: VerifyPolicy::from_ffi(flags).expect("known flags"),
+            ..TlsOptions::default()
+        }
+    }
+
+    fn is_cert_error(
+        result: Result<(), rustls::Error>,
+        expected: fn(&CertificateError) -> bool,
+    ) -> bool {
+        matches!(result, Err(rustls::Error::InvalidCertificate(err)) if expected(&err))
+    }
+
+    #[test]
+    fn revoked_certificates_fail_in_every_mode() {
+        for mode in [RevocationMode::SoftFail, RevocationMode::RequireStapled] {
+            let check = |ocsp| Check {
+                options: revocation(mode),
+                ocsp,
+                ..Check::default()
+            };
+            assert!(check(GOOD).run().is_ok());
+            assert_eq!(check(REVOKED).run(), Err(CertificateError::Revoked.into()));
+        }
+    }
+
+    #[test]
+    fn missing_staple_only_fails_when_required() {
+        let check = |mode| Check {
+            options: revocation(mode),
+            ..Check::default()
+        };
+        assert!(check(RevocationMode::SoftFail).run().is_ok());
+        assert_eq!(
+            check(RevocationMode::RequireStapled).run(),
+            Err(CertificateError::UnknownRevocationStatus.into())
+        );
+    }
+
+    #[test]
+    fn crls_override_a_good_staple() {
+        assert!(is_valid_crl(CRL));
+        let check = Check {
+            options: TlsOptions {
+                crls: vec![CertificateRevocationListDer::from(CRL)],
+                ..revocation(RevocationMode::SoftFail)
+            },
+            ocsp: GOOD,
+            ..Check::default()
+        };
+        assert_eq!(check.run(), Err(CertificateError::Revoked.into()));
+    }
+
+    #[test]
+    fn each_flag_tolerates_only_its_problem() {
+        let later = UnixTime::since_unix_epoch(Duration::from_secs(200 * 365 * 86_400));
+        let expired = |flags| Check {
+            options: policy(flags),
+            now: later,
+            ..Check::default()
+        };
+        let unknown_ca = |flags| Check {
+            options: policy(flags),
+            root: OTHER,
+            ..Check::default()
+        };
+        let wrong_host = |flags| Check {
+            options: policy(flags),
+            host: "example.com",
+            ..Check::default()
+        };
+
+        assert!(expired(1).run().is_ok());
+        assert!(unknown_ca(2).run().is_ok());
+        assert!(wrong_host(4).run().is_ok());
+        for flags in [2, 4] {
+            assert!(expired(flags).run().is_err());
+        }
+        for flags in [1, 4] {
+            assert!(unknown_ca(flags).run().is_err());
+        }
+        for flags in [1, 2] {
+            assert!(wrong_host(flags).run().is_err());
+        }
+    }
+
+    #[test]
+    fn tolerated_problems_do_not_hide_others() {
+        let expired_wrong_host = Check {
+            options: policy(1),
+            host: "example.com",
+            now: UnixTime::since_unix_epoch(Duration::from_secs(200 * 365 * 86_400)),
+            ..Check::default()
+        };
+        assert!(is_cert_error(
+            expired_wrong_host.run(),
+            super::is_name_error
+        ));
+
+        let untrusted_wrong_host = Check {
+            options: policy(2),
+            root: OTHER,
+            host: "example.com",
+            ..Check::default()
+        };
+        assert!(is_cert_error(
+            untrusted_wrong_host.run(),
+            super::is_name_error
+        ));
+        assert!(
+            Check {
+                options: policy(2 | 4),
+                ..untrusted_wrong_host
+            }
+            .run()
+            .is_ok()
+        );
+        assert_eq!(VerifyPolicy::from_ffi(8), None);
+    }
+
+    #[test]
+    fn skipping_the_hostname_still_validates_the_chain() {
+        let skip = TlsOptions {
+            skip_hostname_check: true,
+            ..TlsOptions::default()
+        };
+        for host in ["example.com", "127.0.0.1"] {
+            let check = Check {
+                options: skip.clone(),
+                host,
+                ..Check::default()
+            };
+            assert!(check.run().is_ok());
+        }
+
+        let untrusted = Check {
+            options: skip,
+            root: OTHER,
+            ..Check::default()
+        };
+        assert_eq!(untrusted.run(), Err(CertificateError::UnknownIssuer.into()));
+    }
+
+    #[test]
+    fn added_ca_certificates_are_trusted() {
+        let ca_certs = parse_ca_pem(CA_PEM).expect("pem bundle");
+        assert_eq!(ca_certs, [CertificateDer::from(CA)]);
+        assert_eq!(parse_ca_pem(b""), None);
+        assert_eq!(parse_ca_pem(b"-----BEGIN CERTIFICATE-----\nAAAA\n"), None);
+
+        let verify = |options: &TlsOptions| {
+            let verifier = Verifier::new(super::roots(options), options).expect("verifier");
+            let name = ServerName::try_from("localhost").expect("name");
+            verifier
+                .verify_server_cert(
+                    &CertificateDer::from(LEAF),
+                    &[],
+                    &name,
+                    &[],
+                    UnixTime::now(),
+                )
+                .map(drop)
+        };
+        assert_eq!(
+            verify(&TlsOptions::default()),
+            Err(CertificateError::UnknownIssuer.into())
+        );
+        assert!(
+            verify(&TlsOptions {
+                ca_certs,
+                ..TlsOptions::default()
+            })
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn disabled_verification_accepts_any_certificate() {
+        let verifier = super::AcceptAny {
+            algorithms: super::provider().signature_verification_algorithms,
+        };
+        let name = ServerName::try_from("example.com").expect("name");
+        for cert in [LEAF, OTHER] {
+            assert!(
+                verifier
+                    .verify_server_cert(
+                        &CertificateDer::from(cert),
+                        &[],
+                        &name,
+                        &[],
+                        UnixTime::now()
+                    )
+                    .is_ok()
+            );
+        }
+
+        let options = TlsOptions {
+            skip_verify: true,
+            ..TlsOptions::default()
+        };
+        let config = client_config(&options).expect("config");
+        assert!(!Arc::ptr_eq(
+            &config,
+            &client_config(&TlsOptions::default()).expect("config")
+        ));
+    }
+
+    #[test]
+    fn client_certificates_need_their_own_key() {
+        let cert = ClientCert::from_pem(CLIENT_PEM, CLIENT_KEY).expect("matching pair");
+        assert!(matches!(
+            ClientCert::from_pem(CLIENT_PEM, OTHER_KEY),
+            Err(rustls::Error::InconsistentKeys(_))
+        ));
+        assert!(ClientCert::from_pem(b"", CLIENT_KEY).is_err());
+        assert!(ClientCert::from_pem(CLIENT_PEM, b"").is_err());
+
+        let options = TlsOptions {
+            client_cert: Some(cert),
+            ..TlsOptions::default()
+        };
+        let config = client_config(&options).expect("config");
+        assert!(config.client_auth_cert_resolver.has_certs());
+        assert!(
+            !client_config(&TlsOptions::default())
+                .expect("config")
+                .client_auth_cert_resolver
+                .has_certs()
+        );
+    }
+
+    #[test]
+    fn encrypted_client_keys_unlock_with_their_passphrase() {
+        assert!(LockedClientCert::is_encrypted(CLIENT_ENC_KEY));
+        assert!(!LockedClientCert::is_encrypted(CLIENT_KEY));
+        let locked = LockedClientCert::from_pem(CLIENT_PEM, CLIENT_ENC_KEY).expect("encrypted key");
+        assert!(locked.unlock(b"hunter2").is_ok());
+        assert!(locked.unlock(b"hunter3").is_err());
+        assert!(LockedClientCert::from_pem(b"", CLIENT_ENC_KEY).is_err());
+        assert!(LockedClientCert::from_pem(CLIENT_PEM, CLIENT_KEY).is_err());
+    }
+
+    #[test]
+    fn pkcs12_bundles_load_with_their_passphrase() {
+        for bundle in [CLIENT_P12, CLIENT_LEGACY_P12] {
+            let cert = ClientCert::from_pkcs12(bundle, "hunter2").expect("bundle");
+            assert_eq!(cert.key.cert.len(), 1);
+            assert!(ClientCert::from_pkcs12(bundle, "hunter3").is_err());
+        }
+        assert!(ClientCert::from_pkcs12(CLIENT_PEM, "hunter2").is_err());
+    }
+
+    extern "C" fn hunter2(_: *mut c_void, buf: *mut c_char, cap: u64) -> i64 {
+        assert!(cap >= 7);
+        unsafe { std::ptr::copy_nonoverlapping(b"hunter2".as_ptr(), buf.cast(), 7) };
+        7
+    }
+
+    #[test]
+    fn connects_unlock_with_the_host_passphrase() {
+        let locked = LockedClientCert::from_pem(CLIENT_PEM, CLIENT_ENC_KEY).expect("encrypted key");
+        let mut options = TlsOptions {
+            locked_client_cert: Some(locked),
+            ..TlsOptions::default()
+        };
+        assert!(options.clone().unlock_client_cert().is_err());
+
+        options.key_passphrase = Passphrase::Host(HostPassphrase::new(hunter2, ptr::null_mut()));
+        options.unlock_client_cert().expect("unlocked");
+        assert!(options.client_cert.is_some());
+        assert!(options.locked_client_cert.is_none());
+        assert!(matches!(options.key_passphrase, Passphrase::Missing));
+        assert!(
+            client_config(&options)
+                .expect("config")
+                .client_auth_cert_resolver
+                .has_certs()
+        );
+    }
+
+    #[cfg(feature = "post-quantum")]
+    #[test]
+    fn hybrid_key_exchange_is_offered_first() {
+        let groups = &super::provider().kx_groups;
+        assert_eq!(groups[0].name(), rustls::NamedGroup::X25519MLKEM768);
+    }
+
+    #[test]
+    fn fips_mode_follows_the_feature() {
+        assert_eq!(super::is_fips(), cfg!(feature = "fips"));
+    }
+
+    #[test]
+    fn default_config_is_shared() {
+        let options = TlsOptions::default();
+        let first = client_config(&options).expect("config");
+        assert!(Arc::ptr_eq(
+            &first,
+            &client_config(&options).expect("config")
+        ));
+
+        let relaxed = client_config(&policy(4)).expect("config");
+        assert!(!Arc::ptr_eq(&first, &relaxed));
+        let checked = client_config(&revocation(RevocationMode::SoftFail)).expect("config");
+        assert!(!Arc::ptr_eq(&first, &checked));
+        assert!(!is_valid_crl(b"not a crl"));
+    }
+}
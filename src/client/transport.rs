@@ -0,0 +1,667 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+
+#[cfg(not(all(windows, feature = "schannel")))]
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, lookup_host};
+use tokio::time::{Instant, Sleep};
+#[cfg(all(windows, feature = "schannel"))]
+use tokio_native_tls::TlsStream;
+#[cfg(not(all(windows, feature = "schannel")))]
+use tokio_rustls::TlsConnector;
+#[cfg(not(all(windows, feature = "schannel")))]
+use tokio_rustls::client::TlsStream;
+use url::Url;
+use yawc::WebSocketError;
+
+use crate::logging::{self, WsppWireDirection};
+
+use super::error::{WriteTimedOut, WsppErrorCategory};
+#[cfg(feature = "fault-injection")]
+use super::fault::{Faults, Faulty};
+use super::options::ConnectOptions;
+use super::record::HandshakeRecord;
+#[cfg(all(windows, feature = "schannel"))]
+use super::schannel;
+#[cfg(feature = "diagnostics")]
+use super::session::{self, ReplayStream, SessionRecorder};
+use super::throttle::{BandwidthLimit, TokenBucket};
+#[cfg(not(all(windows, feature = "schannel")))]
+use super::tls;
+use super::tls::TlsOptions;
+
+/// Upper bound on captured head bytes; anything past it is not a handshake
+/// request or response we want to keep around.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+/// Reads done per poll while bypassing yawc before yielding to the runtime.
+#[cfg(feature = "unsafe-protocol")]
+const BYPASS_READS_PER_POLL: usize = 16;
+
+#[derive(Debug)]
+pub enum ConnectError {
+    InvalidUrl(String),
+    Dns(io::Error),
+    Tcp(io::Error),
+    Tls(io::Error),
+    Handshake(WebSocketError),
+}
+
+impl ConnectError {
+    pub fn category(&self) -> WsppErrorCategory {
+        match self {
+            Self::InvalidUrl(_) => WsppErrorCategory::Internal,
+            Self::Dns(_) => WsppErrorCategory::Dns,
+            Self::Tcp(_) => WsppErrorCategory::Tcp,
+            // Handshake failures that are not about revocation or our
+            // certificate stay `Tls` even when rustls reports them as plain
+            // I/O.
+            Self::Tls(err) => match WsppErrorCategory::of_io(err) {
+                category @ (WsppErrorCategory::CertRevoked | WsppErrorCategory::ClientCert) => {
+                    category
+                }
+                _ => WsppErrorCategory::Tls,
+            },
+            // A connection dropped during the upgrade is not the server
+            // rejecting it.
+            Self::Handshake(err) => match WsppErrorCategory::of_socket(err) {
+                WsppErrorCategory::Protocol => WsppErrorCategory::HttpHandshake,
+                category => category,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidUrl(reason) => write!(f, "invalid url: {reason}"),
+            Self::Dns(err) => write!(f, "name resolution failed: {err}"),
+            Self::Tcp(err) => write!(f, "connect failed: {err}"),
+            Self::Tls(err) => write!(f, "tls handshake failed: {err}"),
+            Self::Handshake(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// TCP or TLS byte stream underneath the WebSocket.
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(feature = "diagnostics")]
+    Replay(ReplayStream),
+    #[cfg(feature = "fault-injection")]
+    Faulty(Box<Faulty<Stream>>),
+}
+
+impl Stream {
+    /// Injects `faults` into everything read and written from now on.
+    #[cfg(feature = "fault-injection")]
+    pub fn with_faults(self, faults: Option<Faults>) -> Self {
+        match faults {
+            Some(faults) => Self::Faulty(Box::new(Faulty::new(self, faults))),
+            None => self,
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+            #[cfg(feature = "diagnostics")]
+            Self::Replay(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "fault-injection")]
+            Self::Faulty(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+            #[cfg(feature = "diagnostics")]
+            Self::Replay(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "fault-injection")]
+            Self::Faulty(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+            #[cfg(feature = "diagnostics")]
+            Self::Replay(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "fault-injection")]
+            Self::Faulty(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+            #[cfg(feature = "diagnostics")]
+            Self::Replay(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "fault-injection")]
+            Self::Faulty(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Sits between yawc and the socket. Copies the bytes written and read
+/// until the end of the HTTP request and response heads, so the handshake
+/// can be inspected after yawc consumed it, writes bytes injected by the worker ahead of
+/// yawc's own output and enforces bandwidth limits.
+pub struct Tap<S> {
+    inner: S,
+    up: Option<TokenBucket>,
+    down: Option<TokenBucket>,
+    write_timeout: Option<Duration>,
+    /// When the socket stopped accepting writes, and the timer for it.
+    write_blocked: Option<(Instant, Pin<Box<Sleep>>)>,
+    head: Arc<Mutex<Vec<u8>>>,
+    capturing: bool,
+    request: Arc<Mutex<Vec<u8>>>,
+    capturing_request: bool,
+    bytes_read: Arc<AtomicU64>,
+    injected: Arc<Mutex<BytesMut>>,
+    #[cfg(feature = "unsafe-protocol")]
+    bypass: Arc<Mutex<Bypass>>,
+    #[cfg(feature = "diagnostics")]
+    recorder: Option<SessionRecorder>,
+}
+
+/// Incoming bytes kept away from yawc once enabled, for the worker to
+/// parse itself. yawc then sees a connection that never receives anything.
+#[cfg(feature = "unsafe-protocol")]
+#[derive(Debug, Default)]
+pub struct Bypass {
+    pub enabled: bool,
+    pub data: BytesMut,
+    pub eof: bool,
+    pub error: Option<io::Error>,
+}
+
+/// The worker's ends of a `Tap`.
+pub struct TapHandles {
+    /// Raw response head of the handshake once it was read.
+    pub head: Arc<Mutex<Vec<u8>>>,
+    /// Raw upgrade request head once it was written.
+    pub request: Arc<Mutex<Vec<u8>>>,
+    /// Total bytes read from the socket, for spotting stalled reads.
+    pub bytes_read: Arc<AtomicU64>,
+    /// Bytes written before anything else on the next write or flush.
+    #[cfg_attr(not(feature = "unsafe-protocol"), allow(dead_code))]
+    pub injected: Arc<Mutex<BytesMut>>,
+    #[cfg(feature = "unsafe-protocol")]
+    pub bypass: Arc<Mutex<Bypass>>,
+}
+
+impl<S> Tap<S> {
+    pub fn new(
+        inner: S,
+        limit: BandwidthLimit,
+        write_timeout: Option<Duration>,
+    ) -> (Self, TapHandles) {
+        let handles = TapHandles {
+            head: Arc::default(),
+            request: Arc::default(),
+            bytes_read: Arc::default(),
+            injected: Arc::default(),
+            #[cfg(feature = "unsafe-protocol")]
+            bypass: Arc::default(),
+        };
+        let tap = Self {
+            inner,
+            up: limit.up.map(TokenBucket::new),
+            down: limit.down.map(TokenBucket::new),
+            write_timeout,
+            write_blocked: None,
+            head: handles.head.clone(),
+            capturing: true,
+            request: handles.request.clone(),
+            capturing_request: true,
+            bytes_read: handles.bytes_read.clone(),
+            injected: handles.injected.clone(),
+            #[cfg(feature = "unsafe-protocol")]
+            bypass: handles.bypass.clone(),
+            #[cfg(feature = "diagnostics")]
+            recorder: None,
+        };
+        (tap, handles)
+    }
+
+    /// Writes everything sent and received from now on to a session file.
+    #[cfg(feature = "diagnostics")]
+    pub fn record_to(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Passes bytes that crossed the socket to the wire data handler and
+    /// the session recorder.
+    fn wire(&mut self, direction: WsppWireDirection, data: &[u8]) {
+        logging::emit_wire(direction, data);
+        #[cfg(feature = "diagnostics")]
+        session::record(&mut self.recorder, direction, data);
+    }
+
+    fn note_read(&mut self, data: &[u8]) {
+        self.bytes_read
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.wire(WsppWireDirection::Received, data);
+        if self.capturing {
+            self.capturing = capture(&self.head, data);
+        }
+    }
+
+    fn note_write(&mut self, data: &[u8]) {
+        self.wire(WsppWireDirection::Sent, data);
+        if self.capturing_request {
+            self.capturing_request = capture(&self.request, data);
+        }
+    }
+}
+
+/// Appends `data` to `head` until it holds a complete HTTP head. Returns
+/// whether more bytes are wanted.
+fn capture(head: &Mutex<Vec<u8>>, data: &[u8]) -> bool {
+    let mut head = head.lock().unwrap_or_else(|err| err.into_inner());
+    head.extend_from_slice(data);
+    if let Some(end) = find_head_end(&head) {
+        head.truncate(end);
+        false
+    } else if head.len() > MAX_HEAD_LEN {
+        head.clear();
+        false
+    } else {
+        true
+    }
+}
+
+fn find_head_end(data: &[u8]) -> Option<usize> {
+    data.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+impl<S: AsyncWrite + Unpin> Tap<S> {
+    /// Writes to the socket, failing with `WriteTimedOut` once it accepted
+    /// nothing for the write timeout.
+    fn poll_write_inner(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.check_blocked(cx, poll)
+    }
+
+    fn poll_flush_inner(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_flush(cx);
+        self.check_blocked(cx, poll)
+    }
+
+    fn check_blocked<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let Some(limit) = self.write_timeout.filter(|_| poll.is_pending()) else {
+            self.write_blocked = None;
+            return poll;
+        };
+        let (since, timer) = self.write_blocked.get_or_insert_with(|| {
+            let now = Instant::now();
+            (now, Box::pin(tokio::time::sleep_until(now + limit)))
+        });
+        ready!(timer.as_mut().poll(cx));
+        let elapsed = since.elapsed();
+        self.write_blocked = None;
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            WriteTimedOut(elapsed),
+        )))
+    }
+
+    fn poll_drain_injected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let injected = self.injected.clone();
+        let mut injected = injected.lock().unwrap_or_else(|err| err.into_inner());
+        while !injected.is_empty() {
+            let written = ready!(self.poll_write_inner(cx, &injected))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.wire(WsppWireDirection::Sent, &injected[..written]);
+            injected.advance(written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "unsafe-protocol")]
+impl<S: AsyncRead + Unpin> Tap<S> {
+    /// Moves readable bytes into the bypass buffer. Never hands data to the
+    /// caller, so it stays pending for yawc.
+    fn poll_bypass(&mut self, cx: &mut Context<'_>, bypass: &mut Bypass) -> Poll<io::Result<()>> {
+        let mut chunk = [0_u8; 8192];
+        for _ in 0..BYPASS_READS_PER_POLL {
+            let mut buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                    bypass.eof = true;
+                    return Poll::Pending;
+                }
+                Poll::Ready(Ok(())) => {
+                    self.bytes_read
+                        .fetch_add(buf.filled().len() as u64, Ordering::Relaxed);
+                    self.wire(WsppWireDirection::Received, buf.filled());
+                    bypass.data.extend_from_slice(buf.filled());
+                }
+                Poll::Ready(Err(err)) => {
+                    bypass.error = Some(err);
+                    return Poll::Pending;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tap<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        #[cfg(feature = "unsafe-protocol")]
+        {
+            let bypass = this.bypass.clone();
+            let mut bypass = bypass.lock().unwrap_or_else(|err| err.into_inner());
+            if bypass.enabled {
+                return this.poll_bypass(cx, &mut bypass);
+            }
+        }
+        let Some(down) = this.down.as_mut() else {
+            let before = buf.filled().len();
+            let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+            if matches!(poll, Poll::Ready(Ok(()))) {
+                this.note_read(&buf.filled()[before..]);
+            }
+            return poll;
+        };
+
+        let allowance = ready!(down.poll_allowance(cx));
+        let mut chunk = [0_u8; 8192];
+        let len = allowance.min(chunk.len()).min(buf.remaining());
+        let mut limited = ReadBuf::new(&mut chunk[..len]);
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let read = limited.filled().len();
+        down.consume(read);
+        buf.put_slice(&chunk[..read]);
+        this.note_read(&chunk[..read]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tap<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_injected(cx))?;
+        let len = match this.up.as_mut() {
+            Some(up) => ready!(up.poll_allowance(cx)).min(buf.len()),
+            None => buf.len(),
+        };
+        let written = ready!(this.poll_write_inner(cx, &buf[..len]))?;
+        if let Some(up) = this.up.as_mut() {
+            up.consume(written);
+        }
+        this.note_write(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain_injected(cx))?;
+        this.poll_flush_inner(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Tries each address in turn until one accepts, giving each at most
+/// `per_address` when set. Returns the last address's error if none does.
+async fn connect_any(addrs: &[SocketAddr], per_address: Option<Duration>) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+    for addr in addrs {
+        let attempt = match per_address {
+            Some(limit) => tokio::time::timeout(limit, TcpStream::connect(addr))
+                .await
+                .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+            None => TcpStream::connect(addr).await,
+        };
+        match attempt {
+            Ok(tcp) => return Ok(tcp),
+            Err(err) => {
+                logging::emit(3, &format!("connect to {addr} failed: {err}"));
+                last_err = io::Error::new(err.kind(), format!("{addr}: {err}"));
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// Resolves `host`. `lookup_host` starts a blocking-pool thread, which an
+/// inline connection must not have, so inline connections resolve on the
+/// polling thread instead and a poll blocks as long as the system resolver
+/// takes. IP literals are never looked up.
+async fn resolve(host: &str, port: u16, inline: bool) -> io::Result<Vec<SocketAddr>> {
+    if inline {
+        return (host, port).to_socket_addrs().map(Iterator::collect);
+    }
+    Ok(lookup_host((host, port)).await?.collect())
+}
+
+/// Opens the TCP connection for `url` and runs the TLS handshake for `wss`,
+/// noting how long each phase took in `record`. A session set for replay
+/// stands in for the connection.
+pub async fn open_stream(
+    url: &Url,
+    options: &ConnectOptions,
+    record: &mut HandshakeRecord,
+) -> Result<Stream, ConnectError> {
+    #[cfg(feature = "diagnostics")]
+    if let Some(session) = &options.replay {
+        return Ok(Stream::Replay(ReplayStream::new(session)));
+    }
+    let tls = match url.scheme() {
+        "ws" => false,
+        "wss" => true,
+        scheme => {
+            return Err(ConnectError::InvalidUrl(format!(
+                "unsupported scheme {scheme}"
+            )));
+        }
+    };
+    let host = url
+        .host_str()
+        .ok_or_else(|| ConnectError::InvalidUrl("missing host".into()))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| ConnectError::InvalidUrl("missing port".into()))?;
+    // Brackets around IPv6 literals are url syntax, not part of the address.
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let started = std::time::Instant::now();
+    let addrs: Vec<SocketAddr> = resolve(host, port, options.inline_slice.is_some())
+        .await
+        .map_err(ConnectError::Dns)?
+        .into_iter()
+        .filter(|addr| options.ip_family.allows(addr))
+        .collect();
+    record.phase("dns", started);
+    if addrs.is_empty() {
+        return Err(ConnectError::Dns(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no usable addresses for {host}"),
+        )));
+    }
+    let started = std::time::Instant::now();
+    let tcp = connect_any(&addrs, options.address_timeout)
+        .await
+        .map_err(ConnectError::Tcp)?;
+    record.phase("tcp", started);
+    let _ = tcp.set_nodelay(true);
+    if let Err(err) = options.socket.apply(&tcp) {
+        logging::emit(2, &format!("socket options not applied: {err}"));
+    }
+
+    if !tls {
+        return Ok(Stream::Plain(tcp));
+    }
+    let started = std::time::Instant::now();
+    let stream = start_tls(host, tcp, &options.tls).await?;
+    record.phase("tls", started);
+    Ok(Stream::Tls(Box::new(stream)))
+}
+
+#[cfg(not(all(windows, feature = "schannel")))]
+async fn start_tls(
+    host: &str,
+    tcp: TcpStream,
+    options: &TlsOptions,
+) -> Result<TlsStream<TcpStream>, ConnectError> {
+    let name = ServerName::try_from(host.to_owned())
+        .map_err(|err| ConnectError::InvalidUrl(err.to_string()))?;
+    let config =
+        tls::client_config(options).map_err(|err| ConnectError::Tls(io::Error::other(err)))?;
+    let stream = TlsConnector::from(config)
+        .connect(name, tcp)
+        .await
+        .map_err(ConnectError::Tls)?;
+    let (_, session) = stream.get_ref();
+    if let (Some(version), Some(suite), Some(group)) = (
+        session.protocol_version(),
+        session.negotiated_cipher_suite(),
+        session.negotiated_key_exchange_group(),
+    ) {
+        logging::emit(
+            3,
+            &format!(
+                "negotiated {version:?} with {:?} over {:?}",
+                suite.suite(),
+                group.name()
+            ),
+        );
+    }
+    Ok(stream)
+}
+
+#[cfg(all(windows, feature = "schannel"))]
+async fn start_tls(
+    host: &str,
+    tcp: TcpStream,
+    options: &TlsOptions,
+) -> Result<TlsStream<TcpStream>, ConnectError> {
+    schannel::connect(host, tcp, options)
+        .await
+        .map_err(ConnectError::Tls)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::runtime::Builder;
+
+    use super::{connect_any, find_head_end, resolve};
+
+    #[test]
+    fn inline_lookups_start_no_thread() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let counter = started.clone();
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .on_thread_start(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .build()
+            .expect("test runtime");
+
+        let addrs = rt
+            .block_on(resolve("localhost", 80, true))
+            .expect("resolved");
+        assert!(
+            addrs
+                .iter()
+                .all(|addr| addr.ip().is_loopback() && addr.port() == 80)
+        );
+        assert_eq!(started.load(Ordering::Relaxed), 0);
+
+        rt.block_on(resolve("localhost", 80, false))
+            .expect("resolved");
+        assert_eq!(started.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn falls_through_to_the_next_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let refused = {
+            let probe = TcpListener::bind("127.0.0.1:0").expect("bind probe");
+            probe.local_addr().expect("probe addr")
+        };
+        let addrs: [SocketAddr; 2] = [refused, listener.local_addr().expect("addr")];
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test runtime");
+
+        let tcp = rt
+            .block_on(connect_any(&addrs, Some(Duration::from_secs(5))))
+            .expect("second address accepts");
+        assert_eq!(tcp.peer_addr().ok(), Some(addrs[1]));
+
+        let err = rt
+            .block_on(connect_any(&addrs[..1], None))
+            .expect_err("refused");
+        assert!(err.to_string().starts_with(&refused.to_string()));
+    }
+
+    #[test]
+    fn head_ends_after_blank_line() {
+        let mut data = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n".to_vec();
+        let head_len = data.len();
+        data.extend_from_slice(b"\x81\x02hi");
+        assert_eq!(find_head_end(&data), Some(head_len));
+        assert_eq!(find_head_end(b"HTTP/1.1 101\r\n"), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::worker::Command;
+
+/// Text and binary messages a lost connection never wrote, kept so the
+/// next connection can send them, oldest first.
+#[derive(Debug)]
+pub struct Unsent {
+    limit: usize,
+    messages: Mutex<VecDeque<Command>>,
+}
+
+impl Unsent {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            messages: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Keeps `cmd` if it is a message, discarding the oldest kept one once
+    /// `limit` are. Returns whether one was discarded.
+    pub fn keep(&self, cmd: Command) -> bool {
+        if cmd.priority().is_none() {
+            return false;
+        }
+        let mut messages = self.lock();
+        messages.push_back(cmd);
+        messages.len() > self.limit && messages.pop_front().is_some()
+    }
+
+    pub fn take(&self) -> VecDeque<Command> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Command>> {
+        self.messages.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Unsent;
+    use crate::client::Payload;
+    use crate::client::queue::Priority;
+    use crate::client::worker::Command;
+
+    fn text(data: &'static str) -> Command {
+        Command::SendText {
+            data: Payload::from(data),
+            expires_at: None,
+            priority: Priority::Normal,
+        }
+    }
+
+    #[test]
+    fn keeps_the_newest_messages_in_order() {
+        let unsent = Unsent::new(2);
+        assert!(!unsent.keep(text("a")));
+        assert!(!unsent.keep(Command::Ping(Payload::new())));
+        assert!(!unsent.keep(text("b")));
+        assert!(unsent.keep(text("c")));
+
+        let kept: Vec<_> = unsent
+            .take()
+            .into_iter()
+            .map(|cmd| match cmd {
+                Command::SendText { data, .. } => data.to_vec(),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(kept, [b"b", b"c"]);
+        assert!(unsent.take().is_empty());
+    }
+}
@@ -1,51 +1,234 @@
+use bytes::Bytes;
 use futures::SinkExt;
 
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
-use tokio::net::TcpStream;
-use tokio::runtime::Builder;
+use tokio::runtime::{Builder, Runtime};
 
 use url::Url;
-use yawc::close::CloseCode;
 use yawc::frame::OpCode;
-use yawc::{Frame, MaybeTlsStream, Options, WebSocket, WebSocketError};
+use yawc::{Frame, HttpRequestBuilder, Options, WebSocket, WebSocketError};
 
+use crate::budget::{BudgetAccount, BudgetPolicy};
+use crate::close_code::WsppCloseCode;
+use crate::lifecycle;
 use crate::logging;
+use crate::opcode::WsppOpcode;
 use crate::result::WsppResult;
 
+use super::arena::PayloadArena;
+use super::backpressure::{StallMonitor, WRITE_STALL_THRESHOLD};
+use super::error::{WorkerError, WsppErrorCategory, WsppTimeoutPhase};
+use super::handshake::Handshake;
+use super::heartbeat::{Beat, HeartbeatTimer};
+use super::masking::Masks;
+use super::options::{ConnectOptions, SpillOptions};
+use super::pair::PairLink;
+use super::payload::{INLINE_PAYLOAD, Payload};
+use super::pong::PongPolicy;
+use super::queue::{Priority, SendQueue};
+#[cfg(feature = "unsafe-protocol")]
+use super::raw::{self, RawFrame};
+use super::record::HandshakeRecord;
+#[cfg(feature = "diagnostics")]
+use super::session::SessionRecorder;
+use super::stats::{HandleStats, Loss};
+use super::stream::ChunkSource;
+#[cfg(feature = "unsafe-protocol")]
+use super::transport::Bypass;
+use super::transport::{self, ConnectError, Stream, Tap, TapHandles};
+use super::unsent::Unsent;
+
 const CLOSE_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_OUTSTANDING_PINGS: usize = 32;
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+type Client = WebSocket<Tap<Stream>>;
+
+static SPILL_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Source of connection ids; zero is reserved for "never connected".
+static CONNECTION_COUNTER: AtomicU64 = AtomicU64::new(1);
 
 #[derive(Debug)]
 pub enum Event {
-    Open,
-    Close,
-    Message { data: Vec<u8>, opcode: i32 },
-    Pong(Vec<u8>),
-    Error(String),
+    Open {
+        connection_id: u64,
+        handshake: Handshake,
+    },
+    /// The connection ended. `code` is the one the peer closed with, 1005
+    /// if its close frame carried none and 1006 if none was received.
+    Close {
+        code: u16,
+        reason: Payload,
+    },
+    Message {
+        data: Payload,
+        opcode: WsppOpcode,
+    },
+    MessageFile {
+        path: PathBuf,
+        len: u64,
+        opcode: WsppOpcode,
+    },
+    Pong {
+        data: Payload,
+        rtt: Option<Duration>,
+    },
+    UnsolicitedPong(Payload),
+    #[cfg(feature = "unsafe-protocol")]
+    RawFrame(RawFrame),
+    Watchdog,
+    Backpressure(bool),
+    Error(WorkerError),
+}
+
+/// Stamps each event of a connection with the next sequence number, so
+/// consumers can check they process events in order, and counts it in the
+/// handle's event queue depth.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: Sender<(u64, Event)>,
+    next_seq: Arc<AtomicU64>,
+    stats: Arc<HandleStats>,
+}
+
+impl EventSender {
+    pub fn new(tx: Sender<(u64, Event)>, stats: Arc<HandleStats>) -> Self {
+        Self {
+            tx,
+            next_seq: Arc::new(AtomicU64::new(1)),
+            stats,
+        }
+    }
+
+    // Handing the event back lets callers clean up after it, e.g. remove a
+    // spill file; small payloads make it large by design.
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, event: Event) -> Result<(), mpsc::SendError<Event>> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        self.stats.events.push();
+        self.tx
+            .send((seq, event))
+            .map_err(|mpsc::SendError((_, event))| {
+                self.stats.events.pop();
+                mpsc::SendError(event)
+            })
+    }
 }
 
 #[derive(Debug)]
 pub enum Command {
-    SendText(String),
-    SendBinary(Vec<u8>),
-    Ping(Vec<u8>),
-    Close { code: u16, reason: Option<String> },
+    SendText {
+        data: Payload,
+        expires_at: Option<Instant>,
+        priority: Priority,
+    },
+    SendBinary {
+        data: Payload,
+        expires_at: Option<Instant>,
+        priority: Priority,
+    },
+    Ping(Payload),
+    #[cfg(feature = "unsafe-protocol")]
+    SendRawFrame(RawFrame),
+    SendStream {
+        text: bool,
+        source: ChunkSource,
+    },
+    Close {
+        code: u16,
+        reason: Option<String>,
+    },
     Shutdown,
 }
 
+impl Event {
+    /// The end of a connection without a close frame from the peer.
+    pub fn abnormal_close() -> Self {
+        Self::Close {
+            code: WsppCloseCode::Abnormal.code(),
+            reason: Payload::new(),
+        }
+    }
+
+    /// The end of a connection by the peer's close frame `payload`.
+    pub fn received_close(payload: &[u8]) -> Self {
+        match payload {
+            [high, low, reason @ ..] => Self::Close {
+                code: u16::from_be_bytes([*high, *low]),
+                reason: Payload::copy_from_slice(reason),
+            },
+            _ => Self::Close {
+                code: WsppCloseCode::NoStatus.code(),
+                reason: Payload::new(),
+            },
+        }
+    }
+
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Self::Message { data, .. } | Self::Pong { data, .. } | Self::UnsolicitedPong(data) => {
+                data.len()
+            }
+            #[cfg(feature = "unsafe-protocol")]
+            Self::RawFrame(frame) => frame.payload.len(),
+            _ => 0,
+        }
+    }
+}
+
+impl Command {
+    /// Queue priority of messages that count against the send queue limit.
+    pub fn priority(&self) -> Option<Priority> {
+        match self {
+            Self::SendText { priority, .. } | Self::SendBinary { priority, .. } => Some(*priority),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a message whose time-to-live ran out while queued.
+    pub fn is_expired(&self, now: Instant) -> bool {
+        match self {
+            Self::SendText { expires_at, .. } | Self::SendBinary { expires_at, .. } => {
+                expires_at.is_some_and(|at| at <= now)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Self::SendText { data, .. } | Self::SendBinary { data, .. } | Self::Ping(data) => {
+                data.len()
+            }
+            _ => 0,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum WorkerStartError {
     InvalidUrl(url::ParseError),
     RuntimeInit(std::io::Error),
+    ThreadSpawn(std::io::Error),
 }
 
 impl WorkerStartError {
     pub fn to_wspp_result(&self) -> WsppResult {
         match self {
             Self::InvalidUrl(_) => WsppResult::InvalidArgument,
-            Self::RuntimeInit(_) => WsppResult::IoError,
+            Self::RuntimeInit(_) | Self::ThreadSpawn(_) => WsppResult::IoError,
         }
     }
 }
@@ -55,120 +238,638 @@ impl std::fmt::Display for WorkerStartError {
         match self {
             Self::InvalidUrl(err) => write!(f, "invalid url: {err}"),
             Self::RuntimeInit(err) => write!(f, "runtime init failed: {err}"),
+            Self::ThreadSpawn(err) => write!(f, "worker thread spawn failed: {err}"),
         }
     }
 }
 
 impl std::error::Error for WorkerStartError {}
 
+/// Client-side ends of a freshly spawned connection worker.
+pub struct Worker {
+    pub cmd_tx: mpsc::Sender<Command>,
+    pub event_rx: mpsc::Receiver<(u64, Event)>,
+    pub runner: Runner,
+    /// Set while a write is blocked on the socket.
+    pub write_stalled: Arc<AtomicBool>,
+    pub queue: Arc<SendQueue>,
+    /// Handshake record of the connection attempt once it finished, if
+    /// capture was enabled.
+    pub handshake_record: Arc<Mutex<Option<String>>>,
+}
+
 pub fn spawn_ws_worker(
     uri: String,
-    compression: bool,
-) -> Result<(mpsc::Sender<Command>, mpsc::Receiver<Event>), WorkerStartError> {
+    options: ConnectOptions,
+    account: BudgetAccount,
+    stats: Arc<HandleStats>,
+) -> Result<Worker, WorkerStartError> {
     let (cmd_tx, cmd_rx) = mpsc::channel();
     let (event_tx, event_rx) = mpsc::channel();
+    let event_tx = EventSender::new(event_tx, stats.clone());
 
     let rt = Builder::new_current_thread()
         .enable_all()
         .build()
         .map_err(WorkerStartError::RuntimeInit)?;
     let url = Url::parse(uri.as_str()).map_err(WorkerStartError::InvalidUrl)?;
+    let write_stalled = Arc::new(AtomicBool::new(false));
+    let queue = Arc::new(SendQueue::new(options.queue_limit, options.queue_policy));
+    let handshake_record = Arc::new(Mutex::new(None));
+    let shared = Shared {
+        account,
+        stall: StallMonitor::new(
+            write_stalled.clone(),
+            event_tx.clone(),
+            WRITE_STALL_THRESHOLD,
+        ),
+        queue: queue.clone(),
+        stats,
+        handshake_record: handshake_record.clone(),
+    };
 
-    std::thread::spawn(move || {
-        rt.block_on(connection_worker(url, compression, event_tx, cmd_rx));
-    });
+    let log_handler = logging::thread_log_handler();
+    let runner = match options.inline_slice {
+        Some(slice) => Runner::Inline(InlineWorker {
+            rt,
+            task: Box::pin(connection_worker(url, options, event_tx, cmd_rx, shared)),
+            slice,
+            done: false,
+        }),
+        None => Runner::Thread(
+            std::thread::Builder::new()
+                .name(thread_name(&url))
+                .spawn(move || {
+                    logging::set_thread_log_handler(log_handler);
+                    if let Err(err) = options.priority.apply_current() {
+                        logging::emit(2, &format!("worker priority not applied: {err}"));
+                    }
+                    rt.block_on(connection_worker(url, options, event_tx, cmd_rx, shared));
+                })
+                .map_err(WorkerStartError::ThreadSpawn)?,
+        ),
+    };
+
+    Ok(Worker {
+        cmd_tx,
+        event_rx,
+        runner,
+        write_stalled,
+        queue,
+        handshake_record,
+    })
+}
 
-    Ok((cmd_tx, event_rx))
+/// What drives a connection.
+pub enum Runner {
+    /// A worker thread of its own.
+    Thread(JoinHandle<()>),
+    /// The thread polling the handle, one slice per poll.
+    Inline(InlineWorker),
+    /// An in-memory link to the other handle of a pair.
+    Pair(Arc<Mutex<PairLink>>),
+}
+
+impl Runner {
+    /// Waits up to `timeout` for the connection to finish. Returns `false`
+    /// if it had not by then; a thread is left detached and an inline
+    /// connection dropped.
+    pub fn finish(self, timeout: Duration) -> bool {
+        match self {
+            Self::Thread(thread) => join_with_timeout(thread, timeout),
+            Self::Inline(mut inline) => inline.run(timeout),
+            Self::Pair(link) => {
+                link.lock().unwrap_or_else(|err| err.into_inner()).pump();
+                true
+            }
+        }
+    }
+
+    /// Lets go of the connection without waiting. A thread is adopted so
+    /// `wspp_global_shutdown` can still wait for it.
+    pub fn detach(self) {
+        if let Self::Thread(thread) = self {
+            lifecycle::adopt(thread);
+        }
+    }
+}
+
+/// A connection run on its own current-thread runtime by whoever polls the
+/// handle, for hosts that cannot have background threads.
+pub struct InlineWorker {
+    rt: Runtime,
+    task: Pin<Box<dyn Future<Output = ()>>>,
+    slice: Duration,
+    done: bool,
+}
+
+impl InlineWorker {
+    /// Runs the connection for one slice.
+    pub fn drive(&mut self) {
+        self.run(self.slice);
+    }
+
+    /// Runs the connection for at most `limit`. Returns whether it finished.
+    fn run(&mut self, limit: Duration) -> bool {
+        if !self.done {
+            let task = self.task.as_mut();
+            self.done = self
+                .rt
+                .block_on(async { tokio::time::timeout(limit, task).await })
+                .is_ok();
+        }
+        self.done
+    }
+}
+
+pub fn next_connection_id() -> u64 {
+    CONNECTION_COUNTER.fetch_add(1, Ordering::Relaxed)
+}
+
+fn thread_name(url: &Url) -> String {
+    format!("wspp-worker-{}", url.host_str().unwrap_or("unknown"))
+}
+
+/// Waits up to `timeout` for the worker thread to exit. Returns `false` and
+/// leaves the thread detached if it is still running afterwards.
+pub fn join_with_timeout(thread: JoinHandle<()>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            lifecycle::adopt(thread);
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    let _ = thread.join();
+    true
 }
 
 async fn connect(
     url: Url,
-    compression: bool,
-) -> Result<WebSocket<MaybeTlsStream<TcpStream>>, WebSocketError> {
-    let options = if compression {
+    connect_options: &ConnectOptions,
+    record: &mut HandshakeRecord,
+) -> Result<(Client, Handshake, TapHandles), ConnectError> {
+    let mut options = if connect_options.compression {
         Options::default().with_balanced_compression()
     } else {
         Options::default().without_compression()
     };
+    if let Some(limit) = connect_options.max_message_size {
+        // yawc fails an assembled message once it reaches the buffer size.
+        options = options
+            .with_max_payload_read(limit)
+            .with_max_read_buffer(limit.saturating_add(1));
+    }
+
+    let mut request = HttpRequestBuilder::new();
+    if let Some(key) = connect_options.handshake_key() {
+        request = request.header("Sec-WebSocket-Key", key);
+    }
+    for (name, value) in &connect_options.headers {
+        request = request.header(name, value);
+    }
+
+    let stream = transport::open_stream(&url, connect_options, record).await?;
+    #[cfg(feature = "fault-injection")]
+    let stream = stream.with_faults(connect_options.faults);
+    #[cfg_attr(not(feature = "diagnostics"), allow(unused_mut))]
+    let (mut io, tap) = Tap::new(
+        stream,
+        connect_options.bandwidth,
+        connect_options.write_timeout,
+    );
+    #[cfg(feature = "diagnostics")]
+    if let Some(path) = &connect_options.session_record {
+        match SessionRecorder::create(path) {
+            Ok(recorder) => io.record_to(recorder),
+            Err(err) => logging::emit(2, &format!("session recording not started: {err}")),
+        }
+    }
+    record.attach(&tap);
+    let started = Instant::now();
+    let upgraded = WebSocket::handshake_with_request(url, io, options, request).await;
+    record.phase("upgrade", started);
+    let client = upgraded.map_err(ConnectError::Handshake)?;
+    let handshake = Handshake::parse(&tap.head.lock().unwrap_or_else(|err| err.into_inner()));
+    Ok((client, handshake, tap))
+}
+
+/// Queues an event carrying a payload, charging it to the memory budget
+/// until the client dispatches it.
+fn send_payload_event(event_tx: &EventSender, account: &BudgetAccount, event: Event) {
+    let len = event.payload_len();
+    account.charge(len);
+    if event_tx.send(event).is_err() {
+        account.release(len);
+    }
+}
+
+/// Writes `data` to a new file in `dir` only the current user can read,
+/// as the directory is usually the shared temp dir.
+fn spill_to_file(dir: &Path, data: &[u8]) -> std::io::Result<PathBuf> {
+    let seq = SPILL_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = dir.join(format!("wspp-{}-{seq}.msg", std::process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    if let Err(err) = file.write_all(data) {
+        let _ = std::fs::remove_file(&path);
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// Delivers a data message, spilling it to disk when it exceeds the
+/// configured threshold. Falls back to memory if the file can't be written.
+fn send_message_event(
+    event_tx: &EventSender,
+    account: &BudgetAccount,
+    spill: Option<&SpillOptions>,
+    data: Payload,
+    opcode: WsppOpcode,
+) {
+    if let Some(spill) = spill.filter(|spill| data.len() > spill.threshold) {
+        match spill_to_file(&spill.dir, &data) {
+            Ok(path) => {
+                let event = Event::MessageFile {
+                    path,
+                    len: data.len() as u64,
+                    opcode,
+                };
+                if let Err(mpsc::SendError(Event::MessageFile { path, .. })) = event_tx.send(event)
+                {
+                    let _ = std::fs::remove_file(path);
+                }
+                return;
+            }
+            Err(err) => logging::emit(2, &format!("message spill failed: {err}")),
+        }
+    }
+
+    send_payload_event(event_tx, account, Event::Message { data, opcode });
+}
+
+/// Builds an outgoing frame. Its masking key comes from `masks` when set
+/// and is otherwise left to yawc.
+fn frame(masks: &mut Option<Masks>, fin: bool, opcode: OpCode, payload: impl Into<Bytes>) -> Frame {
+    let mut frame = Frame::from((opcode, payload));
+    frame.set_fin(fin);
+    frame.set_mask(masks.as_mut().and_then(Masks::next_mask));
+    frame
+}
+
+fn close_frame(masks: &mut Option<Masks>, code: u16, reason: &[u8]) -> Frame {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason);
+    frame(masks, true, OpCode::Close, payload)
+}
+
+/// Sends a whole message, fragmenting it when it exceeds `chunk_size`.
+async fn send_data(
+    client: &mut Client,
+    masks: &mut Option<Masks>,
+    text: bool,
+    data: Payload,
+    chunk_size: Option<usize>,
+) -> Result<(), WebSocketError> {
+    let mut opcode = if text { OpCode::Text } else { OpCode::Binary };
+    let Some(chunk_size) = chunk_size.filter(|size| data.len() > *size) else {
+        return client.send(frame(masks, true, opcode, data)).await;
+    };
+
+    let mut data = Bytes::from(data);
+    loop {
+        let chunk = data.split_to(chunk_size.min(data.len()));
+        let fin = data.is_empty();
+        client.send(frame(masks, fin, opcode, chunk)).await?;
+        if fin {
+            return Ok(());
+        }
+        opcode = OpCode::Continuation;
+    }
+}
+
+enum StreamError {
+    /// The source failed; `started` tells whether fragments already went out.
+    Source {
+        started: bool,
+        reason: String,
+    },
+    Socket(WebSocketError),
+}
+
+/// Sends everything `source` yields as one fragmented message. A chunk is
+/// held back until the next one is read so the last frame can carry FIN.
+async fn send_stream(
+    client: &mut Client,
+    masks: &mut Option<Masks>,
+    text: bool,
+    source: &mut ChunkSource,
+    chunk_size: usize,
+) -> Result<(), StreamError> {
+    let mut opcode = if text { OpCode::Text } else { OpCode::Binary };
+    let mut started = false;
+    let mut chunk = source
+        .next_chunk(chunk_size)
+        .map_err(|reason| StreamError::Source { started, reason })?;
+
+    loop {
+        let next = if chunk.is_empty() {
+            Bytes::new()
+        } else {
+            source
+                .next_chunk(chunk_size)
+                .map_err(|reason| StreamError::Source { started, reason })?
+        };
+        let fin = next.is_empty();
+
+        client
+            .send(frame(masks, fin, opcode, chunk))
+            .await
+            .map_err(StreamError::Socket)?;
+        if fin {
+            return Ok(());
+        }
+
+        started = true;
+        chunk = next;
+        opcode = OpCode::Continuation;
+    }
+}
+
+/// Commands of an open connection. Messages still queued when it ends are
+/// kept for the next connection if the handle replays them.
+struct Commands {
+    rx: Receiver<Command>,
+    unsent: Option<Arc<Unsent>>,
+    stats: Arc<HandleStats>,
+}
+
+impl Drop for Commands {
+    fn drop(&mut self) {
+        let Some(unsent) = self.unsent.as_ref() else {
+            return;
+        };
+        for cmd in self.rx.try_iter() {
+            if unsent.keep(cmd) {
+                self.stats.record_loss(Loss::QueueFull);
+            }
+        }
+    }
+}
 
-    WebSocket::connect(url).with_options(options).await
+/// State a worker shares with the handle that spawned it.
+struct Shared {
+    account: BudgetAccount,
+    stall: StallMonitor,
+    queue: Arc<SendQueue>,
+    stats: Arc<HandleStats>,
+    handshake_record: Arc<Mutex<Option<String>>>,
 }
 
 async fn connection_worker(
     url: Url,
-    compression: bool,
-    event_tx: Sender<Event>,
+    options: ConnectOptions,
+    event_tx: EventSender,
     cmd_rx: Receiver<Command>,
+    shared: Shared,
 ) {
     logging::emit(3, "connection worker started");
+    let Shared {
+        account,
+        stall,
+        queue,
+        stats,
+        handshake_record,
+    } = shared;
 
-    let mut client = match connect(url, compression).await {
-        Ok(client) => {
-            let _ = event_tx.send(Event::Open);
-            client
+    // Lives outside the timeout so a timed out attempt still has a record.
+    let mut record = HandshakeRecord::new(&url);
+    let connecting = match options.connect_timeout {
+        Some(limit) => tokio::time::timeout(limit, connect(url, &options, &mut record))
+            .await
+            .map_err(|_| WorkerError::timeout(WsppTimeoutPhase::Connect, limit)),
+        None => Ok(connect(url, &options, &mut record).await),
+    };
+    if options.capture_handshake {
+        let error = match &connecting {
+            Ok(Ok(_)) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(err) => Some(err.message.clone()),
+        };
+        *handshake_record
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Some(record.to_json(error.as_deref()));
+    }
+    let (mut client, tap) = match connecting {
+        Ok(Ok((client, handshake, tap))) => {
+            let connection_id = next_connection_id();
+            logging::emit(3, &format!("connection {connection_id} opened"));
+            let _ = event_tx.send(Event::Open {
+                connection_id,
+                handshake,
+            });
+            (client, tap)
+        }
+        Ok(Err(err)) => {
+            let _ = event_tx.send(Event::Error(WorkerError::new(
+                err.category(),
+                err.to_string(),
+            )));
+            return;
         }
         Err(err) => {
-            let _ = event_tx.send(Event::Error(err.to_string()));
+            let _ = event_tx.send(Event::Error(err));
             return;
         }
     };
 
+    let cmd_rx = Commands {
+        rx: cmd_rx,
+        unsent: options.unsent.clone(),
+        stats: stats.clone(),
+    };
     let mut closing_requested = false;
     let mut close_started_at: Option<Instant> = None;
+    let mut outstanding_pings: VecDeque<(Payload, Instant)> = VecDeque::new();
+    let mut last_frame_at = Instant::now();
+    let mut heartbeat = HeartbeatTimer::new(&options.heartbeat, Instant::now());
+    let mut progress = ReadProgress::new(tap.bytes_read.clone());
+    let mut masks = options.masks.map(Masks::new);
+    let mut arena = options
+        .arena
+        .map(|arena| PayloadArena::new(arena.block_size, arena.growth));
+    // Frames that arrived together with the handshake response were already
+    // buffered by yawc and are not seen in raw mode.
+    #[cfg(feature = "unsafe-protocol")]
+    if options.raw_receive {
+        tap.bypass
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .enabled = true;
+    }
 
     loop {
         let mut should_stop = false;
         let mut disconnected = false;
 
         loop {
-            match cmd_rx.try_recv() {
-                Ok(cmd) => match cmd {
-                    Command::SendText(message) => {
-                        if let Err(err) = client.send(Frame::text(message.into_bytes())).await {
-                            let _ = event_tx.send(Event::Error(err.to_string()));
-                            should_stop = true;
-                            break;
-                        }
+            match cmd_rx.rx.try_recv() {
+                Ok(cmd) => {
+                    stats.commands.pop();
+                    account.release(cmd.payload_len());
+                    if cmd.priority().is_some_and(|priority| !queue.take(priority)) {
+                        stats.record_loss(Loss::QueueFull);
+                        continue;
                     }
-                    Command::SendBinary(data) => {
-                        if let Err(err) = client.send(Frame::binary(data)).await {
-                            let _ = event_tx.send(Event::Error(err.to_string()));
-                            should_stop = true;
-                            break;
-                        }
+                    if cmd.is_expired(Instant::now()) {
+                        stats.record_loss(Loss::Expired);
+                        continue;
                     }
-                    Command::Ping(data) => {
-                        if let Err(err) = client.send(Frame::ping(data)).await {
-                            let _ = event_tx.send(Event::Error(err.to_string()));
-                            should_stop = true;
-                            break;
+                    match cmd {
+                        Command::SendText { data, .. } => {
+                            if let Err(err) = stall
+                                .watch(send_data(
+                                    &mut client,
+                                    &mut masks,
+                                    true,
+                                    data,
+                                    options.write_chunk,
+                                ))
+                                .await
+                            {
+                                let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                                should_stop = true;
+                                break;
+                            }
                         }
-                    }
-                    Command::Close { code, reason } => {
-                        closing_requested = true;
-                        if close_started_at.is_none() {
-                            close_started_at = Some(Instant::now());
+                        Command::SendBinary { data, .. } => {
+                            if let Err(err) = stall
+                                .watch(send_data(
+                                    &mut client,
+                                    &mut masks,
+                                    false,
+                                    data,
+                                    options.write_chunk,
+                                ))
+                                .await
+                            {
+                                let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                                should_stop = true;
+                                break;
+                            }
                         }
-                        let reason_bytes = reason.unwrap_or_default().into_bytes();
-                        if let Err(err) = client
-                            .send(Frame::close(CloseCode::from(code), reason_bytes))
-                            .await
-                        {
-                            if !err.is_closed() {
-                                let _ = event_tx.send(Event::Error(err.to_string()));
+                        Command::Ping(data) => {
+                            if outstanding_pings.len() == MAX_OUTSTANDING_PINGS {
+                                outstanding_pings.pop_front();
                             }
-                            let _ = event_tx.send(Event::Close);
+                            outstanding_pings.push_back((data.clone(), Instant::now()));
+                            if let Err(err) = stall
+                                .watch(client.send(frame(&mut masks, true, OpCode::Ping, data)))
+                                .await
+                            {
+                                let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                                should_stop = true;
+                                break;
+                            }
+                        }
+                        #[cfg(feature = "unsafe-protocol")]
+                        Command::SendRawFrame(frame) => {
+                            let mask = masks
+                                .as_mut()
+                                .and_then(Masks::next_mask)
+                                .unwrap_or_else(raw::random_mask);
+                            tap.injected
+                                .lock()
+                                .unwrap_or_else(|err| err.into_inner())
+                                .extend_from_slice(&frame.encode(mask));
+                            // yawc has nothing buffered between frames, so
+                            // flushing writes the injected bytes on their own.
+                            if let Err(err) = stall.watch(client.flush()).await {
+                                let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                                should_stop = true;
+                                break;
+                            }
+                        }
+                        Command::SendStream { text, mut source } => {
+                            let chunk_size = options.write_chunk.unwrap_or(STREAM_CHUNK_SIZE);
+                            match stall
+                                .watch(send_stream(
+                                    &mut client,
+                                    &mut masks,
+                                    text,
+                                    &mut source,
+                                    chunk_size,
+                                ))
+                                .await
+                            {
+                                Ok(()) => {}
+                                Err(StreamError::Source {
+                                    started: false,
+                                    reason,
+                                }) => {
+                                    logging::emit(2, &format!("stream send aborted: {reason}"));
+                                }
+                                Err(StreamError::Source {
+                                    started: true,
+                                    reason,
+                                }) => {
+                                    // A fragmented message can't be cancelled
+                                    // midway, so the connection has to go.
+                                    let _ = event_tx.send(Event::Error(WorkerError::new(
+                                        WsppErrorCategory::Internal,
+                                        format!("stream source failed mid-message: {reason}"),
+                                    )));
+                                    let _ = client
+                                        .send(close_frame(
+                                            &mut masks,
+                                            WsppCloseCode::InternalError.code(),
+                                            b"Stream aborted",
+                                        ))
+                                        .await;
+                                    should_stop = true;
+                                    break;
+                                }
+                                Err(StreamError::Socket(err)) => {
+                                    let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                                    should_stop = true;
+                                    break;
+                                }
+                            }
+                        }
+                        Command::Close { code, reason } => {
+                            closing_requested = true;
+                            if close_started_at.is_none() {
+                                close_started_at = Some(Instant::now());
+                            }
+                            let reason_bytes = reason.unwrap_or_default().into_bytes();
+                            if let Err(err) = client
+                                .send(close_frame(&mut masks, code, &reason_bytes))
+                                .await
+                            {
+                                if !err.is_closed() {
+                                    let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                                }
+                                let _ = event_tx.send(Event::abnormal_close());
+                                return;
+                            }
+                        }
+                        Command::Shutdown => {
+                            let _ = client
+                                .send(close_frame(
+                                    &mut masks,
+                                    WsppCloseCode::GoingAway.code(),
+                                    b"Going away",
+                                ))
+                                .await;
+                            let _ = event_tx.send(Event::abnormal_close());
                             return;
                         }
                     }
-                    Command::Shutdown => {
-                        let _ = client
-                            .send(Frame::close(CloseCode::Away, b"Going away".as_slice()))
-                            .await;
-                        let _ = event_tx.send(Event::Close);
-                        return;
-                    }
-                },
+                }
                 Err(TryRecvError::Empty) => break,
                 Err(TryRecvError::Disconnected) => {
                     disconnected = true;
@@ -178,57 +879,283 @@ async fn connection_worker(
         }
 
         if should_stop || disconnected {
-            let _ = event_tx.send(Event::Close);
+            let _ = event_tx.send(Event::abnormal_close());
             return;
         }
 
         if close_timed_out(close_started_at, Instant::now(), CLOSE_WAIT_TIMEOUT) {
             logging::emit(2, "close handshake timed out; forcing closed state");
-            let _ = event_tx.send(Event::Close);
+            let elapsed = close_started_at.map_or(CLOSE_WAIT_TIMEOUT, |at| at.elapsed());
+            let _ = event_tx.send(Event::Error(WorkerError::timeout(
+                WsppTimeoutPhase::Close,
+                elapsed,
+            )));
+            let _ = event_tx.send(Event::abnormal_close());
             return;
         }
 
-        match tokio::time::timeout(Duration::from_millis(10), client.next_frame()).await {
-            Ok(Ok(frame)) => match frame.opcode() {
-                OpCode::Text => {
-                    let _ = event_tx.send(Event::Message {
-                        data: frame.payload().to_vec(),
-                        opcode: 1,
-                    });
+        if let Some(silence) = options.watchdog
+            && last_frame_at.elapsed() >= silence
+        {
+            // Re-arm so a connection that stays quiet is reported each period.
+            last_frame_at = Instant::now();
+            let _ = event_tx.send(Event::Watchdog);
+        }
+
+        if let Some(timer) = heartbeat.as_mut().filter(|_| !closing_requested) {
+            match timer.poll(Instant::now()) {
+                Beat::Idle => {}
+                Beat::Ping(payload) => {
+                    let ping = frame(&mut masks, true, OpCode::Ping, payload);
+                    if let Err(err) = stall.watch(client.send(ping)).await {
+                        let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
+                        let _ = event_tx.send(Event::abnormal_close());
+                        return;
+                    }
                 }
-                OpCode::Binary => {
-                    let _ = event_tx.send(Event::Message {
-                        data: frame.payload().to_vec(),
-                        opcode: 2,
-                    });
+                Beat::TimedOut(waited) => {
+                    let _ = event_tx.send(Event::Error(WorkerError::pong_timeout(waited)));
+                    let _ = event_tx.send(Event::abnormal_close());
+                    return;
                 }
-                OpCode::Ping => {
-                    let _ = event_tx.send(Event::Message {
-                        data: frame.payload().to_vec(),
-                        opcode: 9,
-                    });
+            }
+        }
+
+        let over_budget = account.exceeded();
+        if over_budget {
+            match account.policy() {
+                BudgetPolicy::PauseReads => {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    continue;
                 }
-                OpCode::Pong => {
-                    let _ = event_tx.send(Event::Pong(frame.payload().to_vec()));
+                BudgetPolicy::CloseLargest if !closing_requested && account.is_largest() => {
+                    logging::emit(2, "memory budget exceeded; closing largest connection");
+                    closing_requested = true;
+                    close_started_at = Some(Instant::now());
+                    let _ = client
+                        .send(close_frame(
+                            &mut masks,
+                            WsppCloseCode::PolicyViolation.code(),
+                            b"Memory budget exceeded",
+                        ))
+                        .await;
                 }
-                OpCode::Close => {
-                    let _ = event_tx.send(Event::Close);
-                    return;
+                _ => {}
+            }
+        }
+        let drop_messages = over_budget && account.policy() == BudgetPolicy::DropMessages;
+
+        match tokio::time::timeout(Duration::from_millis(10), client.next_frame()).await {
+            Ok(Ok(frame)) => {
+                last_frame_at = Instant::now();
+                progress.frame_done();
+                let payload = match arena.as_mut() {
+                    Some(arena) if frame.payload().len() > INLINE_PAYLOAD => {
+                        Payload::Shared(arena.alloc(frame.payload()))
+                    }
+                    _ => Payload::from(frame.payload().clone()),
+                };
+                match frame.opcode() {
+                    OpCode::Text | OpCode::Binary if drop_messages => {
+                        stats.record_loss(Loss::MemoryBudget);
+                    }
+                    OpCode::Text => {
+                        send_message_event(
+                            &event_tx,
+                            &account,
+                            options.spill.as_ref(),
+                            payload,
+                            WsppOpcode::Text,
+                        );
+                    }
+                    OpCode::Binary => {
+                        send_message_event(
+                            &event_tx,
+                            &account,
+                            options.spill.as_ref(),
+                            payload,
+                            WsppOpcode::Binary,
+                        );
+                    }
+                    OpCode::Ping => {
+                        send_payload_event(
+                            &event_tx,
+                            &account,
+                            Event::Message {
+                                data: payload,
+                                opcode: WsppOpcode::Ping,
+                            },
+                        );
+                    }
+                    OpCode::Pong
+                        if heartbeat.as_mut().is_some_and(|timer| timer.pong(&payload)) => {}
+                    OpCode::Pong => {
+                        let rtt = ping_rtt(&mut outstanding_pings, &payload, Instant::now());
+                        let event = match (rtt, options.pong_policy) {
+                            (Some(_), _) | (None, PongPolicy::Deliver) => {
+                                Some(Event::Pong { data: payload, rtt })
+                            }
+                            (None, PongPolicy::Ignore) => None,
+                            (None, PongPolicy::Report) => Some(Event::UnsolicitedPong(payload)),
+                        };
+                        if rtt.is_none() {
+                            stats.record_unsolicited_pong();
+                        }
+                        if let Some(event) = event {
+                            send_payload_event(&event_tx, &account, event);
+                        }
+                    }
+                    OpCode::Close => {
+                        let _ = event_tx.send(Event::received_close(&payload));
+                        return;
+                    }
+                    // yawc hands over fragmented messages reassembled, with
+                    // the opcode of their first frame, so no continuation
+                    // frame gets here.
+                    OpCode::Continuation => {}
                 }
-                OpCode::Continuation => {}
-            },
+            }
             Ok(Err(err)) => {
+                if matches!(err, WebSocketError::FrameTooLarge) {
+                    let _ = client
+                        .send(close_frame(
+                            &mut masks,
+                            WsppCloseCode::MessageTooBig.code(),
+                            b"Message too big",
+                        ))
+                        .await;
+                }
                 if !closing_requested {
-                    let _ = event_tx.send(Event::Error(err.to_string()));
+                    let _ = event_tx.send(Event::Error(WorkerError::socket(&err)));
                 }
-                let _ = event_tx.send(Event::Close);
+                let _ = event_tx.send(Event::abnormal_close());
                 return;
             }
             Err(_) => {}
         }
+
+        #[cfg(feature = "unsafe-protocol")]
+        if options.raw_receive {
+            match drain_bypass(&tap.bypass, &event_tx, &account) {
+                Drained::Nothing => {}
+                Drained::Frames => {
+                    last_frame_at = Instant::now();
+                    progress.frame_done();
+                }
+                Drained::Ended => return,
+            }
+        }
+
+        if let Some(limit) = options.read_stall_timeout
+            && let Some(stalled) = progress.stalled_for(Instant::now())
+            && stalled >= limit
+        {
+            let _ = event_tx.send(Event::Error(WorkerError::timeout(
+                WsppTimeoutPhase::ReadStall,
+                stalled,
+            )));
+            let _ = event_tx.send(Event::abnormal_close());
+            return;
+        }
     }
 }
 
+/// Notices a frame whose bytes stopped arriving partway through.
+struct ReadProgress {
+    bytes_read: Arc<AtomicU64>,
+    seen: u64,
+    /// When bytes of a not yet complete frame last arrived.
+    progress_at: Option<Instant>,
+}
+
+impl ReadProgress {
+    fn new(bytes_read: Arc<AtomicU64>) -> Self {
+        let seen = bytes_read.load(Ordering::Relaxed);
+        Self {
+            bytes_read,
+            seen,
+            progress_at: None,
+        }
+    }
+
+    fn frame_done(&mut self) {
+        self.seen = self.bytes_read.load(Ordering::Relaxed);
+        self.progress_at = None;
+    }
+
+    /// How long the frame being read has gone without new bytes; `None`
+    /// between frames.
+    fn stalled_for(&mut self, now: Instant) -> Option<Duration> {
+        let total = self.bytes_read.load(Ordering::Relaxed);
+        if total != self.seen {
+            self.seen = total;
+            self.progress_at = Some(now);
+        }
+        self.progress_at.map(|at| now.duration_since(at))
+    }
+}
+
+#[cfg(feature = "unsafe-protocol")]
+enum Drained {
+    Nothing,
+    Frames,
+    /// The connection is gone and `Close` was sent.
+    Ended,
+}
+
+/// Delivers the frames read past yawc in raw receive mode.
+#[cfg(feature = "unsafe-protocol")]
+fn drain_bypass(
+    bypass: &std::sync::Mutex<Bypass>,
+    event_tx: &EventSender,
+    account: &BudgetAccount,
+) -> Drained {
+    let mut bypass = bypass.lock().unwrap_or_else(|err| err.into_inner());
+    let mut drained = Drained::Nothing;
+    loop {
+        match RawFrame::parse(&mut bypass.data) {
+            Ok(Some(frame)) => {
+                drained = Drained::Frames;
+                send_payload_event(event_tx, account, Event::RawFrame(frame));
+            }
+            Ok(None) => break,
+            Err(reason) => {
+                let _ = event_tx.send(Event::Error(WorkerError::new(
+                    WsppErrorCategory::Protocol,
+                    reason,
+                )));
+                let _ = event_tx.send(Event::abnormal_close());
+                return Drained::Ended;
+            }
+        }
+    }
+
+    if let Some(err) = bypass.error.take() {
+        let _ = event_tx.send(Event::Error(WorkerError::new(
+            WsppErrorCategory::of_io(&err),
+            err.to_string(),
+        )));
+    } else if !bypass.eof {
+        return drained;
+    }
+    let _ = event_tx.send(Event::abnormal_close());
+    Drained::Ended
+}
+
+/// Matches a pong against the oldest outstanding ping with the same
+/// payload. Older unanswered pings are discarded.
+fn ping_rtt(
+    outstanding: &mut VecDeque<(Payload, Instant)>,
+    payload: &[u8],
+    now: Instant,
+) -> Option<Duration> {
+    let pos = outstanding
+        .iter()
+        .position(|(data, _)| **data == *payload)?;
+    let (_, sent_at) = outstanding.drain(..=pos).next_back()?;
+    Some(now.duration_since(sent_at))
+}
+
 fn close_timed_out(started_at: Option<Instant>, now: Instant, timeout: Duration) -> bool {
     match started_at {
         Some(started) => now.duration_since(started) >= timeout,
@@ -238,10 +1165,18 @@ fn close_timed_out(started_at: Option<Instant>, now: Instant, timeout: Duration)
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
     use std::time::{Duration, Instant};
 
-    use super::WorkerStartError;
-    use super::close_timed_out;
+    use std::collections::VecDeque;
+
+    use super::{
+        Command, Commands, Event, HandleStats, Payload, Priority, Unsent, WorkerStartError,
+    };
+    use super::{
+        ReadProgress, close_timed_out, join_with_timeout, ping_rtt, spill_to_file, thread_name,
+    };
     use crate::result::WsppResult;
 
     #[test]
@@ -250,6 +1185,75 @@ mod tests {
         assert_eq!(err.to_wspp_result(), WsppResult::InvalidArgument);
     }
 
+    #[test]
+    fn close_frames_yield_code_and_reason() {
+        let close = |payload: &[u8]| match Event::received_close(payload) {
+            Event::Close { code, reason } => (code, reason.to_vec()),
+            other => panic!("unexpected {other:?}"),
+        };
+        assert_eq!(close(b"\x03\xe8bye"), (1000, b"bye".to_vec()));
+        assert_eq!(close(b""), (1005, Vec::new()));
+        assert!(matches!(
+            Event::abnormal_close(),
+            Event::Close { code: 1006, .. }
+        ));
+    }
+
+    #[test]
+    fn join_reports_finished_and_detached_threads() {
+        let quick = std::thread::spawn(|| {});
+        assert!(join_with_timeout(quick, Duration::from_secs(5)));
+
+        let slow = std::thread::spawn(|| std::thread::sleep(Duration::from_millis(200)));
+        assert!(!join_with_timeout(slow, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn only_messages_past_their_deadline_expire() {
+        let now = Instant::now();
+        let message = |expires_at| Command::SendBinary {
+            data: Payload::from("x"),
+            expires_at,
+            priority: Priority::Normal,
+        };
+
+        assert!(message(Some(now)).is_expired(now));
+        assert!(!message(Some(now + Duration::from_millis(1))).is_expired(now));
+        assert!(!message(None).is_expired(now));
+        assert!(!Command::Ping(Payload::new()).is_expired(now));
+    }
+
+    #[test]
+    fn messages_left_when_the_worker_ends_are_kept() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let unsent = Arc::new(Unsent::new(1));
+        let stats = Arc::new(HandleStats::default());
+        let commands = Commands {
+            rx,
+            unsent: Some(unsent.clone()),
+            stats: stats.clone(),
+        };
+        for data in ["a", "b"] {
+            let text = Command::SendText {
+                data: Payload::from(data),
+                expires_at: None,
+                priority: Priority::Normal,
+            };
+            tx.send(text).expect("receiver alive");
+        }
+        drop(commands);
+
+        let kept = Vec::from(unsent.take());
+        assert!(matches!(&kept[..], [Command::SendText { data, .. }] if &data[..] == b"b"));
+        assert_eq!(stats.snapshot().dropped_queue_full, 1);
+    }
+
+    #[test]
+    fn worker_threads_are_named_after_the_host() {
+        let url = url::Url::parse("wss://example.com:8443/ws").expect("url");
+        assert_eq!(thread_name(&url), "wspp-worker-example.com");
+    }
+
     #[test]
     fn start_error_maps_runtime_init() {
         let err = WorkerStartError::RuntimeInit(std::io::Error::other("runtime"));
@@ -271,9 +1275,63 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn pong_matches_oldest_ping_with_payload() {
+        let start = Instant::now();
+        let mut outstanding = VecDeque::from([
+            (Payload::from("a"), start),
+            (Payload::from("b"), start + Duration::from_millis(5)),
+            (Payload::from("c"), start + Duration::from_millis(10)),
+        ]);
+
+        let rtt = ping_rtt(&mut outstanding, b"b", start + Duration::from_millis(25));
+        assert_eq!(rtt, Some(Duration::from_millis(20)));
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(ping_rtt(&mut outstanding, b"x", start), None);
+        assert_eq!(outstanding.len(), 1);
+    }
+
+    #[test]
+    fn spill_writes_unique_files() {
+        let dir = std::env::temp_dir();
+        let first = spill_to_file(&dir, b"first").expect("spill");
+        let second = spill_to_file(&dir, b"second").expect("spill");
+
+        assert_ne!(first, second);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&first)
+                .expect("metadata")
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(std::fs::read(&first).expect("read"), b"first");
+        assert_eq!(std::fs::read(&second).expect("read"), b"second");
+        std::fs::remove_file(first).expect("remove");
+        std::fs::remove_file(second).expect("remove");
+    }
+
     #[test]
     fn close_timeout_is_false_without_close_request() {
         let now = Instant::now();
         assert!(!close_timed_out(None, now, Duration::from_secs(5)));
     }
+
+    #[test]
+    fn read_stall_counts_only_within_a_frame() {
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let mut progress = ReadProgress::new(bytes_read.clone());
+        let start = Instant::now();
+        assert_eq!(progress.stalled_for(start), None);
+
+        bytes_read.store(10, Ordering::Relaxed);
+        assert_eq!(progress.stalled_for(start), Some(Duration::ZERO));
+        let later = start + Duration::from_secs(3);
+        assert_eq!(progress.stalled_for(later), Some(Duration::from_secs(3)));
+
+        progress.frame_done();
+        assert_eq!(progress.stalled_for(later), None);
+    }
 }
@@ -0,0 +1,94 @@
+use std::ffi::CStr;
+
+/// Standard close codes from RFC 6455 and the IANA registry.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WsppCloseCode {
+    Normal = 1000,
+    GoingAway = 1001,
+    ProtocolError = 1002,
+    UnsupportedData = 1003,
+    NoStatus = 1005,
+    Abnormal = 1006,
+    InvalidPayload = 1007,
+    PolicyViolation = 1008,
+    MessageTooBig = 1009,
+    MandatoryExtension = 1010,
+    InternalError = 1011,
+    ServiceRestart = 1012,
+    TryAgainLater = 1013,
+    BadGateway = 1014,
+    TlsHandshake = 1015,
+}
+
+impl WsppCloseCode {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+}
+
+/// Whether an endpoint may send `code` in a close frame. Codes below 3000
+/// are limited to the ones RFC 6455 defines; 1005, 1006 and 1015 are only
+/// ever reported locally.
+pub fn is_sendable(code: u16) -> bool {
+    matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+}
+
+/// Human-readable name of a close code, including the ranges reserved for
+/// libraries and applications.
+pub fn describe(code: u16) -> &'static CStr {
+    match code {
+        1000 => c"Normal closure",
+        1001 => c"Going away",
+        1002 => c"Protocol error",
+        1003 => c"Unsupported data",
+        1005 => c"No status received",
+        1006 => c"Abnormal closure",
+        1007 => c"Invalid frame payload data",
+        1008 => c"Policy violation",
+        1009 => c"Message too big",
+        1010 => c"Mandatory extension",
+        1011 => c"Internal error",
+        1012 => c"Service restart",
+        1013 => c"Try again later",
+        1014 => c"Bad gateway",
+        1015 => c"TLS handshake failure",
+        3000..=3999 => c"Registered close code",
+        4000..=4999 => c"Application close code",
+        _ => c"Unknown close code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WsppCloseCode, describe, is_sendable};
+
+    #[test]
+    fn names_standard_codes() {
+        assert_eq!(
+            describe(WsppCloseCode::Normal.code()).to_str(),
+            Ok("Normal closure")
+        );
+        assert_eq!(
+            describe(WsppCloseCode::Abnormal.code()).to_str(),
+            Ok("Abnormal closure")
+        );
+    }
+
+    #[test]
+    fn only_sendable_codes_pass_validation() {
+        for code in [1000, 1001, 1003, 1007, 1011, 1014, 3000, 4999] {
+            assert!(is_sendable(code), "{code} should be sendable");
+        }
+        for code in [0, 5, 999, 1004, 1005, 1006, 1015, 1016, 2999, 5000] {
+            assert!(!is_sendable(code), "{code} should be rejected");
+        }
+    }
+
+    #[test]
+    fn names_code_ranges() {
+        assert_eq!(describe(4001).to_str(), Ok("Application close code"));
+        assert_eq!(describe(3000).to_str(), Ok("Registered close code"));
+        assert_eq!(describe(5).to_str(), Ok("Unknown close code"));
+    }
+}
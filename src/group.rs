@@ -0,0 +1,255 @@
+use std::ptr::NonNull;
+use std::sync::Weak;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::client::{Payload, WsppWsImpl};
+use crate::result::WsppResult;
+
+/// A set of handles drained together. The group does not own its members;
+/// a deleted handle drops out of every group it was in.
+#[derive(Default)]
+pub struct WsppGroupImpl {
+    members: Vec<Member>,
+}
+
+struct Member {
+    ws: NonNull<WsppWsImpl>,
+    /// Gone once the handle is deleted and `ws` dangles.
+    alive: Weak<()>,
+}
+
+impl WsppGroupImpl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `ws`, which stays a member while `alive` can be upgraded.
+    pub fn add(&mut self, ws: *mut WsppWsImpl, alive: Weak<()>) -> Result<WsppResult, WsppResult> {
+        let ws = NonNull::new(ws).ok_or(WsppResult::InvalidArgument)?;
+        if self.live_members().any(|member| member.ws == ws) {
+            return Err(WsppResult::InvalidState);
+        }
+
+        self.members.push(Member { ws, alive });
+        Ok(WsppResult::Ok)
+    }
+
+    pub fn remove(&mut self, ws: *mut WsppWsImpl) -> Result<WsppResult, WsppResult> {
+        let ws = NonNull::new(ws).ok_or(WsppResult::InvalidArgument)?;
+        let pos = self
+            .live_members()
+            .position(|member| member.ws == ws)
+            .ok_or(WsppResult::InvalidArgument)?;
+
+        self.members.remove(pos);
+        Ok(WsppResult::Ok)
+    }
+
+    /// Forgets deleted handles, then yields the others.
+    fn live_members(&mut self) -> impl Iterator<Item = &mut Member> {
+        self.members
+            .retain(|member| member.alive.strong_count() > 0);
+        self.members.iter_mut()
+    }
+
+    pub fn poll(&mut self) -> u64 {
+        self.live_members()
+            .map(|member| unsafe { member.ws.as_mut() }.poll())
+            .fold(0_u64, u64::saturating_add)
+    }
+
+    /// Queues `message` on every member; large payloads share one allocation.
+    /// Members that reject it are skipped; the first failure is returned.
+    pub fn send_message(&mut self, message: &str) -> Result<WsppResult, WsppResult> {
+        let text = Payload::from(message);
+        self.broadcast(|ws| ws.send_text(text.clone()))
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<WsppResult, WsppResult> {
+        let data = Payload::copy_from_slice(data);
+        self.broadcast(|ws| ws.send_binary(data.clone()))
+    }
+
+    fn broadcast(
+        &mut self,
+        mut send: impl FnMut(&mut WsppWsImpl) -> Result<WsppResult, WsppResult>,
+    ) -> Result<WsppResult, WsppResult> {
+        let mut result = Ok(WsppResult::Ok);
+        for member in self.live_members() {
+            let sent = send(unsafe { member.ws.as_mut() });
+            if result.is_ok() && sent.is_err() {
+                result = sent;
+            }
+        }
+        result
+    }
+
+    /// Polls until at least one event was dispatched or `timeout` elapsed.
+    pub fn wait(&mut self, timeout: Duration) -> u64 {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let handled = self.poll();
+            if handled > 0 || Instant::now() >= deadline {
+                return handled;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::WsppGroupImpl;
+    use crate::client::WsppWsImpl;
+    use crate::result::WsppResult;
+    use crate::test_support::{Recorded, TestServer, connected, install_recorder, recorded};
+
+    #[test]
+    fn add_rejects_null_and_duplicates() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+
+        assert_eq!(
+            group.add(std::ptr::null_mut(), Arc::downgrade(&alive)),
+            Err(WsppResult::InvalidArgument)
+        );
+        assert_eq!(
+            group.add(&mut ws, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+        assert_eq!(
+            group.add(&mut ws, Arc::downgrade(&alive)),
+            Err(WsppResult::InvalidState)
+        );
+        assert_eq!(group.members.len(), 1);
+    }
+
+    #[test]
+    fn remove_requires_membership() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+
+        assert_eq!(group.remove(&mut ws), Err(WsppResult::InvalidArgument));
+        assert_eq!(
+            group.add(&mut ws, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+        assert_eq!(group.remove(&mut ws), Ok(WsppResult::Ok));
+        assert_eq!(group.members.len(), 0);
+    }
+
+    #[test]
+    fn deleted_members_drop_out() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+        assert_eq!(
+            group.add(&mut ws, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+
+        drop(alive);
+        assert_eq!(group.poll(), 0);
+        assert_eq!(group.members.len(), 0);
+        assert_eq!(group.remove(&mut ws), Err(WsppResult::InvalidArgument));
+    }
+
+    #[test]
+    fn wait_times_out_without_events() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+        assert_eq!(
+            group.add(&mut ws, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+
+        assert_eq!(group.wait(Duration::from_millis(5)), 0);
+    }
+
+    #[test]
+    fn poll_drains_every_member() {
+        let server = TestServer::start();
+        let mut first = WsppWsImpl::new(&server.url(), true);
+        let mut second = WsppWsImpl::new(&server.url(), true);
+        install_recorder(&mut first);
+        install_recorder(&mut second);
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+        assert_eq!(
+            group.add(&mut first, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+        assert_eq!(
+            group.add(&mut second, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+
+        assert_eq!(first.connect(), Ok(WsppResult::Ok));
+        assert_eq!(second.connect(), Ok(WsppResult::Ok));
+        let mut handled = 0;
+        while handled < 2 {
+            let drained = group.wait(Duration::from_secs(5));
+            assert!(drained > 0, "timed out waiting for open events");
+            handled += drained;
+        }
+
+        assert_eq!(recorded(), vec![Recorded::Open, Recorded::Open]);
+        first.shutdown();
+        second.shutdown();
+    }
+
+    #[test]
+    fn broadcast_reaches_every_member() {
+        let server = TestServer::start();
+        let mut first = connected(&server.url());
+        let mut second = connected(&server.url());
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+        assert_eq!(
+            group.add(&mut first, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+        assert_eq!(
+            group.add(&mut second, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+
+        assert_eq!(group.send_message("fan-out"), Ok(WsppResult::Ok));
+        assert_eq!(group.send_binary(&[7, 7]), Ok(WsppResult::Ok));
+        let mut handled = 0;
+        while handled < 4 {
+            let drained = group.wait(Duration::from_secs(5));
+            assert!(drained > 0, "timed out waiting for echoes");
+            handled += drained;
+        }
+
+        let events = recorded();
+        let text = Recorded::Message(b"fan-out".to_vec(), 1);
+        let binary = Recorded::Message(vec![7, 7], 2);
+        assert_eq!(events.iter().filter(|e| **e == text).count(), 2);
+        assert_eq!(events.iter().filter(|e| **e == binary).count(), 2);
+        first.shutdown();
+        second.shutdown();
+    }
+
+    #[test]
+    fn broadcast_reports_unconnected_members() {
+        let mut ws = WsppWsImpl::new("ws://127.0.0.1:18765/ws", true);
+        let mut group = WsppGroupImpl::new();
+        let alive = Arc::new(());
+        assert_eq!(
+            group.add(&mut ws, Arc::downgrade(&alive)),
+            Ok(WsppResult::Ok)
+        );
+
+        assert_eq!(group.send_message("x"), Err(WsppResult::InvalidState));
+    }
+}
@@ -1,30 +1,131 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
+mod budget;
 mod callback;
 mod client;
+mod close_code;
+mod group;
+mod lifecycle;
 mod logging;
+mod opcode;
+mod pool;
 mod result;
+#[cfg(test)]
+mod test_support;
+mod uri;
 
+use std::cell::UnsafeCell;
 use std::ffi::{CStr, c_char, c_void};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
 use std::thread;
 use std::time::Duration;
 
+#[cfg(feature = "unsafe-protocol")]
+use bytes::Bytes;
+
+use budget::BudgetPolicy;
 use callback::{
-    OnCloseCallback, OnErrorCallback, OnLogCallback, OnMessageCallback, OnOpenCallback,
-    OnPongCallback,
+    KeyPassphraseSource, MessageFilter, OnBackpressureCallback, OnCloseCallback,
+    OnCloseExtCallback, OnDeletedCallback, OnErrorCallback, OnErrorExtCallback, OnEventsCallback,
+    OnHealthCallback, OnLogCallback, OnMemoryPressureCallback, OnMessageCallback,
+    OnMessageFileCallback, OnOpenCallback, OnOpenExtCallback, OnPongCallback,
+    OnQueuePressureCallback, OnReconnectGaveUpCallback, OnReconnectedCallback,
+    OnReconnectingCallback, OnResponseCallback, OnResumedCallback, OnWatchdogCallback,
+    OnWireDataCallback, PingPayloadSource, RandomSource, ReleaseCallback, ResponseIdExtractor,
+    StreamProvider, Userdata, WithCtx,
 };
-use client::{WsState, WsppWsImpl};
-use result::WsppResult;
+#[cfg(feature = "fault-injection")]
+use client::Faults;
+#[cfg(feature = "pkcs11")]
+use client::Pkcs11Identity;
+use client::{
+    BufferGrowth, CallbackSlots, ChunkSource, HostPassphrase, HostPayload, HostRandom, IpFamily,
+    Jitter, Keepalive, Passphrase, PingPayload, PongPolicy, Priority, ProviderSource, QueuePolicy,
+    Reconnect, RevocationMode, SendFlags, ThreadPriority, VerifyPolicy, WsState, WsppErrorCategory,
+    WsppPollReport, WsppStats, WsppWsImpl,
+};
+use group::WsppGroupImpl;
+use uri::WsppUriError;
+
+pub use callback::Callbacks;
+pub use client::Payload;
+pub use close_code::WsppCloseCode;
+pub use logging::WsppWireDirection;
+pub use opcode::WsppOpcode;
+pub use pool::WsppPoolImpl;
+pub use result::WsppResult;
 
-static WSPP_ABI_VERSION: u64 = 1;
+static WSPP_ABI_VERSION: u64 = 2;
+/// Address of `wspp_new_pair` handles; `.invalid` never resolves.
+static PAIR_URI: &str = "ws://wspp-pair.invalid/";
 
 pub struct WsppWs {
     _private: [u8; 0],
 }
 
+pub struct WsppGroup {
+    _private: [u8; 0],
+}
+
+pub struct WsppPool {
+    _private: [u8; 0],
+}
+
+/// What a `WsppWs` points to. The handler setters only go through
+/// `slots`, so they never borrow the handle while another thread polls it
+/// and holds it mutably.
+struct WsHandle {
+    slots: Arc<CallbackSlots>,
+    ws: UnsafeCell<WsppWsImpl>,
+    /// Freed with the handle; groups keep a `Weak` to skip deleted members.
+    alive: Arc<()>,
+}
+
+fn into_handle(ws: WsppWsImpl) -> *mut WsppWs {
+    let handle = WsHandle {
+        slots: ws.slots(),
+        ws: UnsafeCell::new(ws),
+        alive: Arc::new(()),
+    };
+    Box::into_raw(Box::new(handle)).cast()
+}
+
+/// Takes back a handle made by `into_handle`.
+unsafe fn from_handle(ws: *mut WsppWs) -> WsppWsImpl {
+    unsafe { Box::from_raw(ws.cast::<WsHandle>()) }
+        .ws
+        .into_inner()
+}
+
+#[inline]
+unsafe fn ws_ptr(ws: *mut WsppWs) -> *mut WsppWsImpl {
+    match unsafe { ws.cast::<WsHandle>().as_ref() } {
+        Some(handle) => handle.ws.get(),
+        None => std::ptr::null_mut(),
+    }
+}
+
 #[inline]
 unsafe fn ws_mut<'a>(ws: *mut WsppWs) -> Option<&'a mut WsppWsImpl> {
-    unsafe { ws.cast::<WsppWsImpl>().as_mut() }
+    unsafe { ws_ptr(ws).as_mut() }
+}
+
+/// For the handler setters, which are safe while another thread polls
+/// `ws`.
+#[inline]
+unsafe fn ws_slots<'a>(ws: *mut WsppWs) -> Option<&'a CallbackSlots> {
+    unsafe { ws.cast::<WsHandle>().as_ref() }.map(|handle| &*handle.slots)
+}
+
+#[inline]
+unsafe fn group_mut<'a>(group: *mut WsppGroup) -> Option<&'a mut WsppGroupImpl> {
+    unsafe { group.cast::<WsppGroupImpl>().as_mut() }
+}
+
+#[inline]
+unsafe fn pool_mut<'a>(pool: *mut WsppPool) -> Option<&'a mut WsppPoolImpl> {
+    unsafe { pool.cast::<WsppPoolImpl>().as_mut() }
 }
 
 fn ffi_result(res: Result<WsppResult, WsppResult>) -> WsppResult {
@@ -44,6 +145,29 @@ unsafe fn cstr<'a>(ptr: *const c_char) -> Result<&'a str, WsppResult> {
         .map_err(|_| WsppResult::InvalidArgument)
 }
 
+/// Decodes a NUL-terminated UTF-16 string, as `wchar_t` is on Windows.
+unsafe fn wstr(ptr: *const u16) -> Result<String, WsppResult> {
+    if ptr.is_null() {
+        return Err(WsppResult::InvalidArgument);
+    }
+
+    let mut len = 0;
+    while unsafe { *ptr.add(len) } != 0 {
+        len += 1;
+    }
+    String::from_utf16(unsafe { std::slice::from_raw_parts(ptr, len) })
+        .map_err(|_| WsppResult::InvalidArgument)
+}
+
+/// Maps a data opcode argument to whether the message is text.
+fn stream_is_text(opcode: i32) -> Result<bool, WsppResult> {
+    match WsppOpcode::from_ffi(opcode) {
+        Some(WsppOpcode::Text) => Ok(true),
+        Some(WsppOpcode::Binary) => Ok(false),
+        _ => Err(WsppResult::InvalidArgument),
+    }
+}
+
 unsafe fn data_slice<'a>(data: *const c_void, len: u64) -> Result<&'a [u8], WsppResult> {
     let len_usize = usize::try_from(len).map_err(|_| WsppResult::InvalidArgument)?;
     if len_usize == 0 {
@@ -56,14 +180,75 @@ unsafe fn data_slice<'a>(data: *const c_void, len: u64) -> Result<&'a [u8], Wspp
     Ok(unsafe { std::slice::from_raw_parts(data as *const u8, len_usize) })
 }
 
+/// Writes `value` plus a NUL terminator into the `cap` bytes at `buf`.
+unsafe fn copy_cstr(value: &str, buf: *mut c_char, cap: u64) -> WsppResult {
+    if value.len() as u64 >= cap {
+        return WsppResult::BufferTooSmall;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(value.as_ptr() as *const c_char, buf, value.len());
+        *buf.add(value.len()) = 0;
+    }
+    WsppResult::Ok
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_abi_version() -> u64 {
     WSPP_ABI_VERSION
 }
 
+/// Whether `wss` connections run on a FIPS 140 validated crypto module
+/// using only approved algorithms. Only builds with the `fips` feature
+/// return true.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_is_fips() -> bool {
+    client::is_fips()
+}
+
+/// Static, NUL-terminated description of a close code, e.g. one of
+/// `WsppCloseCode`. Never null; unknown codes get a generic name.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_close_code_str(code: u16) -> *const c_char {
+    close_code::describe(code).as_ptr()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_log_handler(callback: Option<OnLogCallback>, user_data: *mut c_void) {
+    logging::set_log_handler(WithCtx::wrap(callback, user_data));
+}
+
+/// Lets at most `burst` identical log messages through per `window_ms`.
+/// Further repeats are dropped and reported as one "message repeated N
+/// times" line, logged ahead of the first message after their window has
+/// passed. A zero `burst` or
+/// `window_ms` turns limiting off, the default.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_log_rate_limit(burst: u32, window_ms: u64) {
+    let window = Duration::from_millis(window_ms);
+    logging::set_rate_limit((burst > 0 && !window.is_zero()).then_some((burst, window)));
+}
+
+/// Installs a log handler for the calling thread only, taking precedence
+/// over the one from `wspp_set_log_handler`. Connections started from this
+/// thread log to it from their worker threads too. Null removes it, and
+/// the global handler applies again.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_thread_log_handler(
+    callback: Option<OnLogCallback>,
+    user_data: *mut c_void,
+) {
+    logging::set_thread_log_handler(WithCtx::wrap(callback, user_data));
+}
+
+/// Installs a handler for the bytes every connection reads and writes,
+/// after TLS decryption and before frame parsing. It is only called while
+/// the log level is 5 (trace) and runs on worker threads.
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_set_log_handler(callback: Option<OnLogCallback>) {
-    logging::set_log_handler(callback);
+pub extern "C" fn wspp_set_wire_data_handler(
+    callback: Option<OnWireDataCallback>,
+    user_data: *mut c_void,
+) {
+    logging::set_wire_handler(WithCtx::wrap(callback, user_data));
 }
 
 #[unsafe(no_mangle)]
@@ -71,23 +256,122 @@ pub extern "C" fn wspp_set_loglevel(level: i32) {
     logging::set_log_level(level);
 }
 
+/// Caps payload bytes queued across all handles. `max_bytes` of zero
+/// disables the cap. The pressure handler may run on a worker thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_memory_budget(max_bytes: u64, policy: i32) -> WsppResult {
+    let Some(policy) = BudgetPolicy::from_ffi(policy) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    budget::global().configure(max_bytes, policy);
+    WsppResult::Ok
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_memory_pressure_handler(
+    callback: Option<OnMemoryPressureCallback>,
+    user_data: *mut c_void,
+) {
+    budget::global().set_pressure_handler(WithCtx::wrap(callback, user_data));
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_memory_usage() -> u64 {
+    budget::global().used()
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_new(uri: *const c_char) -> *mut WsppWs {
     wspp_new_ext(uri, true)
 }
 
+/// Returns null if `uri` is not a valid `ws` or `wss` URL; see
+/// `wspp_get_create_error` for why.
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_new_ext(uri: *const c_char, compression: bool) -> *mut WsppWs {
-    if uri.is_null() {
-        return std::ptr::null_mut();
+    let uri = unsafe { cstr(uri) }.map(str::to_owned);
+    create(uri, compression)
+}
+
+/// `wspp_new` taking the URI as UTF-16, i.e. a `wchar_t` string on
+/// Windows. Returns null if it is not valid UTF-16.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_new_w(uri: *const u16) -> *mut WsppWs {
+    create(unsafe { wstr(uri) }, true)
+}
+
+fn create(uri: Result<String, WsppResult>, compression: bool) -> *mut WsppWs {
+    let checked =
+        uri.map_err(|_| WsppUriError::Encoding)
+            .and_then(|uri| match uri::validate(&uri) {
+                Ok(_) => Ok(uri),
+                Err(err) => Err(err.kind),
+            });
+    match checked {
+        Ok(uri) => {
+            uri::set_create_error(WsppUriError::None);
+            into_handle(WsppWsImpl::new(&uri, compression))
+        }
+        Err(err) => {
+            uri::set_create_error(err);
+            std::ptr::null_mut()
+        }
     }
+}
 
-    let uri_str = match unsafe { cstr(uri) } {
-        Ok(uri) => uri,
-        Err(_) => return std::ptr::null_mut(),
+/// Checks that `uri` is a `ws` or `wss` URL with a host, a usable port and
+/// well-formed percent-encoding, without creating a handle. Returns
+/// `InvalidArgument` if it is not and, unless `err_buf` is null, writes
+/// what is wrong there, cut to fit the `cap` bytes.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_validate_uri(
+    uri: *const c_char,
+    err_buf: *mut c_char,
+    cap: u64,
+) -> WsppResult {
+    let problem = match unsafe { cstr(uri) } {
+        Ok(uri) => match uri::validate(uri) {
+            Ok(_) => return WsppResult::Ok,
+            Err(err) => err.message,
+        },
+        Err(_) => "null or not valid UTF-8".to_owned(),
     };
+    if !err_buf.is_null() && cap > 0 {
+        let mut end = problem.len().min(cap as usize - 1);
+        while !problem.is_char_boundary(end) {
+            end -= 1;
+        }
+        unsafe { copy_cstr(&problem[..end], err_buf, cap) };
+    }
+    WsppResult::InvalidArgument
+}
 
-    Box::into_raw(Box::new(WsppWsImpl::new(uri_str, compression))) as *mut WsppWs
+/// Creates two handles connected to each other in memory, for tests: no
+/// sockets or threads are involved and events arrive when either handle
+/// polls, so runs are deterministic. Both start out connecting, like after
+/// `wspp_connect`, and get their open event on the first poll. A ping is
+/// answered right away and closing or deleting one closes both. Connecting
+/// either again afterwards fails, as there is no server behind them.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_new_pair(out_a: *mut *mut WsppWs, out_b: *mut *mut WsppWs) -> WsppResult {
+    if out_a.is_null() || out_b.is_null() {
+        return WsppResult::InvalidArgument;
+    }
+
+    let (a, b) = WsppWsImpl::new_pair(PAIR_URI);
+    unsafe {
+        *out_a = into_handle(a);
+        *out_b = into_handle(b);
+    }
+    WsppResult::Ok
+}
+
+/// Why the last `wspp_new*` call on this thread returned null, `None` if
+/// it succeeded.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_create_error() -> WsppUriError {
+    uri::create_error()
 }
 
 #[unsafe(no_mangle)]
@@ -96,15 +380,61 @@ pub extern "C" fn wspp_delete(ws: *mut WsppWs) {
         return;
     }
 
-    if let Some(inner) = unsafe { ws_mut(ws) } {
-        inner.shutdown();
+    let mut inner = unsafe { from_handle(ws) };
+    inner.shutdown();
+}
+
+/// Like `wspp_delete`, but waits up to `join_timeout_ms` for the worker to
+/// finish its shutdown. Returns `Timeout` if the worker had to be detached.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_delete_ex(ws: *mut WsppWs, join_timeout_ms: u64) -> WsppResult {
+    if ws.is_null() {
+        return WsppResult::InvalidState;
     }
 
-    unsafe {
-        drop(Box::from_raw(ws.cast::<WsppWsImpl>()));
+    let mut inner = unsafe { from_handle(ws) };
+    inner.shutdown();
+    if inner.join_worker(Duration::from_millis(join_timeout_ms)) {
+        WsppResult::Ok
+    } else {
+        logging::emit(2, "worker did not stop in time; detaching");
+        WsppResult::Timeout
     }
 }
 
+/// Starts tearing `ws` down and returns immediately. `done` is called with
+/// `userdata` from a background thread once the worker has exited.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_delete_async(
+    ws: *mut WsppWs,
+    done: Option<OnDeletedCallback>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    if ws.is_null() {
+        return WsppResult::InvalidState;
+    }
+
+    let inner = unsafe { from_handle(ws) };
+    let userdata = Userdata(userdata);
+    inner.shutdown_then(move || {
+        if let Some(done) = done {
+            done(userdata.get());
+        }
+    });
+    WsppResult::Ok
+}
+
+/// Prepares the library for being unloaded with `dlclose` or `FreeLibrary`.
+/// Waits up to `timeout_ms` for threads left behind by deleted handles,
+/// including `wspp_delete_async` completions, then clears the log, wire
+/// data and memory pressure handlers. Returns `InvalidState` while any
+/// handle, pool or group still exists and `Timeout` if threads were still
+/// running; call again before unloading in that case.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_global_shutdown(timeout_ms: u64) -> WsppResult {
+    ffi_result(lifecycle::shutdown(Duration::from_millis(timeout_ms)))
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_poll(ws: *mut WsppWs) -> u64 {
     let Some(ws) = (unsafe { ws_mut(ws) }) else {
@@ -114,6 +444,21 @@ pub extern "C" fn wspp_poll(ws: *mut WsppWs) -> u64 {
     ws.poll()
 }
 
+/// Polls like `wspp_poll` and additionally fills `out`, if not null, with
+/// per-kind counts of what was dispatched.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_poll_ex(ws: *mut WsppWs, out: *mut WsppPollReport) -> u64 {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return 0;
+    };
+
+    let report = ws.poll_report();
+    if !out.is_null() {
+        unsafe { *out = report };
+    }
+    report.total
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_run(ws: *mut WsppWs) -> u64 {
     let mut handled = 0_u64;
@@ -138,6 +483,16 @@ pub extern "C" fn wspp_stopped(ws: *mut WsppWs) -> bool {
     matches!(wsp.get_state(), WsState::Closed)
 }
 
+/// The handle's `WsState`: 0 new, 1 connecting, 2 connected, 3 closing,
+/// 4 closed. A null handle reads as closed, as in `wspp_stopped`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_state(ws: *mut WsppWs) -> i32 {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.get_state() as i32,
+        None => WsState::Closed as i32,
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_connect(ws: *mut WsppWs) -> WsppResult {
     let Some(ws) = (unsafe { ws_mut(ws) }) else {
@@ -165,6 +520,26 @@ pub extern "C" fn wspp_close(ws: *mut WsppWs, code: u16, reason: *const c_char)
     ffi_result(ws.close(code, reason_str))
 }
 
+/// `wspp_close` taking the reason as UTF-16, i.e. a `wchar_t` string on
+/// Windows. Null sends no reason.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_close_w(ws: *mut WsppWs, code: u16, reason: *const u16) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let reason = if reason.is_null() {
+        String::new()
+    } else {
+        match unsafe { wstr(reason) } {
+            Ok(r) => r,
+            Err(e) => return e.to_ffi(),
+        }
+    };
+
+    ffi_result(ws.close(code, &reason))
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn wspp_send_text(ws: *mut WsppWs, message: *const c_char) -> WsppResult {
     let Some(ws) = (unsafe { ws_mut(ws) }) else {
@@ -190,90 +565,2211 @@ pub extern "C" fn wspp_send_binary(ws: *mut WsppWs, data: *const c_void, len: u6
         Err(e) => return e.to_ffi(),
     };
 
-    ffi_result(ws.send_binary(bytes.to_vec()))
+    ffi_result(ws.send_binary(Payload::copy_from_slice(bytes)))
 }
 
+/// `wspp_send_binary` without copying `data`: the library reads it in place
+/// and calls `release` with `userdata` once the message was written, or
+/// dropped because the send failed, expired or the connection closed. The
+/// buffer must stay valid and unchanged until then. `release` is called
+/// exactly once, possibly on the worker thread or before this returns.
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_ping(ws: *mut WsppWs, data: *const c_void, len: u64) -> WsppResult {
+pub extern "C" fn wspp_send_binary_noalloc(
+    ws: *mut WsppWs,
+    data: *const c_void,
+    len: u64,
+    release: Option<ReleaseCallback>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    let Some(release) = release else {
+        return WsppResult::InvalidArgument;
+    };
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        release(userdata);
+        return WsppResult::InvalidState;
+    };
+    let Ok(len) = usize::try_from(len) else {
+        release(userdata);
+        return WsppResult::InvalidArgument;
+    };
+    if data.is_null() && len > 0 {
+        release(userdata);
+        return WsppResult::InvalidArgument;
+    }
+    let payload = unsafe { Payload::borrowed(data.cast(), len, release, userdata) };
+    ffi_result(ws.send_binary(payload))
+}
+
+/// `wspp_send_text` with per-message `flags`: bit 0 (1) asks for no
+/// compression, advisory as yawc compresses every message once deflate is
+/// negotiated; bit 1 (2) sends at high priority, as `wspp_send_priority`;
+/// bit 2 (4) drops the message if it is still queued after a second; bit 3
+/// (8) drops it instead of failing with `QueueFull` when the queue is full.
+/// Unknown bits are rejected.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_send_text_flags(
+    ws: *mut WsppWs,
+    message: *const c_char,
+    flags: u32,
+) -> WsppResult {
     let Some(ws) = (unsafe { ws_mut(ws) }) else {
         return WsppResult::InvalidState;
     };
+    let message_str = match unsafe { cstr(message) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+    let Some(flags) = SendFlags::from_ffi(flags) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.send_flagged(true, Payload::from(message_str), flags))
+}
 
+/// `wspp_send_binary` with the per-message `flags` of `wspp_send_text_flags`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_send_binary_flags(
+    ws: *mut WsppWs,
+    data: *const c_void,
+    len: u64,
+    flags: u32,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
     let bytes = match unsafe { data_slice(data, len) } {
         Ok(s) => s,
         Err(e) => return e.to_ffi(),
     };
+    let Some(flags) = SendFlags::from_ffi(flags) else {
+        return WsppResult::InvalidArgument;
+    };
 
-    ffi_result(ws.ping(bytes.to_vec()))
+    ffi_result(ws.send_flagged(false, Payload::copy_from_slice(bytes), flags))
 }
 
+/// Sends a message with a queue `priority` (0 normal, 1 high) used by the
+/// drop-by-priority queue policy. `opcode` is `WsppOpcode::Text` or `Binary`.
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_set_open_handler(ws: *mut WsppWs, f: Option<OnOpenCallback>) {
-    if let Some(ws) = unsafe { ws_mut(ws) } {
-        ws.callbacks.on_open = f;
+pub extern "C" fn wspp_send_priority(
+    ws: *mut WsppWs,
+    data: *const c_void,
+    len: u64,
+    opcode: i32,
+    priority: i32,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let bytes = match unsafe { data_slice(data, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+    let text = match stream_is_text(opcode) {
+        Ok(text) => text,
+        Err(e) => return e.to_ffi(),
+    };
+    if text && std::str::from_utf8(bytes).is_err() {
+        return WsppResult::InvalidArgument;
     }
+    let Some(priority) = Priority::from_ffi(priority) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.send_prioritized(text, Payload::copy_from_slice(bytes), priority))
 }
 
+/// Sends one message whose payload is pulled from `provider` on the worker
+/// thread and written as fragments. `opcode` is `WsppOpcode::Text` or `Binary`.
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_set_close_handler(ws: *mut WsppWs, f: Option<OnCloseCallback>) {
-    if let Some(ws) = unsafe { ws_mut(ws) } {
-        ws.callbacks.on_close = f;
-    }
+pub extern "C" fn wspp_send_stream(
+    ws: *mut WsppWs,
+    opcode: i32,
+    provider: Option<StreamProvider>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(provider) = provider else {
+        return WsppResult::InvalidArgument;
+    };
+    let text = match stream_is_text(opcode) {
+        Ok(text) => text,
+        Err(e) => return e.to_ffi(),
+    };
+
+    let source = ChunkSource::Provider(ProviderSource::new(provider, userdata));
+    ffi_result(ws.send_stream(text, source))
 }
 
+/// Streams the file at `path` as one fragmented message, read in bounded
+/// chunks on the worker thread. `opcode` is `WsppOpcode::Text` or `Binary`.
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_set_message_handler(ws: *mut WsppWs, f: Option<OnMessageCallback>) {
-    if let Some(ws) = unsafe { ws_mut(ws) } {
-        ws.callbacks.on_message = f;
-    }
+pub extern "C" fn wspp_send_file(ws: *mut WsppWs, path: *const c_char, opcode: i32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let path = match unsafe { cstr(path) } {
+        Ok(p) => PathBuf::from(p),
+        Err(e) => return e.to_ffi(),
+    };
+    let text = match stream_is_text(opcode) {
+        Ok(text) => text,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(ws.send_file(text, &path))
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_set_error_handler(ws: *mut WsppWs, f: Option<OnErrorCallback>) {
-    if let Some(ws) = unsafe { ws_mut(ws) } {
-        ws.callbacks.on_error = f;
-    }
+pub extern "C" fn wspp_ping(ws: *mut WsppWs, data: *const c_void, len: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let bytes = match unsafe { data_slice(data, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(ws.ping(Payload::copy_from_slice(bytes)))
 }
 
+/// Sends one frame built from the given fields without any validation, so
+/// servers can be probed with malformed frames. `opcode` is the 4-bit wire
+/// opcode and `rsv_bits` holds RSV1..RSV3 in its low three bits. The frame
+/// is masked like any other. Only built with the `unsafe-protocol` feature.
+#[cfg(feature = "unsafe-protocol")]
 #[unsafe(no_mangle)]
-pub extern "C" fn wspp_set_pong_handler(ws: *mut WsppWs, f: Option<OnPongCallback>) {
-    if let Some(ws) = unsafe { ws_mut(ws) } {
-        ws.callbacks.on_pong = f;
+pub extern "C" fn wspp_send_raw_frame(
+    ws: *mut WsppWs,
+    opcode: i32,
+    rsv_bits: i32,
+    fin: bool,
+    data: *const c_void,
+    len: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let (Ok(opcode), Ok(rsv)) = (u8::try_from(opcode), u8::try_from(rsv_bits)) else {
+        return WsppResult::InvalidArgument;
+    };
+    let bytes = match unsafe { data_slice(data, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(ws.send_raw_frame(opcode, rsv, fin, Bytes::copy_from_slice(bytes)))
+}
+
+/// Receives every frame of later connections through `f` exactly as sent,
+/// with no reassembly, automatic pongs or close handling; the host answers
+/// pings itself with `wspp_send_raw_frame`. Null restores normal delivery.
+/// Only valid while idle. Only built with the `unsafe-protocol` feature.
+#[cfg(feature = "unsafe-protocol")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_raw_frame_handler(
+    ws: *mut WsppWs,
+    f: Option<callback::OnRawFrameCallback>,
+    user_data: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let result = ws.set_raw_receive(f.is_some());
+    if result.is_ok() {
+        ws.set_callbacks(|cb| cb.on_raw_frame = WithCtx::wrap(f, user_data));
     }
+    ffi_result(result)
 }
 
-#[cfg(test)]
-mod tests {
-    use std::ffi::{CString, c_void};
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_request(
+    ws: *mut WsppWs,
+    payload: *const c_void,
+    len: u64,
+    timeout_ms: u64,
+    request_id: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
 
-    use super::{WsppResult, cstr, data_slice};
+    let bytes = match unsafe { data_slice(payload, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+    let Ok(text) = std::str::from_utf8(bytes) else {
+        return WsppResult::InvalidArgument;
+    };
 
-    #[test]
-    fn cstr_rejects_null() {
-        let result = unsafe { cstr(std::ptr::null()) };
-        assert_eq!(result, Err(WsppResult::InvalidArgument));
-    }
+    ffi_result(ws.request(request_id, text, Duration::from_millis(timeout_ms)))
+}
 
-    #[test]
-    fn cstr_rejects_invalid_utf8() {
-        let bytes = [0xFF_u8, 0x00_u8];
-        let result = unsafe { cstr(bytes.as_ptr() as *const i8) };
-        assert_eq!(result, Err(WsppResult::InvalidArgument));
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_latency_percentiles(
+    ws: *mut WsppWs,
+    p50: *mut u64,
+    p95: *mut u64,
+    p99: *mut u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some((v50, v95, v99)) = ws.latency_percentiles() else {
+        return WsppResult::InvalidState;
+    };
+
+    for (out, value) in [(p50, v50), (p95, v95), (p99, v99)] {
+        if let Some(out) = unsafe { out.as_mut() } {
+            *out = value;
+        }
     }
+    WsppResult::Ok
+}
 
-    #[test]
-    fn cstr_accepts_utf8() {
+/// Spills incoming messages above `threshold` bytes to files in `dir`
+/// (the system temp dir when null), created readable only by the current
+/// user. This keeps large messages out of the event queue and the host's
+/// buffers, not out of memory altogether: each is received whole before it
+/// is written out. Zero disables spilling.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_spill(
+    ws: *mut WsppWs,
+    threshold: u64,
+    dir: *const c_char,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let dir = if dir.is_null() {
+        std::env::temp_dir()
+    } else {
+        match unsafe { cstr(dir) } {
+            Ok(d) => PathBuf::from(d),
+            Err(e) => return e.to_ffi(),
+        }
+    };
+    let Ok(threshold) = usize::try_from(threshold) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_spill(threshold, dir))
+}
+
+/// Allocates small incoming payloads from per-connection blocks of
+/// `block_size` bytes, reused once poll has drained them. Zero disables it.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_event_arena(ws: *mut WsppWs, block_size: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Ok(block_size) = usize::try_from(block_size) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_event_arena(block_size))
+}
+
+/// Pre-allocates a receive buffer of `capacity` bytes that incoming payloads
+/// are packed into and reused across polls. `growth` 0 keeps the size fixed,
+/// 1 lets it grow to fit larger messages. Zero capacity disables it.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_receive_buffer(
+    ws: *mut WsppWs,
+    capacity: u64,
+    growth: i32,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Ok(capacity) = usize::try_from(capacity) else {
+        return WsppResult::InvalidArgument;
+    };
+    let Some(growth) = BufferGrowth::from_ffi(growth) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_receive_buffer(capacity, growth))
+}
+
+/// Caps the frame size of outgoing messages at `size` bytes; larger sends,
+/// streams and files are written as fragments of that size. Zero keeps
+/// whole-message frames and 64 KiB stream chunks. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_write_chunk_size(ws: *mut WsppWs, size: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Ok(size) = usize::try_from(size) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_write_chunk_size(size))
+}
+
+/// Sets the scheduling priority of the worker thread: 0 normal, 1 low,
+/// 2 high. Maps to the nice value on Linux, the QoS class on Apple
+/// platforms and `SetThreadPriority` on Windows; elsewhere, or when raising
+/// it needs privileges the process lacks, a warning is logged and the
+/// worker runs at normal priority. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_thread_priority(ws: *mut WsppWs, priority: i32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(priority) = ThreadPriority::from_ffi(priority) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_thread_priority(priority))
+}
+
+/// Runs later connections without a worker thread: each `wspp_poll` drives
+/// the connection on the calling thread for up to `slice_ms`, blocking that
+/// long when there is nothing to do, and nothing happens between polls.
+/// Hostnames are then resolved by the system resolver on the polling
+/// thread, which blocks that poll until the lookup is done; connect to an
+/// IP address to avoid it. Zero goes back to a worker thread per
+/// connection. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_inline_mode(ws: *mut WsppWs, slice_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_inline((slice_ms > 0).then(|| Duration::from_millis(slice_ms))))
+}
+
+/// Chooses what happens to pongs that match no outstanding ping: 0 delivers
+/// them to the pong handler, 1 ignores them and 2 passes them to `f`
+/// instead. They are always counted in the stats. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_pong_policy(
+    ws: *mut WsppWs,
+    policy: i32,
+    f: Option<OnPongCallback>,
+    user_data: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(policy) = PongPolicy::from_ffi(policy) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    let result = ws.set_pong_policy(policy);
+    if result.is_ok() {
+        ws.set_callbacks(|cb| cb.on_unsolicited_pong = WithCtx::wrap(f, user_data));
+    }
+    ffi_result(result)
+}
+
+/// Fails a connect that has not completed TCP, TLS and the upgrade within
+/// `timeout_ms` with a `Timeout` error. Zero leaves it to the OS. Only
+/// valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_connect_timeout(ws: *mut WsppWs, timeout_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_connect_timeout(Duration::from_millis(timeout_ms)))
+}
+
+/// Fails the connection with a `Timeout` error of phase `ReadStall` when a
+/// frame started arriving but no further bytes came for `timeout_ms`. Unlike
+/// the watchdog, quiet periods between frames never trigger it. Zero
+/// disables it. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_read_stall_timeout(ws: *mut WsppWs, timeout_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_read_stall_timeout(Duration::from_millis(timeout_ms)))
+}
+
+/// Fails the connection with a `Timeout` error of phase `Write` when the
+/// peer stops reading and the socket accepts nothing for `timeout_ms`,
+/// instead of letting queued sends wait forever. Zero disables it. Only
+/// valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_write_timeout(ws: *mut WsppWs, timeout_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_write_timeout(Duration::from_millis(timeout_ms)))
+}
+
+/// Sets TCP_USER_TIMEOUT on the connection's socket: once sent data stays
+/// unacknowledged for `timeout_ms`, the kernel drops the connection and the
+/// handle reports an error. This catches half-open connections faster than
+/// keepalive. Linux only; elsewhere a warning is logged on connect. Zero
+/// keeps the system default. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_tcp_user_timeout(ws: *mut WsppWs, timeout_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_tcp_user_timeout(Duration::from_millis(timeout_ms)))
+}
+
+/// Turns on TCP keepalive for the connection's socket. `idle_ms` is the
+/// quiet time before the first probe, `interval_ms` the time between probes
+/// and `count` how many unanswered probes drop the connection; zero keeps
+/// the system default for each. Interval and count are not supported on
+/// every platform; a warning is logged on connect where they are not.
+/// `enabled = false` turns keepalive off. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_tcp_keepalive(
+    ws: *mut WsppWs,
+    enabled: bool,
+    idle_ms: u64,
+    interval_ms: u64,
+    count: u32,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let duration = |ms| (ms > 0).then(|| Duration::from_millis(ms));
+    let keepalive = enabled.then(|| Keepalive {
+        idle: duration(idle_ms),
+        interval: duration(interval_ms),
+        count: (count > 0).then_some(count),
+    });
+    ffi_result(ws.set_tcp_keepalive(keepalive))
+}
+
+/// Chooses which resolved addresses are tried: 0 any, 1 IPv4 only, 2 IPv6
+/// only. Restricting the family avoids waiting for timeouts on networks with
+/// broken IPv6 (or IPv4) routing. A host without addresses of the chosen
+/// family fails with a `Dns` error. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_ip_family(ws: *mut WsppWs, family: i32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(family) = IpFamily::from_ffi(family) else {
+        return WsppResult::InvalidArgument;
+    };
+    ffi_result(ws.set_ip_family(family))
+}
+
+/// When the host name resolves to several addresses they are tried in
+/// order; this limits each TCP attempt to `timeout_ms` so an unreachable
+/// address does not hold up the rest. Zero leaves each attempt to the OS
+/// timeout. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_address_timeout(ws: *mut WsppWs, timeout_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_address_timeout(Duration::from_millis(timeout_ms)))
+}
+
+/// Checks server certificates for revocation on `wss` connects: 0 off,
+/// 1 soft-fail, 2 stapled OCSP required. Soft-fail rejects certificates a
+/// stapled OCSP response or an added CRL reports revoked but lets through
+/// those whose status is unknown; mode 2 also rejects servers that staple
+/// no valid response. Rejections are reported as `CertRevoked` errors.
+/// Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_revocation_mode(ws: *mut WsppWs, mode: i32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(mode) = RevocationMode::from_ffi(mode) else {
+        return WsppResult::InvalidArgument;
+    };
+    ffi_result(ws.set_revocation_mode(mode))
+}
+
+/// Accepts server certificates that fail verification in specific ways,
+/// for staging servers: bit 0 (1) tolerates certificates outside their
+/// validity period, bit 1 (2) chains to an unknown CA, bit 2 (4) a
+/// hostname mismatch. The remaining checks still apply, and every accepted
+/// problem is logged at error level. Zero restores full verification;
+/// unknown bits are rejected. SChannel builds only support bit 2 and
+/// reject the others with `InvalidArgument`. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_tls_verify_flags(ws: *mut WsppWs, flags: u32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(policy) = VerifyPolicy::from_ffi(flags) else {
+        return WsppResult::InvalidArgument;
+    };
+    ffi_result(ws.set_verify_policy(policy))
+}
+
+/// Overrides the compression choice of `wspp_new_ext` for later connects.
+/// Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_compression(ws: *mut WsppWs, enabled: bool) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_compression(enabled))
+}
+
+/// Enables or disables matching the server certificate against the host
+/// in the URI, e.g. to reach members of a cluster sharing one certificate
+/// by IP. The chain, validity and revocation checks still apply. Enabled
+/// by default. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_verify_hostname(ws: *mut WsppWs, enabled: bool) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_verify_hostname(enabled))
+}
+
+/// Enables or disables verifying the server certificate of this handle's
+/// `wss` connects, e.g. for development against a self-signed server. With
+/// verification off any certificate is accepted, whatever the other TLS
+/// settings say, and a warning is logged. Enabled by default. Only valid
+/// while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_tls_verify(ws: *mut WsppWs, enabled: bool) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_tls_verify(enabled))
+}
+
+/// Evaluates server trust through the macOS Security framework instead of
+/// the bundled root store, so keychain trust settings, admin-installed
+/// roots and configuration profiles apply. Revocation is then checked by
+/// the system, and `wspp_set_tls_verify_flags`, `wspp_set_verify_hostname`
+/// and the revocation settings are ignored. Enabling it anywhere but on
+/// Apple platforms returns `InvalidArgument`. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_system_trust(ws: *mut WsppWs, enabled: bool) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_system_trust(enabled))
+}
+
+/// Adds a DER-encoded certificate revocation list consulted while
+/// revocation checks are on. Lists that do not parse are rejected with
+/// `InvalidArgument`; a zero `len` removes all added lists. SChannel
+/// builds reject lists, as it cannot check them. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_add_crl(ws: *mut WsppWs, der: *const c_void, len: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let der = match unsafe { data_slice(der, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+    ffi_result(ws.add_crl(der))
+}
+
+/// Trusts the CA certificates of a PEM bundle for later `wss` connects, on
+/// top of the bundled roots, e.g. a corporate CA. Replaces certificates set
+/// before; a zero `len` removes them. Bundles without certificates or with
+/// one that does not parse are rejected with `InvalidArgument`. Only valid
+/// while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_ca_pem(ws: *mut WsppWs, data: *const c_char, len: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let pem = match unsafe { data_slice(data.cast(), len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+    ffi_result(ws.set_ca_pem(pem))
+}
+
+/// `wspp_set_ca_pem` with the bundle read from the file at `path`. Returns
+/// `IoError` if it cannot be read. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_ca_file(ws: *mut WsppWs, path: *const c_char) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let path = match unsafe { cstr(path) } {
+        Ok(p) => PathBuf::from(p),
+        Err(e) => return e.to_ffi(),
+    };
+    ffi_result(ws.set_ca_file(&path))
+}
+
+/// Presents a client certificate to `wss` servers that ask for one, for
+/// mutual TLS. `cert_pem` holds the certificate chain, leaf first, and
+/// `key_pem` its private key, both as NUL-terminated PEM. A key that does
+/// not belong to the leaf is rejected with `InvalidArgument`; two empty
+/// strings remove the certificate. A server refusing it fails the connect
+/// with a `ClientCert` error. An `ENCRYPTED PRIVATE KEY` (PKCS#8 with
+/// PBES2) is kept encrypted and unlocked on every connect with the
+/// passphrase from `wspp_set_client_key_passphrase` or its callback; a
+/// missing or wrong passphrase, or a key not matching the leaf, then fails
+/// the connect with `InvalidArgument`. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_client_cert(
+    ws: *mut WsppWs,
+    cert_pem: *const c_char,
+    key_pem: *const c_char,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let pair = unsafe { cstr(cert_pem) }.and_then(|cert| Ok((cert, unsafe { cstr(key_pem) }?)));
+    ffi_result(pair.and_then(|(cert, key)| ws.set_client_cert(cert.as_bytes(), key.as_bytes())))
+}
+
+/// Presents the client certificate and key of the PKCS#12 (.p12/.pfx)
+/// bundle in the `len` bytes at `data`, as exported by Windows, macOS or
+/// OpenSSL, instead of a PEM pair. `passphrase` opens it; null is the same
+/// as empty. The first key in the bundle is used with the certificates
+/// chained to it. A bundle that cannot be opened, e.g. for a wrong
+/// passphrase, or holds no key is rejected with `InvalidArgument`.
+/// `wspp_set_client_cert` with two empty strings removes it. Only valid
+/// while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_client_identity_p12(
+    ws: *mut WsppWs,
+    data: *const c_void,
+    len: u64,
+    passphrase: *const c_char,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let der = match unsafe { data_slice(data, len) } {
+        Ok([]) => return WsppResult::InvalidArgument,
+        Ok(der) => der,
+        Err(e) => return e,
+    };
+    let passphrase = if passphrase.is_null() {
+        Ok("")
+    } else {
+        unsafe { cstr(passphrase) }
+    };
+    ffi_result(passphrase.and_then(|passphrase| ws.set_client_identity_p12(der, passphrase)))
+}
+
+/// Sets the passphrase unlocking an encrypted client key on each connect,
+/// replacing a passphrase callback. Null or empty removes it. The library
+/// keeps a copy, wiped when replaced or the handle is deleted; use
+/// `wspp_set_client_key_passphrase_fn` to not have it held at all. Only
+/// valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_client_key_passphrase(
+    ws: *mut WsppWs,
+    passphrase: *const c_char,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    // Taken as bytes: a passphrase need not be UTF-8.
+    let bytes = if passphrase.is_null() {
+        &[][..]
+    } else {
+        unsafe { CStr::from_ptr(passphrase) }.to_bytes()
+    };
+    let passphrase = match bytes {
+        [] => Passphrase::Missing,
+        bytes => Passphrase::Fixed(bytes.to_vec().into()),
+    };
+    ffi_result(ws.set_client_key_passphrase(passphrase))
+}
+
+/// Asks `f` for the passphrase of an encrypted client key whenever a
+/// connect or reconnect attempt needs it, on the thread starting it, with
+/// `userdata`. The passphrase is wiped once the key is decrypted. Replaces
+/// a passphrase set before; a null `f` removes it. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_client_key_passphrase_fn(
+    ws: *mut WsppWs,
+    f: Option<KeyPassphraseSource>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let passphrase = match f {
+        Some(f) => Passphrase::Host(HostPassphrase::new(f, userdata)),
+        None => Passphrase::Missing,
+    };
+    ffi_result(ws.set_client_key_passphrase(passphrase))
+}
+
+/// Presents a client certificate kept on a PKCS#11 token, e.g. a smartcard
+/// or HSM, whose private key never leaves it: the handshake is signed by
+/// the module at `module_path` with the token in `slot`. `label` picks the
+/// certificate, and the key sharing its ID, by `CKA_LABEL`; null takes the
+/// first. Each connect opens a session, logging in with the PIN `pin_fn`
+/// writes, called like a `wspp_set_client_key_passphrase_fn` callback with
+/// `userdata`; a null `pin_fn` skips login. An unusable token fails the
+/// connect with `InvalidArgument`. Replaces any other client certificate;
+/// `wspp_set_client_cert` with two empty strings removes it. RSA and
+/// P-256/P-384 keys are supported. SChannel builds reject it with
+/// `InvalidArgument`. Only valid while idle.
+#[cfg(feature = "pkcs11")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_client_cert_pkcs11(
+    ws: *mut WsppWs,
+    module_path: *const c_char,
+    slot: u64,
+    label: *const c_char,
+    pin_fn: Option<KeyPassphraseSource>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let label = if label.is_null() {
+        Ok(None)
+    } else {
+        unsafe { cstr(label) }.map(|label| Some(label.to_owned()))
+    };
+    let identity = unsafe { cstr(module_path) }.and_then(|module| {
+        Ok(Pkcs11Identity {
+            module: module.into(),
+            slot,
+            label: label?,
+            pin: match pin_fn {
+                Some(f) => Passphrase::Host(HostPassphrase::new(f, userdata)),
+                None => Passphrase::Missing,
+            },
+        })
+    });
+    ffi_result(identity.and_then(|identity| ws.set_client_cert_pkcs11(identity)))
+}
+
+/// `wspp_set_client_cert` with the PEM read from the files at `cert_path`
+/// and `key_path`. Returns `IoError` if either cannot be read. Only valid
+/// while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_client_cert_file(
+    ws: *mut WsppWs,
+    cert_path: *const c_char,
+    key_path: *const c_char,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let paths = unsafe { cstr(cert_path) }.and_then(|cert| Ok((cert, unsafe { cstr(key_path) }?)));
+    ffi_result(paths.and_then(|(cert, key)| ws.set_client_cert_file(cert.as_ref(), key.as_ref())))
+}
+
+/// Pings the server every `interval_ms` while connected. A ping left
+/// without pong for `timeout_ms` fails the connection with a "pong timeout"
+/// error of the `Timeout` category, followed by the close callbacks. Each
+/// ping carries a payload of its own, `wspp-keepalive-<n>`, and its pong
+/// is not reported; pongs to the host's own pings are. A zero `interval_ms` turns it
+/// off; a zero `timeout_ms` with pings on is rejected with
+/// `InvalidArgument`. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_keepalive(
+    ws: *mut WsppWs,
+    interval_ms: u64,
+    timeout_ms: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_keepalive(
+        Duration::from_millis(interval_ms),
+        Duration::from_millis(timeout_ms),
+    ))
+}
+
+/// Sends `data` in every keepalive ping instead of the numbered payload, for
+/// servers expecting particular ping bodies. At most 125 bytes; pongs
+/// carrying it are not reported while a keepalive ping is unanswered. A
+/// zero `len` restores the numbered payload. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_keepalive_payload(
+    ws: *mut WsppWs,
+    data: *const c_void,
+    len: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let payload = match unsafe { data_slice(data, len) } {
+        Ok([]) => PingPayload::Numbered,
+        Ok(s) => PingPayload::Fixed(Payload::copy_from_slice(s)),
+        Err(e) => return e.to_ffi(),
+    };
+    ffi_result(ws.set_keepalive_payload(payload))
+}
+
+/// Asks `f` for the payload of each keepalive ping, called with `userdata`
+/// on the worker thread. A null `f` restores the numbered payload. Only
+/// valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_keepalive_payload_fn(
+    ws: *mut WsppWs,
+    f: Option<PingPayloadSource>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let payload = match f {
+        Some(f) => PingPayload::Host(HostPayload::new(f, userdata)),
+        None => PingPayload::Numbered,
+    };
+    ffi_result(ws.set_keepalive_payload(payload))
+}
+
+/// Calls `f` whenever no frame was received for `silence_ms`, leaving the
+/// connection open. Zero disables the watchdog. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_watchdog(
+    ws: *mut WsppWs,
+    silence_ms: u64,
+    f: Option<OnWatchdogCallback>,
+    user_data: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let result = ws.set_watchdog(Duration::from_millis(silence_ms));
+    if result.is_ok() {
+        ws.set_callbacks(|cb| cb.on_watchdog = WithCtx::wrap(f, user_data));
+    }
+    ffi_result(result)
+}
+
+/// Stores a host pointer with `ws` for `wspp_get_user_data`. Callbacks get
+/// the pointer passed to their own setter instead.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_user_data(ws: *mut WsppWs, user_data: *mut c_void) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ws.set_userdata(Userdata(user_data));
+    WsppResult::Ok
+}
+
+/// The pointer set with `wspp_set_user_data`, null if none was.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_user_data(ws: *mut WsppWs) -> *mut c_void {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.userdata().get(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Like every handler setter, takes a `user_data` pointer that `f` gets
+/// back as its first argument, so one function can serve several handles
+/// or components without globals. The library never dereferences it.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_open_handler(
+    ws: *mut WsppWs,
+    f: Option<OnOpenCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_open = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Like `wspp_set_open_handler`, but also receives the negotiated
+/// subprotocol, extensions and response headers of the handshake.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_open_ext_handler(
+    ws: *mut WsppWs,
+    f: Option<OnOpenExtCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_open_ext = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Runs `f` on every in-memory message before it is routed or delivered;
+/// returning 1 drops the message. Messages spilled to disk bypass it.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_message_filter(
+    ws: *mut WsppWs,
+    f: Option<MessageFilter>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_filter = WithCtx::wrap(f, user_data));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_close_handler(
+    ws: *mut WsppWs,
+    f: Option<OnCloseCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_close = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Like `wspp_set_close_handler`, but also receives the close code and
+/// reason, e.g. to tell a normal 1000 close from a 1011 server error.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_close_ext_handler(
+    ws: *mut WsppWs,
+    f: Option<OnCloseExtCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_close_ext = WithCtx::wrap(f, user_data));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_message_handler(
+    ws: *mut WsppWs,
+    f: Option<OnMessageCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_message = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Registers another message handler, called after the one set with
+/// `wspp_set_message_handler` and any added before it, and stores the id
+/// `wspp_remove_message_handler` takes in `out_id`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_add_message_handler(
+    ws: *mut WsppWs,
+    f: Option<OnMessageCallback>,
+    user_data: *mut c_void,
+    out_id: *mut u64,
+) -> WsppResult {
+    let Some(slots) = (unsafe { ws_slots(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(handler) = WithCtx::wrap(f, user_data) else {
+        return WsppResult::InvalidArgument;
+    };
+    let id = slots.add_message_handler(handler);
+    if !out_id.is_null() {
+        unsafe { *out_id = id };
+    }
+    WsppResult::Ok
+}
+
+/// Removes a handler added with `wspp_add_message_handler`;
+/// `InvalidArgument` if `id` is not registered.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_remove_message_handler(ws: *mut WsppWs, id: u64) -> WsppResult {
+    let Some(slots) = (unsafe { ws_slots(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    if slots.remove_message_handler(id) {
+        WsppResult::Ok
+    } else {
+        WsppResult::InvalidArgument
+    }
+}
+
+/// Unsets every handler of `ws`, including added message handlers, e.g.
+/// before unloading the plugin they belong to. Like the handler setters
+/// this may be called while another thread is inside `wspp_poll`: changes
+/// apply from the next event on, so once the running poll returned none
+/// of the old handlers is called again.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_clear_handlers(ws: *mut WsppWs) -> WsppResult {
+    let Some(slots) = (unsafe { ws_slots(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    slots.clear();
+    WsppResult::Ok
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_message_file_handler(
+    ws: *mut WsppWs,
+    f: Option<OnMessageFileCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_message_file = WithCtx::wrap(f, user_data));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_error_handler(
+    ws: *mut WsppWs,
+    f: Option<OnErrorCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_error = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Receives every event drained by one poll in a single call, in order,
+/// instead of the open, close, message, pong, backpressure, watchdog and
+/// error handlers. Message filtering and response routing still apply.
+/// Spill files are removed once the callback returns.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_events_handler(
+    ws: *mut WsppWs,
+    f: Option<OnEventsCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_events = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Like `wspp_set_error_handler`, but also receives the error category.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_error_ext_handler(
+    ws: *mut WsppWs,
+    f: Option<OnErrorExtCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_error_ext = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Category of the handle's most recent error, `None` if it had none.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_last_error_category(ws: *mut WsppWs) -> WsppErrorCategory {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.last_error_category(),
+        None => WsppErrorCategory::None,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_pong_handler(
+    ws: *mut WsppWs,
+    f: Option<OnPongCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_pong = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Bounds the outgoing queue to `limit` text/binary messages (0 unbounded).
+/// When full, `policy` 0 rejects with `QueueFull`, 1 drops the oldest
+/// message and 2 drops the oldest normal-priority one. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_send_queue(ws: *mut WsppWs, limit: u64, policy: i32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Ok(limit) = usize::try_from(limit) else {
+        return WsppResult::InvalidArgument;
+    };
+    let Some(policy) = QueuePolicy::from_ffi(policy) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_send_queue(limit, policy))
+}
+
+/// Drops text and binary messages that are still queued `ttl_ms` after the
+/// send call instead of delivering them late. Zero disables expiry.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_send_ttl(ws: *mut WsppWs, ttl_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ws.set_send_ttl(Duration::from_millis(ttl_ms));
+    WsppResult::Ok
+}
+
+/// Collapses identical errors, same category and message, that follow each
+/// other within `window_ms` into one: the first is reported at once, the
+/// repeats are held back and reported as a single error whose
+/// `repeat_count` says how many there were, when the window ends or a
+/// different error arrives. The connection state changes as usual for
+/// every one of them. Zero, the default, reports each error.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_error_dedup_window(ws: *mut WsppWs, window_ms: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ws.set_error_dedup_window(Duration::from_millis(window_ms));
+    WsppResult::Ok
+}
+
+/// Lets the library reconnect on its own, up to `max_attempts` times in a
+/// row, when an open connection fails with an I/O error or a read stall or
+/// write timeout. A clean or protocol close is never resumed. Neither the
+/// error nor close and open are reported for it; the resumed handler is
+/// called once the new connection is up, and the state stays `Connected`
+/// throughout. Sends made before the failure was noticed are lost. Only
+/// the error of the last attempt is reported if none succeeds. Zero, the
+/// default, turns it off. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_auto_resume(ws: *mut WsppWs, max_attempts: u32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ffi_result(ws.set_auto_resume(max_attempts))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_resumed_handler(
+    ws: *mut WsppWs,
+    f: Option<OnResumedCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_resumed = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Lets the library connect again on its own when the connection ends with
+/// an error or without a close handshake, after `base_delay_ms` and then
+/// twice as long before each further attempt, up to `max_delay_ms`. The
+/// reconnecting handler is called as each attempt is scheduled, the state
+/// is `Connecting` until the reconnected handler runs in place of the open
+/// callbacks, and requests still
+/// waiting for a response fail. Failed attempts are not reported until
+/// `max_attempts` in a row failed, after which the last error is; zero
+/// attempts never gives up. Closing the handle meanwhile stops it without
+/// a close callback. `max_delay_ms` below `base_delay_ms` is rejected with
+/// `InvalidArgument`. Off by default. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_auto_reconnect(
+    ws: *mut WsppWs,
+    enabled: bool,
+    max_attempts: u32,
+    base_delay_ms: u64,
+    max_delay_ms: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    if enabled && max_delay_ms < base_delay_ms {
+        return WsppResult::InvalidArgument;
+    }
+
+    let reconnect = enabled.then(|| {
+        Reconnect::new(
+            max_attempts,
+            Duration::from_millis(base_delay_ms),
+            Duration::from_millis(max_delay_ms),
+        )
+    });
+    ffi_result(ws.set_auto_reconnect(reconnect))
+}
+
+/// Randomizes the delays of auto-reconnect, so clients that lost the same
+/// server spread out their attempts: 0 keeps them as computed, 1 (full)
+/// waits anywhere up to the delay and 2 (equal) half the delay plus
+/// anywhere up to the other half. `InvalidState` unless auto-reconnect is
+/// on; enabling it again resets this. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_reconnect_jitter(ws: *mut WsppWs, jitter: i32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Some(jitter) = Jitter::from_ffi(jitter) else {
+        return WsppResult::InvalidArgument;
+    };
+
+    ffi_result(ws.set_reconnect_jitter(jitter))
+}
+
+/// Called once auto-reconnect stops because `max_attempts` attempts in a
+/// row failed, after the error or close of the last one.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_reconnect_gave_up_handler(
+    ws: *mut WsppWs,
+    f: Option<OnReconnectGaveUpCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_reconnect_gave_up = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Called instead of the open callbacks when an auto-reconnect attempt is
+/// connected, with its number and the milliseconds since the connection
+/// was lost, so the host can tell it from the first open and restore its
+/// session state.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_reconnected_handler(
+    ws: *mut WsppWs,
+    f: Option<OnReconnectedCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_reconnected = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Keeps up to `max_messages` text and binary messages that were queued but
+/// not yet written when the connection was lost, and sends them again in
+/// order once auto-reconnect succeeds, right after the reconnected handler
+/// ran so it can restore session state first. Past the limit the oldest
+/// are dropped and counted as `dropped_queue_full`. The message being
+/// written when the connection broke is not kept. Zero turns it off, the
+/// default. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_replay_unsent(ws: *mut WsppWs, max_messages: u32) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ffi_result(ws.set_replay_unsent(max_messages as usize))
+}
+
+/// Adds `uri` to the endpoints reconnect attempts rotate through: each
+/// attempt goes to the next one, after the last back to the URI the handle
+/// was created with, instead of retrying the same server. A new connect
+/// always starts with that URI. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_add_fallback_uri(ws: *mut WsppWs, uri: *const c_char) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let uri = match unsafe { cstr(uri) } {
+        Ok(uri) => uri,
+        Err(err) => return err,
+    };
+
+    ffi_result(ws.add_fallback_uri(uri))
+}
+
+/// Stores how many connections to endpoint `index` failed, counting both
+/// failed attempts and connections lost later, in `out`. Index 0 is the
+/// URI the handle was created with, the fallbacks follow in the order
+/// added; `NotFound` past the last one.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_endpoint_failures(
+    ws: *mut WsppWs,
+    index: u64,
+    out: *mut u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    if out.is_null() {
+        return WsppResult::InvalidArgument;
+    }
+    let Some(failures) = usize::try_from(index)
+        .ok()
+        .and_then(|index| ws.endpoint_failures(index))
+    else {
+        return WsppResult::NotFound;
+    };
+
+    unsafe { *out = failures };
+    WsppResult::Ok
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_reconnecting_handler(
+    ws: *mut WsppWs,
+    f: Option<OnReconnectingCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_reconnecting = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Called with `true` when a write has been blocked on a full socket buffer
+/// for a while, and with `false` once it completes.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_backpressure_handler(
+    ws: *mut WsppWs,
+    f: Option<OnBackpressureCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_backpressure = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Id of the handle's most recent successful connection. Ids increase with
+/// every connection in the process, so a reconnect gets a new one. Zero
+/// means the handle never connected.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_connection_id(ws: *mut WsppWs) -> u64 {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.connection_id(),
+        None => 0,
+    }
+}
+
+/// Copies the value of response header `name` from the handshake of the
+/// current connection into `buf` as a NUL-terminated string. Names compare
+/// case-insensitively. Returns `NotFound` if the server did not send it and
+/// `BufferTooSmall` if the value plus terminator exceeds `cap`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_response_header(
+    ws: *mut WsppWs,
+    name: *const c_char,
+    buf: *mut c_char,
+    cap: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let name = match unsafe { cstr(name) } {
+        Ok(name) => name,
+        Err(err) => return err,
+    };
+    if buf.is_null() {
+        return WsppResult::InvalidArgument;
+    }
+
+    match ws.response_header(name) {
+        Ok(value) => unsafe { copy_cstr(value, buf, cap) },
+        Err(err) => err,
+    }
+}
+
+/// Records the upgrade request, response headers and phase timings of later
+/// connection attempts when enabled. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_handshake_capture(ws: *mut WsppWs, enabled: bool) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_handshake_capture(enabled))
+}
+
+/// Caps the size of incoming messages at `max_bytes`. Fragmented messages
+/// are reassembled before delivery and count with all their fragments. A
+/// bigger message is answered with close code 1009 and reported as a
+/// `Protocol` error. Zero keeps the default limit. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_max_message_size(ws: *mut WsppWs, max_bytes: u64) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let Ok(max_bytes) = usize::try_from(max_bytes) else {
+        return WsppResult::InvalidArgument;
+    };
+    ffi_result(ws.set_max_message_size(max_bytes))
+}
+
+/// Sends `name: value` with the upgrade request of later connects, e.g. an
+/// `Authorization` or `Cookie` header. Headers the handshake sets itself,
+/// like `Host` or `Sec-WebSocket-Key`, are refused. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_add_header(
+    ws: *mut WsppWs,
+    name: *const c_char,
+    value: *const c_char,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let header = unsafe { cstr(name) }.and_then(|name| Ok((name, unsafe { cstr(value) }?)));
+    ffi_result(header.and_then(|(name, value)| ws.add_header(name, value)))
+}
+
+/// `wspp_add_header` taking UTF-16 strings, as `wchar_t` is on Windows.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_add_header_w(
+    ws: *mut WsppWs,
+    name: *const u16,
+    value: *const u16,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let header = unsafe { wstr(name) }.and_then(|name| Ok((name, unsafe { wstr(value) }?)));
+    ffi_result(header.and_then(|(name, value)| ws.add_header(&name, &value)))
+}
+
+/// Removes every header added with `wspp_add_header`. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_clear_headers(ws: *mut WsppWs) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.clear_headers())
+}
+
+/// Copies the record of the last connection attempt into `buf` as a
+/// NUL-terminated JSON object with the request, response, timings in
+/// milliseconds and the error if the attempt failed. Credential headers are
+/// redacted. Returns `NotFound` until an attempt with capture enabled
+/// finished and `BufferTooSmall` if the record plus terminator exceeds `cap`.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_handshake_record(
+    ws: *mut WsppWs,
+    buf: *mut c_char,
+    cap: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    if buf.is_null() {
+        return WsppResult::InvalidArgument;
+    }
+
+    match ws.handshake_record() {
+        Ok(record) => unsafe { copy_cstr(&record, buf, cap) },
+        Err(err) => err,
+    }
+}
+
+/// Sends `key` as Sec-WebSocket-Key on later connects; null restores random
+/// keys. Only valid while idle.
+#[cfg(feature = "diagnostics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_handshake_key(ws: *mut WsppWs, key: *const c_char) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let key = if key.is_null() {
+        None
+    } else {
+        match unsafe { cstr(key) } {
+            Ok(key) => Some(key),
+            Err(err) => return err,
+        }
+    };
+    ffi_result(ws.set_handshake_key(key))
+}
+
+/// Records everything later connections send and receive, after TLS, with
+/// timestamps to the file at `path`. Null stops recording. Only valid while
+/// idle.
+#[cfg(feature = "diagnostics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_session_record(ws: *mut WsppWs, path: *const c_char) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let path = if path.is_null() {
+        None
+    } else {
+        match unsafe { cstr(path) } {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(err) => return err,
+        }
+    };
+    ffi_result(ws.set_session_record(path))
+}
+
+/// Makes later connects play back the session recorded at `path` instead
+/// of reaching the server: received bytes arrive on their recorded schedule
+/// and sent ones are discarded. Null connects normally again. Returns
+/// `InvalidArgument` if the file is not a session recording. Only valid
+/// while idle.
+#[cfg(feature = "diagnostics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_session_replay(ws: *mut WsppWs, path: *const c_char) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    let path = if path.is_null() {
+        None
+    } else {
+        match unsafe { cstr(path) } {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(err) => return err,
+        }
+    };
+    ffi_result(ws.set_session_replay(path.as_deref()))
+}
+
+/// Injects faults into later connections, after TLS decryption and before
+/// frame parsing: received bytes are held back `latency_ms`, handed on at
+/// most `max_read` bytes per read, and with chances given in parts per
+/// million, each read or write drops the connection (`disconnect_ppm`) and
+/// each read gets one bit flipped (`corrupt_ppm`). `seed` makes the draws
+/// repeat across runs. All zero turns injection off. Only valid while idle.
+#[cfg(feature = "fault-injection")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_faults(
+    ws: *mut WsppWs,
+    latency_ms: u64,
+    max_read: u64,
+    disconnect_ppm: u32,
+    corrupt_ppm: u32,
+    seed: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    if disconnect_ppm > 1_000_000 || corrupt_ppm > 1_000_000 {
+        return WsppResult::InvalidArgument;
+    }
+    let faults = Faults {
+        latency: (latency_ms > 0).then(|| Duration::from_millis(latency_ms)),
+        max_read: (max_read > 0).then(|| usize::try_from(max_read).unwrap_or(usize::MAX)),
+        disconnect_ppm,
+        corrupt_ppm,
+        seed,
+    };
+    ffi_result(ws.set_faults(Some(faults)))
+}
+
+/// Derives frame masking keys from `seed` so captures repeat byte for byte.
+/// `enabled` false restores random masks. Only valid while idle.
+#[cfg(feature = "diagnostics")]
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_mask_seed(ws: *mut WsppWs, seed: u64, enabled: bool) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_mask_seed(enabled.then_some(seed)))
+}
+
+/// Draws frame masking keys from `f` instead of the built-in secure RNG,
+/// e.g. to use a platform-mandated source. Null restores the default. If
+/// `f` fails, that frame falls back to the built-in RNG. Only valid while
+/// idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_mask_source(
+    ws: *mut WsppWs,
+    f: Option<RandomSource>,
+    userdata: *mut c_void,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_mask_source(f.map(|f| HostRandom::new(f, userdata))))
+}
+
+/// Sequence number of the event whose callback is running. Numbering
+/// restarts at 1 with each connection and rises by one per event, so gaps
+/// or reordering downstream can be detected. Zero before the first event.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_event_sequence(ws: *mut WsppWs) -> u64 {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.event_sequence(),
+        None => 0,
+    }
+}
+
+/// Connection quality from 0 to 100, combining round-trip time, unanswered
+/// pings, reconnects and errors of the last few minutes. Zero while not
+/// connected.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_health(ws: *mut WsppWs) -> u32 {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.health(),
+        None => 0,
+    }
+}
+
+/// Calls `f` with the health score from `wspp_poll` every `interval_ms`.
+/// Zero or a null `f` stops the reports.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_health_handler(
+    ws: *mut WsppWs,
+    interval_ms: u64,
+    f: Option<OnHealthCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(ws) = unsafe { ws_mut(ws) } {
+        let enabled = interval_ms > 0 && f.is_some();
+        ws.set_health_interval(enabled.then(|| Duration::from_millis(interval_ms)));
+        ws.set_callbacks(|cb| cb.on_health = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Limits socket throughput to `up_bps` bytes per second outgoing and
+/// `down_bps` incoming, with short bursts allowed. Zero leaves that
+/// direction unlimited. Only valid while idle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_bandwidth_limit(
+    ws: *mut WsppWs,
+    up_bps: u64,
+    down_bps: u64,
+) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    ffi_result(ws.set_bandwidth_limit(up_bps, down_bps))
+}
+
+/// Number of commands (sends, pings, closes) queued toward the worker and
+/// not yet picked up. A value that keeps growing points at a stalled worker
+/// rather than a slow network. Zero for a null handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pending_commands(ws: *mut WsppWs) -> u64 {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.pending_commands(),
+        None => 0,
+    }
+}
+
+/// Calls `f` from `wspp_poll` when the command or event queue holds at
+/// least `threshold` items, once per excursion. A zero threshold or null
+/// `f` disables it.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_queue_pressure_handler(
+    ws: *mut WsppWs,
+    threshold: u64,
+    f: Option<OnQueuePressureCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(ws) = unsafe { ws_mut(ws) } {
+        ws.set_queue_pressure_threshold(threshold);
+        ws.set_callbacks(|cb| cb.on_queue_pressure = WithCtx::wrap(f, user_data));
+    }
+}
+
+/// Copies the handle's counters into `out`. They accumulate across
+/// reconnects for the lifetime of the handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_get_stats(ws: *mut WsppWs, out: *mut WsppStats) -> WsppResult {
+    let Some(ws) = (unsafe { ws_mut(ws) }) else {
+        return WsppResult::InvalidState;
+    };
+    if out.is_null() {
+        return WsppResult::InvalidArgument;
+    }
+
+    unsafe { *out = ws.stats() };
+    WsppResult::Ok
+}
+
+/// Whether the worker is currently blocked writing to the socket.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_is_write_stalled(ws: *mut WsppWs) -> bool {
+    match unsafe { ws_mut(ws) } {
+        Some(ws) => ws.is_write_stalled(),
+        None => false,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_response_handler(
+    ws: *mut WsppWs,
+    f: Option<OnResponseCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.on_response = WithCtx::wrap(f, user_data));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_set_response_id_extractor(
+    ws: *mut WsppWs,
+    f: Option<ResponseIdExtractor>,
+    user_data: *mut c_void,
+) {
+    if let Some(slots) = unsafe { ws_slots(ws) } {
+        slots.update(|cb| cb.extract_response_id = WithCtx::wrap(f, user_data));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_new() -> *mut WsppGroup {
+    Box::into_raw(Box::new(WsppGroupImpl::new())) as *mut WsppGroup
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_delete(group: *mut WsppGroup) {
+    if group.is_null() {
+        return;
+    }
+
+    unsafe {
+        drop(Box::from_raw(group.cast::<WsppGroupImpl>()));
+    }
+}
+
+/// Adds `ws` to `group`, which does not own it. Deleting `ws` takes it out
+/// of every group it is in.
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_add(group: *mut WsppGroup, ws: *mut WsppWs) -> WsppResult {
+    let Some(group) = (unsafe { group_mut(group) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let alive = unsafe { ws.cast::<WsHandle>().as_ref() }
+        .map_or_else(Weak::new, |handle| Arc::downgrade(&handle.alive));
+    ffi_result(group.add(unsafe { ws_ptr(ws) }, alive))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_remove(group: *mut WsppGroup, ws: *mut WsppWs) -> WsppResult {
+    let Some(group) = (unsafe { group_mut(group) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ffi_result(group.remove(unsafe { ws_ptr(ws) }))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_poll(group: *mut WsppGroup) -> u64 {
+    let Some(group) = (unsafe { group_mut(group) }) else {
+        return 0;
+    };
+
+    group.poll()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_wait(group: *mut WsppGroup, timeout_ms: u64) -> u64 {
+    let Some(group) = (unsafe { group_mut(group) }) else {
+        return 0;
+    };
+
+    group.wait(Duration::from_millis(timeout_ms))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_send_text(
+    group: *mut WsppGroup,
+    message: *const c_char,
+) -> WsppResult {
+    let Some(group) = (unsafe { group_mut(group) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let message_str = match unsafe { cstr(message) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(group.send_message(message_str))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_group_send_binary(
+    group: *mut WsppGroup,
+    data: *const c_void,
+    len: u64,
+) -> WsppResult {
+    let Some(group) = (unsafe { group_mut(group) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let bytes = match unsafe { data_slice(data, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(group.send_binary(bytes))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_new(uri: *const c_char, size: u32, compression: bool) -> *mut WsppPool {
+    let uri_str = match unsafe { cstr(uri) } {
+        Ok(uri) => uri,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    if size == 0 {
+        return std::ptr::null_mut();
+    }
+
+    Box::into_raw(Box::new(WsppPoolImpl::new(
+        uri_str,
+        size as usize,
+        compression,
+    ))) as *mut WsppPool
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_delete(pool: *mut WsppPool) {
+    if pool.is_null() {
+        return;
+    }
+
+    if let Some(inner) = unsafe { pool_mut(pool) } {
+        inner.shutdown();
+    }
+
+    unsafe {
+        drop(Box::from_raw(pool.cast::<WsppPoolImpl>()));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_connect(pool: *mut WsppPool) -> WsppResult {
+    let Some(pool) = (unsafe { pool_mut(pool) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    ffi_result(pool.connect())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_close(
+    pool: *mut WsppPool,
+    code: u16,
+    reason: *const c_char,
+) -> WsppResult {
+    let Some(pool) = (unsafe { pool_mut(pool) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let reason_str = if reason.is_null() {
+        ""
+    } else {
+        match unsafe { cstr(reason) } {
+            Ok(r) => r,
+            Err(e) => return e.to_ffi(),
+        }
+    };
+
+    ffi_result(pool.close(code, reason_str))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_poll(pool: *mut WsppPool) -> u64 {
+    let Some(pool) = (unsafe { pool_mut(pool) }) else {
+        return 0;
+    };
+
+    pool.poll()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_connected(pool: *mut WsppPool) -> u64 {
+    let Some(pool) = (unsafe { pool_mut(pool) }) else {
+        return 0;
+    };
+
+    pool.connected_count()
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_send_text(pool: *mut WsppPool, message: *const c_char) -> WsppResult {
+    let Some(pool) = (unsafe { pool_mut(pool) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let message_str = match unsafe { cstr(message) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(pool.send_message(message_str))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_send_binary(
+    pool: *mut WsppPool,
+    data: *const c_void,
+    len: u64,
+) -> WsppResult {
+    let Some(pool) = (unsafe { pool_mut(pool) }) else {
+        return WsppResult::InvalidState;
+    };
+
+    let bytes = match unsafe { data_slice(data, len) } {
+        Ok(s) => s,
+        Err(e) => return e.to_ffi(),
+    };
+
+    ffi_result(pool.send_binary(Payload::copy_from_slice(bytes)))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_set_open_handler(
+    pool: *mut WsppPool,
+    f: Option<OnOpenCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(pool) = unsafe { pool_mut(pool) } {
+        pool.callbacks.on_open = WithCtx::wrap(f, user_data);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_set_close_handler(
+    pool: *mut WsppPool,
+    f: Option<OnCloseCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(pool) = unsafe { pool_mut(pool) } {
+        pool.callbacks.on_close = WithCtx::wrap(f, user_data);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_set_message_handler(
+    pool: *mut WsppPool,
+    f: Option<OnMessageCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(pool) = unsafe { pool_mut(pool) } {
+        pool.callbacks.on_message = WithCtx::wrap(f, user_data);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_set_error_handler(
+    pool: *mut WsppPool,
+    f: Option<OnErrorCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(pool) = unsafe { pool_mut(pool) } {
+        pool.callbacks.on_error = WithCtx::wrap(f, user_data);
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn wspp_pool_set_pong_handler(
+    pool: *mut WsppPool,
+    f: Option<OnPongCallback>,
+    user_data: *mut c_void,
+) {
+    if let Some(pool) = unsafe { pool_mut(pool) } {
+        pool.callbacks.on_pong = WithCtx::wrap(f, user_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString, c_char, c_void};
+
+    use super::{
+        WsppResult, WsppUriError, WsppWs, copy_cstr, cstr, data_slice, wspp_add_header_w,
+        wspp_clear_handlers, wspp_close, wspp_delete, wspp_get_create_error, wspp_get_state,
+        wspp_group_add, wspp_group_delete, wspp_group_new, wspp_group_poll, wspp_group_remove,
+        wspp_group_wait, wspp_new, wspp_new_pair, wspp_poll, wspp_send_binary_noalloc,
+        wspp_send_text, wspp_set_client_identity_p12, wspp_set_close_ext_handler,
+        wspp_set_message_handler, wspp_validate_uri, wstr,
+    };
+
+    extern "C" fn ignore(_: *mut c_void, _data: *const c_char, _len: u64, _op_code: i32) {}
+
+    extern "C" fn count_bytes(total: *mut c_void, _data: *const c_char, len: u64, _op: i32) {
+        unsafe { *total.cast::<u64>() += len };
+    }
+
+    extern "C" fn note_close(last: *mut c_void, code: u16, _reason: *const c_char, _len: u64) {
+        unsafe { *last.cast::<u16>() = code };
+    }
+
+    extern "C" fn count_release(count: *mut c_void) {
+        unsafe { *count.cast::<u32>() += 1 };
+    }
+
+    #[test]
+    fn deleted_handles_leave_their_groups() {
+        let uri = CString::new("ws://127.0.0.1:18765/ws").expect("uri");
+        let group = wspp_group_new();
+        let ws = wspp_new(uri.as_ptr());
+        let other = wspp_new(uri.as_ptr());
+        assert_eq!(wspp_group_add(group, ws), WsppResult::Ok);
+        assert_eq!(wspp_group_add(group, other), WsppResult::Ok);
+
+        wspp_delete(ws);
+        assert_eq!(wspp_group_poll(group), 0);
+        assert_eq!(wspp_group_wait(group, 1), 0);
+        assert_eq!(wspp_group_remove(group, other), WsppResult::Ok);
+        wspp_delete(other);
+        wspp_group_delete(group);
+    }
+
+    #[test]
+    fn noalloc_sends_release_without_a_handle() {
+        let data = [1_u8, 2, 3];
+        let mut released = 0_u32;
+        let result = wspp_send_binary_noalloc(
+            std::ptr::null_mut(),
+            data.as_ptr().cast(),
+            data.len() as u64,
+            Some(count_release),
+            (&raw mut released).cast(),
+        );
+        assert_eq!(result, WsppResult::InvalidState);
+        assert_eq!(released, 1);
+    }
+
+    #[test]
+    fn client_identities_load_from_pkcs12() {
+        let bundle: &[u8] = include_bytes!("client/testdata/tls/client.p12");
+        let uri = CString::new("wss://127.0.0.1:18765/ws").expect("uri");
+        let ws = wspp_new(uri.as_ptr());
+        let set = |passphrase: &CStr| {
+            wspp_set_client_identity_p12(
+                ws,
+                bundle.as_ptr().cast(),
+                bundle.len() as u64,
+                passphrase.as_ptr(),
+            )
+        };
+        assert_eq!(set(c"hunter3"), WsppResult::InvalidArgument);
+        assert_eq!(set(c"hunter2"), WsppResult::Ok);
+        let empty = wspp_set_client_identity_p12(ws, std::ptr::null(), 0, std::ptr::null());
+        assert_eq!(empty, WsppResult::InvalidArgument);
+        wspp_delete(ws);
+    }
+
+    #[test]
+    fn cstr_rejects_null() {
+        let result = unsafe { cstr(std::ptr::null()) };
+        assert_eq!(result, Err(WsppResult::InvalidArgument));
+    }
+
+    #[test]
+    fn cstr_rejects_invalid_utf8() {
+        let bytes = [0xFF_u8, 0x00_u8];
+        let result = unsafe { cstr(bytes.as_ptr() as *const i8) };
+        assert_eq!(result, Err(WsppResult::InvalidArgument));
+    }
+
+    #[test]
+    fn cstr_accepts_utf8() {
         let msg = CString::new("hello").expect("valid cstr");
         let result = unsafe { cstr(msg.as_ptr()) };
         assert_eq!(result, Ok("hello"));
     }
 
+    #[test]
+    fn wstr_decodes_utf16() {
+        let wide: Vec<u16> = "wss://h\u{f6}st/\u{1f600}\0".encode_utf16().collect();
+        let result = unsafe { wstr(wide.as_ptr()) };
+        assert_eq!(result.as_deref(), Ok("wss://h\u{f6}st/\u{1f600}"));
+    }
+
+    #[test]
+    fn wstr_rejects_null_and_unpaired_surrogates() {
+        assert_eq!(
+            unsafe { wstr(std::ptr::null()) },
+            Err(WsppResult::InvalidArgument)
+        );
+        let wide = [0x61_u16, 0xD800, 0];
+        assert_eq!(
+            unsafe { wstr(wide.as_ptr()) },
+            Err(WsppResult::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn add_header_w_takes_utf16() {
+        let uri = CString::new("ws://127.0.0.1:18765/ws").expect("valid cstr");
+        let ws = wspp_new(uri.as_ptr());
+        let name: Vec<u16> = "X-Echo\0".encode_utf16().collect();
+        let value: Vec<u16> = "caf\u{e9}\0".encode_utf16().collect();
+        assert_eq!(
+            wspp_add_header_w(ws, name.as_ptr(), value.as_ptr()),
+            WsppResult::Ok
+        );
+        let unpaired = [0x61_u16, 0xD800, 0];
+        assert_eq!(
+            wspp_add_header_w(ws, name.as_ptr(), unpaired.as_ptr()),
+            WsppResult::InvalidArgument
+        );
+        wspp_delete(ws);
+    }
+
+    #[test]
+    fn new_refuses_invalid_uris() {
+        let uri = CString::new("http://example.com/").expect("valid cstr");
+        assert!(wspp_new(uri.as_ptr()).is_null());
+        assert_eq!(wspp_get_create_error(), WsppUriError::Scheme);
+        assert!(wspp_new(std::ptr::null()).is_null());
+        assert_eq!(wspp_get_create_error(), WsppUriError::Encoding);
+
+        let uri = CString::new("ws://127.0.0.1:18765/ws").expect("valid cstr");
+        let ws = wspp_new(uri.as_ptr());
+        assert!(!ws.is_null());
+        assert_eq!(wspp_get_create_error(), WsppUriError::None);
+        wspp_delete(ws);
+    }
+
+    #[test]
+    fn get_state_reports_the_handle_state() {
+        let uri = CString::new("ws://127.0.0.1:18765/ws").expect("valid cstr");
+        let ws = wspp_new(uri.as_ptr());
+        assert_eq!(wspp_get_state(ws), 0);
+        wspp_delete(ws);
+        assert_eq!(wspp_get_state(std::ptr::null_mut()), 4);
+    }
+
+    #[test]
+    fn validate_uri_describes_the_problem() {
+        let uri = CString::new("wss://example.com/feed").expect("valid cstr");
+        assert_eq!(
+            wspp_validate_uri(uri.as_ptr(), std::ptr::null_mut(), 0),
+            WsppResult::Ok
+        );
+
+        let uri = CString::new("ftp://example.com/").expect("valid cstr");
+        let mut buf = [0 as c_char; 16];
+        assert_eq!(
+            wspp_validate_uri(uri.as_ptr(), buf.as_mut_ptr(), buf.len() as u64),
+            WsppResult::InvalidArgument
+        );
+        let message = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        assert_eq!(message.to_str(), Ok("unsupported sch"));
+    }
+
+    #[test]
+    fn handlers_get_the_user_data_they_were_set_with() {
+        let (mut a, mut b) = (std::ptr::null_mut(), std::ptr::null_mut());
+        assert_eq!(wspp_new_pair(&mut a, &mut b), WsppResult::Ok);
+        let (mut received, mut close_code) = (0_u64, 0_u16);
+        wspp_set_message_handler(b, Some(count_bytes), (&raw mut received).cast());
+        wspp_set_close_ext_handler(b, Some(note_close), (&raw mut close_code).cast());
+        wspp_poll(a);
+        wspp_poll(b);
+
+        let text = CString::new("hello").expect("valid cstr");
+        assert_eq!(wspp_send_text(a, text.as_ptr()), WsppResult::Ok);
+        assert_eq!(wspp_close(a, 1000, c"".as_ptr()), WsppResult::Ok);
+        wspp_poll(a);
+        wspp_poll(b);
+        assert_eq!((received, close_code), (5, 1000));
+        wspp_delete(a);
+        wspp_delete(b);
+    }
+
+    #[test]
+    fn handlers_change_while_another_thread_polls() {
+        let (mut a, mut b) = (std::ptr::null_mut(), std::ptr::null_mut());
+        assert_eq!(wspp_new_pair(&mut a, &mut b), WsppResult::Ok);
+        let text = CString::new("hi").expect("valid cstr");
+        wspp_poll(b);
+
+        let polled = a as usize;
+        let poller = std::thread::spawn(move || {
+            for _ in 0..500 {
+                wspp_poll(polled as *mut WsppWs);
+            }
+        });
+        for _ in 0..500 {
+            wspp_set_message_handler(a, Some(ignore), std::ptr::null_mut());
+            assert_eq!(wspp_send_text(b, text.as_ptr()), WsppResult::Ok);
+            assert_eq!(wspp_clear_handlers(a), WsppResult::Ok);
+        }
+        poller.join().expect("poller");
+        wspp_delete(a);
+        wspp_delete(b);
+    }
+
     #[test]
     fn data_slice_validates_null_for_nonzero_len() {
         let result = unsafe { data_slice(std::ptr::null::<c_void>(), 1) };
         assert_eq!(result, Err(WsppResult::InvalidArgument));
     }
 
+    #[test]
+    fn copy_cstr_needs_room_for_terminator() {
+        let mut buf = [1 as c_char; 4];
+        let result = unsafe { copy_cstr("abcd", buf.as_mut_ptr(), 4) };
+        assert_eq!(result, WsppResult::BufferTooSmall);
+
+        let result = unsafe { copy_cstr("abc", buf.as_mut_ptr(), 4) };
+        assert_eq!(result, WsppResult::Ok);
+        let copied = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        assert_eq!(copied.to_str(), Ok("abc"));
+    }
+
     #[test]
     fn data_slice_allows_null_for_zero_len() {
         let result = unsafe { data_slice(std::ptr::null::<c_void>(), 0) };
@@ -0,0 +1,128 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::budget;
+use crate::logging;
+use crate::result::WsppResult;
+
+static LIVE_HANDLES: AtomicUsize = AtomicUsize::new(0);
+static DETACHED: Threads = Threads::new();
+
+/// Counts a connection handle for as long as it exists.
+pub struct Live(());
+
+impl Live {
+    pub fn new() -> Self {
+        LIVE_HANDLES.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Default for Live {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        LIVE_HANDLES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Threads that outlived whatever started them.
+struct Threads {
+    list: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Threads {
+    const fn new() -> Self {
+        Self {
+            list: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn adopt(&self, thread: JoinHandle<()>) {
+        let mut list = self.list.lock().unwrap_or_else(|err| err.into_inner());
+        list.retain(|thread| !thread.is_finished());
+        list.push(thread);
+    }
+
+    /// Joins every thread that exits within `timeout`. The rest stay
+    /// adopted; returns whether none did.
+    fn join_all(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut list = self.list.lock().unwrap_or_else(|err| err.into_inner());
+            let (finished, running): (Vec<_>, Vec<_>) =
+                list.drain(..).partition(JoinHandle::is_finished);
+            *list = running;
+            let done = list.is_empty();
+            drop(list);
+            for thread in finished {
+                let _ = thread.join();
+            }
+            if done {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+/// Keeps a thread nobody waits for joinable, so `shutdown` can make sure it
+/// no longer runs library code before the library is unloaded.
+pub fn adopt(thread: JoinHandle<()>) {
+    DETACHED.adopt(thread);
+}
+
+/// Waits up to `timeout` for every thread the library started to exit and
+/// unhooks the process-wide host callbacks. Refused while handles exist,
+/// as their workers may still be running. Each worker owns its runtime, so
+/// once the threads are joined no runtime is left either.
+pub fn shutdown(timeout: Duration) -> Result<WsppResult, WsppResult> {
+    if LIVE_HANDLES.load(Ordering::Relaxed) > 0 {
+        return Err(WsppResult::InvalidState);
+    }
+    if !DETACHED.join_all(timeout) {
+        logging::emit(2, "library threads still running at shutdown");
+        return Err(WsppResult::Timeout);
+    }
+    logging::set_log_handler(None);
+    logging::set_wire_handler(None);
+    budget::global().set_pressure_handler(None);
+    Ok(WsppResult::Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Threads;
+
+    #[test]
+    fn joins_adopted_threads() {
+        let threads = Threads::new();
+        threads.adopt(std::thread::spawn(|| {
+            std::thread::sleep(Duration::from_millis(20))
+        }));
+        assert!(threads.join_all(Duration::from_secs(5)));
+        assert!(threads.list.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn keeps_threads_that_outlast_the_timeout() {
+        let threads = Threads::new();
+        threads.adopt(std::thread::spawn(|| {
+            std::thread::sleep(Duration::from_millis(200))
+        }));
+        assert!(!threads.join_all(Duration::from_millis(10)));
+        assert_eq!(threads.list.lock().unwrap().len(), 1);
+        assert!(threads.join_all(Duration::from_secs(5)));
+    }
+}
@@ -1,21 +1,64 @@
-use std::ffi::CString;
-use std::sync::RwLock;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::{CString, c_char};
 use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use crate::callback::OnLogCallback;
+use crate::callback::{OnLogCallback, OnWireDataCallback, WithCtx};
 
 const LOG_OFF: i32 = 0;
 const LOG_TRACE: i32 = 5;
+/// Distinct messages the rate limit keeps counts for; others pass freely.
+const MAX_LIMITED_MESSAGES: usize = 256;
 
 static LOG_LEVEL: AtomicI32 = AtomicI32::new(1);
-static LOG_HANDLER: RwLock<Option<OnLogCallback>> = RwLock::new(None);
+static LOG_HANDLER: RwLock<Option<WithCtx<OnLogCallback>>> = RwLock::new(None);
+static WIRE_HANDLER: RwLock<Option<WithCtx<OnWireDataCallback>>> = RwLock::new(None);
+static RATE_LIMIT: Mutex<Option<RateLimit>> = Mutex::new(None);
 
-pub fn set_log_handler(handler: Option<OnLogCallback>) {
+thread_local! {
+    /// Log handler of this thread, used instead of the global one.
+    static THREAD_HANDLER: Cell<Option<WithCtx<OnLogCallback>>> = const { Cell::new(None) };
+}
+
+/// Which way bytes passed to the wire data handler went.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WsppWireDirection {
+    Received = 0,
+    Sent = 1,
+}
+
+pub fn set_log_handler(handler: Option<WithCtx<OnLogCallback>>) {
     if let Ok(mut slot) = LOG_HANDLER.write() {
         *slot = handler;
     }
 }
 
+/// Sets the calling thread's log handler. Worker threads take over the one
+/// of the thread that started them.
+pub fn set_thread_log_handler(handler: Option<WithCtx<OnLogCallback>>) {
+    THREAD_HANDLER.set(handler);
+}
+
+pub fn thread_log_handler() -> Option<WithCtx<OnLogCallback>> {
+    THREAD_HANDLER.get()
+}
+
+pub fn set_wire_handler(handler: Option<WithCtx<OnWireDataCallback>>) {
+    if let Ok(mut slot) = WIRE_HANDLER.write() {
+        *slot = handler;
+    }
+}
+
+/// Lets `burst` identical messages through per `window`; `None` turns
+/// limiting off.
+pub fn set_rate_limit(limit: Option<(u32, Duration)>) {
+    let mut slot = RATE_LIMIT.lock().unwrap_or_else(|err| err.into_inner());
+    *slot = limit.map(|(burst, window)| RateLimit::new(burst, window));
+}
+
 pub fn set_log_level(level: i32) {
     let clamped = level.clamp(LOG_OFF, LOG_TRACE);
     LOG_LEVEL.store(clamped, Ordering::Relaxed);
@@ -29,10 +72,27 @@ pub fn emit(level: i32, msg: &str) {
         return;
     }
 
-    let handler = match LOG_HANDLER.read() {
+    let (admitted, summaries) = match RATE_LIMIT
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_mut()
+    {
+        Some(limit) => limit.admit(level, msg, Instant::now()),
+        None => (true, Vec::new()),
+    };
+    for (level, summary) in summaries {
+        deliver(level, &summary);
+    }
+    if admitted {
+        deliver(level, msg);
+    }
+}
+
+fn deliver(level: i32, msg: &str) {
+    let handler = THREAD_HANDLER.get().or_else(|| match LOG_HANDLER.read() {
         Ok(slot) => *slot,
         Err(_) => None,
-    };
+    });
     let Some(handler) = handler else {
         return;
     };
@@ -42,31 +102,142 @@ pub fn emit(level: i32, msg: &str) {
         Err(_) => return,
     };
 
-    handler(level, c_msg.as_ptr());
+    (handler.f)(handler.ctx, level, c_msg.as_ptr());
+}
+
+/// Counts of one message within the current window.
+struct Seen {
+    level: i32,
+    since: Instant,
+    count: u32,
+    suppressed: u64,
+}
+
+/// Suppresses identical messages past a burst per window and summarizes
+/// them once the window is over.
+struct RateLimit {
+    burst: u32,
+    window: Duration,
+    seen: HashMap<String, Seen>,
+    next_sweep: Instant,
+}
+
+impl RateLimit {
+    fn new(burst: u32, window: Duration) -> Self {
+        Self {
+            burst,
+            window,
+            seen: HashMap::new(),
+            next_sweep: Instant::now() + window,
+        }
+    }
+
+    /// Whether `msg` may be delivered now, plus summaries of messages whose
+    /// window ended with repeats suppressed.
+    fn admit(&mut self, level: i32, msg: &str, now: Instant) -> (bool, Vec<(i32, String)>) {
+        let mut summaries = Vec::new();
+        if now >= self.next_sweep {
+            let window = self.window;
+            self.seen.retain(|msg, seen| {
+                if now.duration_since(seen.since) < window {
+                    return true;
+                }
+                if seen.suppressed > 0 {
+                    summaries.push((
+                        seen.level,
+                        format!("message repeated {} times: {msg}", seen.suppressed),
+                    ));
+                }
+                false
+            });
+            self.next_sweep = now + window;
+        }
+
+        let tracked = self.seen.len();
+        match self.seen.get_mut(msg) {
+            Some(seen) if seen.count >= self.burst => {
+                seen.suppressed += 1;
+                return (false, summaries);
+            }
+            Some(seen) => seen.count += 1,
+            None if tracked < MAX_LIMITED_MESSAGES => {
+                let seen = Seen {
+                    level,
+                    since: now,
+                    count: 1,
+                    suppressed: 0,
+                };
+                self.seen.insert(msg.to_owned(), seen);
+            }
+            None => {}
+        }
+        (true, summaries)
+    }
+}
+
+/// Hands socket bytes, after TLS and before frame parsing, to the wire data
+/// handler. Only done at trace level.
+pub fn emit_wire(direction: WsppWireDirection, data: &[u8]) {
+    if data.is_empty() || LOG_LEVEL.load(Ordering::Relaxed) < LOG_TRACE {
+        return;
+    }
+    let handler = match WIRE_HANDLER.read() {
+        Ok(slot) => *slot,
+        Err(_) => None,
+    };
+    if let Some(handler) = handler {
+        (handler.f)(
+            handler.ctx,
+            direction as i32,
+            data.as_ptr() as *const c_char,
+            data.len() as u64,
+        );
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::ffi::CStr;
+    use std::ffi::{CStr, c_void};
     use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
     use std::sync::{Mutex, OnceLock};
 
-    use super::{emit, set_log_handler, set_log_level};
+    use std::time::{Duration, Instant};
+
+    use crate::callback::WithCtx;
+
+    use super::{
+        RateLimit, WsppWireDirection, emit, emit_wire, set_log_handler, set_log_level,
+        set_thread_log_handler, set_wire_handler,
+    };
 
     static TEST_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
     static CALLS: AtomicUsize = AtomicUsize::new(0);
     static LAST_LEVEL: AtomicI32 = AtomicI32::new(-1);
+    static WIRE_CALLS: AtomicUsize = AtomicUsize::new(0);
+    static THREAD_CALLS: AtomicUsize = AtomicUsize::new(0);
 
-    extern "C" fn test_logger(level: i32, msg: *const i8) {
+    extern "C" fn test_logger(_: *mut c_void, level: i32, msg: *const i8) {
         let _ = unsafe { CStr::from_ptr(msg) };
         LAST_LEVEL.store(level, Ordering::Relaxed);
         CALLS.fetch_add(1, Ordering::Relaxed);
     }
 
+    extern "C" fn thread_logger(calls: *mut c_void, _level: i32, _msg: *const i8) {
+        unsafe { &*calls.cast::<AtomicUsize>() }.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts only its own marker, as workers of other tests may be writing.
+    extern "C" fn test_wire(_: *mut c_void, direction: i32, data: *const i8, len: u64) {
+        let data = unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) };
+        if direction == WsppWireDirection::Sent as i32 && data == b"wire-test" {
+            WIRE_CALLS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     fn reset() {
         CALLS.store(0, Ordering::Relaxed);
         LAST_LEVEL.store(-1, Ordering::Relaxed);
-        set_log_handler(Some(test_logger));
+        set_log_handler(WithCtx::wrap(Some(test_logger), std::ptr::null_mut()));
     }
 
     #[test]
@@ -104,6 +275,68 @@ mod tests {
         set_log_handler(None);
     }
 
+    #[test]
+    fn thread_handler_takes_precedence_on_its_thread() {
+        let _guard = TEST_LOCK
+            .get_or_init(|| Mutex::new(()))
+            .lock()
+            .expect("lock poisoned");
+
+        reset();
+        set_log_level(1);
+        std::thread::spawn(|| {
+            let calls = (&raw const THREAD_CALLS).cast_mut().cast();
+            set_thread_log_handler(WithCtx::wrap(Some(thread_logger), calls));
+            emit(1, "to the thread handler");
+            set_thread_log_handler(None);
+            emit(1, "to the global handler");
+        })
+        .join()
+        .expect("logging thread panicked");
+        emit(1, "to the global handler");
+
+        assert_eq!(THREAD_CALLS.load(Ordering::Relaxed), 1);
+        assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+        set_log_handler(None);
+    }
+
+    #[test]
+    fn rate_limit_summarizes_suppressed_repeats() {
+        let start = Instant::now();
+        let mut limit = RateLimit::new(2, Duration::from_secs(1));
+        let admitted: Vec<bool> = (0..5).map(|_| limit.admit(1, "flap", start).0).collect();
+        assert_eq!(admitted, [true, true, false, false, false]);
+        assert!(limit.admit(1, "other", start).0);
+
+        let later = start + Duration::from_millis(1100);
+        let (admitted, summaries) = limit.admit(1, "flap", later);
+        assert!(admitted);
+        assert_eq!(
+            summaries,
+            [(1, "message repeated 3 times: flap".to_owned())]
+        );
+    }
+
+    #[test]
+    fn wire_data_only_at_trace_level() {
+        let _guard = TEST_LOCK
+            .get_or_init(|| Mutex::new(()))
+            .lock()
+            .expect("lock poisoned");
+
+        set_wire_handler(WithCtx::wrap(Some(test_wire), std::ptr::null_mut()));
+        set_log_level(4);
+        emit_wire(WsppWireDirection::Sent, b"wire-test");
+        assert_eq!(WIRE_CALLS.load(Ordering::Relaxed), 0);
+
+        set_log_level(5);
+        emit_wire(WsppWireDirection::Sent, b"wire-test");
+        emit_wire(WsppWireDirection::Received, b"wire-test");
+        assert_eq!(WIRE_CALLS.load(Ordering::Relaxed), 1);
+        set_wire_handler(None);
+        set_log_level(1);
+    }
+
     #[test]
     fn handler_updates_are_thread_safe() {
         let _guard = TEST_LOCK
@@ -116,7 +349,7 @@ mod tests {
 
         let toggler = std::thread::spawn(|| {
             for _ in 0..500 {
-                set_log_handler(Some(test_logger));
+                set_log_handler(WithCtx::wrap(Some(test_logger), std::ptr::null_mut()));
                 set_log_handler(None);
             }
         });
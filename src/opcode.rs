@@ -0,0 +1,50 @@
+/// Frame opcodes as reported in the `op_code` argument of message callbacks
+/// and accepted by the send functions that take one.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WsppOpcode {
+    Continuation = 0,
+    Text = 1,
+    Binary = 2,
+    Close = 8,
+    Ping = 9,
+    Pong = 10,
+}
+
+impl WsppOpcode {
+    pub fn from_ffi(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Continuation),
+            1 => Some(Self::Text),
+            2 => Some(Self::Binary),
+            8 => Some(Self::Close),
+            9 => Some(Self::Ping),
+            10 => Some(Self::Pong),
+            _ => None,
+        }
+    }
+
+    pub fn to_ffi(self) -> i32 {
+        self as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WsppOpcode;
+
+    #[test]
+    fn round_trips_ffi_values() {
+        for opcode in [
+            WsppOpcode::Continuation,
+            WsppOpcode::Text,
+            WsppOpcode::Binary,
+            WsppOpcode::Close,
+            WsppOpcode::Ping,
+            WsppOpcode::Pong,
+        ] {
+            assert_eq!(WsppOpcode::from_ffi(opcode.to_ffi()), Some(opcode));
+        }
+        assert_eq!(WsppOpcode::from_ffi(3), None);
+    }
+}
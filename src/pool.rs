@@ -0,0 +1,223 @@
+use std::time::{Duration, Instant};
+
+use crate::callback::Callbacks;
+use crate::client::{Payload, WsState, WsppWsImpl};
+use crate::logging;
+use crate::result::WsppResult;
+
+const REPLACE_DELAY: Duration = Duration::from_millis(250);
+
+struct PoolMember {
+    ws: WsppWsImpl,
+    retry_at: Option<Instant>,
+}
+
+/// Maintains `size` connections to one URI, spreading sends across the
+/// connected ones and reconnecting members that fail while the pool is up.
+pub struct WsppPoolImpl {
+    members: Vec<PoolMember>,
+    next: usize,
+    running: bool,
+    pub callbacks: Callbacks,
+}
+
+impl WsppPoolImpl {
+    pub fn new(uri: &str, size: usize, compression: bool) -> Self {
+        let members = (0..size)
+            .map(|_| PoolMember {
+                ws: WsppWsImpl::new(uri, compression),
+                retry_at: None,
+            })
+            .collect();
+
+        Self {
+            members,
+            next: 0,
+            running: false,
+            callbacks: Callbacks::default(),
+        }
+    }
+
+    /// Connects every member. If one fails, those already started are shut
+    /// down again and its error is returned.
+    pub fn connect(&mut self) -> Result<WsppResult, WsppResult> {
+        if self.running || self.members.is_empty() {
+            return Err(WsppResult::InvalidState);
+        }
+
+        for idx in 0..self.members.len() {
+            let member = &mut self.members[idx];
+            member.ws.set_callbacks(|cb| *cb = self.callbacks);
+            if let Err(err) = member.ws.connect() {
+                for started in &mut self.members[..idx] {
+                    started.ws.shutdown();
+                }
+                return Err(err);
+            }
+            member.retry_at = None;
+        }
+        self.running = true;
+        Ok(WsppResult::Ok)
+    }
+
+    pub fn poll(&mut self) -> u64 {
+        let now = Instant::now();
+        let mut count = 0_u64;
+
+        for member in &mut self.members {
+            member.ws.set_callbacks(|cb| *cb = self.callbacks);
+            count = count.saturating_add(member.ws.poll());
+
+            if !self.running || !matches!(member.ws.get_state(), WsState::Closed) {
+                continue;
+            }
+            match member.retry_at {
+                None => member.retry_at = Some(now + REPLACE_DELAY),
+                Some(at) if now >= at => {
+                    logging::emit(3, "replacing failed pool member");
+                    member.retry_at = None;
+                    if let Err(err) = member.ws.connect() {
+                        logging::emit(2, &format!("pool member reconnect failed: {err:?}"));
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        count
+    }
+
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<WsppResult, WsppResult> {
+        if !self.running {
+            return Err(WsppResult::InvalidState);
+        }
+
+        self.running = false;
+        for member in &mut self.members {
+            let _ = member.ws.close(code, reason);
+        }
+        Ok(WsppResult::Ok)
+    }
+
+    pub fn send_message(&mut self, message: &str) -> Result<WsppResult, WsppResult> {
+        let idx = self.next_connected().ok_or(WsppResult::InvalidState)?;
+        self.members[idx].ws.send_message(message)
+    }
+
+    pub fn send_binary(&mut self, data: Payload) -> Result<WsppResult, WsppResult> {
+        let idx = self.next_connected().ok_or(WsppResult::InvalidState)?;
+        self.members[idx].ws.send_binary(data)
+    }
+
+    pub fn connected_count(&self) -> u64 {
+        self.members
+            .iter()
+            .filter(|member| matches!(member.ws.get_state(), WsState::Connected))
+            .count() as u64
+    }
+
+    pub fn shutdown(&mut self) {
+        self.running = false;
+        for member in &mut self.members {
+            member.ws.shutdown();
+        }
+    }
+
+    fn next_connected(&mut self) -> Option<usize> {
+        let len = self.members.len();
+        let idx = (0..len)
+            .map(|offset| (self.next + offset) % len)
+            .find(|idx| matches!(self.members[*idx].ws.get_state(), WsState::Connected))?;
+
+        self.next = (idx + 1) % len;
+        Some(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::WsppPoolImpl;
+    use crate::client::WsState;
+    use crate::result::WsppResult;
+    use crate::test_support::{EVENT_TIMEOUT, Recorded, TestServer, install_recorder, recorded};
+
+    fn poll_until_connected(pool: &mut WsppPoolImpl, count: u64) {
+        let deadline = Instant::now() + EVENT_TIMEOUT;
+        while pool.connected_count() < count && Instant::now() < deadline {
+            pool.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(pool.connected_count(), count);
+    }
+
+    fn recording_pool(url: &str, size: usize) -> WsppPoolImpl {
+        let mut pool = WsppPoolImpl::new(url, size, true);
+        install_recorder(&mut pool.members[0].ws);
+        pool.callbacks = pool.members[0].ws.callbacks();
+        pool
+    }
+
+    #[test]
+    fn send_requires_a_connected_member() {
+        let mut pool = WsppPoolImpl::new("ws://127.0.0.1:18765/ws", 2, true);
+        assert_eq!(pool.send_message("x"), Err(WsppResult::InvalidState));
+    }
+
+    #[test]
+    fn empty_pool_cannot_connect() {
+        let mut pool = WsppPoolImpl::new("ws://127.0.0.1:18765/ws", 0, true);
+        assert_eq!(pool.connect(), Err(WsppResult::InvalidState));
+    }
+
+    #[test]
+    fn failed_connect_shuts_down_started_members() {
+        let server = TestServer::start();
+        let mut pool = WsppPoolImpl::new(&server.url(), 3, true);
+        assert_eq!(pool.members[1].ws.connect(), Ok(WsppResult::Ok));
+
+        assert_eq!(pool.connect(), Err(WsppResult::InvalidState));
+        assert!(matches!(pool.members[0].ws.get_state(), WsState::Closed));
+        assert!(matches!(pool.members[2].ws.get_state(), WsState::New));
+        pool.poll();
+        assert_eq!(pool.connected_count(), 0);
+        pool.members[1].ws.shutdown();
+    }
+
+    #[test]
+    fn sends_round_robin_across_members() {
+        let server = TestServer::start();
+        let mut pool = recording_pool(&server.url(), 2);
+        assert_eq!(pool.connect(), Ok(WsppResult::Ok));
+        poll_until_connected(&mut pool, 2);
+
+        assert_eq!(pool.next_connected(), Some(0));
+        assert_eq!(pool.next_connected(), Some(1));
+        assert_eq!(pool.next_connected(), Some(0));
+        pool.shutdown();
+    }
+
+    #[test]
+    fn replaces_failed_members() {
+        let server = TestServer::start();
+        let mut pool = recording_pool(&server.url(), 2);
+        assert_eq!(pool.connect(), Ok(WsppResult::Ok));
+        poll_until_connected(&mut pool, 2);
+
+        pool.next = 0;
+        assert_eq!(pool.send_message("drop"), Ok(WsppResult::Ok));
+        let deadline = Instant::now() + EVENT_TIMEOUT;
+        while pool.connected_count() == 2 && Instant::now() < deadline {
+            pool.poll();
+            thread::sleep(Duration::from_millis(1));
+        }
+        poll_until_connected(&mut pool, 2);
+
+        let events = recorded();
+        assert_eq!(events.iter().filter(|e| **e == Recorded::Open).count(), 3);
+        assert!(events.iter().any(|e| matches!(e, Recorded::Error(_))));
+        pool.shutdown();
+    }
+}
@@ -6,6 +6,10 @@ pub enum WsppResult {
     InvalidArgument = 2,
     IoError = 9,
     ProtocolError = 10,
+    Timeout = 11,
+    QueueFull = 12,
+    NotFound = 13,
+    BufferTooSmall = 14,
     Unknown = -1,
 }
 
@@ -42,5 +46,15 @@ mod tests {
             WsppResult::ProtocolError.to_ffi() as i32,
             WsppResult::ProtocolError as i32
         );
+        assert_eq!(
+            WsppResult::Timeout.to_ffi() as i32,
+            WsppResult::Timeout as i32
+        );
+        assert_eq!(
+            WsppResult::QueueFull.to_ffi() as i32,
+            WsppResult::QueueFull as i32
+        );
+        assert_eq!(WsppResult::NotFound.to_ffi() as i32, 13);
+        assert_eq!(WsppResult::BufferTooSmall.to_ffi() as i32, 14);
     }
 }
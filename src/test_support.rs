@@ -0,0 +1,423 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, c_char, c_void};
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use socket2::SockRef;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Builder;
+use tokio::sync::oneshot;
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{CloseCode, Data, OpCode};
+
+use crate::callback::WithCtx;
+use crate::client::{
+    WsState, WsppErrorCategory, WsppErrorInfo, WsppHandshakeInfo, WsppTimeoutPhase, WsppWsImpl,
+};
+use crate::result::WsppResult;
+
+pub const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// In-process WebSocket server used by the end-to-end tests.
+///
+/// Echoes text and binary messages back and answers the upgrade with an
+/// extra `X-Test-Server: wspp` header, plus `X-Client-Key` repeating the
+/// client's Sec-WebSocket-Key and `X-Echo` repeating the request header of
+/// that name. A few text commands trigger
+/// server-side behavior: `close` starts a clean close handshake, `drop`
+/// drops the TCP connection without a close frame, `ping-me` sends a ping
+/// and `pong-me` sends an unsolicited pong.
+pub struct TestServer {
+    addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Controls how the test server echoes binary messages back.
+#[derive(Clone, Debug, Default)]
+pub struct ServerConfig {
+    /// Fragment sizes used when echoing binary messages, cycled until the
+    /// payload is exhausted. Empty sends each message as a single frame.
+    pub fragment_sizes: Vec<usize>,
+    /// Sends a ping between consecutive fragments of an echoed message.
+    pub ping_between_fragments: bool,
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        Self::start_with(ServerConfig::default())
+    }
+
+    pub fn start_with(config: ServerConfig) -> Self {
+        let listener = StdTcpListener::bind("127.0.0.1:0").expect("bind test server");
+        listener
+            .set_nonblocking(true)
+            .expect("nonblocking test listener");
+        let addr = listener.local_addr().expect("test server addr");
+
+        let rt = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("test server runtime");
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel();
+        let config = Arc::new(config);
+
+        let thread = thread::spawn(move || {
+            rt.block_on(async move {
+                let listener = TcpListener::from_std(listener).expect("tokio listener");
+                loop {
+                    tokio::select! {
+                        _ = &mut shutdown_rx => break,
+                        accepted = listener.accept() => {
+                            if let Ok((stream, _)) = accepted {
+                                tokio::spawn(serve(stream, config.clone()));
+                            }
+                        }
+                    }
+                }
+            });
+        });
+
+        Self {
+            addr,
+            shutdown: Some(shutdown_tx),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("ws://{}/ws", self.addr)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The error type is fixed by tungstenite's callback signature.
+#[allow(clippy::result_large_err)]
+fn tag_response(request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+    let headers = response.headers_mut();
+    headers.insert("X-Test-Server", "wspp".parse().expect("header value"));
+    if let Some(key) = request.headers().get("Sec-WebSocket-Key") {
+        headers.insert("X-Client-Key", key.clone());
+    }
+    if let Some(echo) = request.headers().get("X-Echo") {
+        headers.insert("X-Echo", echo.clone());
+    }
+    Ok(response)
+}
+
+async fn serve(stream: TcpStream, config: Arc<ServerConfig>) {
+    let Ok(mut ws) = tokio_tungstenite::accept_hdr_async(stream, tag_response).await else {
+        return;
+    };
+
+    while let Some(Ok(msg)) = ws.next().await {
+        match msg {
+            Message::Text(text) => match text.as_str() {
+                "close" => {
+                    let _ = ws
+                        .close(Some(CloseFrame {
+                            code: CloseCode::Normal,
+                            reason: "bye".into(),
+                        }))
+                        .await;
+                }
+                "drop" => return,
+                // Kills the connection with a TCP reset.
+                "reset" => {
+                    let _ = SockRef::from(ws.get_ref()).set_linger(Some(Duration::ZERO));
+                    return;
+                }
+                "ping-me" => {
+                    let _ = ws.send(Message::Ping(b"srv".to_vec().into())).await;
+                }
+                "pong-me" => {
+                    let _ = ws.send(Message::Pong(b"srv".to_vec().into())).await;
+                }
+                // Keeps the connection open without reading from it again.
+                "deaf" => std::future::pending::<()>().await,
+                // Starts a 256 byte binary frame and never finishes it.
+                "stall" => {
+                    let _ = ws
+                        .get_mut()
+                        .write_all(&[0x82, 0x7E, 0x01, 0x00, 1, 2, 3])
+                        .await;
+                }
+                _ => {
+                    let _ = ws.send(Message::Text(text)).await;
+                }
+            },
+            Message::Binary(data) if !config.fragment_sizes.is_empty() => {
+                let _ = send_fragmented(&mut ws, &data, &config).await;
+            }
+            Message::Binary(data) => {
+                let _ = ws.send(Message::Binary(data)).await;
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+}
+
+async fn send_fragmented(
+    ws: &mut WebSocketStream<TcpStream>,
+    data: &[u8],
+    config: &ServerConfig,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let mut offset = 0;
+    let mut sizes = config.fragment_sizes.iter().cycle();
+    let mut opcode = OpCode::Data(Data::Binary);
+
+    loop {
+        let size = (*sizes.next().unwrap_or(&data.len())).max(1);
+        let end = (offset + size).min(data.len());
+        let fin = end == data.len();
+        let chunk = data[offset..end].to_vec();
+        ws.send(Message::Frame(Frame::message(chunk, opcode, fin)))
+            .await?;
+        if fin {
+            return Ok(());
+        }
+        if config.ping_between_fragments {
+            ws.send(Message::Ping(b"frag".to_vec().into())).await?;
+        }
+        offset = end;
+        opcode = OpCode::Data(Data::Continue);
+    }
+}
+
+/// Returns a `ws://` url on which nothing is listening.
+pub fn unused_url() -> String {
+    let listener = StdTcpListener::bind("127.0.0.1:0").expect("bind probe");
+    let addr = listener.local_addr().expect("probe addr");
+    drop(listener);
+    format!("ws://{addr}/ws")
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Recorded {
+    Open,
+    /// Status and `X-Test-Server` header seen by the extended open callback.
+    OpenExt(u16, Option<String>),
+    Close,
+    /// Code and reason seen by the extended close callback.
+    CloseExt(u16, Vec<u8>),
+    Resumed,
+    Reconnecting(u32),
+    /// Attempt that connected; the downtime is left out as it varies.
+    Reconnected(u32),
+    ReconnectGaveUp(u32),
+    Message(Vec<u8>, i32),
+    MessageFile(Vec<u8>, i32),
+    Pong(Vec<u8>),
+    UnsolicitedPong(Vec<u8>),
+    /// fin, rsv bits, opcode and payload of a frame seen in raw mode.
+    #[cfg(feature = "unsafe-protocol")]
+    RawFrame(bool, i32, i32, Vec<u8>),
+    Watchdog,
+    Error(String),
+    /// Category, timeout phase and elapsed milliseconds of an error.
+    ErrorExt(WsppErrorCategory, WsppTimeoutPhase, u64),
+    Response(u64, Vec<u8>, WsppResult),
+}
+
+thread_local! {
+    static RECORDED: RefCell<Vec<Recorded>> = const { RefCell::new(Vec::new()) };
+}
+
+fn record(event: Recorded) {
+    RECORDED.with(|events| events.borrow_mut().push(event));
+}
+
+unsafe fn payload(data: *const c_char, len: u64) -> Vec<u8> {
+    if len == 0 {
+        return Vec::new();
+    }
+    unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) }.to_vec()
+}
+
+extern "C" fn on_open(_: *mut c_void) {
+    record(Recorded::Open);
+}
+
+/// Not installed by `install_recorder`; tests that want it set it directly.
+pub extern "C" fn on_open_ext(_: *mut c_void, info: *const WsppHandshakeInfo) {
+    let info = unsafe { &*info };
+    let headers = unsafe { CStr::from_ptr(info.headers) }.to_string_lossy();
+    let server = headers
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .find(|(name, _)| name.eq_ignore_ascii_case("X-Test-Server"))
+        .map(|(_, value)| value.to_owned());
+    record(Recorded::OpenExt(info.status, server));
+}
+
+/// Not installed by `install_recorder`; tests that want it set it directly.
+pub extern "C" fn on_error_ext(_: *mut c_void, info: *const WsppErrorInfo) {
+    let info = unsafe { &*info };
+    record(Recorded::ErrorExt(
+        info.category,
+        info.timeout_phase,
+        info.elapsed_ms,
+    ));
+}
+
+/// Not installed by `install_recorder`; tests that want it set it directly.
+pub extern "C" fn on_close_ext(_: *mut c_void, code: u16, reason: *const c_char, len: u64) {
+    record(Recorded::CloseExt(code, unsafe { payload(reason, len) }));
+}
+
+extern "C" fn on_close(_: *mut c_void) {
+    record(Recorded::Close);
+}
+
+extern "C" fn on_resumed(_: *mut c_void) {
+    record(Recorded::Resumed);
+}
+
+extern "C" fn on_reconnecting(_: *mut c_void, attempt: u32) {
+    record(Recorded::Reconnecting(attempt));
+}
+
+extern "C" fn on_reconnected(_: *mut c_void, attempt: u32, _downtime_ms: u64) {
+    record(Recorded::Reconnected(attempt));
+}
+
+extern "C" fn on_reconnect_gave_up(_: *mut c_void, attempts: u32) {
+    record(Recorded::ReconnectGaveUp(attempts));
+}
+
+extern "C" fn on_message(_: *mut c_void, data: *const c_char, len: u64, op_code: i32) {
+    record(Recorded::Message(unsafe { payload(data, len) }, op_code));
+}
+
+extern "C" fn on_message_file(_: *mut c_void, path: *const c_char, len: u64, op_code: i32) {
+    let path = unsafe { CStr::from_ptr(path) }
+        .to_string_lossy()
+        .into_owned();
+    let data = std::fs::read(path).expect("spilled message readable");
+    assert_eq!(data.len() as u64, len);
+    record(Recorded::MessageFile(data, op_code));
+}
+
+extern "C" fn on_error(_: *mut c_void, msg: *const c_char) {
+    let msg = unsafe { CStr::from_ptr(msg) }
+        .to_string_lossy()
+        .into_owned();
+    record(Recorded::Error(msg));
+}
+
+extern "C" fn on_pong(_: *mut c_void, data: *const c_char, len: u64) {
+    record(Recorded::Pong(unsafe { payload(data, len) }));
+}
+
+extern "C" fn on_unsolicited_pong(_: *mut c_void, data: *const c_char, len: u64) {
+    record(Recorded::UnsolicitedPong(unsafe { payload(data, len) }));
+}
+
+#[cfg(feature = "unsafe-protocol")]
+extern "C" fn on_raw_frame(
+    _: *mut c_void,
+    fin: bool,
+    rsv_bits: i32,
+    opcode: i32,
+    data: *const c_char,
+    len: u64,
+) {
+    record(Recorded::RawFrame(fin, rsv_bits, opcode, unsafe {
+        payload(data, len)
+    }));
+}
+
+extern "C" fn on_watchdog(_: *mut c_void) {
+    record(Recorded::Watchdog);
+}
+
+extern "C" fn on_response(
+    _: *mut c_void,
+    request_id: u64,
+    data: *const c_char,
+    len: u64,
+    result: WsppResult,
+) {
+    record(Recorded::Response(
+        request_id,
+        unsafe { payload(data, len) },
+        result,
+    ));
+}
+
+/// `f` with null user data, for tests that do not need any.
+pub fn no_ctx<F>(f: F) -> WithCtx<F> {
+    WithCtx {
+        f,
+        ctx: std::ptr::null_mut(),
+    }
+}
+
+/// Installs recording callbacks on `ws`. Callbacks fire on the polling
+/// thread, so each test thread sees only its own events.
+pub fn install_recorder(ws: &mut WsppWsImpl) {
+    RECORDED.with(|events| events.borrow_mut().clear());
+    ws.set_callbacks(|cb| {
+        cb.on_open = Some(no_ctx(on_open));
+        cb.on_close = Some(no_ctx(on_close));
+        cb.on_resumed = Some(no_ctx(on_resumed));
+        cb.on_reconnecting = Some(no_ctx(on_reconnecting));
+        cb.on_reconnected = Some(no_ctx(on_reconnected));
+        cb.on_reconnect_gave_up = Some(no_ctx(on_reconnect_gave_up));
+        cb.on_message = Some(no_ctx(on_message));
+        cb.on_message_file = Some(no_ctx(on_message_file));
+        cb.on_error = Some(no_ctx(on_error));
+        cb.on_pong = Some(no_ctx(on_pong));
+        cb.on_unsolicited_pong = Some(no_ctx(on_unsolicited_pong));
+        cb.on_watchdog = Some(no_ctx(on_watchdog));
+        #[cfg(feature = "unsafe-protocol")]
+        {
+            cb.on_raw_frame = Some(no_ctx(on_raw_frame));
+        }
+        cb.on_response = Some(no_ctx(on_response));
+    });
+}
+
+pub fn recorded() -> Vec<Recorded> {
+    RECORDED.with(|events| events.borrow().clone())
+}
+
+/// Polls `ws` until at least `count` events were recorded or the timeout
+/// expires, returning everything recorded so far.
+pub fn poll_until(ws: &mut WsppWsImpl, count: usize) -> Vec<Recorded> {
+    let deadline = Instant::now() + EVENT_TIMEOUT;
+    while recorded().len() < count && Instant::now() < deadline {
+        ws.poll();
+        thread::sleep(Duration::from_millis(1));
+    }
+    recorded()
+}
+
+/// Connects a recording handle to `url` and waits for the open event.
+pub fn connected(url: &str) -> WsppWsImpl {
+    let mut ws = WsppWsImpl::new(url, true);
+    install_recorder(&mut ws);
+    assert_eq!(ws.connect(), Ok(WsppResult::Ok));
+    assert_eq!(poll_until(&mut ws, 1), vec![Recorded::Open]);
+    assert!(matches!(ws.get_state(), WsState::Connected));
+    ws
+}
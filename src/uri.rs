@@ -0,0 +1,126 @@
+use std::cell::Cell;
+
+use url::Url;
+
+thread_local! {
+    /// Why the last `wspp_new*` call on this thread returned null.
+    static CREATE_ERROR: Cell<WsppUriError> = const { Cell::new(WsppUriError::None) };
+}
+
+/// What is wrong with a server URI.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WsppUriError {
+    #[default]
+    None = 0,
+    /// Null, or not valid UTF-8 or UTF-16.
+    Encoding = 1,
+    /// Not an absolute URL.
+    Syntax = 2,
+    /// A scheme other than `ws` or `wss`.
+    Scheme = 3,
+    MissingHost = 4,
+    /// Port 0 or out of range.
+    Port = 5,
+    /// A `%` not followed by two hex digits.
+    PercentEncoding = 6,
+}
+
+/// A rejected URI: what kind of problem and a description for users.
+#[derive(Debug)]
+pub struct UriError {
+    pub kind: WsppUriError,
+    pub message: String,
+}
+
+impl UriError {
+    fn new(kind: WsppUriError, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parses `uri` and checks it names a WebSocket server.
+pub fn validate(uri: &str) -> Result<Url, UriError> {
+    if let Some(at) = bad_percent_escape(uri) {
+        return Err(UriError::new(
+            WsppUriError::PercentEncoding,
+            format!("malformed percent-encoding at offset {at}"),
+        ));
+    }
+    let url = Url::parse(uri).map_err(|err| {
+        let kind = match err {
+            url::ParseError::InvalidPort => WsppUriError::Port,
+            url::ParseError::EmptyHost => WsppUriError::MissingHost,
+            _ => WsppUriError::Syntax,
+        };
+        UriError::new(kind, err.to_string())
+    })?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        return Err(UriError::new(
+            WsppUriError::Scheme,
+            format!("unsupported scheme {}, expected ws or wss", url.scheme()),
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(UriError::new(WsppUriError::MissingHost, "missing host"));
+    }
+    if url.port() == Some(0) {
+        return Err(UriError::new(WsppUriError::Port, "port 0 is not usable"));
+    }
+    Ok(url)
+}
+
+/// Offset of the first `%` not followed by two hex digits.
+fn bad_percent_escape(uri: &str) -> Option<usize> {
+    let bytes = uri.as_bytes();
+    bytes.iter().enumerate().find_map(|(at, &byte)| {
+        let escape = bytes.get(at + 1..at + 3);
+        let valid = escape.is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+        (byte == b'%' && !valid).then_some(at)
+    })
+}
+
+pub fn set_create_error(error: WsppUriError) {
+    CREATE_ERROR.set(error);
+}
+
+pub fn create_error() -> WsppUriError {
+    CREATE_ERROR.get()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WsppUriError, validate};
+
+    fn kind(uri: &str) -> Option<WsppUriError> {
+        validate(uri).err().map(|err| err.kind)
+    }
+
+    #[test]
+    fn accepts_websocket_urls() {
+        assert!(validate("ws://127.0.0.1:8080/chat").is_ok());
+        assert!(validate("wss://[::1]/").is_ok());
+        assert!(validate("wss://example.com").is_ok());
+    }
+
+    #[test]
+    fn names_what_is_wrong() {
+        assert_eq!(kind("not a url"), Some(WsppUriError::Syntax));
+        assert_eq!(kind("http://example.com/"), Some(WsppUriError::Scheme));
+        assert_eq!(kind("ws://:8080/"), Some(WsppUriError::MissingHost));
+        assert_eq!(kind("ws://example.com:99999/"), Some(WsppUriError::Port));
+        assert_eq!(kind("ws://example.com:0/"), Some(WsppUriError::Port));
+        assert_eq!(
+            kind("ws://example.com/a%2"),
+            Some(WsppUriError::PercentEncoding)
+        );
+        assert_eq!(
+            kind("ws://example.com/%zz"),
+            Some(WsppUriError::PercentEncoding)
+        );
+        assert_eq!(kind("ws://example.com/a%2Fb"), None);
+    }
+}
@@ -0,0 +1,90 @@
+/* Drives one connection through the C API against an echo server whose URI
+ * is the only argument. Exits non-zero after naming the step that failed. */
+#include <stdio.h>
+#include <string.h>
+#include <time.h>
+
+#include "wspp.h"
+
+static int opened;
+static int closed;
+static char echoed[64];
+
+static void on_open(void *user_data) {
+    (void)user_data;
+    opened = 1;
+}
+
+static void on_close(void *user_data) {
+    (void)user_data;
+    closed = 1;
+}
+
+static void on_message(void *user_data, const char *data, uint64_t len, int32_t op_code) {
+    (void)user_data;
+    (void)op_code;
+    if (len < sizeof(echoed)) {
+        memcpy(echoed, data, len);
+        echoed[len] = '\0';
+    }
+}
+
+static void on_error(void *user_data, const char *msg) {
+    (void)user_data;
+    fprintf(stderr, "error: %s\n", msg);
+}
+
+static int is_opened(void) { return opened; }
+
+static int is_closed(void) { return closed; }
+
+static int is_echoed(void) { return strcmp(echoed, "from c") == 0; }
+
+/* Polls until `done` holds, for about five seconds at most. */
+static int poll_until(WsppWs *ws, int (*done)(void)) {
+    struct timespec pause = {0, 1000000};
+    for (int i = 0; i < 5000 && !done(); i++) {
+        wspp_poll(ws);
+        nanosleep(&pause, NULL);
+    }
+    return done();
+}
+
+static int fail(const char *step) {
+    fprintf(stderr, "%s failed\n", step);
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <uri>\n", argv[0]);
+        return 2;
+    }
+    if (wspp_abi_version() != 2) {
+        return fail("abi version check");
+    }
+
+    WsppWs *ws = wspp_new(argv[1]);
+    if (ws == NULL) {
+        return fail("create");
+    }
+    wspp_set_open_handler(ws, on_open, NULL);
+    wspp_set_close_handler(ws, on_close, NULL);
+    wspp_set_message_handler(ws, on_message, NULL);
+    wspp_set_error_handler(ws, on_error, NULL);
+
+    if (wspp_connect(ws) != WsppResult_Ok || !poll_until(ws, is_opened)) {
+        return fail("connect");
+    }
+    if (wspp_send_text(ws, "from c") != WsppResult_Ok || !poll_until(ws, is_echoed)) {
+        return fail("echo");
+    }
+    if (wspp_close(ws, 1000, "done") != WsppResult_Ok || !poll_until(ws, is_closed)) {
+        return fail("close");
+    }
+    wspp_delete(ws);
+    if (wspp_global_shutdown(5000) != WsppResult_Ok) {
+        return fail("global shutdown");
+    }
+    return 0;
+}
@@ -0,0 +1,144 @@
+//! Compiles `tests/c/smoke.c` against a header generated from the current
+//! sources, links it to the built cdylib and runs it against an echo
+//! server, so ABI breakage fails `cargo test`.
+#![cfg(any(target_os = "linux", target_os = "macos"))]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpListener;
+use tokio::runtime::Builder;
+
+/// Serves echo connections on a background thread until the test exits.
+fn start_echo_server() -> String {
+    let rt = Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("server runtime");
+    let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).expect("bind");
+    let url = format!("ws://{}/", listener.local_addr().expect("addr"));
+    std::thread::spawn(move || {
+        rt.block_on(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+                        return;
+                    };
+                    while let Some(Ok(msg)) = ws.next().await {
+                        if msg.is_text() || msg.is_binary() {
+                            let _ = ws.send(msg).await;
+                        }
+                    }
+                });
+            }
+        });
+    });
+    url
+}
+
+/// Names of the callback type aliases in `src/callback.rs`.
+fn callback_aliases() -> Vec<String> {
+    let source =
+        std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("src/callback.rs"))
+            .expect("read callback.rs");
+    source
+        .lines()
+        .filter_map(|line| line.strip_prefix("pub type "))
+        .filter_map(|rest| rest.split([' ', '=']).next())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn generate_header(dir: &Path) {
+    // cbindgen only sees through `Option` of a bare function pointer, so
+    // `Option<OnOpenCallback>` would become an opaque `Option_OnOpenCallback`
+    // struct. Emit the aliases themselves and use them in its place.
+    let aliases = callback_aliases();
+    let export = cbindgen::ExportConfig {
+        include: aliases.clone(),
+        exclude: aliases
+            .iter()
+            .map(|name| format!("Option_{name}"))
+            .collect(),
+        rename: aliases
+            .iter()
+            .map(|name| (format!("Option_{name}"), name.clone()))
+            .collect(),
+        ..Default::default()
+    };
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        style: cbindgen::Style::Type,
+        include_guard: Some("WSPP_H".into()),
+        export,
+        enumeration: cbindgen::EnumConfig {
+            prefix_with_name: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    cbindgen::Builder::new()
+        .with_crate(env!("CARGO_MANIFEST_DIR"))
+        .with_config(config)
+        .generate()
+        .expect("header generation")
+        .write_to_file(dir.join("wspp.h"));
+}
+
+/// Directory holding the cdylib built for this test run.
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().expect("test executable");
+    exe.parent().expect("deps dir").to_path_buf()
+}
+
+fn target() -> String {
+    let arch = std::env::consts::ARCH;
+    if cfg!(target_os = "macos") {
+        format!("{arch}-apple-darwin")
+    } else {
+        format!("{arch}-unknown-linux-gnu")
+    }
+}
+
+#[test]
+fn c_program_round_trips_a_message() {
+    let work = std::env::temp_dir().join(format!("wspp-c-smoke-{}", std::process::id()));
+    std::fs::create_dir_all(&work).expect("work dir");
+    generate_header(&work);
+
+    let lib_dir = library_dir();
+    let program = work.join("smoke");
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .opt_level(0)
+        .target(&target())
+        .host(&target())
+        .warnings(true)
+        .get_compiler();
+    let status = compiler
+        .to_command()
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/c/smoke.c"))
+        .arg("-I")
+        .arg(&work)
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lc_wspp_rs")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("run C compiler");
+    assert!(status.success(), "C smoke program failed to compile");
+
+    let output = Command::new(&program)
+        .arg(start_echo_server())
+        .output()
+        .expect("run C smoke program");
+    let _ = std::fs::remove_dir_all(&work);
+    assert!(
+        output.status.success(),
+        "C smoke program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}